@@ -0,0 +1,9 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // the `fftw` feature links libfftw3, which is located using pkg-config
+    #[cfg(feature = "fftw")]
+    if let Err(error) = pkg_config::probe_library("fftw3") {
+        panic!("the fftw feature requires libfftw3: {}", error);
+    }
+}
@@ -0,0 +1,39 @@
+//! Fuzz the encoding of (multi-stranded) sequences and the mapping of subsequences to parent positions.
+//! Run with `cargo fuzz run encoding` from the repository root.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rafft::encoding::EncodedSequence;
+use rafft::testing::{decode_roundtrip, parent_mapping_roundtrip, subsequence_roundtrip};
+
+fuzz_target!(|data: &[u8]| {
+    // the first two bytes select the boundaries of a subsequence, the remaining bytes the nucleotides
+    let (bounds, nucleotides) = match data {
+        [a, b, rest @ ..] => ((*a as usize, *b as usize), rest),
+        _ => return,
+    };
+
+    let sequence: String = nucleotides
+        .iter()
+        .map(|&byte| ['A', 'C', 'G', 'U', 'T', '&'][byte as usize % 6])
+        .collect();
+
+    // strands have to be non-empty to be recovered
+    if sequence.split('&').any(str::is_empty) {
+        return;
+    }
+
+    assert!(decode_roundtrip(&sequence));
+
+    let encoded = EncodedSequence::new(&sequence).unwrap();
+    let (start, end) = (
+        bounds.0 % (encoded.len() + 1),
+        bounds.1 % (encoded.len() + 1),
+    );
+
+    assert!(subsequence_roundtrip(&encoded, start, end));
+    assert!(parent_mapping_roundtrip(
+        &encoded,
+        &encoded.subsequence(start, end)
+    ));
+});
@@ -0,0 +1,1148 @@
+//! This module provides the evaluation of the prediction accuracy of RAFFT on benchmark datasets of
+//! known structures, e.g. ArchiveII or bpRNA.
+//!
+//! Reference structures are read from connectivity tables (CT, see [`read_ct()`]) or dot-bracket files
+//! (see [`read_dot_bracket()`]). Each sequence is folded
+//! and both the structure of lowest free energy and the maximum expected accuracy (MEA) structure of the
+//! fast folding graph (see [`mea_structure()`]) are compared to the reference base pair by base pair:
+//!
+//! - PPV (precision): the fraction of predicted pairs that are in the reference,
+//! - sensitivity (recall): the fraction of reference pairs that are predicted,
+//! - MCC: the Matthews correlation coefficient over all possible pairs.
+//!
+//! Optionally, pairs shifted by a few positions are considered correct, e.g. the common `±1` slippage
+//! tolerance (see [`compare_with_slippage()`]), since strict matching understates helix-level agreement.
+//!
+//! Whole families, e.g. of Rfam, can be evaluated from Stockholm alignments (see [`read_stockholm()`]),
+//! where the consensus structure is mapped onto each member (see [`Alignment::reference_records()`])
+//! and the accuracies are averaged per family (see [`evaluate_family()`]).
+
+use crate::encoding::{self, PairTable, SeqPos1, GAP_SYMBOLS};
+use crate::fast_folding::RafftConfig;
+use crate::folding_graph::RafftGraph;
+use crate::kinetics::boltzmann_probabilities;
+use crate::vienna::VCompound;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use thiserror::Error;
+
+/// Error type representing errors that may arise while reading reference structures.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// The reference structures could not be read.
+    #[error("failed to read reference structures: {0}")]
+    Io(#[from] io::Error),
+    /// A line could not be parsed (`1`-indexed line number).
+    #[error("line {0} of the connectivity table is invalid: {1}")]
+    InvalidLine(usize, String),
+    /// A record is incomplete or its pairs are inconsistent.
+    #[error("record {0:?} of the connectivity table is invalid: {1}")]
+    InvalidRecord(String, String),
+    /// A line of a dot-bracket file could not be parsed (`1`-indexed line number).
+    #[error("line {0} of the dot-bracket file is invalid: {1}")]
+    InvalidDotBracket(usize, String),
+    /// A line of a Stockholm alignment could not be parsed (`1`-indexed line number).
+    #[error("line {0} of the Stockholm alignment is invalid: {1}")]
+    InvalidStockholm(usize, String),
+    /// The consensus structure of an alignment is missing or does not match its columns.
+    #[error("alignment {0:?} is invalid: {1}")]
+    InvalidAlignment(String, String),
+}
+
+/// A sequence with its reference structure, e.g. a record of a connectivity table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReferenceRecord {
+    /// title of the record
+    pub name: String,
+    /// nucleotides in upper case
+    pub sequence: String,
+    /// reference structure
+    pub structure: PairTable,
+}
+
+/// Read all records of a connectivity table (CT), e.g. as written by RNAstructure or mfold.
+///
+/// Every record starts with a header line containing the number of nucleotides followed by the title
+/// (an `ENERGY = ...` annotation preceding the title is dropped). Each of the following lines describes
+/// a nucleotide by its `1`-indexed position, the nucleotide and its partner in the fourth column
+/// after the positions of its neighbors (`0` if unpaired). Further columns are ignored.
+/// Empty lines and lines starting with `#` are skipped.
+pub fn read_ct<R: BufRead>(reader: R) -> Result<Vec<ReferenceRecord>, Error> {
+    let mut records = vec![];
+    // the current record and its partners
+    let mut current: Option<(ReferenceRecord, usize, Vec<usize>)> = None;
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        let invalid = |message: &str| Error::InvalidLine(number + 1, message.to_string());
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let columns: Vec<&str> = line.split_whitespace().collect();
+
+        match &mut current {
+            Some((record, length, partners)) if partners.len() < *length => {
+                if columns.len() < 5 {
+                    return Err(invalid("expected at least 5 columns"));
+                }
+
+                let position: usize = columns[0]
+                    .parse()
+                    .map_err(|_| invalid("invalid position"))?;
+                let partner: usize = columns[4].parse().map_err(|_| invalid("invalid partner"))?;
+
+                if position != partners.len() + 1 {
+                    return Err(invalid("positions are not consecutive"));
+                }
+                if partner > *length || partner == position {
+                    return Err(invalid("partner out of range"));
+                }
+
+                record.sequence.push_str(&columns[1].to_uppercase());
+                partners.push(partner);
+            }
+            _ => {
+                if let Some(record) = current.take() {
+                    records.push(finish_record(record)?);
+                }
+
+                let length: usize = columns[0]
+                    .parse()
+                    .map_err(|_| invalid("expected the number of nucleotides"))?;
+                let title = line[columns[0].len()..].trim();
+                let name = match title.find("ENERGY") {
+                    // "ENERGY = -12.3 name" or "ENERGY = -12.3\tname"
+                    Some(0) => title
+                        .split_whitespace()
+                        .skip(3)
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    _ => title.to_string(),
+                };
+
+                current = Some((
+                    ReferenceRecord {
+                        name,
+                        sequence: String::with_capacity(length),
+                        structure: PairTable::new(length),
+                    },
+                    length,
+                    Vec::with_capacity(length),
+                ));
+            }
+        }
+    }
+
+    if let Some(record) = current {
+        records.push(finish_record(record)?);
+    }
+
+    Ok(records)
+}
+
+// Check the partners of a complete record and insert them into its structure.
+fn finish_record(
+    (mut record, length, partners): (ReferenceRecord, usize, Vec<usize>),
+) -> Result<ReferenceRecord, Error> {
+    if partners.len() < length {
+        return Err(Error::InvalidRecord(
+            record.name,
+            format!("ends after {} of {} nucleotides", partners.len(), length),
+        ));
+    }
+
+    for (i, &j) in partners.iter().enumerate().map(|(i, j)| (i + 1, j)) {
+        if j > 0 && partners[j - 1] != i {
+            return Err(Error::InvalidRecord(
+                record.name,
+                format!("pairs {} with {}, but not vice versa", i, j),
+            ));
+        }
+        if i < j {
+            record.structure.insert(i as i16, j as i16);
+        }
+    }
+
+    Ok(record)
+}
+
+/// Read all records of a dot-bracket file, e.g. as written by RNAfold or RNAsubopt.
+///
+/// A record consists of an optional `>` header line with its title, the sequence and at least one structure
+/// in dot-bracket notation. Anything following the structure on its line, e.g. its free energy, is ignored.
+/// Sequences followed by several structures yield one record per structure, with the same title and sequence.
+/// Empty lines and lines starting with `#` are skipped.
+pub fn read_dot_bracket<R: BufRead>(reader: R) -> Result<Vec<ReferenceRecord>, Error> {
+    let mut records = vec![];
+    // the title of the next record
+    let mut title = String::new();
+    // the current record and whether a structure was read for it
+    let mut current: Option<(ReferenceRecord, bool)> = None;
+    let mut lines = 0;
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        lines = number + 1;
+        let line = line.trim();
+        let invalid = |message: &str| Error::InvalidDotBracket(number + 1, message.to_string());
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let token = line.split_whitespace().next().unwrap_or_default();
+        match &mut current {
+            Some((record, has_structure)) if token.starts_with(['.', '(', ')', '&']) => {
+                let structure: PairTable = token
+                    .parse()
+                    .map_err(|error: encoding::Error| invalid(&error.to_string()))?;
+                if structure.len() != record.structure.len() {
+                    return Err(invalid(
+                        "structure does not match the length of the sequence",
+                    ));
+                }
+
+                *has_structure = true;
+                records.push(ReferenceRecord {
+                    structure,
+                    ..record.clone()
+                });
+            }
+            Some((_, false)) => return Err(invalid("expected a structure")),
+            _ => {
+                if let Some(header) = line.strip_prefix('>') {
+                    title = header.trim().to_string();
+                    current = None;
+                } else if token.chars().all(|c| c.is_ascii_alphabetic() || c == '&') {
+                    let sequence = token.to_uppercase();
+                    let length = sequence.chars().filter(|&c| c != '&').count();
+
+                    current = Some((
+                        ReferenceRecord {
+                            name: std::mem::take(&mut title),
+                            sequence,
+                            structure: PairTable::new(length),
+                        },
+                        false,
+                    ));
+                } else {
+                    return Err(invalid("expected a header or a sequence"));
+                }
+            }
+        }
+    }
+
+    match current {
+        Some((_, false)) => Err(Error::InvalidDotBracket(
+            lines + 1,
+            "expected a structure".to_string(),
+        )),
+        _ => Ok(records),
+    }
+}
+
+/// Counts of correctly and incorrectly predicted base pairs, see [`compare()`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Accuracy {
+    /// number of predicted pairs that are in the reference
+    pub true_positives: usize,
+    /// number of predicted pairs that are not in the reference
+    pub false_positives: usize,
+    /// number of reference pairs that are not predicted
+    pub false_negatives: usize,
+    /// number of nucleotides
+    pub length: usize,
+}
+
+impl Accuracy {
+    /// Return the positive predictive value, `1` if no pairs are predicted.
+    pub fn ppv(&self) -> f64 {
+        ratio(
+            self.true_positives,
+            self.true_positives + self.false_positives,
+        )
+    }
+
+    /// Return the sensitivity, `1` if the reference does not contain any pairs.
+    pub fn sensitivity(&self) -> f64 {
+        ratio(
+            self.true_positives,
+            self.true_positives + self.false_negatives,
+        )
+    }
+
+    /// Return the Matthews correlation coefficient, where the true negatives are all other possible pairs
+    /// of the `n (n - 1) / 2` pairs of positions. Returns `0` if undefined, unless the prediction is perfect.
+    pub fn mcc(&self) -> f64 {
+        let (tp, fp, fn_) = (
+            self.true_positives as f64,
+            self.false_positives as f64,
+            self.false_negatives as f64,
+        );
+        let all = (self.length * self.length.saturating_sub(1) / 2) as f64;
+        let tn = all - tp - fp - fn_;
+        let denominator = ((tp + fp) * (tp + fn_) * (tn + fp) * (tn + fn_)).sqrt();
+
+        if fp == 0.0 && fn_ == 0.0 {
+            1.0
+        } else if denominator > 0.0 {
+            (tp * tn - fp * fn_) / denominator
+        } else {
+            0.0
+        }
+    }
+}
+
+fn ratio(numerator: usize, denominator: usize) -> f64 {
+    if denominator == 0 {
+        1.0
+    } else {
+        numerator as f64 / denominator as f64
+    }
+}
+
+/// Compare the pairs of `predicted` to the pairs of `reference`.
+/// Panics if the structures differ in length.
+pub fn compare(predicted: &PairTable, reference: &PairTable) -> Accuracy {
+    compare_with_slippage(predicted, reference, 0)
+}
+
+/// Compare the pairs of `predicted` to the pairs of `reference` like [`compare()`], where a pair `(i, j)`
+/// matches all pairs `(k, l)` of the other structure with `|i - k| + |j - l| <= slippage`.
+/// Predicted pairs with a match are true positives, reference pairs without a match are false negatives.
+/// Panics if the structures differ in length.
+pub fn compare_with_slippage(
+    predicted: &PairTable,
+    reference: &PairTable,
+    slippage: usize,
+) -> Accuracy {
+    assert_eq!(predicted.len(), reference.len(), "lengths differ");
+
+    let true_positives = predicted
+        .paired()
+        .filter(|&pair| has_match(reference, pair, slippage))
+        .count();
+    let found = reference
+        .paired()
+        .filter(|&pair| has_match(predicted, pair, slippage))
+        .count();
+
+    Accuracy {
+        true_positives,
+        false_positives: predicted.pairs() - true_positives,
+        false_negatives: reference.pairs() - found,
+        length: reference.len(),
+    }
+}
+
+// Return whether `structure` contains a pair within `slippage` of `(i, j)`.
+fn has_match(structure: &PairTable, (i, j): (usize, usize), slippage: usize) -> bool {
+    let first = i.saturating_sub(slippage).max(1);
+    let last = (i + slippage).min(structure.len());
+
+    (first..=last).any(|k| match structure.partner(SeqPos1(k)) {
+        Some(SeqPos1(l)) => k < l && k.abs_diff(i) + l.abs_diff(j) <= slippage,
+        None => false,
+    })
+}
+
+/// Return the probabilities of the pairs `(i, j)` (`1`-indexed) in the ensemble of the structures of `graph`,
+/// weighted by their Boltzmann probabilities at thermal energy `kt` (see [`boltzmann_probabilities()`]).
+/// Pairs that do not occur in any structure are omitted.
+pub fn pair_probabilities(graph: &RafftGraph, kt: f64) -> BTreeMap<(usize, usize), f64> {
+    let mut probabilities: BTreeMap<(usize, usize), f64> = BTreeMap::new();
+
+    for (node, probability) in graph.iter().zip(boltzmann_probabilities(graph, kt)) {
+        for pair in node.structure.pair_table().paired() {
+            *probabilities.entry(pair).or_default() += probability;
+        }
+    }
+
+    probabilities
+}
+
+/// Return the maximum expected accuracy (MEA) structure of the ensemble of the structures of `graph`
+/// at thermal energy `kt`, see [`pair_probabilities()`].
+/// The structure maximizes `sum(2 gamma p(i, j))` over its pairs plus the sum of the probabilities
+/// of its unpaired positions to be unpaired, where `p(i, j)` is the probability of the pair in the ensemble.
+/// Larger values of `gamma` favor more pairs.
+pub fn mea_structure(graph: &RafftGraph, kt: f64, gamma: f64) -> PairTable {
+    let length = graph
+        .iter()
+        .next()
+        .map_or(0, |root| root.structure.pair_table().len());
+    let pair_probabilities = pair_probabilities(graph, kt);
+
+    // 1-indexed probabilities of positions to be unpaired and the possible partners of each position
+    let mut unpaired = vec![1.0; length + 1];
+    let mut partners: Vec<Vec<(usize, f64)>> = vec![vec![]; length + 1];
+    for (&(i, j), &p) in &pair_probabilities {
+        unpaired[i] -= p;
+        unpaired[j] -= p;
+        partners[j].push((i, p));
+    }
+
+    // scores[i][j] of the best structure on positions i..=j, with empty intervals scoring 0
+    let mut scores = vec![vec![0.0; length + 2]; length + 2];
+    for span in 0..length {
+        for i in 1..=length - span {
+            let j = i + span;
+            let mut best = scores[i][j - 1] + unpaired[j];
+
+            for &(k, p) in partners[j].iter().filter(|&&(k, _)| k >= i) {
+                best = f64::max(
+                    best,
+                    scores[i][k - 1] + scores[k + 1][j - 1] + 2.0 * gamma * p,
+                );
+            }
+            scores[i][j] = best;
+        }
+    }
+
+    let mut structure = PairTable::new(length);
+    let mut intervals = vec![(1, length)];
+    while let Some((i, j)) = intervals.pop() {
+        if i >= j {
+            continue;
+        }
+
+        #[allow(clippy::float_cmp)]
+        if scores[i][j] == scores[i][j - 1] + unpaired[j] {
+            intervals.push((i, j - 1));
+        } else if let Some(&(k, _)) = partners[j].iter().find(|&&(k, p)| {
+            k >= i && scores[i][j] == scores[i][k - 1] + scores[k + 1][j - 1] + 2.0 * gamma * p
+        }) {
+            structure.insert(k as i16, j as i16);
+            intervals.push((i, k - 1));
+            intervals.push((k + 1, j - 1));
+        }
+    }
+
+    structure
+}
+
+/// Accuracies of the structure of lowest free energy and of the MEA structure of a record.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Evaluation {
+    /// accuracy of the structure of lowest free energy
+    pub best: Accuracy,
+    /// accuracy of the MEA structure with `gamma = 1`
+    pub mea: Accuracy,
+}
+
+impl Evaluation {
+    // PPV, sensitivity and MCC of both structures in the order of the columns of `TSV_HEADER`
+    fn values(&self) -> [f64; 6] {
+        [
+            self.best.ppv(),
+            self.best.sensitivity(),
+            self.best.mcc(),
+            self.mea.ppv(),
+            self.mea.sensitivity(),
+            self.mea.mcc(),
+        ]
+    }
+}
+
+/// Averages of the accuracies of several records, see [`summarize()`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    /// number of evaluated records
+    pub evaluated: usize,
+    /// mean length of the evaluated records, rounded down
+    pub mean_length: usize,
+    /// mean PPV, sensitivity and MCC of the structures of lowest free energy
+    pub best: [f64; 3],
+    /// mean PPV, sensitivity and MCC of the MEA structures
+    pub mea: [f64; 3],
+}
+
+impl Summary {
+    // mean accuracies in the order of the columns of `TSV_HEADER`
+    fn values(&self) -> [f64; 6] {
+        let [a, b, c] = self.best;
+        let [d, e, f] = self.mea;
+        [a, b, c, d, e, f]
+    }
+}
+
+/// Average the accuracies of all records that could be evaluated, or return `None` if there are none.
+pub fn summarize(
+    records: &[ReferenceRecord],
+    results: &[Result<Evaluation, encoding::Error>],
+) -> Option<Summary> {
+    let mut totals = [0.0; 6];
+    let mut evaluated = 0;
+    let mut total_length = 0;
+
+    for (record, evaluation) in records
+        .iter()
+        .zip(results)
+        .filter_map(|(record, result)| Some((record, result.as_ref().ok()?)))
+    {
+        totals
+            .iter_mut()
+            .zip(evaluation.values())
+            .for_each(|(total, value)| *total += value);
+        evaluated += 1;
+        total_length += record.structure.len();
+    }
+
+    let mean_length = total_length.checked_div(evaluated)?;
+    let mean = |k: usize| totals[k] / evaluated as f64;
+
+    Some(Summary {
+        evaluated,
+        mean_length,
+        best: [mean(0), mean(1), mean(2)],
+        mea: [mean(3), mean(4), mean(5)],
+    })
+}
+
+/// Header line of the tab-separated output of [`write_tsv()`].
+pub const TSV_HEADER: &str =
+    "name\tlength\tbest_ppv\tbest_sensitivity\tbest_mcc\tmea_ppv\tmea_sensitivity\tmea_mcc";
+
+/// Fold the sequence of `record` using `config` and evaluate the predicted structures,
+/// where the MEA structure is computed at thermal energy `kt` (in `kcal/mol`)
+/// and pairs are compared with a tolerance of `slippage` positions (see [`compare_with_slippage()`]).
+pub fn evaluate(
+    record: &ReferenceRecord,
+    config: &RafftConfig,
+    kt: f64,
+    slippage: usize,
+) -> Result<Evaluation, encoding::Error> {
+    let encoded = config.encode(&record.sequence)?;
+    let mut graph = RafftGraph::new(encoded, VCompound::new(&record.sequence), config.clone());
+    graph.construct_trajectories();
+
+    let best = graph
+        .iter()
+        .min_by_key(|node| node.energy)
+        .expect("the root is always present");
+
+    Ok(Evaluation {
+        best: compare_with_slippage(&best.structure.pair_table(), &record.structure, slippage),
+        mea: compare_with_slippage(&mea_structure(&graph, kt, 1.0), &record.structure, slippage),
+    })
+}
+
+/// Evaluate all `records` in parallel, see [`evaluate()`].
+/// Like [`crate::screening::screen_all_with()`], `on_result` is called as soon as each record is evaluated.
+/// Results are in the order of `records`.
+pub fn evaluate_all_with<F>(
+    records: &[ReferenceRecord],
+    config: &RafftConfig,
+    kt: f64,
+    slippage: usize,
+    on_result: F,
+) -> Vec<Result<Evaluation, encoding::Error>>
+where
+    F: Fn(&Result<Evaluation, encoding::Error>) + Sync,
+{
+    config.install(|| {
+        records
+            .par_iter()
+            .map(|record| {
+                let result = evaluate(record, config, kt, slippage);
+                on_result(&result);
+                result
+            })
+            .collect()
+    })
+}
+
+/// Write the accuracies per record as tab-separated values with a header line (see [`TSV_HEADER`]),
+/// followed by a row named `mean` containing the averages (including the length) over all evaluated records.
+/// Records that could not be encoded are skipped and returned with their errors, in the order of `records`.
+pub fn write_tsv<'a, W: Write>(
+    records: &'a [ReferenceRecord],
+    results: &'a [Result<Evaluation, encoding::Error>],
+    writer: &mut W,
+) -> io::Result<Vec<(&'a str, &'a encoding::Error)>> {
+    writeln!(writer, "{}", TSV_HEADER)?;
+    let mut skipped = vec![];
+
+    for (record, result) in records.iter().zip(results) {
+        match result {
+            Ok(evaluation) => write!(writer, "{}\t{}", record.name, record.structure.len())
+                .and_then(|()| write_values(writer, &evaluation.values()))?,
+            Err(error) => skipped.push((record.name.as_str(), error)),
+        }
+    }
+
+    if let Some(summary) = summarize(records, results) {
+        write!(writer, "mean\t{}", summary.mean_length)?;
+        write_values(writer, &summary.values())?;
+    }
+
+    Ok(skipped)
+}
+
+// Write tab-separated accuracies, each preceded by a tab, and end the line.
+fn write_values<W: Write>(writer: &mut W, values: &[f64; 6]) -> io::Result<()> {
+    for value in values {
+        write!(writer, "\t{:.4}", value)?;
+    }
+    writeln!(writer)
+}
+
+/// A multiple sequence alignment of a family with its consensus structure, see [`read_stockholm()`].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Alignment {
+    /// identifier of the family (`#=GF ID`), empty if not annotated
+    pub family: String,
+    /// names and aligned sequences of the members in order of their first appearance
+    pub sequences: Vec<(String, String)>,
+    /// consensus structure in WUSS notation (`#=GC SS_cons`), if annotated
+    pub consensus_structure: Option<String>,
+}
+
+impl Alignment {
+    /// Map the consensus structure onto each member, resulting in its reference structure.
+    /// Columns are paired according to the brackets `<>`, `()`, `[]` and `{}` of the consensus structure;
+    /// pseudoknots (letters) are ignored. A pair is retained for a member if neither of its columns is a gap
+    /// (see [`GAP_SYMBOLS`]) and the nucleotides form a canonical pair (AU, GC or GU).
+    /// Sequences are ungapped and converted to upper case.
+    pub fn reference_records(&self) -> Result<Vec<ReferenceRecord>, Error> {
+        let invalid =
+            |message: &str| Error::InvalidAlignment(self.family.clone(), message.to_string());
+        let consensus: Vec<char> = self
+            .consensus_structure
+            .as_ref()
+            .ok_or_else(|| invalid("missing consensus structure (#=GC SS_cons)"))?
+            .chars()
+            .collect();
+
+        let mut column_pairs = vec![];
+        let mut stacks: [Vec<usize>; 4] = Default::default();
+        for (column, symbol) in consensus.iter().enumerate() {
+            if let Some(kind) = "<([{".find(*symbol) {
+                stacks[kind].push(column);
+            } else if let Some(kind) = ">)]}".find(*symbol) {
+                let opening = stacks[kind]
+                    .pop()
+                    .ok_or_else(|| invalid("unbalanced consensus structure"))?;
+                column_pairs.push((opening, column));
+            }
+        }
+        if stacks.iter().any(|stack| !stack.is_empty()) {
+            return Err(invalid("unbalanced consensus structure"));
+        }
+
+        self.sequences
+            .iter()
+            .map(|(name, aligned)| {
+                let aligned: Vec<char> = aligned.to_uppercase().chars().collect();
+                if aligned.len() != consensus.len() {
+                    return Err(invalid(&format!(
+                        "{} has {} columns, the consensus structure {}",
+                        name,
+                        aligned.len(),
+                        consensus.len()
+                    )));
+                }
+
+                // 1-indexed positions of the ungapped sequence per column
+                let mut positions = vec![None; aligned.len()];
+                let mut sequence = String::with_capacity(aligned.len());
+                for (column, &c) in aligned.iter().enumerate() {
+                    if !GAP_SYMBOLS.contains(&c) {
+                        sequence.push(c);
+                        positions[column] = Some(sequence.len());
+                    }
+                }
+
+                let mut structure = PairTable::new(sequence.len());
+                for &(a, b) in &column_pairs {
+                    if let (Some(i), Some(j)) = (positions[a], positions[b]) {
+                        if canonical(aligned[a], aligned[b]) {
+                            structure.insert(i as i16, j as i16);
+                        }
+                    }
+                }
+
+                Ok(ReferenceRecord {
+                    name: name.clone(),
+                    sequence,
+                    structure,
+                })
+            })
+            .collect()
+    }
+}
+
+fn canonical(a: char, b: char) -> bool {
+    let normalize = |c: char| if c == 'T' { 'U' } else { c };
+
+    matches!(
+        (normalize(a), normalize(b)),
+        ('A', 'U') | ('U', 'A') | ('G', 'C') | ('C', 'G') | ('G', 'U') | ('U', 'G')
+    )
+}
+
+/// Read all alignments of a Stockholm file, e.g. a family of Rfam or the complete `Rfam.seed`.
+/// Alignments are terminated by `//`, sequences may be split into several blocks.
+/// Apart from the family identifier (`#=GF ID`) and the consensus structure (`#=GC SS_cons`),
+/// annotations are ignored.
+pub fn read_stockholm<R: BufRead>(reader: R) -> Result<Vec<Alignment>, Error> {
+    let mut alignments = vec![];
+    let mut current = Alignment::default();
+    let mut started = false;
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        let invalid = |message: &str| Error::InvalidStockholm(number + 1, message.to_string());
+
+        if line.is_empty() {
+            continue;
+        } else if line.starts_with("# STOCKHOLM") {
+            started = true;
+        } else if line == "//" {
+            alignments.push(std::mem::take(&mut current));
+            started = false;
+        } else if !started {
+            return Err(invalid("expected the header # STOCKHOLM 1.0"));
+        } else if let Some(annotation) = line.strip_prefix("#=GF") {
+            if let Some(("ID", family)) = annotation.trim().split_once(char::is_whitespace) {
+                current.family = family.trim().to_string();
+            }
+        } else if let Some(annotation) = line.strip_prefix("#=GC") {
+            if let Some(("SS_cons", structure)) = annotation.trim().split_once(char::is_whitespace)
+            {
+                current
+                    .consensus_structure
+                    .get_or_insert_with(String::new)
+                    .push_str(structure.trim());
+            }
+        } else if !line.starts_with('#') {
+            let (name, aligned) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| invalid("expected a name and an aligned sequence"))?;
+
+            match current
+                .sequences
+                .iter_mut()
+                .find(|(other, _)| other == name)
+            {
+                Some((_, sequence)) => sequence.push_str(aligned.trim()),
+                None => current
+                    .sequences
+                    .push((name.to_string(), aligned.trim().to_string())),
+            }
+        }
+    }
+
+    if started {
+        return Err(Error::InvalidAlignment(
+            current.family,
+            "missing terminating //".to_string(),
+        ));
+    }
+
+    Ok(alignments)
+}
+
+/// Accuracies of the members of a family, see [`evaluate_family()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct FamilySummary {
+    /// identifier of the family
+    pub family: String,
+    /// number of members
+    pub members: usize,
+    /// averages over all members that could be evaluated, `None` if there are none
+    pub summary: Option<Summary>,
+    /// names of the members that could not be encoded, with the reason
+    pub skipped: Vec<(String, String)>,
+}
+
+/// Header line of the tab-separated output of [`write_family_tsv()`].
+pub const FAMILY_TSV_HEADER: &str = "family\tmembers\tevaluated\tmean_length\tbest_ppv\tbest_sensitivity\tbest_mcc\tmea_ppv\tmea_sensitivity\tmea_mcc";
+
+/// Map the consensus structure of `alignment` onto its members, fold them in parallel and average
+/// their accuracies, see [`evaluate_all_with()`]. Members that could not be encoded are skipped and
+/// listed in [`FamilySummary::skipped`].
+pub fn evaluate_family(
+    alignment: &Alignment,
+    config: &RafftConfig,
+    kt: f64,
+    slippage: usize,
+) -> Result<FamilySummary, Error> {
+    let records = alignment.reference_records()?;
+    let results = evaluate_all_with(&records, config, kt, slippage, |_| ());
+    let skipped = records
+        .iter()
+        .zip(&results)
+        .filter_map(|(record, result)| {
+            let error = result.as_ref().err()?;
+            Some((record.name.clone(), error.to_string()))
+        })
+        .collect();
+
+    Ok(FamilySummary {
+        family: alignment.family.clone(),
+        members: records.len(),
+        summary: summarize(&records, &results),
+        skipped,
+    })
+}
+
+/// Write family summaries as tab-separated values with a header line (see [`FAMILY_TSV_HEADER`]).
+/// Averages of families without any evaluated member are written as `NA`.
+pub fn write_family_tsv<W: Write>(families: &[FamilySummary], writer: &mut W) -> io::Result<()> {
+    writeln!(writer, "{}", FAMILY_TSV_HEADER)?;
+
+    for family in families {
+        write!(writer, "{}\t{}", family.family, family.members)?;
+
+        match &family.summary {
+            Some(summary) => {
+                write!(writer, "\t{}\t{}", summary.evaluated, summary.mean_length)?;
+                write_values(writer, &summary.values())?;
+            }
+            None => writeln!(writer, "\t0{}", "\tNA".repeat(7))?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use std::str::FromStr;
+
+    const CT: &str = "\
+# two records
+12  ENERGY = -3.40  hairpin
+1 G 0 2 12 1
+2 G 1 3 11 2
+3 G 2 4 10 3
+4 A 3 5 0 4
+5 A 4 6 0 5
+6 A 5 7 0 6
+7 A 6 8 0 7
+8 A 7 9 0 8
+9 A 8 10 0 9
+10 c 9 11 3 10
+11 C 10 12 2 11
+12 C 11 0 1 12
+
+4 open chain
+1 A 0 2 0 1
+2 C 1 3 0 2
+3 G 2 4 0 3
+4 U 3 0 0 4
+";
+
+    #[test]
+    fn test_read_ct() {
+        let records = read_ct(CT.as_bytes()).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].name, "hairpin");
+        assert_eq!(records[0].sequence, "GGGAAAAAACCC");
+        assert_eq!(records[0].structure.to_string(), "(((......)))");
+        assert_eq!(records[1].name, "open chain");
+        assert_eq!(records[1].structure.to_string(), "....");
+
+        let asymmetric = CT.replace("11 C 10 12 2 11", "11 C 10 12 0 11");
+        assert!(matches!(
+            read_ct(asymmetric.as_bytes()),
+            Err(Error::InvalidRecord(name, _)) if name == "hairpin"
+        ));
+        let truncated = CT.lines().take(8).collect::<Vec<_>>().join("\n");
+        assert!(matches!(
+            read_ct(truncated.as_bytes()),
+            Err(Error::InvalidRecord(..))
+        ));
+        assert!(matches!(
+            read_ct("3 x\n1 A 0 2 0 1\n3 A 2 0 0 3\n".as_bytes()),
+            Err(Error::InvalidLine(3, _))
+        ));
+    }
+
+    #[test]
+    fn test_read_dot_bracket() {
+        let records = read_dot_bracket(
+            "\
+>hairpin
+gggaaaaaaccc
+(((......))) ( -1.20)
+((........))
+# a complex without a title
+GGGG&CCCC
+((((&))))
+"
+            .as_bytes(),
+        )
+        .unwrap();
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].name, "hairpin");
+        assert_eq!(records[0].sequence, "GGGAAAAAACCC");
+        assert_eq!(records[0].structure.to_string(), "(((......)))");
+        assert_eq!(records[1].name, "hairpin");
+        assert_eq!(records[1].structure.to_string(), "((........))");
+        assert_eq!(records[2].name, "");
+        assert_eq!(records[2].structure.len(), 8);
+
+        assert!(matches!(
+            read_dot_bracket(">a\nGGGAAACCC\n(((...)))\n>b\nGGAAACC\n>c\n".as_bytes()),
+            Err(Error::InvalidDotBracket(6, _))
+        ));
+        assert!(matches!(
+            read_dot_bracket("GGGAAACCC\n((....)))\n".as_bytes()),
+            Err(Error::InvalidDotBracket(2, _))
+        ));
+        assert!(matches!(
+            read_dot_bracket("GGGAAACCC\n(((...))\n".as_bytes()),
+            Err(Error::InvalidDotBracket(2, _))
+        ));
+        assert!(matches!(
+            read_dot_bracket("(((...)))\n".as_bytes()),
+            Err(Error::InvalidDotBracket(1, _))
+        ));
+        assert!(matches!(
+            read_dot_bracket("GGGAAACCC\n".as_bytes()),
+            Err(Error::InvalidDotBracket(2, _))
+        ));
+    }
+
+    #[test]
+    fn test_compare() {
+        let reference = PairTable::from_str("((((....))))").unwrap();
+        let predicted = PairTable::from_str("(((......)))").unwrap();
+        let shifted = PairTable::from_str(".((((...))))").unwrap();
+
+        let accuracy = compare(&predicted, &reference);
+        assert_eq!(
+            (
+                accuracy.true_positives,
+                accuracy.false_positives,
+                accuracy.false_negatives
+            ),
+            (3, 0, 1)
+        );
+        assert_relative_eq!(accuracy.ppv(), 1.0);
+        assert_relative_eq!(accuracy.sensitivity(), 0.75);
+        // tn = 66 - 4
+        assert_relative_eq!(
+            accuracy.mcc(),
+            3.0 * 62.0 / (3.0 * 4.0 * 62.0 * 63.0f64).sqrt()
+        );
+
+        assert_relative_eq!(compare(&reference, &reference).mcc(), 1.0);
+        assert_eq!(compare(&shifted, &reference).true_positives, 0);
+        assert_relative_eq!(
+            compare(&shifted, &reference).mcc(),
+            -4.0 * 4.0 / (4.0 * 4.0 * 62.0 * 62.0f64).sqrt()
+        );
+
+        let open = PairTable::new(12);
+        assert_relative_eq!(compare(&open, &reference).ppv(), 1.0);
+        assert_relative_eq!(compare(&open, &reference).sensitivity(), 0.0);
+        assert_relative_eq!(compare(&open, &open).mcc(), 1.0);
+    }
+
+    #[test]
+    fn test_slippage() {
+        let reference = PairTable::from_str("((((....))))").unwrap();
+        // the upper side is shifted by one position
+        let slipped = PairTable::from_str("((((...)))).").unwrap();
+        // both sides are shifted by one position
+        let shifted = PairTable::from_str("(((....)))..").unwrap();
+
+        assert_eq!(compare(&slipped, &reference).true_positives, 0);
+        let accuracy = compare_with_slippage(&slipped, &reference, 1);
+        assert_eq!(
+            (
+                accuracy.true_positives,
+                accuracy.false_positives,
+                accuracy.false_negatives
+            ),
+            (4, 0, 0)
+        );
+
+        assert_eq!(
+            compare_with_slippage(&shifted, &reference, 1).true_positives,
+            0
+        );
+        let accuracy = compare_with_slippage(&shifted, &reference, 2);
+        assert_eq!(
+            (
+                accuracy.true_positives,
+                accuracy.false_positives,
+                accuracy.false_negatives
+            ),
+            (3, 0, 0)
+        );
+        assert_relative_eq!(accuracy.sensitivity(), 1.0);
+
+        assert_eq!(
+            compare_with_slippage(&reference, &reference, 1),
+            compare(&reference, &reference)
+        );
+    }
+
+    const STOCKHOLM: &str = "\
+# STOCKHOLM 1.0
+#=GF ID   toy
+#=GF DE   two blocks
+
+first     GGGGA-AAACC
+second    GGAGA.AAA-C
+#=GC SS_cons <<<<.....>>
+
+first     CC
+second    CU
+#=GC SS_cons >>
+//
+# STOCKHOLM 1.0
+#=GF ID   unannotated
+single    ACGU
+//
+";
+
+    #[test]
+    fn test_stockholm() {
+        let alignments = read_stockholm(STOCKHOLM.as_bytes()).unwrap();
+
+        assert_eq!(alignments.len(), 2);
+        assert_eq!(alignments[0].family, "toy");
+        assert_eq!(alignments[0].sequences.len(), 2);
+        assert_eq!(alignments[0].sequences[1].1, "GGAGA.AAA-CCU");
+        assert_eq!(
+            alignments[0].consensus_structure.as_deref(),
+            Some("<<<<.....>>>>")
+        );
+
+        let records = alignments[0].reference_records().unwrap();
+        assert_eq!(records[0].sequence, "GGGGAAAACCCC");
+        assert_eq!(records[0].structure.to_string(), "((((....))))");
+        // the gap in column 10 and the non-canonical AC pair are dropped
+        assert_eq!(records[1].sequence, "GGAGAAAACCU");
+        assert_eq!(records[1].structure.to_string(), "((.......))");
+
+        assert!(matches!(
+            alignments[1].reference_records(),
+            Err(Error::InvalidAlignment(family, _)) if family == "unannotated"
+        ));
+        assert!(read_stockholm("first ACGU\n//\n".as_bytes()).is_err());
+        assert!(read_stockholm("# STOCKHOLM 1.0\nfirst ACGU\n".as_bytes()).is_err());
+
+        let config = RafftConfig::new().maximum_trajectories(5);
+        let family = evaluate_family(&alignments[0], &config, 0.6, 1).unwrap();
+        assert_eq!(family.members, 2);
+        assert_eq!(family.summary.unwrap().evaluated, 2);
+        assert_eq!(family.summary.unwrap().mean_length, 11);
+        assert!(family.skipped.is_empty());
+
+        let mut invalid = alignments[0].clone();
+        invalid
+            .sequences
+            .push(("third".to_string(), "GGNGA-AAACCCC".to_string()));
+        let skipped = evaluate_family(&invalid, &config, 0.6, 1).unwrap().skipped;
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].0, "third");
+
+        let mut tsv = vec![];
+        let empty = FamilySummary {
+            family: "empty".to_string(),
+            members: 0,
+            summary: None,
+            skipped: vec![],
+        };
+        write_family_tsv(&[family, empty], &mut tsv).unwrap();
+        let tsv = String::from_utf8(tsv).unwrap();
+        let lines: Vec<_> = tsv.lines().collect();
+        assert_eq!(lines[0], FAMILY_TSV_HEADER);
+        assert!(lines[1].starts_with("toy\t2\t2\t11\t"));
+        assert_eq!(lines[2], "empty\t0\t0\tNA\tNA\tNA\tNA\tNA\tNA\tNA");
+        assert!(lines
+            .iter()
+            .all(|line| line.split('\t').count() == FAMILY_TSV_HEADER.split('\t').count()));
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let sequence = "GGGGAAAACCCCAUAUAUGGGGAAAACCCC";
+        let graph = {
+            let mut graph = RafftConfig::new()
+                .maximum_trajectories(5)
+                .folding_graph(sequence);
+            graph.construct_trajectories();
+            graph
+        };
+        let best = graph.iter().min_by_key(|node| node.energy).unwrap();
+
+        let probabilities = pair_probabilities(&graph, 0.6);
+        assert!(probabilities.values().all(|&p| p > 0.0 && p <= 1.0 + 1e-9));
+        let expected_accuracy = |structure: &PairTable, gamma: f64| {
+            let paired: f64 = structure
+                .paired()
+                .map(|pair| 2.0 * gamma * probabilities.get(&pair).copied().unwrap_or(0.0))
+                .sum();
+            let unpaired: f64 = structure
+                .unpaired()
+                .map(|i| {
+                    1.0 - probabilities
+                        .iter()
+                        .filter(|(&(a, b), _)| a == i || b == i)
+                        .map(|(_, p)| p)
+                        .sum::<f64>()
+                })
+                .sum();
+            paired + unpaired
+        };
+
+        // the MEA structure is at least as accurate as every structure of the graph
+        for gamma in [0.5, 1.0, 4.0] {
+            let mea = mea_structure(&graph, 0.6, gamma);
+            assert!(graph.iter().all(|node| {
+                expected_accuracy(&node.structure.pair_table(), gamma)
+                    <= expected_accuracy(&mea, gamma) + 1e-9
+            }));
+        }
+        // without any weight on pairs, the open chain is the MEA structure
+        assert_eq!(mea_structure(&graph, 0.6, 0.0).pairs(), 0);
+
+        let record = ReferenceRecord {
+            name: "test".to_string(),
+            sequence: sequence.to_string(),
+            structure: best.structure.pair_table().into_owned(),
+        };
+        let config = RafftConfig::new().maximum_trajectories(5);
+        let evaluation = evaluate(&record, &config, 0.01, 0).unwrap();
+        assert_relative_eq!(evaluation.best.mcc(), 1.0);
+        assert_eq!(
+            evaluation.mea,
+            compare(&mea_structure(&graph, 0.01, 1.0), &record.structure)
+        );
+
+        let invalid = ReferenceRecord {
+            sequence: "GGNNCC".to_string(),
+            structure: PairTable::new(6),
+            ..record.clone()
+        };
+        let records = [record, invalid];
+        let results = evaluate_all_with(&records, &config, 0.01, 0, |_| ());
+        assert!(results[1].is_err());
+
+        let mut tsv = vec![];
+        let skipped = write_tsv(&records, &results, &mut tsv).unwrap();
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].0, records[1].name);
+        let tsv = String::from_utf8(tsv).unwrap();
+        let lines: Vec<_> = tsv.lines().collect();
+        assert_eq!(lines[0], TSV_HEADER);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[2].starts_with("mean\t30\t1.0000\t1.0000\t1.0000\t"));
+    }
+}
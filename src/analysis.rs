@@ -0,0 +1,596 @@
+//! This module provides comparisons across the trajectories of a fast folding graph.
+//! A trajectory is the path from the root to a leaf of the graph (see [`RafftGraph::leaves()`]),
+//! following parents of lowest depth (see [`RafftGraph::path_to_root()`]).
+//! Per-step profiles of trajectories (see [`Trajectory`]) describe the folding funnel,
+//! e.g. to plot the free energy, the fraction of paired nucleotides or the number of helices
+//! against the number of stacks formed.
+//! Trajectories are grouped by the abstract shapes of their final structures (see [`shape_groups()`]),
+//! which compares the predicted ensemble at the level of its coarse architecture.
+//! The distribution of shapes per depth (see [`shape_census()`]) shows how the search narrows onto final folds.
+
+use crate::coordinates::GenomicInterval;
+use crate::folding_graph::{RafftGraph, RafftNodeId};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/// A trajectory of a fast folding graph, i.e. the structures from the root to a leaf, see [`trajectories()`].
+#[derive(Clone)]
+pub struct Trajectory<'a> {
+    graph: &'a RafftGraph,
+    nodes: Vec<RafftNodeId>,
+}
+
+impl<'a> Trajectory<'a> {
+    /// Return the trajectory of `graph` from the root to `node`, which is empty if `node` is not part of the graph.
+    pub fn new(graph: &'a RafftGraph, node: RafftNodeId) -> Self {
+        let mut nodes = graph.path_to_root(node);
+        nodes.reverse();
+
+        Self { graph, nodes }
+    }
+
+    /// Return the structures of the trajectory, starting with the root.
+    /// The structure at step `k` was formed by adding `k` stacks to the unfolded structure.
+    pub fn nodes(&self) -> &[RafftNodeId] {
+        &self.nodes
+    }
+
+    /// Return the last structure of the trajectory.
+    pub fn leaf(&self) -> Option<RafftNodeId> {
+        self.nodes.last().copied()
+    }
+
+    /// Return the free energy in `kcal/mol` of each structure of the trajectory, starting with the root.
+    pub fn energy_profile(&self) -> Vec<f64> {
+        self.nodes
+            .iter()
+            .filter_map(|&id| self.graph.node(id))
+            .map(|node| node.energy as f64 * 0.01)
+            .collect()
+    }
+
+    /// Return the fraction of paired nucleotides of each structure of the trajectory, starting with the root.
+    pub fn pairing_fraction_profile(&self) -> Vec<f64> {
+        self.nodes
+            .iter()
+            .filter_map(|&id| self.graph.node(id))
+            .map(|node| {
+                let pt = node.structure.pair_table();
+                2.0 * pt.pairs() as f64 / pt.len() as f64
+            })
+            .collect()
+    }
+
+    /// Return the number of helices (see [`PairTable::helices()`](crate::encoding::PairTable::helices)) of each structure of the trajectory,
+    /// starting with the root.
+    pub fn helix_profile(&self) -> Vec<usize> {
+        self.nodes
+            .iter()
+            .filter_map(|&id| self.graph.node(id))
+            .map(|node| node.structure.pair_table().helices())
+            .collect()
+    }
+
+    /// Return the abstract shape at `level` (see [`PairTable::abstract_shape()`](crate::encoding::PairTable::abstract_shape))
+    /// of each structure of the trajectory, starting with the root.
+    pub fn shape_profile(&self, level: u8) -> Vec<String> {
+        self.nodes
+            .iter()
+            .filter_map(|&id| self.graph.node(id))
+            .map(|node| node.structure.pair_table().abstract_shape(level))
+            .collect()
+    }
+}
+
+/// Return the trajectories of a constructed `graph`, one per leaf in the order of [`RafftGraph::leaves()`].
+pub fn trajectories(graph: &RafftGraph) -> Vec<Trajectory<'_>> {
+    graph
+        .leaves()
+        .into_iter()
+        .map(|leaf| Trajectory::new(graph, leaf))
+        .collect()
+}
+
+/// Column names of [`write_profiles_csv()`].
+pub const PROFILES_CSV_HEADER: &str = "trajectory,step,energy,pairing_fraction,helices";
+
+/// Write the profiles of `trajectories` as comma-separated values with a header line (see [`PROFILES_CSV_HEADER`]),
+/// one line per structure. Trajectories are numbered in the given order, starting at `0`.
+pub fn write_profiles_csv<W: Write>(
+    trajectories: &[Trajectory<'_>],
+    writer: &mut W,
+) -> io::Result<()> {
+    writeln!(writer, "{}", PROFILES_CSV_HEADER)?;
+
+    for (index, trajectory) in trajectories.iter().enumerate() {
+        let profiles = trajectory
+            .energy_profile()
+            .into_iter()
+            .zip(trajectory.pairing_fraction_profile())
+            .zip(trajectory.helix_profile());
+
+        for (step, ((energy, fraction), helices)) in profiles.enumerate() {
+            writeln!(
+                writer,
+                "{},{},{:.2},{:.4},{}",
+                index, step, energy, fraction, helices
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Occurrences of a base pair in the final structures of all trajectories.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairPersistenceEntry {
+    /// base pair `(i, j)` (`1`-indexed)
+    pub pair: (usize, usize),
+    /// number of trajectories whose final structure contains the pair
+    pub occurrences: usize,
+    /// fraction of trajectories whose final structure contains the pair
+    pub fraction: f64,
+    /// number of trajectories per depth at which the pair was formed
+    pub formation_depths: BTreeMap<usize, usize>,
+}
+
+impl PairPersistenceEntry {
+    /// Return the mean depth at which the pair was formed.
+    pub fn mean_depth(&self) -> f64 {
+        self.formation_depths
+            .iter()
+            .map(|(&depth, &count)| (depth * count) as f64)
+            .sum::<f64>()
+            / self.occurrences as f64
+    }
+}
+
+/// Sparse matrix of the persistence of base pairs across trajectories, see [`pair_persistence()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairPersistence {
+    /// number of trajectories
+    pub trajectories: usize,
+    /// pairs occurring in any trajectory, ordered by `(i, j)`
+    pub entries: Vec<PairPersistenceEntry>,
+}
+
+/// Column names of [`PairPersistence::write_tsv()`].
+pub const TSV_HEADER: &str = "i\tj\toccurrences\tfraction\tmean_depth\tformation_depths";
+
+/// Column names of [`PairPersistence::write_genomic_tsv()`].
+pub const GENOMIC_TSV_HEADER: &str =
+    "chrom\ti\tj\toccurrences\tfraction\tmean_depth\tformation_depths";
+
+impl PairPersistence {
+    /// Return the entry of the pair `(i, j)` (`1`-indexed), if it occurs in any trajectory.
+    pub fn get(&self, i: usize, j: usize) -> Option<&PairPersistenceEntry> {
+        self.entries
+            .binary_search_by_key(&(i, j), |entry| entry.pair)
+            .ok()
+            .map(|index| &self.entries[index])
+    }
+
+    /// Write the entries as tab-separated values with a header line (see [`TSV_HEADER`]).
+    /// Formation depths are written as comma-separated `depth:count` items.
+    pub fn write_tsv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "{}", TSV_HEADER)?;
+        self.write_entries(writer, |(i, j)| format!("{}\t{}", i, j))
+    }
+
+    /// Write the entries like [`PairPersistence::write_tsv()`], but with the pairs in genomic coordinates of
+    /// the folded sequence's `interval` (see [`GenomicInterval::pair()`]) and the chromosome in the first column
+    /// (see [`GENOMIC_TSV_HEADER`]).
+    pub fn write_genomic_tsv<W: Write>(
+        &self,
+        interval: &GenomicInterval,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        writeln!(writer, "{}", GENOMIC_TSV_HEADER)?;
+        self.write_entries(writer, |pair| {
+            let (i, j) = interval.pair(pair);
+            format!("{}\t{}\t{}", interval.chrom, i, j)
+        })
+    }
+
+    // Write one line per entry, starting with the columns of `pair`.
+    fn write_entries<W, F>(&self, writer: &mut W, pair: F) -> io::Result<()>
+    where
+        W: Write,
+        F: Fn((usize, usize)) -> String,
+    {
+        for entry in &self.entries {
+            let depths = entry
+                .formation_depths
+                .iter()
+                .map(|(depth, count)| format!("{}:{}", depth, count))
+                .collect::<Vec<_>>()
+                .join(",");
+
+            writeln!(
+                writer,
+                "{}\t{}\t{:.4}\t{:.2}\t{}",
+                pair(entry.pair),
+                entry.occurrences,
+                entry.fraction,
+                entry.mean_depth(),
+                depths
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Compute for each base pair the fraction of trajectories of a constructed `graph` whose final structure
+/// contains it and the distribution of depths at which it was formed (see [`RafftGraph::pair_formation_depths()`]).
+/// Pairs present in many trajectories and formed early are likely obligatory for folding.
+pub fn pair_persistence(graph: &RafftGraph) -> PairPersistence {
+    let leaves = graph.leaves();
+    let mut pairs: BTreeMap<(usize, usize), BTreeMap<usize, usize>> = BTreeMap::new();
+
+    for &leaf in &leaves {
+        for (pair, depth) in graph.pair_formation_depths(leaf) {
+            *pairs.entry(pair).or_default().entry(depth).or_default() += 1;
+        }
+    }
+
+    let entries = pairs
+        .into_iter()
+        .map(|(pair, formation_depths)| {
+            let occurrences = formation_depths.values().sum();
+            PairPersistenceEntry {
+                pair,
+                occurrences,
+                fraction: occurrences as f64 / leaves.len() as f64,
+                formation_depths,
+            }
+        })
+        .collect();
+
+    PairPersistence {
+        trajectories: leaves.len(),
+        entries,
+    }
+}
+
+/// Trajectories whose final structures have the same abstract shape, see [`shape_groups()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShapeGroup {
+    /// abstract shape of the final structures
+    pub shape: String,
+    /// indices of the trajectories in the order of [`trajectories()`]
+    pub trajectories: Vec<usize>,
+    /// fraction of all trajectories in the group
+    pub fraction: f64,
+    /// final structure of lowest free energy in the group
+    pub best: RafftNodeId,
+    /// free energy of `best` in `kcal/mol`
+    pub best_energy: f64,
+}
+
+/// Trajectories of a fast folding graph grouped by the abstract shapes of their final structures,
+/// see [`shape_groups()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShapeGroups {
+    /// abstract shape level (`1` to `5`)
+    pub level: u8,
+    /// groups ordered by the free energy of their best structure
+    pub groups: Vec<ShapeGroup>,
+}
+
+/// Column names of [`ShapeGroups::write_tsv()`].
+pub const SHAPES_TSV_HEADER: &str = "shape	trajectories	fraction	best_energy	best_structure";
+
+impl ShapeGroups {
+    /// Write the groups as tab-separated values with a header line (see [`SHAPES_TSV_HEADER`]),
+    /// where the best structures of `graph` are written in dot-bracket notation.
+    pub fn write_tsv<W: Write>(&self, graph: &RafftGraph, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "{}", SHAPES_TSV_HEADER)?;
+
+        for group in &self.groups {
+            let structure = graph
+                .node(group.best)
+                .map(|node| {
+                    node.structure
+                        .pair_table()
+                        .to_string_with_cut_points(graph.cut_points())
+                })
+                .unwrap_or_default();
+
+            writeln!(
+                writer,
+                "{}	{}	{:.4}	{:.2}	{}",
+                group.shape,
+                group.trajectories.len(),
+                group.fraction,
+                group.best_energy,
+                structure
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Group the trajectories of a constructed `graph` by the abstract shapes at `level` of their final structures,
+/// see [`PairTable::abstract_shape()`](crate::encoding::PairTable::abstract_shape).
+/// Panics if `level` is not in `1..=5`.
+pub fn shape_groups(graph: &RafftGraph, level: u8) -> ShapeGroups {
+    let leaves = graph.leaves();
+    let mut groups: Vec<ShapeGroup> = vec![];
+
+    for (index, node) in leaves
+        .iter()
+        .filter_map(|&leaf| graph.node(leaf))
+        .enumerate()
+    {
+        let shape = node.structure.pair_table().abstract_shape(level);
+        let energy = node.energy as f64 * 0.01;
+
+        match groups.iter_mut().find(|group| group.shape == shape) {
+            Some(group) => {
+                group.trajectories.push(index);
+                if energy < group.best_energy {
+                    group.best = node.id;
+                    group.best_energy = energy;
+                }
+            }
+            None => groups.push(ShapeGroup {
+                shape,
+                trajectories: vec![index],
+                fraction: 0.0,
+                best: node.id,
+                best_energy: energy,
+            }),
+        }
+    }
+
+    for group in &mut groups {
+        group.fraction = group.trajectories.len() as f64 / leaves.len() as f64;
+    }
+    groups.sort_by(|a, b| a.best_energy.total_cmp(&b.best_energy));
+
+    ShapeGroups { level, groups }
+}
+
+/// The abstract shapes of the structures at one depth of a fast folding graph, see [`shape_census()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepthShapes {
+    /// depth, i.e. the number of stacks formed
+    pub depth: usize,
+    /// number of structures at `depth`
+    pub structures: usize,
+    /// shapes and their number of structures, ordered by decreasing count and then by shape
+    pub counts: Vec<(String, usize)>,
+}
+
+/// Return the distribution of abstract shapes at `level` among the structures of each depth of `graph`,
+/// see [`PairTable::abstract_shape()`](crate::encoding::PairTable::abstract_shape).
+/// Panics if `level` is not in `1..=5`.
+pub fn shape_census(graph: &RafftGraph, level: u8) -> Vec<DepthShapes> {
+    let mut depths: BTreeMap<usize, BTreeMap<String, usize>> = BTreeMap::new();
+
+    for node in graph.iter() {
+        let shape = node.structure.pair_table().abstract_shape(level);
+        *depths
+            .entry(node.depth)
+            .or_default()
+            .entry(shape)
+            .or_default() += 1;
+    }
+
+    depths
+        .into_iter()
+        .map(|(depth, shapes)| {
+            let mut counts: Vec<(String, usize)> = shapes.into_iter().collect();
+            // stable, i.e. shapes of equal counts remain ordered
+            counts.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+
+            DepthShapes {
+                depth,
+                structures: counts.iter().map(|(_, count)| count).sum(),
+                counts,
+            }
+        })
+        .collect()
+}
+
+/// Column names of [`write_shape_census_tsv()`].
+pub const SHAPE_CENSUS_TSV_HEADER: &str = "depth\tshape\tcount\tfraction";
+
+/// Write a shape census as tab-separated values with a header line (see [`SHAPE_CENSUS_TSV_HEADER`]),
+/// one line per shape and depth. The fraction refers to the structures of the same depth.
+pub fn write_shape_census_tsv<W: Write>(census: &[DepthShapes], writer: &mut W) -> io::Result<()> {
+    writeln!(writer, "{}", SHAPE_CENSUS_TSV_HEADER)?;
+
+    for depth in census {
+        for (shape, count) in &depth.counts {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{:.4}",
+                depth.depth,
+                shape,
+                count,
+                *count as f64 / depth.structures as f64
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fast_folding::RafftConfig;
+
+    #[test]
+    fn test_trajectories() {
+        let sequence = "GGGGAAAACCCCAUAUAUGGGGAAAACCCC";
+        let mut graph = RafftConfig::new()
+            .maximum_trajectories(5)
+            .folding_graph(sequence);
+        graph.construct_trajectories();
+
+        let trajectories = trajectories(&graph);
+        assert_eq!(trajectories.len(), graph.leaves().len());
+
+        for trajectory in &trajectories {
+            let profile = trajectory.energy_profile();
+            assert_eq!(profile.len(), trajectory.nodes().len());
+            assert_eq!(trajectory.nodes()[0], graph.root());
+            assert_eq!(profile[0], 0.0);
+
+            let leaf = graph.node(trajectory.leaf().unwrap()).unwrap();
+            assert_eq!(profile.len(), leaf.depth + 1);
+            assert_eq!(profile[leaf.depth], leaf.energy as f64 * 0.01);
+
+            let fractions = trajectory.pairing_fraction_profile();
+            let helices = trajectory.helix_profile();
+            assert_eq!(fractions.len(), profile.len());
+            assert_eq!((fractions[0], helices[0]), (0.0, 0));
+            // stacks are only added along a trajectory
+            assert!(fractions.windows(2).all(|step| step[0] < step[1]));
+            assert!(helices[1..].iter().all(|&count| count >= 1));
+            assert_eq!(
+                fractions[leaf.depth],
+                2.0 * leaf.structure.pairs() as f64 / sequence.len() as f64
+            );
+        }
+
+        let mut csv = vec![];
+        write_profiles_csv(&trajectories, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(
+            csv.lines().count(),
+            trajectories
+                .iter()
+                .map(|trajectory| trajectory.nodes().len())
+                .sum::<usize>()
+                + 1
+        );
+        assert!(csv.lines().nth(1).unwrap().starts_with("0,0,"));
+    }
+
+    #[test]
+    fn test_pair_persistence() {
+        let sequence =
+            "GGGUUUGCGGUGUAAGUGCAGCCCGUCUUACACCGUGCGGCACAGGCACUAGUACUGAUGUCGUAUACAGGGCUUUUGACAU";
+        let mut graph = RafftConfig::new()
+            .maximum_trajectories(5)
+            .folding_graph(sequence);
+        graph.construct_trajectories();
+
+        let persistence = pair_persistence(&graph);
+        assert_eq!(persistence.trajectories, graph.leaves().len());
+        assert!(!persistence.entries.is_empty());
+
+        for entry in &persistence.entries {
+            assert!(entry.fraction > 0.0 && entry.fraction <= 1.0);
+            assert!(entry.occurrences <= persistence.trajectories);
+            assert!(entry.mean_depth() >= 1.0);
+            assert_eq!(persistence.get(entry.pair.0, entry.pair.1), Some(entry));
+        }
+        assert!(persistence
+            .entries
+            .windows(2)
+            .all(|entries| entries[0].pair < entries[1].pair));
+        assert_eq!(persistence.get(1, 2), None);
+
+        let mut tsv = vec![];
+        persistence.write_tsv(&mut tsv).unwrap();
+        assert_eq!(
+            String::from_utf8(tsv).unwrap().lines().count(),
+            persistence.entries.len() + 1
+        );
+
+        // the first pair on the reverse strand of chr1:1-82 is (83 - j, 83 - i)
+        let interval: GenomicInterval = "chr1:1-82:-".parse().unwrap();
+        let mut tsv = vec![];
+        persistence.write_genomic_tsv(&interval, &mut tsv).unwrap();
+        let tsv = String::from_utf8(tsv).unwrap();
+        let (i, j) = persistence.entries[0].pair;
+        assert!(tsv.lines().nth(1).unwrap().starts_with(&format!(
+            "chr1\t{}\t{}\t",
+            83 - j,
+            83 - i
+        )));
+    }
+
+    #[test]
+    fn test_shape_groups() {
+        let sequence =
+            "GGGUUUGCGGUGUAAGUGCAGCCCGUCUUACACCGUGCGGCACAGGCACUAGUACUGAUGUCGUAUACAGGGCUUUUGACAU";
+        let mut graph = RafftConfig::new()
+            .maximum_trajectories(5)
+            .folding_graph(sequence);
+        graph.construct_trajectories();
+
+        let trajectories = trajectories(&graph);
+        for level in 1..=5 {
+            let shapes = shape_groups(&graph, level);
+            assert_eq!(shapes.level, level);
+
+            // every trajectory is in exactly one group
+            let mut grouped: Vec<usize> = shapes
+                .groups
+                .iter()
+                .flat_map(|group| group.trajectories.clone())
+                .collect();
+            grouped.sort_unstable();
+            assert_eq!(grouped, (0..trajectories.len()).collect::<Vec<_>>());
+            assert!((shapes.groups.iter().map(|g| g.fraction).sum::<f64>() - 1.0).abs() < 1e-9);
+            assert!(shapes
+                .groups
+                .windows(2)
+                .all(|groups| groups[0].best_energy <= groups[1].best_energy));
+
+            for group in &shapes.groups {
+                for &index in &group.trajectories {
+                    let shape_profile = trajectories[index].shape_profile(level);
+                    assert_eq!(shape_profile.last(), Some(&group.shape));
+                    assert_eq!(shape_profile[0], "_");
+                }
+            }
+
+            let mut tsv = vec![];
+            shapes.write_tsv(&graph, &mut tsv).unwrap();
+            assert_eq!(
+                String::from_utf8(tsv).unwrap().lines().count(),
+                shapes.groups.len() + 1
+            );
+        }
+
+        let census = shape_census(&graph, 5);
+        assert_eq!(
+            census.len(),
+            graph.iter().map(|node| node.depth).max().unwrap() + 1
+        );
+        assert_eq!(census[0].counts, vec![("_".to_string(), 1)]);
+        for depth in &census {
+            assert_eq!(
+                depth.structures,
+                graph
+                    .iter()
+                    .filter(|node| node.depth == depth.depth)
+                    .count()
+            );
+            assert!(depth
+                .counts
+                .windows(2)
+                .all(|counts| counts[0].1 >= counts[1].1));
+        }
+        let mut tsv = vec![];
+        write_shape_census_tsv(&census, &mut tsv).unwrap();
+        assert_eq!(
+            String::from_utf8(tsv).unwrap().lines().count(),
+            census.iter().map(|depth| depth.counts.len()).sum::<usize>() + 1
+        );
+
+        let best = shape_groups(&graph, 5).groups[0].best;
+        assert_eq!(
+            graph.node(best).unwrap().energy,
+            graph.iter().map(|node| node.energy).min().unwrap()
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! This module provides [`fold_async()`] to fold sequences from asynchronous code, e.g. tokio-based services,
+//! without blocking the executor.
+//!
+//! Fast folding graphs are constructed on tokio's blocking thread pool.
+//! The returned [`FoldTask`] is a future resolving to the [`RafftResult`] and reports the progress
+//! of the construction through a watch channel:
+//!
+//! ```no_run
+//! # async fn example() {
+//! let task = rafft::fold_async("GGGGAAAACCCC", rafft::fast_folding::RafftConfig::new()).unwrap();
+//! let mut progress = task.progress();
+//!
+//! tokio::spawn(async move {
+//!     while progress.changed().await.is_ok() {
+//!         let current = *progress.borrow();
+//!         println!("depth {}: {} structures", current.depth, current.structures);
+//!     }
+//! });
+//!
+//! let result = task.await.unwrap();
+//! println!("{} {:.2}", result.structure(), result.energy());
+//! # }
+//! ```
+
+use crate::cancel::CancelToken;
+use crate::encoding;
+use crate::fast_folding::{RafftConfig, RafftResult};
+use crate::folding_graph::RafftGraph;
+use crate::vienna::VCompound;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::sync::watch;
+use tokio::task::{JoinError, JoinHandle};
+
+/// Progress of a fold running in the background, updated whenever a depth of the graph is completed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FoldProgress {
+    /// The last completed depth, i.e. the number of stacks formed along the longest trajectory so far.
+    pub depth: usize,
+    /// The number of structures in the graph so far, including the root.
+    pub structures: usize,
+    /// The estimated memory usage of the graph in bytes, see [`RafftGraph::memory_usage()`].
+    pub memory_bytes: usize,
+}
+
+/// A fold running on tokio's blocking thread pool, see [`fold_async()`].
+///
+/// Awaiting the task yields the [`RafftResult`], or a [`JoinError`] if the fold panicked.
+/// Dropping the task does not stop the fold; use [`FoldTask::cancel()`] instead.
+pub struct FoldTask {
+    handle: JoinHandle<RafftResult>,
+    progress: watch::Receiver<FoldProgress>,
+    cancel_token: CancelToken,
+}
+
+impl FoldTask {
+    /// Return a receiver of the progress of the fold.
+    /// The channel is closed once the fold has finished.
+    pub fn progress(&self) -> watch::Receiver<FoldProgress> {
+        self.progress.clone()
+    }
+
+    /// Stop the construction of the graph between structures.
+    /// The task still resolves to the structures found so far, see [`RafftGraph::is_cancelled()`].
+    pub fn cancel(&self) {
+        self.cancel_token.cancel();
+    }
+}
+
+impl Future for FoldTask {
+    type Output = Result<RafftResult, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.handle).poll(cx)
+    }
+}
+
+/// Fold `sequence` using `config` on tokio's blocking thread pool.
+/// The sequence is encoded immediately, such that invalid sequences are rejected before any work is scheduled.
+/// If `config` does not have a [`CancelToken`], a new one is used for [`FoldTask::cancel()`].
+///
+/// # Panics
+///
+/// Panics if called outside of a tokio runtime.
+pub fn fold_async(sequence: &str, config: RafftConfig) -> Result<FoldTask, encoding::Error> {
+    let encoded = config.encode(sequence)?;
+    let cancel_token = config.cancel_token.clone().unwrap_or_default();
+    let config = config.cancel_token(cancel_token.clone());
+
+    let sequence = sequence.to_string();
+    let mut graph = RafftGraph::new(encoded, VCompound::new(&sequence), config);
+    let (sender, progress) = watch::channel(FoldProgress::default());
+
+    let handle = tokio::task::spawn_blocking(move || {
+        let mut structures = 0;
+
+        graph.construct_trajectories_with_progress(|progress| {
+            structures += progress.nodes.len();
+            sender.send_replace(FoldProgress {
+                depth: progress.depth,
+                structures,
+                memory_bytes: progress.memory.total(),
+            });
+        });
+
+        RafftResult::from_graph(&sequence, graph)
+    });
+
+    Ok(FoldTask {
+        handle,
+        progress,
+        cancel_token,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fold_async() {
+        let sequence = "GGGGAAAACCCCAUGGGGAAAACCCC";
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        let (result, progress) = runtime.block_on(async {
+            let task = fold_async(sequence, RafftConfig::new()).unwrap();
+            let progress = task.progress();
+
+            (task.await.unwrap(), progress)
+        });
+
+        let expected = crate::fold_sequence(sequence).unwrap();
+        assert_eq!(result.structure(), expected.structure());
+        assert_eq!(result.energy(), expected.energy());
+
+        let last = *progress.borrow();
+        assert_eq!(last.structures, result.graph().iter().count());
+        assert!(last.memory_bytes > 0);
+        assert!(progress.has_changed().is_err());
+
+        let token = CancelToken::new();
+        token.cancel();
+        let cancelled = runtime.block_on(async {
+            let task = fold_async(sequence, RafftConfig::new().cancel_token(token)).unwrap();
+            task.await.unwrap()
+        });
+        assert!(cancelled.graph().is_cancelled());
+
+        assert!(runtime
+            .block_on(async { fold_async("GGXA", RafftConfig::new()).map(|_| ()) })
+            .is_err());
+    }
+}
@@ -1,69 +1,390 @@
 //! This extends the module [`encoding`].
 use crate::encoding::EncodedSequence;
-use ndarray::Array1;
-use realfft::RealFftPlanner;
+use crate::fft::{padded_length, FftBackend, RustFft};
+use ndarray::{Array1, ArrayView1};
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
 
-fn convolution(a: &[f64], b: &[f64]) -> Array1<f64> {
-    assert_ne!(a.len(), 0);
-    assert_ne!(b.len(), 0);
+/// The (auto)correlation of an [`EncodedSequence`] with its complementary strand representation
+/// for each positional lag.
+/// High values indicate positional lags at which stacks of consecutive base pairs are likely.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorrelationSpectrum(Array1<f64>);
 
-    let length = a.len() + b.len() - 1;
+/// A local maximum of a [`CorrelationSpectrum`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Peak {
+    /// positional lag of the peak
+    pub lag: usize,
+    /// correlation at the peak
+    pub value: f64,
+    /// vertical distance between the peak and the highest of its two lowest contour lines
+    pub prominence: f64,
+}
 
-    let mut planner = RealFftPlanner::<f64>::new();
+impl CorrelationSpectrum {
+    /// Return a view of the correlation per positional lag.
+    pub fn view(&self) -> ArrayView1<'_, f64> {
+        self.0.view()
+    }
 
-    let mut in_a = a
-        .iter()
-        .cloned()
-        .chain(std::iter::repeat(0f64))
-        .take(length)
-        .collect::<Vec<_>>();
-    let mut in_b = b
-        .iter()
-        .cloned()
-        .chain(std::iter::repeat(0f64))
-        .take(length)
-        .collect::<Vec<_>>();
+    /// Return the inner array of correlations per positional lag.
+    pub fn into_inner(self) -> Array1<f64> {
+        self.0
+    }
+
+    /// Return the number of positional lags.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Return whether the spectrum is empty.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
 
-    let fft = planner.plan_fft_forward(length);
+    /// Return all positional lags ordered by decreasing correlation.
+    /// Ties are broken by increasing lag such that the order is fully determined by the correlation.
+    pub fn ranked_lags(&self) -> Vec<usize> {
+        let mut lags = (0..self.len()).collect::<Vec<_>>();
+        lags.sort_by(|&a, &b| self.0[b].total_cmp(&self.0[a]).then_with(|| a.cmp(&b)));
+        lags
+    }
 
-    let mut out_a = fft.make_output_vec();
-    let mut out_b = fft.make_output_vec();
+    /// Return the `n` positional lags with the highest correlation, see [`CorrelationSpectrum::ranked_lags()`].
+    pub fn top_lags(&self, n: usize) -> Vec<usize> {
+        let mut lags = self.ranked_lags();
+        lags.truncate(n);
+        lags
+    }
 
-    fft.process(&mut in_a, &mut out_a).unwrap();
-    fft.process(&mut in_b, &mut out_b).unwrap();
+    /// Return all local maxima with a prominence of at least `min_prominence`, ordered by lag.
+    /// Similar to `scipy.signal.find_peaks()`, the first and last lag are never considered as peaks
+    /// and plateaus are reported at their first lag.
+    pub fn peaks(&self, min_prominence: f64) -> Vec<Peak> {
+        let values = &self.0;
+        let n = values.len();
+        let mut peaks = vec![];
 
-    let mut in_ab = out_a
-        .iter()
-        .zip(out_b.iter())
-        .map(|(ai, bi)| ai * bi / length as f64)
-        .collect::<Vec<_>>();
-    let ifft = planner.plan_fft_inverse(length);
-    let mut out_ab = ifft.make_output_vec();
+        let mut i = 1;
+        while i + 1 < n {
+            if values[i] > values[i - 1] {
+                // skip plateaus
+                let mut j = i + 1;
+                while j < n && values[j] == values[i] {
+                    j += 1;
+                }
+
+                if j < n && values[j] < values[i] {
+                    let prominence = self.prominence(i, j - 1);
+                    if prominence >= min_prominence {
+                        peaks.push(Peak {
+                            lag: i,
+                            value: values[i],
+                            prominence,
+                        });
+                    }
+                }
+                i = j;
+            } else {
+                i += 1;
+            }
+        }
+
+        peaks
+    }
+
+    /// Return the spectral entropy, i.e. the Shannon entropy of the correlations (clamped to be non-negative)
+    /// normalized to a distribution over positional lags, divided by its maximum `ln(len)`.
+    /// Values close to `0` indicate a signal concentrated at few lags, values close to `1` a flat spectrum.
+    /// Empty spectra or spectra without positive correlation have an entropy of `1`.
+    pub fn entropy(&self) -> f64 {
+        let total: f64 = self.0.iter().map(|c| c.max(0.0)).sum();
+
+        if self.len() < 2 || total <= 0.0 {
+            return 1.0;
+        }
+
+        let entropy: f64 = self
+            .0
+            .iter()
+            .map(|c| c.max(0.0) / total)
+            .filter(|&p| p > 0.0)
+            .map(|p| -p * p.ln())
+            .sum();
+
+        entropy / (self.len() as f64).ln()
+    }
+
+    // prominence of a peak with plateau `first..=last`
+    fn prominence(&self, first: usize, last: usize) -> f64 {
+        let values = &self.0;
+        let height = values[first];
+
+        let left_base = values
+            .iter()
+            .take(first)
+            .rev()
+            .take_while(|&&v| v <= height)
+            .fold(height, |acc, &v| acc.min(v));
+        let right_base = values
+            .iter()
+            .skip(last + 1)
+            .take_while(|&&v| v <= height)
+            .fold(height, |acc, &v| acc.min(v));
+
+        height - left_base.max(right_base)
+    }
+}
+
+/// Window functions that can be applied to a [`CorrelationSpectrum`] over positional lags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    /// Constant weights, i.e. no windowing.
+    Rectangular,
+    /// The Hann window, `0.5 - 0.5 cos(2πk/(N-1))`.
+    Hann,
+    /// The Hamming window, `0.54 - 0.46 cos(2πk/(N-1))`.
+    Hamming,
+    /// The Blackman window, `0.42 - 0.5 cos(2πk/(N-1)) + 0.08 cos(4πk/(N-1))`.
+    Blackman,
+}
+
+impl Window {
+    /// Return the weight of lag `k` in a window spanning `n` lags.
+    pub fn weight(&self, k: usize, n: usize) -> f64 {
+        if n < 2 {
+            return 1.0;
+        }
+
+        let x = 2.0 * PI * k as f64 / (n - 1) as f64;
 
-    // For some sequences (like GUGCCUUGCGCCGGGAAACCACGCAAGGGGCGUAUGGCGCGCCGAUGAAGGUGUAGA)
-    // the forward real-to-complex FFT produces non-zero imaginary parts in the first vector component.
-    // This error appears to originate in rustfft or realfft.
-    // However, the non-zero imaginary parts seem to be very small (i.e. approximately zero).
-    // Therefore, we consider this to be non-critical and proceed with the computation.
-    //
-    // Nevertheless, we still want to report on these for now, might be relevent at some point.
-    // This should probably be reported using the `log` crate but the warnings can be piped away using
-    // `2> /dev/null` on the commandline.
-    //
-    // See also https://github.com/HEnquist/realfft/issues/11
-    match ifft.process(&mut in_ab, &mut out_ab) {
-        Ok(()) => (),
-        Err(error) => eprintln!("[autocorrelation.rs] INFO: {}", error),
+        match self {
+            Self::Rectangular => 1.0,
+            Self::Hann => 0.5 - 0.5 * x.cos(),
+            Self::Hamming => 0.54 - 0.46 * x.cos(),
+            Self::Blackman => 0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos(),
+        }
     }
+}
+
+impl FromStr for Window {
+    type Err = String;
+
+    fn from_str(window: &str) -> Result<Self, Self::Err> {
+        match window.to_lowercase().as_str() {
+            "rectangular" => Ok(Self::Rectangular),
+            "hann" => Ok(Self::Hann),
+            "hamming" => Ok(Self::Hamming),
+            "blackman" => Ok(Self::Blackman),
+            _ => Err(format!(
+                "unknown window {:?} (expected one of rectangular, hann, hamming, blackman)",
+                window
+            )),
+        }
+    }
+}
+
+/// A lag-dependent weight `(lag, number of lags) -> weight`, see [`LagWeighting::Custom`].
+pub type LagWeightFn = Arc<dyn Fn(usize, usize) -> f64 + Send + Sync>;
+
+/// Weighting of a [`CorrelationSpectrum`] by the range of the pairs at each positional lag.
+///
+/// The pairs at lag `k` of a spectrum spanning `n` lags span at most `min(k, n - 1 - k)` positions.
+/// Since correlations are normalized by the number of aligned positions, short-range lags at either end
+/// are ranked as high as the long-range lags in the center although they contain much fewer pairs.
+#[derive(Clone, Default)]
+pub enum LagWeighting {
+    /// Constant weights, i.e. no weighting.
+    #[default]
+    Uniform,
+    /// Multiply by the number of aligned positions raised to an exponent.
+    /// Positive exponents emphasize long-range lags (`1.0` reverts the normalization),
+    /// negative exponents emphasize short-range lags.
+    Aligned(f64),
+    /// Penalize long-range lags by `exp(-span / scale)`, where `span` is the maximum span of the pairs at a lag.
+    SpanDecay(f64),
+    /// A custom weight per lag and number of lags.
+    Custom(LagWeightFn),
+}
+
+impl LagWeighting {
+    /// Create a custom weighting from a function of the lag and the number of lags.
+    pub fn custom(weight: impl Fn(usize, usize) -> f64 + Send + Sync + 'static) -> Self {
+        Self::Custom(Arc::new(weight))
+    }
+
+    /// Return the weight of lag `k` in a spectrum spanning `n` lags.
+    pub fn weight(&self, k: usize, n: usize) -> f64 {
+        let span = k.min(n.saturating_sub(k + 1));
+
+        match self {
+            Self::Uniform => 1.0,
+            Self::Aligned(exponent) => ((span + 1) as f64).powf(*exponent),
+            Self::SpanDecay(scale) => (-(span as f64) / scale).exp(),
+            Self::Custom(weight) => weight(k, n),
+        }
+    }
+}
+
+impl fmt::Debug for LagWeighting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Uniform => write!(f, "Uniform"),
+            Self::Aligned(exponent) => f.debug_tuple("Aligned").field(exponent).finish(),
+            Self::SpanDecay(scale) => f.debug_tuple("SpanDecay").field(scale).finish(),
+            Self::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
+
+// custom weightings are only equal to themselves
+impl PartialEq for LagWeighting {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Uniform, Self::Uniform) => true,
+            (Self::Aligned(a), Self::Aligned(b)) | (Self::SpanDecay(a), Self::SpanDecay(b)) => {
+                a == b
+            }
+            (Self::Custom(a), Self::Custom(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for LagWeighting {
+    type Err = String;
+
+    /// Parse `uniform`, `aligned:<exponent>` or `span-decay:<scale>`.
+    fn from_str(weighting: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "unknown lag weighting {:?} (expected one of uniform, aligned:<exponent>, span-decay:<scale>)",
+                weighting
+            )
+        };
+        let lowercase = weighting.to_lowercase();
+        let (name, parameter) = match lowercase.split_once(':') {
+            Some((name, parameter)) => {
+                (name, Some(parameter.parse::<f64>().map_err(|_| invalid())?))
+            }
+            None => (lowercase.as_str(), None),
+        };
 
-    Array1::from_vec(out_ab)
+        match (name, parameter) {
+            ("uniform", None) => Ok(Self::Uniform),
+            ("aligned", Some(exponent)) => Ok(Self::Aligned(exponent)),
+            ("span-decay", Some(scale)) if scale > 0.0 => Ok(Self::SpanDecay(scale)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Transformation applied to a [`CorrelationSpectrum`] before positional lags are ranked.
+/// The correlation is first raised to `power`, then smoothed using a Gaussian kernel with standard
+/// deviation `smoothing` (in lags) and finally multiplied by `window` and `weighting`.
+/// The [default](CorrelationKernel::default) leaves the spectrum unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorrelationKernel {
+    /// exponent the correlation is raised to
+    pub power: f64,
+    /// standard deviation of the Gaussian smoothing over lags, if any
+    pub smoothing: Option<f64>,
+    /// window function over lags
+    pub window: Window,
+    /// weighting by the range of pairs per lag
+    pub weighting: LagWeighting,
+}
+
+impl Default for CorrelationKernel {
+    fn default() -> Self {
+        Self {
+            power: 1.0,
+            smoothing: None,
+            window: Window::Rectangular,
+            weighting: LagWeighting::Uniform,
+        }
+    }
+}
+
+impl CorrelationKernel {
+    /// Apply the kernel to a spectrum.
+    pub fn apply(&self, spectrum: &CorrelationSpectrum) -> CorrelationSpectrum {
+        let mut values = spectrum.0.clone();
+
+        #[allow(clippy::float_cmp)]
+        if self.power != 1.0 {
+            values.mapv_inplace(|v| v.max(0.0).powf(self.power));
+        }
+
+        if let Some(sigma) = self.smoothing.filter(|&sigma| sigma > 0.0) {
+            values = gaussian_smoothing(&values, sigma);
+        }
+
+        if self.window != Window::Rectangular {
+            let n = values.len();
+            values
+                .indexed_iter_mut()
+                .for_each(|(k, v)| *v *= self.window.weight(k, n));
+        }
+
+        if self.weighting != LagWeighting::Uniform {
+            let n = values.len();
+            values
+                .indexed_iter_mut()
+                .for_each(|(k, v)| *v *= self.weighting.weight(k, n));
+        }
+
+        CorrelationSpectrum(values)
+    }
+}
+
+// Gaussian kernel truncated at 3 standard deviations, renormalized at the boundaries
+fn gaussian_smoothing(values: &Array1<f64>, sigma: f64) -> Array1<f64> {
+    let radius = (3.0 * sigma).ceil() as usize;
+    let weights = (0..=radius)
+        .map(|d| (-0.5 * (d as f64 / sigma).powi(2)).exp())
+        .collect::<Vec<_>>();
+
+    Array1::from_iter((0..values.len()).map(|k| {
+        let first = k.saturating_sub(radius);
+        let last = (k + radius).min(values.len() - 1);
+
+        let (sum, norm) = (first..=last).fold((0.0, 0.0), |(sum, norm), l| {
+            let w = weights[k.abs_diff(l)];
+            (sum + w * values[l], norm + w)
+        });
+
+        sum / norm
+    }))
+}
+
+/// Number of real FFTs (two forward and one inverse per nucleotide) computed by
+/// [`EncodedSequence::autocorrelation()`] and [`EncodedSequence::cross_correlation()`].
+pub const FFTS_PER_CORRELATION: usize = 12;
+
+/// Return the length of the FFTs computed to correlate sequences of the lengths `a` and `b`,
+/// i.e. the length `a + b - 1` of the correlation padded as described in [`crate::fft::padded_length()`].
+pub fn correlation_fft_length(a: usize, b: usize) -> usize {
+    padded_length(a + b - 1)
 }
 
 impl EncodedSequence {
     /// Compute the (auto)correlation of an [`EncodedSequence`] with its complementary strand representation using FFT.
     /// A sane value for `padding` is `1.0`.
     /// The `padding` parameter might be removed in the future.
-    pub fn autocorrelation(&self, padding: f64) -> Array1<f64> {
+    pub fn autocorrelation(&self, padding: f64) -> CorrelationSpectrum {
+        self.autocorrelation_with(padding, &RustFft)
+    }
+
+    /// Compute the (auto)correlation like [`EncodedSequence::autocorrelation()`] using the FFT of `backend`.
+    pub fn autocorrelation_with(
+        &self,
+        padding: f64,
+        backend: &dyn FftBackend,
+    ) -> CorrelationSpectrum {
         // TODO: remove padding parameter
         assert!(padding > 0.0);
 
@@ -72,7 +393,7 @@ impl EncodedSequence {
             .rows()
             .into_iter()
             .zip(self.mirrored.rows().into_iter())
-            .map(|(f, m)| convolution(f.as_slice().unwrap(), m.as_slice().unwrap()))
+            .map(|(f, m)| backend.convolution(f.as_slice().unwrap(), m.as_slice().unwrap()))
             .collect::<Vec<_>>();
 
         let shape = correlates[0].dim();
@@ -88,7 +409,43 @@ impl EncodedSequence {
             .fold(Array1::zeros(shape), |acc, c| acc + c);
 
         correlates.zip_mut_with(&norm, |c, n| *c /= *n);
-        correlates
+        CorrelationSpectrum(correlates)
+    }
+
+    /// Compute the correlation of an [`EncodedSequence`] with the complementary strand representation
+    /// of `other` using FFT, e.g. of two strands of a complex.
+    /// The lag `k` corresponds to pairs of the positions `i` of `self` and `j` of `other` with `i + j = k`.
+    /// Scores are normalized by the number of aligned positions, see [`EncodedSequence::autocorrelation()`].
+    pub fn cross_correlation(&self, other: &EncodedSequence, padding: f64) -> CorrelationSpectrum {
+        self.cross_correlation_with(other, padding, &RustFft)
+    }
+
+    /// Compute the correlation like [`EncodedSequence::cross_correlation()`] using the FFT of `backend`.
+    pub fn cross_correlation_with(
+        &self,
+        other: &EncodedSequence,
+        padding: f64,
+        backend: &dyn FftBackend,
+    ) -> CorrelationSpectrum {
+        assert!(padding > 0.0);
+
+        let shape = self.len() + other.len() - 1;
+        let shortest = self.len().min(other.len());
+
+        let mut correlates = self
+            .forward
+            .rows()
+            .into_iter()
+            .zip(other.mirrored.rows().into_iter())
+            .map(|(f, m)| backend.convolution(f.as_slice().unwrap(), m.as_slice().unwrap()))
+            .fold(Array1::zeros(shape), |acc, c| acc + c);
+
+        correlates.indexed_iter_mut().for_each(|(k, c)| {
+            let aligned = (k + 1).min(shape - k).min(shortest);
+            *c /= aligned as f64 - 1.0 + padding;
+        });
+
+        CorrelationSpectrum(correlates)
     }
 }
 
@@ -121,7 +478,7 @@ mod tests {
             1., 2., 2., 0., 1., 0., 1., 1., 0., 1., 0., 0., 0., 1., 1., 1.,
         ];*/
 
-        let mut ab_conv = convolution(&a, &bb);
+        let mut ab_conv = RustFft.convolution(&a, &bb);
         ab_conv.mapv_inplace(|f| f.round());
 
         let ab = Array1::from_vec(vec![
@@ -176,6 +533,125 @@ mod tests {
 
         let ac = encoded.autocorrelation(1.0);
 
-        assert_relative_eq!(ac, _ac, epsilon = std::f32::EPSILON as f64);
+        assert_relative_eq!(ac.view(), _ac.view(), epsilon = std::f32::EPSILON as f64);
+    }
+
+    #[test]
+    fn test_cross_correlation() {
+        let a = EncodedSequence::new("GGGGA").unwrap();
+        let b = EncodedSequence::new("ACCCC").unwrap();
+
+        let cc = a.cross_correlation(&b, 1.0);
+
+        assert_eq!(cc.len(), 9);
+        // all four G pair with all four C
+        assert_eq!(cc.top_lags(1), vec![4]);
+        assert_relative_eq!(cc.view()[4], 0.8);
+        assert_relative_eq!(cc.view()[0], 0.0, epsilon = 1e-12);
+    }
+
+    #[test]
+    fn test_spectrum() {
+        let spectrum = CorrelationSpectrum(Array1::from_vec(vec![
+            0.0, 2.0, 1.0, 1.0, 3.0, 3.0, 0.5, 1.5, 1.0, 4.0,
+        ]));
+
+        assert_eq!(spectrum.top_lags(4), vec![9, 4, 5, 1]);
+
+        let peaks = spectrum.peaks(0.0);
+        assert_eq!(
+            peaks.iter().map(|p| p.lag).collect::<Vec<_>>(),
+            vec![1, 4, 7]
+        );
+        assert_relative_eq!(peaks[0].prominence, 1.0);
+        assert_relative_eq!(peaks[1].prominence, 2.5);
+        assert_relative_eq!(peaks[2].prominence, 0.5);
+
+        assert_eq!(spectrum.peaks(0.8).len(), 2);
+
+        let flat = CorrelationSpectrum(Array1::from_elem(8, 0.5));
+        let single = CorrelationSpectrum(Array1::from_vec(vec![0.0, 0.0, 2.0, -1.0]));
+        assert_relative_eq!(flat.entropy(), 1.0);
+        assert_relative_eq!(single.entropy(), 0.0);
+        assert!(spectrum.entropy() > 0.0 && spectrum.entropy() < 1.0);
+    }
+
+    #[test]
+    fn test_kernel() {
+        let spectrum = CorrelationSpectrum(Array1::from_vec(vec![1.0, 2.0, 3.0, 2.0, 1.0]));
+
+        assert_eq!(CorrelationKernel::default().apply(&spectrum), spectrum);
+
+        let squared = CorrelationKernel {
+            power: 2.0,
+            ..Default::default()
+        };
+        assert_relative_eq!(
+            squared.apply(&spectrum).into_inner(),
+            Array1::from_vec(vec![1.0, 4.0, 9.0, 4.0, 1.0])
+        );
+
+        let smoothed = CorrelationKernel {
+            smoothing: Some(1.0),
+            ..Default::default()
+        }
+        .apply(&spectrum);
+        // smoothing preserves symmetry and flattens the peak
+        assert_relative_eq!(smoothed.view()[0], smoothed.view()[4]);
+        assert!(smoothed.view()[2] < 3.0 && smoothed.view()[0] > 1.0);
+
+        let hann = CorrelationKernel {
+            window: Window::Hann,
+            ..Default::default()
+        }
+        .apply(&spectrum);
+        assert_relative_eq!(
+            hann.into_inner(),
+            Array1::from_vec(vec![0.0, 1.0, 3.0, 1.0, 0.0]),
+            epsilon = 1e-12
+        );
+    }
+
+    #[test]
+    fn test_lag_weighting() {
+        let spectrum = CorrelationSpectrum(Array1::from_elem(5, 1.0));
+        let weighted = |weighting| {
+            CorrelationKernel {
+                weighting,
+                ..Default::default()
+            }
+            .apply(&spectrum)
+            .into_inner()
+        };
+
+        assert_relative_eq!(
+            weighted(LagWeighting::Aligned(1.0)),
+            Array1::from_vec(vec![1.0, 2.0, 3.0, 2.0, 1.0])
+        );
+        assert_relative_eq!(
+            weighted(LagWeighting::Aligned(-1.0)),
+            Array1::from_vec(vec![1.0, 0.5, 1.0 / 3.0, 0.5, 1.0])
+        );
+        let decayed = weighted(LagWeighting::SpanDecay(1.0));
+        assert_relative_eq!(decayed[2], (-2.0f64).exp());
+
+        let custom = LagWeighting::custom(|k, _| if k == 3 { 2.0 } else { 1.0 });
+        assert_eq!(
+            CorrelationKernel {
+                weighting: custom.clone(),
+                ..Default::default()
+            }
+            .apply(&spectrum)
+            .top_lags(1),
+            vec![3]
+        );
+        assert_eq!(custom, custom.clone());
+        assert_ne!(custom, LagWeighting::custom(|_, _| 1.0));
+
+        assert_eq!("uniform".parse(), Ok(LagWeighting::Uniform));
+        assert_eq!("aligned:0.5".parse(), Ok(LagWeighting::Aligned(0.5)));
+        assert_eq!("Span-Decay:20".parse(), Ok(LagWeighting::SpanDecay(20.0)));
+        assert!("aligned".parse::<LagWeighting>().is_err());
+        assert!("span-decay:-1".parse::<LagWeighting>().is_err());
     }
 }
@@ -1,11 +1,51 @@
-use clap::Parser;
-use itertools::Itertools;
-use std::io::Write;
-use std::path::PathBuf;
+use clap::{Args, CommandFactory, ErrorKind, Parser, Subcommand};
+use clap_complete::{generate, Shell};
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use serde_json::json;
+use std::io::{BufReader, IsTerminal, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 
+use rafft::accuracy::{
+    self, evaluate_all_with, evaluate_family, read_ct, read_dot_bracket, read_stockholm,
+    write_family_tsv, ReferenceRecord,
+};
+use rafft::analysis::{
+    pair_persistence, shape_census, shape_groups, trajectories, write_profiles_csv,
+    write_shape_census_tsv,
+};
+use rafft::autocorrelation::{CorrelationKernel, LagWeighting, Window};
+use rafft::coordinates::{self, write_helices_bed, write_pairing_bedgraph, GenomicInterval};
+#[cfg(feature = "drawing")]
+use rafft::drawing::{svg_with_formation_depths, svg_with_layout};
+use rafft::encoding::{ungapped, EncodedSequence, HairpinEnforcement, PairTable, Summation};
+use rafft::export::{forna_json, graph_json, r2dt_payload, write_ct, write_dot, write_graphml};
 use rafft::fast_folding::RafftConfig;
-use rafft::folding_graph::RafftNodeInfo;
-use rafft::{set_global_energy_parameters, set_global_temperature, VIENNA_VERSION};
+use rafft::fft::{backend_by_name, FftBackend, BACKENDS};
+use rafft::folding_graph::{BranchRanking, RafftGraph, RafftNodeInfo, TrajectorySelection};
+use rafft::formatting::TrajectoryTable;
+use rafft::kinetics::{
+    boltzmann_probabilities, committors, mean_first_passage_times, populations,
+    temperature_protocol, write_protocol, write_rate_matrix, write_sparse_rates, write_states,
+    ArrheniusModel, Macrostates, TemperatureStep,
+};
+use rafft::manifest::{self, read_manifest, write_results};
+use rafft::motifs::ElementClassifier;
+use rafft::reference::{compare, parse_trajectories, run_reference};
+#[cfg(feature = "drawing")]
+use rafft::report::html_report;
+use rafft::screening::{self, read_fasta, screen_all_with};
+use rafft::sweep::{self, sweep_with, write_tsv, SweepGrid};
+use rafft::{
+    evaluate_structure, loop_contributions, set_global_energy_parameters, set_global_parameter_set,
+    set_global_temperature, ParameterSet, VIENNA_VERSION,
+};
+#[cfg(feature = "drawing")]
+use rafft::{plot_coordinates, PlotLayout};
 
 #[derive(Parser, Debug)]
 #[clap(
@@ -14,29 +54,104 @@ use rafft::{set_global_energy_parameters, set_global_temperature, VIENNA_VERSION
     about = "RAFFT implemented in Rust. RNA structure and folding dynamics prediction using fast Fourier transform (https://github.com/strevol-mpi-mis/rafft-rs).",
     after_help(VIENNA_VERSION)
 )]
-struct Args {
+struct Cli {
+    #[clap(flatten)]
+    energy: EnergyArgs,
+    #[clap(
+        long = "errors",
+        value_name = "FORMAT",
+        global = true,
+        default_value = "text",
+        help = "Format of error messages and warnings on stderr: text or json. The exit code distinguishes causes of failure: 1 discrepancies found by verify, 2 invalid usage, 3 invalid sequence, 4 conflicting structure or constraints, 5 time limit exceeded (output is still written), 6 input/output error, 70 internal error"
+    )]
+    errors: ErrorFormat,
+    #[clap(subcommand)]
+    command: Option<Command>,
+    // The flat invocation `rufft [OPTIONS] <SEQUENCE>` is deprecated in favor of `rufft fold`.
+    #[clap(flatten)]
+    legacy: LegacyArgs,
+}
+
+// parsed once per invocation, the size of the variants does not matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand, Debug)]
+enum Command {
+    #[clap(about = "Construct the fast-folding graph of a sequence and print its structures")]
+    Fold(FoldArgs),
+    #[clap(about = "Evaluate the free energy of a structure and decompose it into loops")]
+    Eval(EvalArgs),
+    #[clap(
+        about = "Summarize the autocorrelation spectrum of each record of a FASTA file as tab-separated values, without folding"
+    )]
+    Screen(ScreenArgs),
+    #[clap(
+        about = "Fold a sequence for all parameter combinations of a TOML grid in parallel and print a summary per combination as tab-separated values"
+    )]
+    Sweep(SweepArgs),
+    #[clap(about = "Export the fast-folding graph as DOT, GraphML, JSON or edge list")]
+    Graph(GraphArgs),
+    #[clap(
+        about = "Compute transition rates and populations of the Markov chain defined by the fast-folding graph"
+    )]
+    Kinetics(KineticsArgs),
+    #[clap(
+        about = "Fold the sequences of connectivity tables and print the accuracy (PPV, sensitivity, MCC) of the predicted structures as tab-separated values"
+    )]
+    BenchmarkAccuracy(BenchmarkAccuracyArgs),
+    #[clap(
+        about = "Fold the members of the families of Stockholm alignments (e.g. Rfam) and print the mean accuracy per family as tab-separated values"
+    )]
+    BenchmarkFamily(BenchmarkFamilyArgs),
+    #[clap(
+        about = "Compare the fast-folding graph of a sequence to the output of the reference implementation of RAFFT and print the discrepancies"
+    )]
+    Verify(VerifyArgs),
+    #[clap(about = "Print a completion script for the given shell to stdout")]
+    Completions {
+        #[clap(arg_enum, help = "Target shell")]
+        shell: Shell,
+    },
+    #[clap(about = "Print the man page in roff format to stdout")]
+    Man,
+}
+
+// Energy model options shared by all subcommands.
+#[derive(Args, Debug)]
+struct EnergyArgs {
     #[clap(
         parse(from_os_str),
         long = "params",
         short = 'P',
+        global = true,
         help = "RNA secondary structure energy parameters."
     )]
     parameters: Option<PathBuf>,
-    #[clap(help = "input RNA sequence")]
-    sequence: String,
-    #[clap(long = "AU", help = "Weight of AU base pairs", default_value = "2.0")]
-    au: f64,
-    #[clap(long = "GC", help = "Weight of GC base pairs", default_value = "3.0")]
-    gc: f64,
-    #[clap(long = "GU", help = "Weight of GU base pairs", default_value = "1.0")]
-    gu: f64,
+    #[clap(
+        long = "parameter-set",
+        conflicts_with = "parameters",
+        global = true,
+        help = "Built-in energy parameters: rna, turner2004, turner1999, andronescu2007, dna, mathews2004 or mathews1999. Use --params for RNA-DNA hybrid parameter files."
+    )]
+    parameter_set: Option<ParameterSet>,
     #[clap(
         long = "temperature",
         short = 'T',
+        global = true,
         help = "Temperature in °C, passed to ViennaRNA",
         default_value = "37.0"
     )]
     temperature: f64,
+}
+
+// Options of the construction of fast-folding graphs.
+#[derive(Args, Debug)]
+struct FoldingArgs {
+    #[clap(long = "AU", help = "Weight of AU base pairs", default_value = "2.0")]
+    au: f64,
+    #[clap(long = "GC", help = "Weight of GC base pairs", default_value = "3.0")]
+    gc: f64,
+    #[clap(long = "GU", help = "Weight of GU base pairs", default_value = "1.0")]
+    gu: f64,
     #[clap(
         long = "min-unpaired",
         short = 'u',
@@ -44,6 +159,17 @@ struct Args {
         default_value = "3"
     )]
     min_unpaired: usize,
+    #[clap(
+        long = "hairpin-enforcement",
+        help = "Handling of candidate stacks continuing into hairpin loops smaller than --min-unpaired [truncate, reject]",
+        default_value = "truncate"
+    )]
+    hairpin_enforcement: HairpinEnforcement,
+    #[clap(
+        long = "max-span",
+        help = "Maximum distance j - i of base pairs (i, j) considered by the stack search, e.g. to fold long sequences locally"
+    )]
+    max_pair_span: Option<usize>,
     #[clap(
         long = "minimum-helix-energy",
         short = 'e',
@@ -51,6 +177,26 @@ struct Args {
         default_value = "0.0"
     )]
     min_loop_energy: f64,
+    #[clap(
+        long = "energy-free",
+        help = "Accept and rank structures by their negative sum of base pair weights instead of free energies (reported in place of energies)"
+    )]
+    energy_free: bool,
+    #[clap(
+        long = "strict-stacks",
+        help = "Fail on candidate stacks inconsistent with their fragment (e.g. spanning a concatenation site) instead of skipping them"
+    )]
+    strict_stacks: bool,
+    #[clap(
+        long = "compensated-summation",
+        help = "Sum pair weights using compensated summation for results identical across platforms"
+    )]
+    compensated_summation: bool,
+    #[clap(
+        long = "integer-scores",
+        help = "Accumulate pairing scores of stacks in exact integer arithmetic (requires integer base pair weights)"
+    )]
+    integer_scores: bool,
     #[clap(
         long = "positional-lags",
         short = 'l',
@@ -58,6 +204,12 @@ struct Args {
         default_value = "100"
     )]
     positional_lags: usize,
+    #[clap(
+        long = "stacks-per-lag",
+        help = "Number of disjoint stacks to consider per positional lag",
+        default_value = "1"
+    )]
+    stacks_per_lag: usize,
     #[clap(
         long = "branch",
         short = 'b',
@@ -65,6 +217,66 @@ struct Args {
         default_value = "1000"
     )]
     number_of_branches: usize,
+    #[clap(
+        long = "block",
+        value_name = "START-END",
+        parse(try_from_str = parse_region),
+        multiple_occurrences(true),
+        help = "Forbid pairing within the given region (1-indexed, inclusive), e.g. a protein footprint; may be repeated"
+    )]
+    blocked_regions: Vec<Range<usize>>,
+    #[clap(
+        long = "branch-ranking",
+        help = "Criterion to rank structures per depth [energy, density, improvement]",
+        default_value = "energy"
+    )]
+    branch_ranking: BranchRanking,
+    #[clap(
+        long = "min-distance",
+        help = "Minimum base pair distance between structures saved per depth",
+        default_value = "0"
+    )]
+    minimum_distance: usize,
+    #[clap(
+        long = "select-diverse",
+        help = "Select the structures saved per depth by maximizing their base pair distances instead of by rank"
+    )]
+    select_diverse: bool,
+    #[clap(
+        long = "keep-lowest-energy",
+        help = "Always save the structure of lowest free energy per depth, regardless of ranking and sampling"
+    )]
+    keep_lowest_energy: bool,
+    #[clap(
+        long = "keep-target",
+        value_name = "STRUCTURE",
+        help = "Always save the best structure per depth that can still reach the given target structure (dot-bracket)"
+    )]
+    keep_target: Option<String>,
+    #[clap(
+        long = "track-target",
+        value_name = "STRUCTURE",
+        help = "Report the minimum base pair distance per depth to the given target structure (dot-bracket) in the statistics, and whether it was reached on stderr"
+    )]
+    track_target: Option<String>,
+    #[clap(
+        long = "exhaustive",
+        value_name = "MAX_LENGTH",
+        help = "Search all positional lags and stacks and keep all structures per depth for sequences of at most MAX_LENGTH nucleotides"
+    )]
+    exhaustive: Option<usize>,
+    #[clap(
+        long = "sample",
+        value_name = "KT",
+        help = "Sample saved structures per depth proportional to exp(-dG/KT) [kcal/mol] instead of keeping the best"
+    )]
+    sampling_temperature: Option<f64>,
+    #[clap(
+        long = "seed",
+        help = "Seed of the random number generator used for sampling",
+        default_value = "0"
+    )]
+    seed: u64,
     #[clap(
         long = "saved-trajectories",
         short = 's',
@@ -72,6 +284,100 @@ struct Args {
         default_value = "1"
     )]
     saved_trajectories: usize,
+    #[clap(
+        long = "correlation-power",
+        help = "Exponent the autocorrelation is raised to before positional lags are ranked",
+        default_value = "1.0"
+    )]
+    correlation_power: f64,
+    #[clap(
+        long = "smoothing",
+        value_name = "SIGMA",
+        help = "Standard deviation (in lags) of a Gaussian smoothing of the autocorrelation"
+    )]
+    smoothing: Option<f64>,
+    #[clap(
+        long = "window",
+        help = "Window function applied to the autocorrelation [rectangular, hann, hamming, blackman]",
+        default_value = "rectangular"
+    )]
+    window: Window,
+    #[clap(
+        long = "lag-weighting",
+        help = "Weighting of the autocorrelation by the range of pairs per lag [uniform, aligned:<exponent>, span-decay:<scale>]",
+        default_value = "uniform"
+    )]
+    lag_weighting: LagWeighting,
+    #[clap(
+        long = "fft-backend",
+        value_name = "NAME",
+        parse(try_from_str = parse_fft_backend),
+        help = "FFT backend used to compute autocorrelations [rustfft, fftw (requires the fftw feature)]",
+        default_value = "rustfft"
+    )]
+    fft_backend: Arc<dyn FftBackend>,
+    #[clap(
+        long = "cache-size",
+        help = "Number of fragments whose stack search results are cached; 0 disables caching",
+        default_value = "1024"
+    )]
+    cache_size: usize,
+    #[clap(
+        long = "time-limit",
+        value_name = "SECONDS",
+        help = "Stop the construction of the fast-folding graph after the given time and report the structures found so far"
+    )]
+    time_limit: Option<f64>,
+    #[clap(
+        long = "threads",
+        help = "Number of threads of batch computations (e.g. screen, sweep, batch); defaults to the number of CPUs"
+    )]
+    threads: Option<usize>,
+    #[clap(
+        long = "compact",
+        help = "Store structures as the pairs added to their parents to reduce memory usage of large fast-folding graphs"
+    )]
+    compact: bool,
+    #[clap(
+        long = "quiet",
+        short = 'q',
+        help = "Do not display progress on stderr"
+    )]
+    quiet: bool,
+}
+
+#[derive(Args, Debug)]
+struct FoldArgs {
+    #[clap(
+        help = "input RNA sequence, strands of complexes are separated by '&' and gaps ('-', '.') are removed",
+        required_unless_present = "manifest"
+    )]
+    sequence: Option<String>,
+    #[clap(flatten)]
+    options: FoldOptions,
+    #[clap(
+        parse(from_os_str),
+        long = "manifest",
+        value_name = "JOBS",
+        conflicts_with = "sequence",
+        help = "Fold the jobs of a tab-separated manifest in parallel instead of a single sequence. Each row names an input sequence file (column input), an output file for the structures (column output) and optional parameter overrides (further columns, see the documentation of rafft::manifest). The status of each job is written as tab-separated values to stdout or --results."
+    )]
+    manifest: Option<PathBuf>,
+    #[clap(
+        parse(from_os_str),
+        long = "results",
+        value_name = "FILE",
+        requires = "manifest",
+        help = "Write the status of each job of --manifest to the specified file instead of stdout"
+    )]
+    results: Option<PathBuf>,
+}
+
+// Options of `rufft fold`, which are also accepted by the flat invocation.
+#[derive(Args, Debug)]
+struct FoldOptions {
+    #[clap(flatten)]
+    folding: FoldingArgs,
     #[clap(
         long = "benchmark",
         short = 'B',
@@ -84,6 +390,43 @@ struct Args {
         help = "Use an output format compatible to the kinetics scripts of RAFFT. This includes duplicate structures."
     )]
     compat: bool,
+    #[clap(
+        long = "json",
+        help = "Print structures, edges and the loop decomposition of the best structure as JSON",
+        conflicts_with_all = &["compat", "benchmark", "stream", "relax"]
+    )]
+    json: bool,
+    #[clap(
+        long = "candidates",
+        help = "Print the ranked candidate stacks of the first step as tab-separated values (lag, i, j, pairs, score, dG in kcal/mol, accepted) without constructing the graph",
+        conflicts_with_all = &["compat", "benchmark", "stream", "relax", "json"]
+    )]
+    candidates: bool,
+    #[clap(
+        long = "shapes",
+        help = "Print the distribution of abstract shapes (see --shape-level) among the structures of each depth as tab-separated values (depth, shape, count, fraction) instead of the structures",
+        conflicts_with_all = &["compat", "benchmark", "stream", "relax", "json", "candidates"]
+    )]
+    shapes: bool,
+    #[clap(
+        long = "relax",
+        help = "Additionally print the local minimum reached by a gradient walk from each structure",
+        conflicts_with_all = &["compat", "benchmark", "stream"]
+    )]
+    relax: bool,
+    #[clap(
+        long = "stream",
+        help = "Print the structures of each depth as soon as it is constructed",
+        conflicts_with_all = &["compat", "benchmark", "verify"]
+    )]
+    stream: bool,
+    #[clap(
+        long = "color",
+        value_name = "WHEN",
+        default_value = "auto",
+        help = "Print the structures as a table under the sequence, highlighting new pairs and the best trajectory: auto (if stdout is a terminal), always or never. Otherwise, each structure is printed as `[depth] structure energy`"
+    )]
+    color: ColorChoice,
     #[clap(
         parse(from_os_str),
         long = "output-edges",
@@ -91,94 +434,1886 @@ struct Args {
         help = "Write edges (pairs of structure indices) to the specified file. The indices correspond to the order of the printed structures."
     )]
     outfile: Option<PathBuf>,
+    #[clap(
+        parse(from_os_str),
+        long = "forna-out",
+        value_name = "FILE",
+        help = "Write the structure of lowest free energy as forna JSON to the specified file, colored by the depth at which positions were first paired"
+    )]
+    forna_outfile: Option<PathBuf>,
+    #[clap(
+        parse(from_os_str),
+        long = "r2dt-out",
+        value_name = "FILE",
+        help = "Write an R2DT submission payload to the specified file (the email parameter has to be added)"
+    )]
+    r2dt_outfile: Option<PathBuf>,
+    #[clap(
+        parse(from_os_str),
+        long = "ct-out",
+        value_name = "FILE",
+        help = "Write the structure of lowest free energy as connectivity table to the specified file, with the depth at which each pair was formed in a seventh column"
+    )]
+    ct_outfile: Option<PathBuf>,
+    #[clap(
+        parse(from_os_str),
+        long = "persistence-out",
+        value_name = "FILE",
+        help = "Write the fraction of trajectories containing each base pair and its formation depths as tab-separated values to the specified file, in genomic coordinates with --region or --bed"
+    )]
+    persistence_outfile: Option<PathBuf>,
+    #[clap(
+        long = "region",
+        value_name = "CHROM:START-END[:STRAND]",
+        group = "genomic",
+        help = "Genomic interval of the sequence (1-based, inclusive), e.g. chr1:1001-1120:-. Pairs of --json, --persistence-out and --candidates, and --bed-out and --bedgraph-out are reported in genomic coordinates; dot-bracket structures and the forna, R2DT and CT exports keep the positions of the sequence"
+    )]
+    region: Option<GenomicInterval>,
+    #[clap(
+        parse(from_os_str),
+        long = "bed",
+        value_name = "FILE",
+        group = "genomic",
+        help = "Read the genomic interval of the sequence from the first record of a BED file, see --region"
+    )]
+    bed: Option<PathBuf>,
+    #[clap(
+        parse(from_os_str),
+        long = "bed-out",
+        value_name = "FILE",
+        requires = "genomic",
+        help = "Write the helices of the structure of lowest free energy as BED12 in genomic coordinates to the specified file"
+    )]
+    bed_outfile: Option<PathBuf>,
+    #[clap(
+        parse(from_os_str),
+        long = "bedgraph-out",
+        value_name = "FILE",
+        requires = "genomic",
+        help = "Write the fraction of trajectories whose final structure pairs each position as bedGraph in genomic coordinates to the specified file"
+    )]
+    bedgraph_outfile: Option<PathBuf>,
+    #[clap(
+        parse(from_os_str),
+        long = "profiles-out",
+        value_name = "FILE",
+        help = "Write the free energy, fraction of paired nucleotides and number of helices of each structure along each trajectory as comma-separated values to the specified file"
+    )]
+    profiles_outfile: Option<PathBuf>,
+    #[clap(
+        parse(from_os_str),
+        long = "shapes-out",
+        value_name = "FILE",
+        help = "Write the trajectories grouped by the abstract shapes of their final structures as tab-separated values to the specified file"
+    )]
+    shapes_outfile: Option<PathBuf>,
+    #[clap(
+        long = "shape-level",
+        value_name = "LEVEL",
+        parse(try_from_str = parse_shape_level),
+        help = "Level of abstract shapes from 1 (all loops and unpaired regions) to 5 (helices only)",
+        default_value = "5"
+    )]
+    shape_level: u8,
+    #[clap(
+        long = "element",
+        value_name = "NAME:START-END:PATTERN",
+        multiple_occurrences(true),
+        help = "Register a structural element formed if PATTERN (hard constraint notation, e.g. ((((xxxx))))) is satisfied within the region (1-indexed, inclusive); may be repeated"
+    )]
+    elements: Vec<String>,
+    #[clap(
+        parse(from_os_str),
+        long = "elements-out",
+        value_name = "FILE",
+        requires = "elements",
+        help = "Write which of the structural elements (see --element) each structure forms as tab-separated values to the specified file"
+    )]
+    elements_outfile: Option<PathBuf>,
+    #[cfg(feature = "drawing")]
+    #[clap(
+        parse(from_os_str),
+        long = "svg-out",
+        value_name = "FILE",
+        help = "Draw the structure of lowest free energy as SVG to the specified file"
+    )]
+    svg_outfile: Option<PathBuf>,
+    #[cfg(feature = "drawing")]
+    #[clap(
+        long = "svg-layout",
+        value_name = "LAYOUT",
+        requires = "svg-outfile",
+        help = "Place nucleotides of the SVG drawing using the layout of ViennaRNA (simple, naview, circular, turtle or puzzler) instead of on a circle"
+    )]
+    svg_layout: Option<PlotLayout>,
+    #[cfg(feature = "drawing")]
+    #[clap(
+        parse(from_os_str),
+        long = "report",
+        value_name = "FILE",
+        help = "Write a standalone HTML report with the structures, an energy chart and drawings of the best trajectory to the specified file"
+    )]
+    report_outfile: Option<PathBuf>,
+    #[clap(
+        parse(from_os_str),
+        long = "stats-out",
+        help = "Write search statistics per depth as tab-separated values to the specified file"
+    )]
+    stats_outfile: Option<PathBuf>,
+    #[clap(
+        long = "verify",
+        value_name = "REFERENCE_COMMAND",
+        help = "Compare the fast-folding graph to the output of the reference implementation of RAFFT run as the given command instead of printing it, like rufft verify"
+    )]
+    verify: Option<String>,
+    #[clap(
+        long = "tolerance",
+        help = "Tolerated energy difference [kcal/mol] when verifying against the reference implementation",
+        default_value = "0.01"
+    )]
+    tolerance: f64,
 }
 
-fn main() {
-    let args = Args::parse();
-
-    if let Some(path) = args.parameters {
-        set_global_energy_parameters(path);
-    }
-
-    #[allow(clippy::float_cmp)]
-    if args.temperature != 37.0 {
-        set_global_temperature(args.temperature);
-    }
+#[derive(Args, Debug)]
+struct EvalArgs {
+    #[clap(
+        help = "input RNA sequence, strands of complexes are separated by '&'",
+        required_unless_present_any = &["ct", "db"]
+    )]
+    sequence: Option<String>,
+    #[clap(
+        help = "structure in dot-bracket notation, strands may be separated by '&'",
+        required_unless_present_any = &["ct", "db"]
+    )]
+    structure: Option<String>,
+    #[clap(
+        parse(from_os_str),
+        long = "ct",
+        value_name = "FILE",
+        conflicts_with_all = &["sequence", "structure", "db"],
+        help = "Evaluate the structures of all records of a connectivity table (CT) instead"
+    )]
+    ct: Option<PathBuf>,
+    #[clap(
+        parse(from_os_str),
+        long = "db",
+        value_name = "FILE",
+        conflicts_with_all = &["sequence", "structure"],
+        help = "Evaluate the structures of all records of a dot-bracket file instead, e.g. as written by RNAsubopt"
+    )]
+    db: Option<PathBuf>,
+    #[clap(
+        long = "verbose",
+        short = 'v',
+        help = "Print the free energy contribution of each loop, similar to RNAeval -v"
+    )]
+    verbose: bool,
+    #[clap(
+        long = "json",
+        help = "Print the free energy and its loop decomposition as JSON, one object per structure"
+    )]
+    json: bool,
+    #[clap(
+        long = "ensemble",
+        help = "Fold each sequence and report the rank of each structure by free energy among the structures of the fast-folding graph"
+    )]
+    ensemble: bool,
+    #[clap(flatten)]
+    folding: FoldingArgs,
+}
 
-    let rafft_config = RafftConfig::new()
-        .maximum_trajectories(args.saved_trajectories)
-        .basepair_weights(args.au, args.gc, args.gu)
-        .minimum_unpaired_in_hairpins(args.min_unpaired)
-        .minimum_loop_energy(args.min_loop_energy)
-        .maximum_branches(args.number_of_branches)
-        .positional_lags(args.positional_lags);
+#[derive(Args, Debug)]
+struct ScreenArgs {
+    #[clap(parse(from_os_str), help = "FASTA file, '-' for stdin")]
+    fasta: PathBuf,
+    #[clap(flatten)]
+    folding: FoldingArgs,
+}
 
-    let mut ffgraph = rafft_config.folding_graph(&args.sequence);
+#[derive(Args, Debug)]
+struct BenchmarkAccuracyArgs {
+    #[clap(
+        parse(from_os_str),
+        required = true,
+        help = "Connectivity tables (CT) of reference structures, e.g. of ArchiveII or bpRNA"
+    )]
+    ct_files: Vec<PathBuf>,
+    #[clap(
+        long = "slippage",
+        help = "Consider predicted pairs correct if they are shifted by at most this many positions, e.g. 1",
+        default_value = "0"
+    )]
+    slippage: usize,
+    #[clap(flatten)]
+    folding: FoldingArgs,
+}
 
-    ffgraph.construct_trajectories();
+#[derive(Args, Debug)]
+struct BenchmarkFamilyArgs {
+    #[clap(
+        parse(from_os_str),
+        required = true,
+        help = "Stockholm alignments with consensus structures (#=GC SS_cons), e.g. Rfam seed alignments"
+    )]
+    stockholm_files: Vec<PathBuf>,
+    #[clap(
+        long = "slippage",
+        help = "Consider predicted pairs correct if they are shifted by at most this many positions, e.g. 1",
+        default_value = "0"
+    )]
+    slippage: usize,
+    #[clap(flatten)]
+    folding: FoldingArgs,
+}
 
-    if !args.benchmark {
-        if !args.compat {
-            ffgraph.iter().for_each(|node| {
-                println!(
-                    "[{}] {} {:.2}",
-                    node.depth,
-                    node.structure.to_string(),
-                    node.energy as f64 * 0.01
-                );
-            });
-        } else {
-            let mut grouped: Vec<(usize, Vec<&RafftNodeInfo>)> = vec![];
-            for (depth, nodes) in &ffgraph.iter().group_by(|node| node.depth) {
-                let mut nodes = nodes.collect::<Vec<_>>();
+#[derive(Args, Debug)]
+struct SweepArgs {
+    #[clap(
+        help = "input RNA sequence, strands of complexes are separated by '&' and gaps ('-', '.') are removed"
+    )]
+    sequence: String,
+    #[clap(parse(from_os_str), help = "TOML grid of parameter values")]
+    grid: PathBuf,
+    #[clap(flatten)]
+    folding: FoldingArgs,
+}
 
-                if nodes.len() < args.saved_trajectories {
-                    if let Some(previous) = grouped.last() {
-                        let mut missing_previous =
-                            previous.1[..args.saved_trajectories - nodes.len()].to_vec();
+#[derive(Args, Debug)]
+struct GraphArgs {
+    #[clap(
+        help = "input RNA sequence, strands of complexes are separated by '&' and gaps ('-', '.') are removed"
+    )]
+    sequence: String,
+    #[clap(flatten)]
+    folding: FoldingArgs,
+    #[clap(
+        long = "format",
+        short = 'f',
+        help = "Output format [dot, graphml, json, edges]; nodes carry their Boltzmann probability except for edges",
+        default_value = "dot"
+    )]
+    format: GraphFormat,
+    #[clap(
+        parse(from_os_str),
+        long = "output",
+        short = 'o',
+        value_name = "FILE",
+        help = "Write the graph to the specified file instead of stdout"
+    )]
+    outfile: Option<PathBuf>,
+}
 
-                        nodes.append(&mut missing_previous);
-                        assert_eq!(nodes.len(), args.saved_trajectories);
+#[derive(Args, Debug)]
+struct VerifyArgs {
+    #[clap(
+        help = "input RNA sequence, strands of complexes are separated by '&' and gaps ('-', '.') are removed"
+    )]
+    sequence: String,
+    #[clap(
+        help = "Command running the reference implementation of RAFFT. The placeholders {sequence} and {saved_trajectories} are substituted, e.g. \"python rafft.py -s {sequence} -ms {saved_trajectories} --traj\"."
+    )]
+    reference: String,
+    #[clap(
+        long = "tolerance",
+        help = "Tolerated energy difference [kcal/mol]",
+        default_value = "0.01"
+    )]
+    tolerance: f64,
+    #[clap(flatten)]
+    folding: FoldingArgs,
+}
 
-                        nodes.sort_by_key(|node| node.energy);
-                    }
-                }
-                grouped.push((depth, nodes));
-            }
+#[derive(Args, Debug)]
+struct KineticsArgs {
+    #[clap(
+        help = "input RNA sequence, strands of complexes are separated by '&' and gaps ('-', '.') are removed"
+    )]
+    sequence: String,
+    #[clap(flatten)]
+    folding: FoldingArgs,
+    #[clap(
+        long = "prefactor",
+        help = "Prefactor of the Arrhenius rates",
+        default_value = "1.0"
+    )]
+    prefactor: f64,
+    #[clap(
+        long = "barrier",
+        help = "Energy barrier [kcal/mol] added to all transitions",
+        default_value = "0.0"
+    )]
+    barrier: f64,
+    #[clap(
+        long = "sparse",
+        help = "Print non-zero rates as 'i j rate' per line instead of the dense rate matrix in the format of barriers (rates.out)"
+    )]
+    sparse: bool,
+    #[clap(
+        parse(from_os_str),
+        long = "states-out",
+        value_name = "FILE",
+        help = "Write the states of the rate matrix in the format of barriers to the specified file"
+    )]
+    states_outfile: Option<PathBuf>,
+    #[clap(
+        long = "time",
+        help = "Print the populations of all structures at the given time, starting from the open chain, instead of rates",
+        conflicts_with = "protocol"
+    )]
+    time: Option<f64>,
+    #[clap(
+        long = "protocol",
+        value_name = "T:DURATION,...",
+        parse(try_from_str = parse_protocol_step),
+        use_value_delimiter = true,
+        help = "Print time-resolved populations as tab-separated values under a temperature protocol, e.g. 37:100,55:100,37:100 (°C and time units of the prefactor), instead of rates"
+    )]
+    protocol: Vec<TemperatureStep>,
+    #[clap(
+        long = "samples",
+        help = "Number of population snapshots per step of the temperature protocol",
+        default_value = "10"
+    )]
+    samples: usize,
+    #[clap(
+        long = "target",
+        value_name = "STRUCTURE",
+        multiple_occurrences(true),
+        conflicts_with_all = &["time", "protocol"],
+        help = "Print the committors (reaching the targets before the open chain) and mean first-passage times into the given target structures (dot-bracket) of all structures instead of rates; may be repeated"
+    )]
+    targets: Vec<String>,
+    #[clap(
+        long = "macrostates",
+        conflicts_with_all = &["time", "protocol", "targets"],
+        help = "Coarse-grain the structures into the gradient basins of their local minima and print the aggregated rates between these macrostates (and write them with --states-out)"
+    )]
+    macrostates: bool,
+}
 
-            for (depth, nodes) in grouped {
-                println!("# ---------{}----------", depth);
-                nodes.iter().for_each(|node| {
-                    println!(
-                        "{} {:.2}",
-                        node.structure.to_string(),
-                        node.energy as f64 * 0.01
-                    );
-                });
-            }
-        }
+// Deprecated flat invocation, equivalent to `rufft fold` with the former options of `screen`, `sweep`, `graph` and `kinetics`.
+#[derive(Args, Debug)]
+struct LegacyArgs {
+    #[clap(
+        help = "input RNA sequence, strands of complexes are separated by '&' and gaps ('-', '.') are removed (deprecated, use `rufft fold`)"
+    )]
+    sequence: Option<String>,
+    #[clap(flatten)]
+    options: FoldOptions,
+    #[clap(
+        parse(from_os_str),
+        long = "sweep-grid",
+        value_name = "GRID",
+        hide = true,
+        conflicts_with_all = &["stream", "verify"]
+    )]
+    sweep_grid: Option<PathBuf>,
+    #[clap(
+        long = "screen",
+        hide = true,
+        conflicts_with_all = &["stream", "verify", "sweep-grid"]
+    )]
+    screen: bool,
+    #[clap(parse(from_os_str), long = "dot-out", value_name = "FILE", hide = true)]
+    dot_outfile: Option<PathBuf>,
+    #[clap(
+        parse(from_os_str),
+        long = "graphml-out",
+        value_name = "FILE",
+        hide = true
+    )]
+    graphml_outfile: Option<PathBuf>,
+    #[clap(parse(from_os_str), long = "rates-out", hide = true)]
+    rates_outfile: Option<PathBuf>,
+    #[clap(long = "sparse-rates", requires = "rates-outfile", hide = true)]
+    sparse_rates: bool,
+    #[clap(long = "prefactor", default_value = "1.0", hide = true)]
+    prefactor: f64,
+    #[clap(long = "barrier", default_value = "0.0", hide = true)]
+    barrier: f64,
+}
+
+// Parse a 1-indexed, inclusive region `START-END` into a 0-indexed range.
+fn parse_region(region: &str) -> Result<Range<usize>, String> {
+    let error = || format!("invalid region {:?} (expected START-END)", region);
+    let (start, end) = region.split_once('-').ok_or_else(error)?;
+    let (start, end): (usize, usize) = (
+        start.trim().parse().map_err(|_| error())?,
+        end.trim().parse().map_err(|_| error())?,
+    );
+
+    if start == 0 || start > end {
+        return Err(error());
+    }
+
+    Ok(start - 1..end)
+}
+
+// Parse the name of an FFT backend available in this build.
+fn parse_fft_backend(name: &str) -> Result<Arc<dyn FftBackend>, String> {
+    backend_by_name(name).ok_or_else(|| {
+        format!(
+            "unknown or unavailable FFT backend {:?} (available: {})",
+            name,
+            BACKENDS.join(", ")
+        )
+    })
+}
+
+// Return the classifier of the structural elements `NAME:START-END:PATTERN`,
+// exiting with `Failure::Usage` if any of them is invalid.
+fn element_classifier(elements: &[String]) -> ElementClassifier {
+    elements
+        .iter()
+        .fold(ElementClassifier::new(), |classifier, element| {
+            let invalid = |reason: String| -> ! {
+                fail(
+                    Failure::Usage,
+                    format!("invalid element {:?}: {}", element, reason),
+                )
+            };
+
+            let mut parts = element.splitn(3, ':');
+            let (name, region, pattern) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(name), Some(region), Some(pattern)) => (name, region, pattern),
+                _ => invalid("expected NAME:START-END:PATTERN".to_string()),
+            };
+            let region = parse_region(region).unwrap_or_else(|error| invalid(error));
+
+            classifier
+                .element(name, region.start + 1..=region.end, pattern)
+                .unwrap_or_else(|error| invalid(error.to_string()))
+        })
+}
+
+// Parse an abstract shape level from 1 to 5.
+fn parse_shape_level(level: &str) -> Result<u8, String> {
+    match level.trim().parse() {
+        Ok(level @ 1..=5) => Ok(level),
+        _ => Err(format!("invalid shape level {:?} (expected 1 to 5)", level)),
+    }
+}
+
+// Parse a step `T:DURATION` of a temperature protocol (°C and time units of the rates).
+fn parse_protocol_step(step: &str) -> Result<TemperatureStep, String> {
+    let error = || format!("invalid protocol step {:?} (expected T:DURATION)", step);
+    let (temperature, duration) = step.split_once(':').ok_or_else(error)?;
+
+    Ok(TemperatureStep {
+        temperature: temperature.trim().parse().map_err(|_| error())?,
+        duration: duration.trim().parse().map_err(|_| error())?,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GraphFormat {
+    Dot,
+    GraphMl,
+    Json,
+    Edges,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "dot" => Ok(Self::Dot),
+            "graphml" => Ok(Self::GraphMl),
+            "json" => Ok(Self::Json),
+            "edges" => Ok(Self::Edges),
+            _ => Err(format!(
+                "invalid graph format {:?} (expected one of dot, graphml, json, edges)",
+                format
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(choice: &str) -> Result<Self, Self::Err> {
+        match choice {
+            "auto" => Ok(Self::Auto),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => Err(format!(
+                "invalid color choice {:?} (expected one of auto, always, never)",
+                choice
+            )),
+        }
+    }
+}
+
+// Dot-bracket notation of a node, where strands of complexes are separated by '&'.
+fn dot_bracket(node: &RafftNodeInfo, cut_points: &[usize]) -> String {
+    node.structure
+        .pair_table()
+        .to_string_with_cut_points(cut_points)
+}
+
+// Progress bar with ETA for batches of `length` independent runs, drawn on stderr.
+fn progress_bar(length: usize, quiet: bool) -> ProgressBar {
+    if quiet {
+        return ProgressBar::hidden();
+    }
 
-        if let Some(outfile) = args.outfile {
-            if let Ok(mut file) = std::fs::File::create(&outfile) {
-                ffgraph.adjacent_indices().for_each(|(i, j)| {
-                    writeln!(file, "{} {}", i, j).unwrap();
+    ProgressBar::new(length as u64).with_style(
+        ProgressStyle::with_template("{bar:40} {pos}/{len} [{elapsed_precise}, ETA {eta_precise}]")
+            .expect("valid template"),
+    )
+}
+
+// Spinner reporting the depth of a single fold, drawn on stderr.
+// The final depth is not known in advance, so there is no ETA.
+fn depth_spinner(quiet: bool) -> ProgressBar {
+    if quiet {
+        return ProgressBar::hidden();
+    }
+
+    let spinner = ProgressBar::new_spinner().with_style(
+        ProgressStyle::with_template("{spinner} depth {pos}, {msg} [{elapsed_precise}]")
+            .expect("valid template"),
+    );
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    spinner
+}
+
+impl EnergyArgs {
+    // Set the global energy parameters and temperature of ViennaRNA.
+    fn apply(&self) {
+        if let Some(path) = &self.parameters {
+            set_global_energy_parameters(path.clone());
+        }
+
+        if let Some(set) = self.parameter_set {
+            set_global_parameter_set(set);
+        }
+
+        #[allow(clippy::float_cmp)]
+        if self.temperature != 37.0 {
+            set_global_temperature(self.temperature);
+        }
+    }
+}
+
+impl FoldingArgs {
+    fn config(&self) -> RafftConfig {
+        let mut rafft_config = RafftConfig::new()
+            .maximum_trajectories(self.saved_trajectories)
+            .basepair_weights(self.au, self.gc, self.gu)
+            .minimum_unpaired_in_hairpins(self.min_unpaired)
+            .hairpin_enforcement(self.hairpin_enforcement)
+            .minimum_loop_energy(self.min_loop_energy)
+            .energy_free(self.energy_free)
+            .strict_stacks(self.strict_stacks)
+            .integer_scores(self.integer_scores)
+            .summation(if self.compensated_summation {
+                Summation::Compensated
+            } else {
+                Summation::Naive
+            })
+            .maximum_branches(self.number_of_branches)
+            .positional_lags(self.positional_lags)
+            .stacks_per_lag(self.stacks_per_lag)
+            .branch_ranking(self.branch_ranking)
+            .minimum_distance(self.minimum_distance)
+            .trajectory_selection(TrajectorySelection {
+                diversity: self.select_diverse,
+                lowest_energy: self.keep_lowest_energy,
+                target: self.keep_target.as_deref().map(|target| {
+                    target.parse().unwrap_or_else(|error| {
+                        fail(
+                            Failure::Usage,
+                            format!("invalid target structure {:?}: {}", target, error),
+                        )
+                    })
+                }),
+            })
+            .compact_structures(self.compact)
+            .cache_size(self.cache_size)
+            .seed(self.seed)
+            .correlation_kernel(CorrelationKernel {
+                power: self.correlation_power,
+                smoothing: self.smoothing,
+                window: self.window,
+                weighting: self.lag_weighting.clone(),
+            })
+            .fft_backend(self.fft_backend.clone());
+
+        if let Some(max_pair_span) = self.max_pair_span {
+            rafft_config = rafft_config.max_pair_span(max_pair_span);
+        }
+
+        if let Some(kt) = self.sampling_temperature {
+            rafft_config = rafft_config.boltzmann_sampling(kt);
+        }
+
+        for region in &self.blocked_regions {
+            rafft_config = rafft_config.block_region(region.clone());
+        }
+
+        if let Some(seconds) = self.time_limit {
+            rafft_config = rafft_config.time_limit(Duration::from_secs_f64(seconds));
+        }
+
+        if let Some(threads) = self.threads {
+            rafft_config = rafft_config.threads(threads).unwrap_or_else(|error| {
+                fail(
+                    Failure::Internal,
+                    format!("failed to create a pool of {} threads: {}", threads, error),
+                )
+            });
+        }
+
+        if let Some(max_length) = self.exhaustive {
+            rafft_config = rafft_config.exhaustive(max_length);
+        }
+
+        if let Some(target) = &self.track_target {
+            rafft_config = rafft_config.track_target(target.parse().unwrap_or_else(|error| {
+                fail(
+                    Failure::Usage,
+                    format!("invalid target structure {:?}: {}", target, error),
+                )
+            }));
+        }
+
+        rafft_config
+    }
+}
+
+fn main() {
+    let cli = Cli::try_parse().unwrap_or_else(|error| {
+        // the format of errors is not known if parsing fails
+        let json = std::env::args()
+            .collect::<Vec<_>>()
+            .windows(2)
+            .any(|args| args[0] == "--errors" && args[1] == "json")
+            || std::env::args().any(|arg| arg == "--errors=json");
+
+        match error.kind() {
+            ErrorKind::DisplayHelp | ErrorKind::DisplayVersion => error.exit(),
+            _ if json => {
+                ERROR_FORMAT.set(ErrorFormat::Json).ok();
+                fail(Failure::Usage, error.to_string().trim())
+            }
+            _ => error.exit(),
+        }
+    });
+
+    ERROR_FORMAT.set(cli.errors).ok();
+    std::panic::set_hook(Box::new(|info| fail(Failure::Internal, info)));
+    cli.energy.apply();
+
+    match cli.command {
+        Some(Command::Fold(args)) => match (&args.sequence, &args.manifest) {
+            (_, Some(manifest)) => run_manifest(
+                manifest,
+                args.results.as_deref(),
+                &args.options.folding,
+                cli.energy.temperature,
+            ),
+            (Some(sequence), None) => {
+                fold(&ungapped(sequence), &args.options, cli.energy.temperature);
+            }
+            (None, None) => unreachable!("clap requires a sequence or a manifest"),
+        },
+        Some(Command::Eval(args)) => eval(&args),
+        Some(Command::Screen(args)) => screen(&args.fasta, &args.folding),
+        Some(Command::Sweep(args)) => sweep(&ungapped(&args.sequence), &args.grid, &args.folding),
+        Some(Command::Graph(args)) => graph(&args, cli.energy.temperature),
+        Some(Command::Kinetics(args)) => kinetics(&args, cli.energy.temperature),
+        Some(Command::Verify(args)) => {
+            let sequence = ungapped(&args.sequence);
+            let ffgraph = construct(&sequence, &args.folding, false);
+            verify(
+                &sequence,
+                &ffgraph,
+                &args.reference,
+                &args.folding,
+                args.tolerance,
+            );
+        }
+        Some(Command::BenchmarkAccuracy(args)) => benchmark_accuracy(&args, cli.energy.temperature),
+        Some(Command::BenchmarkFamily(args)) => benchmark_family(&args, cli.energy.temperature),
+        Some(Command::Completions { shell }) => {
+            generate(shell, &mut Cli::command(), "rufft", &mut std::io::stdout());
+        }
+        Some(Command::Man) => write_stdout(|handle| write_man(&Cli::command(), handle)),
+        None => legacy(cli.legacy, cli.energy.temperature),
+    }
+
+    if TRUNCATED.load(Ordering::Relaxed) {
+        fail(
+            Failure::ResourceLimit,
+            "time limit exceeded, the fast-folding graph is incomplete",
+        );
+    }
+}
+
+static ERROR_FORMAT: OnceLock<ErrorFormat> = OnceLock::new();
+// Set if the construction of any fast-folding graph was stopped by the time limit.
+static TRUNCATED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ErrorFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "invalid error format {:?} (expected one of text, json)",
+                format
+            )),
+        }
+    }
+}
+
+// Causes of failure, distinguished by exit codes (see `--errors`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    Usage,
+    InvalidSequence,
+    ConstraintConflict,
+    ResourceLimit,
+    Io,
+    Internal,
+}
+
+impl Failure {
+    fn code(self) -> i32 {
+        match self {
+            Self::Usage => 2,
+            Self::InvalidSequence => 3,
+            Self::ConstraintConflict => 4,
+            Self::ResourceLimit => 5,
+            Self::Io => 6,
+            Self::Internal => 70,
+        }
+    }
+
+    fn kind(self) -> &'static str {
+        match self {
+            Self::Usage => "usage",
+            Self::InvalidSequence => "invalid_sequence",
+            Self::ConstraintConflict => "constraint_conflict",
+            Self::ResourceLimit => "resource_limit",
+            Self::Io => "io",
+            Self::Internal => "internal",
+        }
+    }
+}
+
+// Report `message` on stderr in the requested format and exit with the code of `failure`.
+fn fail<M: std::fmt::Display>(failure: Failure, message: M) -> ! {
+    match ERROR_FORMAT.get() {
+        Some(ErrorFormat::Json) => eprintln!(
+            "{}",
+            json!({
+                "error": {
+                    "kind": failure.kind(),
+                    "code": failure.code(),
+                    "message": message.to_string(),
+                }
+            })
+        ),
+        _ => eprintln!("error: {}", message),
+    }
+
+    std::process::exit(failure.code());
+}
+
+// Report the warning `message` on stderr in the requested format without exiting, see `fail()`.
+fn warn<M: std::fmt::Display>(message: M) {
+    match ERROR_FORMAT.get() {
+        Some(ErrorFormat::Json) => {
+            eprintln!(
+                "{}",
+                json!({ "warning": { "message": message.to_string() } })
+            )
+        }
+        _ => eprintln!("warning: {}", message),
+    }
+}
+
+// Report the informational `message` on stderr in the requested format, see `warn()`.
+fn note<M: std::fmt::Display>(message: M) {
+    match ERROR_FORMAT.get() {
+        Some(ErrorFormat::Json) => {
+            eprintln!("{}", json!({ "note": { "message": message.to_string() } }))
+        }
+        _ => eprintln!("{}", message),
+    }
+}
+
+// Create the file at `path` and write to it using `write`,
+// exiting with `Failure::Io` if the file cannot be created or written.
+fn write_file<F>(path: &Path, write: F)
+where
+    F: FnOnce(&mut std::fs::File) -> std::io::Result<()>,
+{
+    if let Err(error) = std::fs::File::create(path).and_then(|mut file| write(&mut file)) {
+        fail(Failure::Io, format!("{}: {}", path.display(), error));
+    }
+}
+
+// Write to stdout using `write`, exiting with `Failure::Io` instead of panicking if stdout cannot be written,
+// e.g. because the reading end of a pipe was closed.
+fn write_stdout<T, F>(write: F) -> T
+where
+    F: FnOnce(&mut std::io::StdoutLock<'static>) -> std::io::Result<T>,
+{
+    let mut handle = std::io::stdout().lock();
+    write(&mut handle)
+        .and_then(|value| handle.flush().map(|()| value))
+        .unwrap_or_else(|error| fail(Failure::Io, format!("stdout: {}", error)))
+}
+
+// Exit with `Failure::InvalidSequence` unless `sequence` can be encoded, and with `Failure::ConstraintConflict`
+// if a blocked region exceeds it.
+fn validate(sequence: &str, folding: &FoldingArgs) {
+    let encoded = EncodedSequence::new(sequence)
+        .unwrap_or_else(|error| fail(Failure::InvalidSequence, error));
+
+    if let Some(region) = folding
+        .blocked_regions
+        .iter()
+        .find(|region| region.end > encoded.len())
+    {
+        fail(
+            Failure::ConstraintConflict,
+            format!(
+                "blocked region {}-{} exceeds the sequence of length {}",
+                region.start + 1,
+                region.end,
+                encoded.len()
+            ),
+        );
+    }
+
+    for target in folding.keep_target.iter().chain(&folding.track_target) {
+        let length = target.chars().filter(|&c| c != '&').count();
+
+        if length != encoded.len() {
+            fail(
+                Failure::ConstraintConflict,
+                format!(
+                    "target structure of length {} does not match the sequence of length {}",
+                    length,
+                    encoded.len()
+                ),
+            );
+        }
+    }
+}
+
+// Run the deprecated flat invocation by dispatching to the corresponding subcommand.
+fn legacy(args: LegacyArgs, temperature: f64) {
+    let sequence = args
+        .sequence
+        .unwrap_or_else(|| fail(Failure::Usage, "a subcommand or <SEQUENCE> is required"));
+    warn("`rufft [OPTIONS] <SEQUENCE>` is deprecated and will be removed in the next release, use `rufft fold` (or `screen`, `sweep`, `graph` and `kinetics`) instead.");
+
+    if args.screen {
+        screen(&PathBuf::from(sequence), &args.options.folding);
+        return;
+    }
+    let sequence = ungapped(&sequence);
+
+    if let Some(grid) = &args.sweep_grid {
+        sweep(&sequence, grid, &args.options.folding);
+        return;
+    }
+
+    let ffgraph = fold(&sequence, &args.options, temperature);
+
+    if args.dot_outfile.is_some() || args.graphml_outfile.is_some() {
+        let probabilities =
+            boltzmann_probabilities(&ffgraph, ArrheniusModel::at_temperature(temperature).kt);
+
+        for (outfile, format) in [
+            (&args.dot_outfile, GraphFormat::Dot),
+            (&args.graphml_outfile, GraphFormat::GraphMl),
+        ] {
+            if let Some(outfile) = outfile {
+                write_file(outfile, |file| {
+                    write_graph(&ffgraph, format, &probabilities, file)
+                });
+            }
+        }
+    }
+
+    if let Some(rates_outfile) = args.rates_outfile {
+        let model = ArrheniusModel {
+            prefactor: args.prefactor,
+            barrier: args.barrier,
+            ..ArrheniusModel::at_temperature(temperature)
+        };
+
+        write_file(&rates_outfile, |file| match args.sparse_rates {
+            true => write_sparse_rates(&ffgraph, &model, file),
+            false => write_rate_matrix(&ffgraph, &model, file),
+        });
+        write_file(&rates_outfile.with_extension("states"), |file| {
+            write_states(&ffgraph, file)
+        });
+    }
+}
+
+// Print the candidate stacks of the first step as tab-separated values with a header line.
+// The outer pairs of the stacks are mapped to genomic coordinates if an `interval` is given.
+fn print_candidates(sequence: &str, folding: &FoldingArgs, interval: Option<&GenomicInterval>) {
+    validate(sequence, folding);
+
+    let candidates = folding.config().folding_graph(sequence).candidate_stacks();
+    write_stdout(|handle| {
+        writeln!(handle, "lag\ti\tj\tpairs\tscore\tenergy\taccepted")?;
+        candidates.iter().try_for_each(|stack| {
+            let (i, j) = match interval {
+                Some(interval) => interval.pair((stack.i, stack.j)),
+                None => (stack.i as u64, stack.j as u64),
+            };
+            writeln!(
+                handle,
+                "{}\t{}\t{}\t{}\t{:.4}\t{:.2}\t{}",
+                stack.lag,
+                i,
+                j,
+                stack.pairs,
+                stack.score,
+                stack.delta_energy as f64 * 0.01,
+                stack.accepted as u8
+            )
+        })
+    });
+}
+
+// Construct the fast-folding graph of `sequence`, displaying progress on stderr unless `quiet` is set.
+// If `stream` is set, the structures of each depth are printed as soon as they are constructed.
+fn construct(sequence: &str, folding: &FoldingArgs, stream: bool) -> RafftGraph {
+    validate(sequence, folding);
+
+    let mut ffgraph = folding.config().folding_graph(sequence);
+    let cut_points = ffgraph.cut_points().to_vec();
+
+    let progress = depth_spinner(folding.quiet);
+    let mut structures = 0;
+    let constructed = ffgraph.try_construct_trajectories_with_progress(|depth_progress| {
+        let nodes = depth_progress.nodes;
+        structures += nodes.len();
+        progress.set_position(depth_progress.depth as u64);
+        progress.set_message(format!(
+            "{} structures, {}",
+            structures,
+            HumanBytes(depth_progress.memory.total() as u64)
+        ));
+
+        if stream {
+            progress.suspend(|| {
+                write_stdout(|handle| {
+                    nodes.iter().try_for_each(|node| {
+                        writeln!(
+                            handle,
+                            "[{}] {} {:.2}",
+                            node.depth,
+                            dot_bracket(node, &cut_points),
+                            node.energy as f64 * 0.01
+                        )
+                    })
                 })
+            });
+        }
+    });
+    progress.finish_and_clear();
+
+    if let Err(error) = constructed {
+        fail(Failure::Internal, error);
+    }
+
+    if ffgraph.is_truncated() {
+        TRUNCATED.store(true, Ordering::Relaxed);
+    }
+
+    ffgraph
+}
+
+fn screen(fasta: &Path, folding: &FoldingArgs) {
+    let records = if fasta == Path::new("-") {
+        read_fasta(std::io::stdin().lock())
+    } else {
+        std::fs::File::open(fasta).and_then(|file| read_fasta(BufReader::new(file)))
+    }
+    .unwrap_or_else(|error| fail(Failure::Io, format!("{}: {}", fasta.display(), error)));
+
+    let progress = progress_bar(records.len(), folding.quiet);
+    let results = screen_all_with(&records, &folding.config(), |_| progress.inc(1));
+    progress.finish_and_clear();
+
+    let skipped = write_stdout(|handle| screening::write_tsv(&records, &results, handle));
+    for (name, error) in skipped {
+        warn(format!("skipping {}: {}", name, error));
+    }
+}
+
+fn benchmark_accuracy(args: &BenchmarkAccuracyArgs, temperature: f64) {
+    let mut records = vec![];
+    for path in &args.ct_files {
+        records.extend(read_references(path, read_ct));
+    }
+
+    let kt = ArrheniusModel::at_temperature(temperature).kt;
+    let progress = progress_bar(records.len(), args.folding.quiet);
+    let results = evaluate_all_with(&records, &args.folding.config(), kt, args.slippage, |_| {
+        progress.inc(1)
+    });
+    progress.finish_and_clear();
+
+    let skipped = write_stdout(|handle| accuracy::write_tsv(&records, &results, handle));
+    for (name, error) in skipped {
+        warn(format!("skipping {}: {}", name, error));
+    }
+}
+
+fn benchmark_family(args: &BenchmarkFamilyArgs, temperature: f64) {
+    let mut alignments = vec![];
+    for path in &args.stockholm_files {
+        let file_alignments = std::fs::File::open(path)
+            .map_err(accuracy::Error::from)
+            .and_then(|file| read_stockholm(BufReader::new(file)))
+            .unwrap_or_else(|error| match error {
+                accuracy::Error::Io(_) => {
+                    fail(Failure::Io, format!("{}: {}", path.display(), error))
+                }
+                _ => fail(Failure::Usage, format!("{}: {}", path.display(), error)),
+            });
+        alignments.extend(file_alignments);
+    }
+
+    let config = args.folding.config();
+    let kt = ArrheniusModel::at_temperature(temperature).kt;
+    let progress = progress_bar(alignments.len(), args.folding.quiet);
+    let families: Vec<_> = alignments
+        .iter()
+        .filter_map(|alignment| {
+            let family = evaluate_family(alignment, &config, kt, args.slippage);
+            progress.inc(1);
+            if let Ok(family) = &family {
+                for (name, error) in &family.skipped {
+                    progress.suspend(|| warn(format!("skipping {}: {}", name, error)));
+                }
+            }
+            family
+                .map_err(|error| progress.suspend(|| warn(format!("skipping {}", error))))
+                .ok()
+        })
+        .collect();
+    progress.finish_and_clear();
+
+    write_stdout(|handle| write_family_tsv(&families, handle));
+}
+
+fn sweep(sequence: &str, grid: &Path, folding: &FoldingArgs) {
+    validate(sequence, folding);
+
+    let results = SweepGrid::from_file(grid)
+        .and_then(|grid| {
+            let progress = progress_bar(grid.cells().len(), folding.quiet);
+            let results = sweep_with(sequence, &folding.config(), &grid, |_| progress.inc(1));
+            progress.finish_and_clear();
+            results
+        })
+        .unwrap_or_else(|error| match error {
+            sweep::Error::Io(_) => fail(Failure::Io, format!("{}: {}", grid.display(), error)),
+            _ => fail(Failure::Usage, format!("{}: {}", grid.display(), error)),
+        });
+
+    write_stdout(|handle| write_tsv(&results, handle));
+}
+
+fn run_manifest(manifest: &Path, results: Option<&Path>, folding: &FoldingArgs, temperature: f64) {
+    let base = manifest.parent().unwrap_or_else(|| Path::new("."));
+    let jobs = std::fs::File::open(manifest)
+        .map_err(manifest::Error::from)
+        .and_then(|file| read_manifest(BufReader::new(file), base))
+        .unwrap_or_else(|error| match error {
+            manifest::Error::Io(_) => {
+                fail(Failure::Io, format!("{}: {}", manifest.display(), error))
+            }
+            _ => fail(Failure::Usage, error),
+        });
+
+    let progress = progress_bar(jobs.len(), folding.quiet);
+    let statuses = manifest::run_with(&jobs, &folding.config(), temperature, |_| progress.inc(1));
+    progress.finish_and_clear();
+
+    match results {
+        Some(results) => write_file(results, |file| write_results(&jobs, &statuses, file)),
+        None => write_stdout(|handle| write_results(&jobs, &statuses, handle)),
+    }
+}
+
+fn eval(args: &EvalArgs) {
+    let records = match (&args.ct, &args.db, &args.sequence, &args.structure) {
+        (Some(path), ..) => read_references(path, read_ct),
+        (_, Some(path), ..) => read_references(path, read_dot_bracket),
+        (None, None, Some(sequence), Some(structure)) => vec![ReferenceRecord {
+            name: String::new(),
+            sequence: sequence.clone(),
+            structure: structure
+                .parse()
+                .unwrap_or_else(|error| fail(Failure::ConstraintConflict, error)),
+        }],
+        _ => unreachable!("clap requires a sequence and a structure or a file"),
+    };
+
+    // consecutive structures of the same sequence share their fast-folding graph
+    let mut start = 0;
+    while start < records.len() {
+        let sequence = &records[start].sequence;
+        let end = start
+            + records[start..]
+                .iter()
+                .take_while(|record| &record.sequence == sequence)
+                .count();
+        let group = &records[start..end];
+        start = end;
+
+        let encoded = EncodedSequence::new(sequence)
+            .unwrap_or_else(|error| fail(Failure::InvalidSequence, error));
+        if let Some(record) = group
+            .iter()
+            .find(|record| record.structure.len() != encoded.len())
+        {
+            fail(
+                Failure::ConstraintConflict,
+                format!(
+                    "structure has length {}, but the sequence has length {}",
+                    record.structure.len(),
+                    encoded.len()
+                ),
+            );
+        }
+
+        let ffgraph = args
+            .ensemble
+            .then(|| construct(&ungapped(sequence), &args.folding, false));
+
+        if !args.json {
+            write_stdout(|handle| {
+                if !group[0].name.is_empty() {
+                    writeln!(handle, ">{}", group[0].name)?;
+                }
+                writeln!(handle, "{}", sequence)
+            });
+        }
+
+        for ReferenceRecord { structure, .. } in group {
+            let dot_bracket = structure.to_string_with_cut_points(encoded.cut_points());
+            let energy = evaluate_structure(sequence, structure);
+            let contributions = loop_contributions(sequence, structure);
+            let position = ffgraph
+                .as_ref()
+                .map(|ffgraph| EnsemblePosition::new(ffgraph, structure, energy));
+
+            if args.json {
+                let mut output = json!({
+                    "sequence": sequence,
+                    "structure": dot_bracket,
+                    "energy": energy as f64 * 0.01,
+                    "loops": contributions
+                        .iter()
+                        .map(|contribution| json!({
+                            "type": contribution.loop_type,
+                            "closing_pair": contribution.closing_pair,
+                            "energy": contribution.energy as f64 * 0.01,
+                        }))
+                        .collect::<Vec<_>>(),
+                });
+                if !group[0].name.is_empty() {
+                    output["name"] = json!(group[0].name);
+                }
+                if let Some(position) = position {
+                    output["ensemble"] = json!({
+                        "rank": position.rank,
+                        "structures": position.structures,
+                        "lowest_energy": position.lowest_energy as f64 * 0.01,
+                        "in_graph": position.in_graph,
+                    });
+                }
+                write_stdout(|handle| writeln!(handle, "{}", output));
+                continue;
+            }
+
+            write_stdout(|handle| {
+                match position {
+                    Some(position) => writeln!(
+                        handle,
+                        "{} ({:6.2}) [rank {} of {}, {:+.2} to the lowest, {}]",
+                        dot_bracket,
+                        energy as f64 * 0.01,
+                        position.rank,
+                        position.structures,
+                        (energy - position.lowest_energy) as f64 * 0.01,
+                        if position.in_graph {
+                            "in the graph"
+                        } else {
+                            "not in the graph"
+                        }
+                    ),
+                    None => writeln!(handle, "{} ({:6.2})", dot_bracket, energy as f64 * 0.01),
+                }?;
+
+                if args.verbose {
+                    for contribution in contributions {
+                        let closing_pair = contribution
+                            .closing_pair
+                            .map_or_else(|| "-".to_string(), |(i, j)| format!("({}, {})", i, j));
+                        writeln!(
+                            handle,
+                            "{:<9} {:>12} {:6.2}",
+                            format!("{:?}", contribution.loop_type).to_lowercase(),
+                            closing_pair,
+                            contribution.energy as f64 * 0.01
+                        )?;
+                    }
+                }
+
+                Ok(())
+            });
+        }
+    }
+}
+
+// Read the records of a file of reference structures using `read`, e.g. `read_ct()`.
+fn read_references<F>(path: &Path, read: F) -> Vec<ReferenceRecord>
+where
+    F: FnOnce(BufReader<std::fs::File>) -> Result<Vec<ReferenceRecord>, accuracy::Error>,
+{
+    std::fs::File::open(path)
+        .map_err(accuracy::Error::from)
+        .and_then(|file| read(BufReader::new(file)))
+        .unwrap_or_else(|error| match error {
+            accuracy::Error::Io(_) => fail(Failure::Io, format!("{}: {}", path.display(), error)),
+            _ => fail(Failure::Usage, format!("{}: {}", path.display(), error)),
+        })
+}
+
+// Position of an evaluated structure among the structures of a fast-folding graph for the same sequence.
+struct EnsemblePosition {
+    // 1 + the number of structures of lower free energy
+    rank: usize,
+    structures: usize,
+    lowest_energy: i32,
+    in_graph: bool,
+}
+
+impl EnsemblePosition {
+    fn new(ffgraph: &RafftGraph, structure: &PairTable, energy: i32) -> Self {
+        Self {
+            rank: 1 + ffgraph.iter().filter(|node| node.energy < energy).count(),
+            structures: ffgraph.iter().count(),
+            lowest_energy: ffgraph
+                .iter()
+                .map(|node| node.energy)
+                .min()
+                .unwrap_or(energy),
+            in_graph: ffgraph
+                .iter()
+                .any(|node| *node.structure.pair_table() == *structure),
+        }
+    }
+}
+
+fn graph(args: &GraphArgs, temperature: f64) {
+    let sequence = ungapped(&args.sequence);
+    let ffgraph = construct(&sequence, &args.folding, false);
+    let probabilities =
+        boltzmann_probabilities(&ffgraph, ArrheniusModel::at_temperature(temperature).kt);
+
+    match &args.outfile {
+        Some(outfile) => write_file(outfile, |file| {
+            write_graph(&ffgraph, args.format, &probabilities, file)
+        }),
+        None => write_stdout(|handle| write_graph(&ffgraph, args.format, &probabilities, handle)),
+    }
+}
+
+fn write_graph<W: Write>(
+    ffgraph: &RafftGraph,
+    format: GraphFormat,
+    probabilities: &[f64],
+    writer: &mut W,
+) -> std::io::Result<()> {
+    match format {
+        GraphFormat::Dot => write_dot(ffgraph, Some(probabilities), writer),
+        GraphFormat::GraphMl => write_graphml(ffgraph, Some(probabilities), writer),
+        GraphFormat::Json => writeln!(writer, "{}", graph_json(ffgraph, Some(probabilities))),
+        GraphFormat::Edges => ffgraph
+            .adjacent_indices()
+            .try_for_each(|(i, j)| writeln!(writer, "{} {}", i, j)),
+    }
+}
+
+fn kinetics(args: &KineticsArgs, temperature: f64) {
+    let sequence = ungapped(&args.sequence);
+    let ffgraph = construct(&sequence, &args.folding, false);
+    let model = ArrheniusModel {
+        prefactor: args.prefactor,
+        barrier: args.barrier,
+        ..ArrheniusModel::at_temperature(temperature)
+    };
+
+    let macrostates = args
+        .macrostates
+        .then(|| Macrostates::gradient_basins(&ffgraph, &model));
+
+    if let Some(states_outfile) = &args.states_outfile {
+        write_file(states_outfile, |file| match &macrostates {
+            Some(macrostates) => macrostates.write_states(&ffgraph, file),
+            None => write_states(&ffgraph, file),
+        });
+    }
+
+    if let Some(macrostates) = macrostates {
+        if args.sparse {
+            write_stdout(|handle| macrostates.write_sparse_rates(handle));
+        } else {
+            write_stdout(|handle| macrostates.write_rate_matrix(handle));
+        }
+    } else if !args.targets.is_empty() {
+        let targets = kinetic_targets(&ffgraph, &args.targets);
+        let times = mean_first_passage_times(&ffgraph, &model, &targets);
+
+        let committors = committors(&ffgraph, &model, &targets);
+
+        write_stdout(|handle| {
+            ffgraph
+                .iter()
+                .zip(committors.into_iter().zip(times))
+                .enumerate()
+                .try_for_each(|(index, (node, (committor, time)))| {
+                    writeln!(
+                        handle,
+                        "{:>4} {} {:6.2} {:.6} {:.6e}",
+                        index + 1,
+                        dot_bracket(node, ffgraph.cut_points()),
+                        node.energy as f64 * 0.01,
+                        committor,
+                        time
+                    )
+                })
+        });
+    } else if !args.protocol.is_empty() {
+        let snapshots =
+            temperature_protocol(&ffgraph, &sequence, &model, &args.protocol, args.samples);
+        write_stdout(|handle| write_protocol(&snapshots, handle));
+    } else if let Some(time) = args.time {
+        let populations = populations(&ffgraph, &model, time);
+
+        write_stdout(|handle| {
+            ffgraph.iter().zip(populations).enumerate().try_for_each(
+                |(index, (node, population))| {
+                    writeln!(
+                        handle,
+                        "{:>4} {} {:6.2} {:.6e}",
+                        index + 1,
+                        dot_bracket(node, ffgraph.cut_points()),
+                        node.energy as f64 * 0.01,
+                        population
+                    )
+                },
+            )
+        });
+    } else if args.sparse {
+        write_stdout(|handle| write_sparse_rates(&ffgraph, &model, handle));
+    } else {
+        write_stdout(|handle| write_rate_matrix(&ffgraph, &model, handle));
+    }
+}
+
+// Print the discrepancies between `ffgraph` and the output of the reference implementation run as `command`,
+// exiting with code 1 if there are any.
+fn verify(
+    sequence: &str,
+    ffgraph: &RafftGraph,
+    command: &str,
+    folding: &FoldingArgs,
+    tolerance: f64,
+) {
+    let discrepancies = run_reference(command, sequence, folding.saved_trajectories)
+        .and_then(|output| parse_trajectories(&output))
+        .map(|reference| compare(ffgraph, &reference, tolerance))
+        .unwrap_or_else(|error| fail(Failure::Io, error));
+
+    write_stdout(|handle| {
+        discrepancies
+            .iter()
+            .try_for_each(|discrepancy| writeln!(handle, "{:?}", discrepancy))
+    });
+
+    if !discrepancies.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+// Return the indices of the structures of `ffgraph` given as `targets` in dot-bracket notation,
+// exiting with `Failure::ConstraintConflict` if none of them is part of the graph.
+fn kinetic_targets(ffgraph: &RafftGraph, targets: &[String]) -> Vec<usize> {
+    let targets: Vec<PairTable> = targets
+        .iter()
+        .map(|target| {
+            target.parse().unwrap_or_else(|error| {
+                fail(
+                    Failure::Usage,
+                    format!("invalid target structure {:?}: {}", target, error),
+                )
+            })
+        })
+        .collect();
+
+    let indices: Vec<usize> = ffgraph
+        .iter()
+        .enumerate()
+        .filter(|(_, node)| targets.contains(&*node.structure.pair_table()))
+        .map(|(index, _)| index)
+        .collect();
+
+    if indices.is_empty() {
+        fail(
+            Failure::ConstraintConflict,
+            "none of the target structures is part of the fast folding graph",
+        );
+    }
+
+    indices
+}
+
+// Write a man page of `command` and its subcommands in roff format, omitting hidden arguments.
+fn write_man<W: Write>(command: &clap::Command, writer: &mut W) -> std::io::Result<()> {
+    let name = command.get_name();
+    writeln!(
+        writer,
+        ".TH {} 1 \"\" \"{} {}\"",
+        roff(&name.to_uppercase()),
+        name,
+        command.get_version().unwrap_or_default()
+    )?;
+    writeln!(writer, ".SH NAME")?;
+    writeln!(
+        writer,
+        "{} \\- {}",
+        name,
+        roff(command.get_about().unwrap_or_default())
+    )?;
+    writeln!(writer, ".SH SYNOPSIS")?;
+    writeln!(writer, "\\fB{}\\fR <SUBCOMMAND> [OPTIONS]", name)?;
+
+    // arguments of the deprecated flat invocation are documented with `fold`
+    writeln!(writer, ".SH OPTIONS")?;
+    write_man_arguments(command, |arg| arg.is_global_set(), writer)?;
+
+    writeln!(writer, ".SH SUBCOMMANDS")?;
+    for subcommand in command.get_subcommands() {
+        writeln!(writer, ".SS {} {}", name, subcommand.get_name())?;
+        writeln!(
+            writer,
+            "{}",
+            roff(subcommand.get_about().unwrap_or_default())
+        )?;
+        write_man_arguments(subcommand, |_| true, writer)?;
+    }
+
+    if let Some(after_help) = command.get_after_help() {
+        writeln!(writer, ".SH VERSION")?;
+        writeln!(writer, "{}", roff(after_help))?;
+    }
+
+    Ok(())
+}
+
+fn write_man_arguments<W: Write, F>(
+    command: &clap::Command,
+    filter: F,
+    writer: &mut W,
+) -> std::io::Result<()>
+where
+    F: Fn(&clap::Arg) -> bool,
+{
+    for arg in command
+        .get_arguments()
+        .filter(|arg| !arg.is_hide_set() && filter(arg))
+    {
+        let value = arg
+            .get_value_names()
+            .map(|names| names.join(" "))
+            .unwrap_or_else(|| arg.get_id().to_uppercase());
+
+        let mut flags = vec![];
+        if let Some(short) = arg.get_short() {
+            flags.push(format!("\\fB\\-{}\\fR", short));
+        }
+        if let Some(long) = arg.get_long() {
+            flags.push(format!("\\fB\\-\\-{}\\fR", roff(long)));
+        }
+
+        writeln!(writer, ".TP")?;
+        if arg.is_positional() {
+            writeln!(writer, "<{}>", roff(&value))?;
+        } else if arg.is_takes_value_set() {
+            writeln!(writer, "{} <{}>", flags.join(", "), roff(&value))?;
+        } else {
+            writeln!(writer, "{}", flags.join(", "))?;
+        }
+        writeln!(writer, "{}", roff(arg.get_help().unwrap_or_default()))?;
+    }
+
+    Ok(())
+}
+
+// Escape backslashes, dashes and leading dots for roff.
+fn roff(text: &str) -> String {
+    let escaped = text.replace('\\', "\\e").replace('-', "\\-");
+
+    if escaped.starts_with('.') || escaped.starts_with('\'') {
+        format!("\\&{}", escaped)
+    } else {
+        escaped
+    }
+}
+
+// Return the genomic interval of `sequence` given by --region or --bed, checking that the lengths match.
+fn genomic_interval(sequence: &str, args: &FoldOptions) -> Option<GenomicInterval> {
+    let interval = match (&args.region, &args.bed) {
+        (Some(region), _) => region.clone(),
+        (_, Some(path)) => std::fs::File::open(path)
+            .map_err(coordinates::Error::from)
+            .and_then(|file| GenomicInterval::from_bed(BufReader::new(file)))
+            .unwrap_or_else(|error| match error {
+                coordinates::Error::Io(_) => {
+                    fail(Failure::Io, format!("{}: {}", path.display(), error))
+                }
+                _ => fail(Failure::Usage, format!("{}: {}", path.display(), error)),
+            }),
+        (None, None) => return None,
+    };
+
+    let length = EncodedSequence::new(sequence)
+        .unwrap_or_else(|error| fail(Failure::InvalidSequence, error))
+        .len();
+    if interval.len() != length {
+        fail(
+            Failure::Usage,
+            format!(
+                "genomic interval {} has length {}, but the sequence has length {}",
+                interval,
+                interval.len(),
+                length
+            ),
+        );
+    }
+
+    Some(interval)
+}
+
+fn fold(sequence: &str, args: &FoldOptions, temperature: f64) -> RafftGraph {
+    let interval = genomic_interval(sequence, args);
+
+    if args.candidates {
+        print_candidates(sequence, &args.folding, interval.as_ref());
+        std::process::exit(0);
+    }
+
+    let ffgraph = construct(sequence, &args.folding, args.stream);
+    let cut_points = ffgraph.cut_points().to_vec();
+
+    if let Some(stats_outfile) = &args.stats_outfile {
+        write_file(stats_outfile, |file| ffgraph.write_stats(file));
+    }
+
+    if args.folding.track_target.is_some() {
+        match ffgraph.target_reached() {
+            Some(depth) => note(format!("target reached at depth {}", depth)),
+            None => note(format!(
+                "target not reached (minimum base pair distance {})",
+                ffgraph
+                    .stats()
+                    .iter()
+                    .filter_map(|stats| stats.target_distance)
+                    .min()
+                    .map_or_else(|| "NA".to_string(), |distance| distance.to_string())
+            )),
+        }
+    }
+
+    if let Some(command) = &args.verify {
+        verify(sequence, &ffgraph, command, &args.folding, args.tolerance);
+        return ffgraph;
+    }
+
+    if !args.benchmark {
+        if args.stream {
+            // structures have already been printed during construction
+        } else if args.json {
+            let best = ffgraph.iter().min_by_key(|node| node.energy).unwrap();
+            let probabilities =
+                boltzmann_probabilities(&ffgraph, ArrheniusModel::at_temperature(temperature).kt);
+            // pairs are reported in genomic coordinates if a region is given
+            let pair = |pair: (usize, usize)| match &interval {
+                Some(interval) => json!(interval.pair(pair)),
+                None => json!(pair),
+            };
+            let mut output = json!({
+                "sequence": sequence,
+                "structures": ffgraph
+                    .iter()
+                    .zip(&probabilities)
+                    .map(|(node, probability)| json!({
+                        "id": node.id.to_string(),
+                        "depth": node.depth,
+                        "structure": dot_bracket(node, &cut_points),
+                        "elements": node.structure.pair_table().element_string(),
+                        "energy": node.energy as f64 * 0.01,
+                        "population": probability,
+                    }))
+                    .collect::<Vec<_>>(),
+                "edges": ffgraph.adjacent_indices().collect::<Vec<_>>(),
+                "best": {
+                    "structure": dot_bracket(best, &cut_points),
+                    "elements": best.structure.pair_table().element_string(),
+                    "energy": best.energy as f64 * 0.01,
+                    "formation_depths": ffgraph
+                        .pair_formation_depths(best.id)
+                        .iter()
+                        .map(|&(formed, depth)| json!({ "pair": pair(formed), "depth": depth }))
+                        .collect::<Vec<_>>(),
+                    "loops": loop_contributions(sequence, &best.structure.pair_table())
+                        .iter()
+                        .map(|contribution| json!({
+                            "type": contribution.loop_type,
+                            "closing_pair": contribution.closing_pair.map(pair),
+                            "energy": contribution.energy as f64 * 0.01,
+                        }))
+                        .collect::<Vec<_>>(),
+                },
+            });
+            if let Some(interval) = &interval {
+                output["region"] = json!(interval.to_string());
             }
+            write_stdout(|handle| writeln!(handle, "{}", output));
+        } else if args.shapes {
+            let census = shape_census(&ffgraph, args.shape_level);
+            write_stdout(|handle| write_shape_census_tsv(&census, handle));
+        } else if args.relax {
+            write_stdout(|handle| {
+                ffgraph
+                    .iter()
+                    .zip(ffgraph.gradient_walks())
+                    .try_for_each(|(node, walk)| {
+                        writeln!(
+                            handle,
+                            "[{}] {} {:.2} {} {:.2}",
+                            node.depth,
+                            node.structure,
+                            node.energy as f64 * 0.01,
+                            walk.relaxed.to_string(),
+                            walk.relaxed_energy as f64 * 0.01
+                        )
+                    })
+            });
+        } else if !args.compat {
+            let color = match args.color {
+                ColorChoice::Always => true,
+                ColorChoice::Never => false,
+                ColorChoice::Auto => std::io::stdout().is_terminal(),
+            };
+
+            // the table is for terminals, pipes and scripts get the plain lines of previous releases
+            if color {
+                write_stdout(|handle| {
+                    write!(
+                        handle,
+                        "{}",
+                        TrajectoryTable::new(&ffgraph, sequence).color(true)
+                    )
+                });
+            } else {
+                write_stdout(|handle| {
+                    ffgraph.iter().try_for_each(|node| {
+                        writeln!(
+                            handle,
+                            "[{}] {} {:.2}",
+                            node.depth,
+                            dot_bracket(node, &cut_points),
+                            node.energy as f64 * 0.01
+                        )
+                    })
+                });
+            }
+        } else {
+            write_stdout(|handle| {
+                for (depth, nodes) in ffgraph.compat_layers() {
+                    writeln!(handle, "# ---------{}----------", depth)?;
+                    for node in nodes {
+                        writeln!(
+                            handle,
+                            "{} {:.2}",
+                            dot_bracket(node, &cut_points),
+                            node.energy as f64 * 0.01
+                        )?;
+                    }
+                }
+                Ok(())
+            });
+        }
+
+        if let Some(outfile) = &args.outfile {
+            write_file(outfile, |file| {
+                ffgraph
+                    .adjacent_indices()
+                    .try_for_each(|(i, j)| writeln!(file, "{} {}", i, j))
+            });
+        }
+
+        if let Some(forna_outfile) = &args.forna_outfile {
+            let best = ffgraph.iter().min_by_key(|node| node.energy).unwrap();
+            let forna = forna_json(&ffgraph, sequence, "rafft", best.id).unwrap();
+
+            write_file(forna_outfile, |file| write!(file, "{}", forna));
+        }
+
+        if let Some(ct_outfile) = &args.ct_outfile {
+            let best = ffgraph.iter().min_by_key(|node| node.energy).unwrap();
+
+            write_file(ct_outfile, |file| {
+                write_ct(&ffgraph, sequence, "rafft", best.id, file)
+            });
+        }
+
+        if let Some(persistence_outfile) = &args.persistence_outfile {
+            let persistence = pair_persistence(&ffgraph);
+
+            write_file(persistence_outfile, |file| match &interval {
+                Some(interval) => persistence.write_genomic_tsv(interval, file),
+                None => persistence.write_tsv(file),
+            });
+        }
+
+        if let (Some(bed_outfile), Some(interval)) = (&args.bed_outfile, &interval) {
+            let best = ffgraph.iter().min_by_key(|node| node.energy).unwrap();
+
+            write_file(bed_outfile, |file| {
+                write_helices_bed(&best.structure.pair_table(), interval, "helix", file)
+            });
+        }
+
+        if let (Some(bedgraph_outfile), Some(interval)) = (&args.bedgraph_outfile, &interval) {
+            write_file(bedgraph_outfile, |file| {
+                write_pairing_bedgraph(&ffgraph, interval, file)
+            });
+        }
+
+        if let Some(profiles_outfile) = &args.profiles_outfile {
+            write_file(profiles_outfile, |file| {
+                write_profiles_csv(&trajectories(&ffgraph), file)
+            });
+        }
+
+        if let Some(shapes_outfile) = &args.shapes_outfile {
+            write_file(shapes_outfile, |file| {
+                shape_groups(&ffgraph, args.shape_level).write_tsv(&ffgraph, file)
+            });
+        }
+
+        if let Some(elements_outfile) = &args.elements_outfile {
+            let classifier = element_classifier(&args.elements);
+            let annotations = classifier.annotate(&ffgraph);
+
+            write_file(elements_outfile, |file| {
+                classifier.write_tsv(&ffgraph, &annotations, file)
+            });
+        }
+
+        if let Some(r2dt_outfile) = &args.r2dt_outfile {
+            let payload = r2dt_payload(sequence, "rafft");
+
+            write_file(r2dt_outfile, |file| write!(file, "{}", payload));
+        }
+
+        #[cfg(feature = "drawing")]
+        if let Some(svg_outfile) = &args.svg_outfile {
+            let best = ffgraph.iter().min_by_key(|node| node.energy).unwrap();
+            let title = format!("{:.2} kcal/mol", best.energy as f64 * 0.01);
+            let structure = best.structure.pair_table();
+            let formation_depths = ffgraph.pair_formation_depths(best.id);
+            let drawing = match args.svg_layout {
+                Some(layout) => {
+                    let coordinates = plot_coordinates(&structure, layout);
+                    if coordinates.len() != structure.len() {
+                        fail(
+                            Failure::Internal,
+                            format!("ViennaRNA failed to compute the {:?} layout", layout),
+                        );
+                    }
+
+                    svg_with_layout(
+                        sequence,
+                        &structure,
+                        &coordinates,
+                        &formation_depths,
+                        Some(&title),
+                    )
+                }
+                None => {
+                    svg_with_formation_depths(sequence, &structure, &formation_depths, Some(&title))
+                }
+            };
+
+            write_file(svg_outfile, |file| file.write_all(drawing.as_bytes()));
+        }
+
+        #[cfg(feature = "drawing")]
+        if let Some(report_outfile) = &args.report_outfile {
+            write_file(report_outfile, |file| {
+                file.write_all(html_report(&ffgraph, sequence).as_bytes())
+            });
         }
     } else {
         let mut trajectories: Vec<_> = ffgraph.iter().collect();
 
         trajectories.sort_by_key(|node| node.energy);
 
-        for node in &trajectories[..args.saved_trajectories.min(trajectories.len())] {
-            println!(
-                "{} {} {} {:.1} {}",
-                args.sequence,
-                args.sequence.len(),
-                node.structure.to_string(),
-                node.energy as f64 * 0.01,
-                node.structure.pairs()
-            );
-        }
+        write_stdout(|handle| {
+            trajectories[..args.folding.saved_trajectories.min(trajectories.len())]
+                .iter()
+                .try_for_each(|node| {
+                    writeln!(
+                        handle,
+                        "{} {} {} {:.1} {}",
+                        sequence,
+                        sequence.len(),
+                        node.structure,
+                        node.energy as f64 * 0.01,
+                        node.structure.pairs()
+                    )
+                })
+        });
     }
+
+    ffgraph
 }
@@ -7,7 +7,7 @@ pub(crate) fn register(_py: Python, m: &PyModule) -> PyResult<()> {
     Ok(())
 }
 
-// TODO: see if VCompound in vienna.rs is safe to send before removing `unsendable`
+// `RafftGraph` is `Send`, but not `Sync` since it caches stack candidates in a `RefCell`
 #[pyclass(module = "rafft", unsendable)]
 struct FastFoldingGraph {
     inner: RafftGraph,
@@ -24,7 +24,8 @@ impl FastFoldingGraph {
         gc = "3.0",
         gu = "1.0",
         min_unpaired = "3",
-        min_loop_energy = "0.0"
+        min_loop_energy = "0.0",
+        compact_structures = "false"
     )]
     #[allow(clippy::too_many_arguments)]
     fn new(
@@ -37,6 +38,7 @@ impl FastFoldingGraph {
         gu: f64,
         min_unpaired: usize,
         min_loop_energy: f64,
+        compact_structures: bool,
     ) -> Self {
         let config = RafftConfig::new()
             .maximum_trajectories(saved_trajectories)
@@ -44,7 +46,8 @@ impl FastFoldingGraph {
             .minimum_unpaired_in_hairpins(min_unpaired)
             .minimum_loop_energy(min_loop_energy)
             .maximum_branches(number_of_branches)
-            .positional_lags(number_of_lags);
+            .positional_lags(number_of_lags)
+            .compact_structures(compact_structures);
 
         FastFoldingGraph {
             inner: config.folding_graph(sequence),
@@ -74,6 +77,17 @@ impl FastFoldingGraph {
         Ok(self.inner.transition_rates(beta))
     }
 
+    fn adjacency_matrix(&self) -> PyResult<(Vec<f64>, Vec<usize>, Vec<usize>)> {
+        let matrix = self.inner.adjacency_matrix();
+        Ok((matrix.values, matrix.indices, matrix.indptr))
+    }
+
+    #[args(temperature = "37.0")]
+    fn rate_matrix(&self, temperature: f64) -> PyResult<(Vec<f64>, Vec<usize>, Vec<usize>)> {
+        let matrix = self.inner.rate_matrix(temperature);
+        Ok((matrix.values, matrix.indices, matrix.indptr))
+    }
+
     fn directed_edges(&self) -> PyResult<(Vec<usize>, Vec<usize>)> {
         let (is, js): (Vec<_>, Vec<_>) = self.inner.adjacent_indices().unzip();
         Ok((is, js))
@@ -0,0 +1,53 @@
+//! This module provides [`CancelToken`], a flag to abort long-running computations cooperatively from another thread,
+//! e.g. in servers or graphical applications.
+//!
+//! Tokens are checked at safe points only: the construction of fast folding graphs stops between structures
+//! and keeps the structures found so far (see [`RafftConfig::cancel_token()`](crate::fast_folding::RafftConfig::cancel_token())),
+//! while kinetics computations return [`Cancelled`].
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use thiserror::Error;
+
+/// A shared flag signalling that work should be aborted.
+/// Clones refer to the same flag, such that a clone can be cancelled from another thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Create a new token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation of all work observing this token (or any of its clones).
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Return whether cancellation was requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Error returned by computations that were aborted using a [`CancelToken`].
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("the computation was cancelled")]
+pub struct Cancelled;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_token() {
+        let token = CancelToken::new();
+        let clone = token.clone();
+        assert!(!token.is_cancelled());
+
+        std::thread::spawn(move || clone.cancel()).join().unwrap();
+        assert!(token.is_cancelled());
+        assert!(!CancelToken::new().is_cancelled());
+    }
+}
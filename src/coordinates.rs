@@ -0,0 +1,415 @@
+//! This module maps positions of folded sequences to genomic coordinates, such that predicted structures
+//! can be shown in genome browsers alongside other annotations.
+//!
+//! The folded sequence is described by a [`GenomicInterval`], parsed from a region like `chr1:1001-1120:-`
+//! or read from the first record of a BED file (see [`GenomicInterval::from_bed()`]).
+//! Sequences of the reverse strand are the reverse complement of the genome, so their first nucleotide
+//! maps to the last position of the interval.
+//!
+//! Structures are written as BED12 with one record per helix and a block per strand of the helix
+//! (see [`write_helices_bed()`]), and the fraction of trajectories pairing each position as bedGraph
+//! (see [`write_pairing_bedgraph()`]). Pairs of other outputs are mapped using [`GenomicInterval::pair()`],
+//! e.g. by [`PairPersistence::write_genomic_tsv()`](crate::analysis::PairPersistence::write_genomic_tsv).
+//! Formats defined on the positions of the sequence, i.e. dot-bracket structures, connectivity tables and
+//! the forna and R2DT exports, are not mapped.
+
+use crate::encoding::{PairTable, SeqPos1};
+use crate::folding_graph::RafftGraph;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Error type representing errors that may arise while reading genomic intervals.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// A region could not be parsed.
+    #[error("invalid region {0:?}: {1}")]
+    InvalidRegion(String, String),
+    /// A BED record could not be parsed (`1`-indexed line number).
+    #[error("line {0} of the BED file is invalid: {1}")]
+    InvalidBed(usize, String),
+    /// The BED file does not contain any record.
+    #[error("the BED file does not contain any record")]
+    EmptyBed,
+    /// The BED file could not be read.
+    #[error("failed to read the BED file: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Strand of the genome a folded sequence was read from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strand {
+    /// `+`, or `.` for unstranded intervals
+    #[default]
+    Forward,
+    /// `-`
+    Reverse,
+}
+
+impl FromStr for Strand {
+    type Err = String;
+
+    fn from_str(strand: &str) -> Result<Self, Self::Err> {
+        match strand {
+            "+" | "." => Ok(Self::Forward),
+            "-" => Ok(Self::Reverse),
+            _ => Err(format!("unknown strand {:?} (expected +, - or .)", strand)),
+        }
+    }
+}
+
+impl fmt::Display for Strand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Forward => write!(f, "+"),
+            Self::Reverse => write!(f, "-"),
+        }
+    }
+}
+
+/// The genomic interval a folded sequence was read from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GenomicInterval {
+    /// name of the chromosome or contig
+    pub chrom: String,
+    /// `0`-based start of the interval, as in BED
+    pub start: u64,
+    /// end of the interval (exclusive)
+    pub end: u64,
+    /// strand the sequence was read from
+    pub strand: Strand,
+}
+
+impl GenomicInterval {
+    /// Return the number of nucleotides of the interval.
+    pub fn len(&self) -> usize {
+        (self.end - self.start) as usize
+    }
+
+    /// Return `true` if the interval does not contain any nucleotide.
+    pub fn is_empty(&self) -> bool {
+        self.end == self.start
+    }
+
+    /// Return the `1`-based genomic position of the `1`-indexed position `i` of the folded sequence.
+    pub fn position(&self, i: usize) -> u64 {
+        match self.strand {
+            Strand::Forward => self.start + i as u64,
+            Strand::Reverse => self.end + 1 - i as u64,
+        }
+    }
+
+    /// Return the `1`-based genomic positions of the pair `(i, j)` of the folded sequence in ascending order.
+    pub fn pair(&self, (i, j): (usize, usize)) -> (u64, u64) {
+        let (i, j) = (self.position(i), self.position(j));
+        (i.min(j), i.max(j))
+    }
+
+    /// Read the interval of the first record of a BED file, i.e. its chromosome, start, end and optional strand
+    /// in the first, second, third and sixth column. Empty lines, comments and `track` or `browser` lines
+    /// are skipped.
+    pub fn from_bed<R: BufRead>(reader: R) -> Result<Self, Error> {
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let invalid = |message: &str| Error::InvalidBed(number + 1, message.to_string());
+
+            if line.trim().is_empty()
+                || ["#", "track", "browser"]
+                    .iter()
+                    .any(|prefix| line.starts_with(prefix))
+            {
+                continue;
+            }
+
+            let columns: Vec<&str> = line.split('\t').map(str::trim).collect();
+            if columns.len() < 3 {
+                return Err(invalid("expected at least 3 columns"));
+            }
+
+            let start: u64 = columns[1].parse().map_err(|_| invalid("invalid start"))?;
+            let end: u64 = columns[2].parse().map_err(|_| invalid("invalid end"))?;
+            if end < start {
+                return Err(invalid("end precedes start"));
+            }
+            let strand = match columns.get(5) {
+                Some(strand) => strand.parse().map_err(|error: String| invalid(&error))?,
+                None => Strand::Forward,
+            };
+
+            return Ok(Self {
+                chrom: columns[0].to_string(),
+                start,
+                end,
+                strand,
+            });
+        }
+
+        Err(Error::EmptyBed)
+    }
+}
+
+impl FromStr for GenomicInterval {
+    type Err = Error;
+
+    /// Parse a region `CHROM:START-END[:STRAND]` with `1`-based inclusive positions, as used by genome browsers
+    /// and samtools, e.g. `chr1:1001-1120:-`. The strand defaults to `+`.
+    fn from_str(region: &str) -> Result<Self, Self::Err> {
+        let invalid = |message: &str| Error::InvalidRegion(region.to_string(), message.to_string());
+
+        let (rest, strand) = match region.rsplit_once(':') {
+            Some((rest, strand)) if ["+", "-", "."].contains(&strand) => (
+                rest,
+                strand.parse().map_err(|error: String| invalid(&error))?,
+            ),
+            _ => (region, Strand::Forward),
+        };
+        let (chrom, range) = rest
+            .rsplit_once(':')
+            .ok_or_else(|| invalid("expected CHROM:START-END[:STRAND]"))?;
+        let (start, end) = range
+            .split_once('-')
+            .ok_or_else(|| invalid("expected a range START-END"))?;
+
+        let start: u64 = start
+            .replace(',', "")
+            .parse()
+            .map_err(|_| invalid("invalid start"))?;
+        let end: u64 = end
+            .replace(',', "")
+            .parse()
+            .map_err(|_| invalid("invalid end"))?;
+        if chrom.is_empty() || start == 0 || end < start {
+            return Err(invalid("expected a chromosome and 1 <= START <= END"));
+        }
+
+        Ok(Self {
+            chrom: chrom.to_string(),
+            start: start - 1,
+            end,
+            strand,
+        })
+    }
+}
+
+impl fmt::Display for GenomicInterval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}-{}:{}",
+            self.chrom,
+            self.start + 1,
+            self.end,
+            self.strand
+        )
+    }
+}
+
+/// Write the helices of `structure`, i.e. maximal runs of stacked pairs, as BED12 records in genomic coordinates,
+/// ordered by their start. Each record is named `name` followed by the `1`-based index of the helix in `5'` to `3'`
+/// order of the folded sequence and consists of two blocks, one per strand of the helix.
+/// Panics if `structure` does not match the length of `interval`.
+pub fn write_helices_bed<W: Write>(
+    structure: &PairTable,
+    interval: &GenomicInterval,
+    name: &str,
+    writer: &mut W,
+) -> io::Result<()> {
+    assert_eq!(structure.len(), interval.len());
+
+    let mut records: Vec<(u64, String)> = structure
+        .paired()
+        .filter(|&(i, j)| i == 1 || structure.partner(SeqPos1(i - 1)) != Some(SeqPos1(j + 1)))
+        .enumerate()
+        .map(|(index, (i, j))| {
+            let length = (0..)
+                .take_while(|&k| {
+                    i + k < j - k && structure.partner(SeqPos1(i + k)) == Some(SeqPos1(j - k))
+                })
+                .count();
+            // 0-based half-open genomic ranges of both strands of the helix
+            let (first, _) = interval.pair((i, i + length - 1));
+            let (second, _) = interval.pair((j - length + 1, j));
+            let (first, second) = (first.min(second) - 1, first.max(second) - 1);
+            let (start, end) = (first, second + length as u64);
+
+            (
+                start,
+                format!(
+                    "{}\t{}\t{}\t{}{}\t0\t{}\t{}\t{}\t0\t2\t{},{},\t0,{},",
+                    interval.chrom,
+                    start,
+                    end,
+                    name,
+                    index + 1,
+                    interval.strand,
+                    start,
+                    end,
+                    length,
+                    length,
+                    second - start
+                ),
+            )
+        })
+        .collect();
+    records.sort_by_key(|&(start, _)| start);
+
+    for (_, record) in records {
+        writeln!(writer, "{}", record)?;
+    }
+
+    Ok(())
+}
+
+/// Write the fraction of trajectories of a constructed `graph` whose final structure pairs each position
+/// as bedGraph in genomic coordinates, one line per position in ascending order.
+/// Panics if the sequence of `graph` does not match the length of `interval`.
+pub fn write_pairing_bedgraph<W: Write>(
+    graph: &RafftGraph,
+    interval: &GenomicInterval,
+    writer: &mut W,
+) -> io::Result<()> {
+    let leaves = graph.leaves();
+    let mut paired = vec![0; interval.len()];
+
+    for leaf in &leaves {
+        if let Some(node) = graph.node(*leaf) {
+            let structure = node.structure.pair_table();
+            assert_eq!(structure.len(), interval.len());
+
+            for (i, j) in structure.paired() {
+                paired[i - 1] += 1;
+                paired[j - 1] += 1;
+            }
+        }
+    }
+
+    let mut profile: Vec<(u64, f64)> = paired
+        .into_iter()
+        .enumerate()
+        .map(|(i, count)| {
+            (
+                interval.position(i + 1),
+                count as f64 / leaves.len().max(1) as f64,
+            )
+        })
+        .collect();
+    profile.sort_by_key(|&(position, _)| position);
+
+    for (position, fraction) in profile {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{:.4}",
+            interval.chrom,
+            position - 1,
+            position,
+            fraction
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_genomic_interval() {
+        let forward: GenomicInterval = "chr1:1,001-1012".parse().unwrap();
+        assert_eq!(forward.start, 1000);
+        assert_eq!(forward.len(), 12);
+        assert_eq!(forward.strand, Strand::Forward);
+        assert_eq!(forward.position(1), 1001);
+        assert_eq!(forward.pair((2, 11)), (1002, 1011));
+        assert_eq!(forward.to_string(), "chr1:1001-1012:+");
+
+        let reverse: GenomicInterval = "HLA-A*01:01:01:01:1-12:-".parse().unwrap();
+        assert_eq!(reverse.chrom, "HLA-A*01:01:01:01");
+        assert_eq!(reverse.position(1), 12);
+        assert_eq!(reverse.position(12), 1);
+        assert_eq!(reverse.pair((2, 11)), (2, 11));
+        assert_eq!(reverse.pair((1, 4)), (9, 12));
+
+        for region in ["chr1", "chr1:0-10", "chr1:10-5", ":1-5", "chr1:a-5:+"] {
+            assert!(region.parse::<GenomicInterval>().is_err(), "{}", region);
+        }
+
+        let bed = "track name=rna\n# comment\nchr2\t99\t111\tmiR\t0\t-\n";
+        let interval = GenomicInterval::from_bed(bed.as_bytes()).unwrap();
+        assert_eq!(interval.to_string(), "chr2:100-111:-");
+        assert_eq!(
+            GenomicInterval::from_bed("chr2\t99\t111\n".as_bytes())
+                .unwrap()
+                .strand,
+            Strand::Forward
+        );
+        assert!(matches!(
+            GenomicInterval::from_bed("\nchr2\t99\n".as_bytes()),
+            Err(Error::InvalidBed(2, _))
+        ));
+        assert!(matches!(
+            GenomicInterval::from_bed("chr2\t99\t111\tx\t0\tx\n".as_bytes()),
+            Err(Error::InvalidBed(1, _))
+        ));
+        assert!(matches!(
+            GenomicInterval::from_bed("# nothing\n".as_bytes()),
+            Err(Error::EmptyBed)
+        ));
+    }
+
+    #[test]
+    fn test_helices_bed() {
+        let structure: PairTable = "((((....))))((...))".parse().unwrap();
+
+        let mut bed = vec![];
+        let forward: GenomicInterval = "chr1:101-119".parse().unwrap();
+        write_helices_bed(&structure, &forward, "helix", &mut bed).unwrap();
+        assert_eq!(
+            String::from_utf8(bed).unwrap(),
+            "chr1\t100\t112\thelix1\t0\t+\t100\t112\t0\t2\t4,4,\t0,8,\n\
+             chr1\t112\t119\thelix2\t0\t+\t112\t119\t0\t2\t2,2,\t0,5,\n"
+        );
+
+        let mut bed = vec![];
+        let reverse: GenomicInterval = "chr1:101-119:-".parse().unwrap();
+        write_helices_bed(&structure, &reverse, "helix", &mut bed).unwrap();
+        assert_eq!(
+            String::from_utf8(bed).unwrap(),
+            "chr1\t100\t107\thelix2\t0\t-\t100\t107\t0\t2\t2,2,\t0,5,\n\
+             chr1\t107\t119\thelix1\t0\t-\t107\t119\t0\t2\t4,4,\t0,8,\n"
+        );
+    }
+
+    #[test]
+    fn test_pairing_bedgraph() {
+        let sequence = "GGGGAAAACCCC";
+        let mut graph = crate::fast_folding::RafftConfig::new().folding_graph(sequence);
+        graph.construct_trajectories();
+
+        let interval: GenomicInterval = "chrX:11-22:-".parse().unwrap();
+        let mut bedgraph = vec![];
+        write_pairing_bedgraph(&graph, &interval, &mut bedgraph).unwrap();
+        let bedgraph = String::from_utf8(bedgraph).unwrap();
+        let lines: Vec<Vec<&str>> = bedgraph
+            .lines()
+            .map(|line| line.split('\t').collect())
+            .collect();
+
+        assert_eq!(lines.len(), sequence.len());
+        assert_eq!(lines[0][..3], ["chrX", "10", "11"]);
+        assert_eq!(lines[11][..3], ["chrX", "21", "22"]);
+
+        // the last position of the sequence is the first of the reverse strand
+        let leaves = graph.leaves();
+        let paired = leaves
+            .iter()
+            .filter_map(|&leaf| graph.node(leaf))
+            .filter(|node| node.structure.pair_table().partner(SeqPos1(12)).is_some())
+            .count();
+        assert_eq!(
+            lines[0][3],
+            format!("{:.4}", paired as f64 / leaves.len() as f64)
+        );
+    }
+}
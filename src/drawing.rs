@@ -0,0 +1,286 @@
+//! This module provides drawings of secondary structures as SVG images, available using the `drawing` feature.
+//! Nucleotides are placed on a circle (5' end at the top, clockwise) and base pairs are drawn as
+//! arcs bending towards the center, which never intersect for structures without crossing pairs.
+//! Alternatively, nucleotides are placed at given coordinates with pairs drawn as straight lines (see [`svg_with_layout()`]),
+//! e.g. the layouts of ViennaRNA to match `RNAfold` figures.
+
+use crate::encoding::PairTable;
+use std::f64::consts::PI;
+use std::fmt::Write;
+
+// radius of the circle in user units, the image is padded by `MARGIN`
+const RADIUS: f64 = 200.0;
+const MARGIN: f64 = 30.0;
+
+/// Return the coordinates of `length` nucleotides evenly spaced on the unit circle around `(0, 0)`,
+/// starting at the top and proceeding clockwise. A gap of one position separates the 5' and 3' ends.
+pub fn circular_layout(length: usize) -> Vec<(f64, f64)> {
+    let step = 2.0 * PI / (length + 1) as f64;
+
+    (0..length)
+        .map(|i| {
+            let angle = (i as f64 + 0.5) * step;
+            (angle.sin(), -angle.cos())
+        })
+        .collect()
+}
+
+// colors of pairs formed at successive depths, repeated for deeper trajectories
+const PALETTE: [&str; 6] = [
+    "steelblue",
+    "darkorange",
+    "seagreen",
+    "crimson",
+    "mediumpurple",
+    "sienna",
+];
+
+/// Draw `structure` of `sequence` as an SVG image using the [`circular_layout()`].
+/// An optional `title`, e.g. the free energy, is written below the structure.
+/// Panics if `structure` does not match the length of `sequence`.
+pub fn svg(sequence: &str, structure: &PairTable, title: Option<&str>) -> String {
+    draw(sequence, structure, None, |_, _| PALETTE[0], title)
+}
+
+/// Draw `structure` of `sequence` like [`svg()`], coloring each pair by the depth at which it was formed,
+/// e.g. as returned by [`RafftGraph::pair_formation_depths()`](crate::folding_graph::RafftGraph::pair_formation_depths).
+/// Pairs without a depth are drawn in gray.
+pub fn svg_with_formation_depths(
+    sequence: &str,
+    structure: &PairTable,
+    formation_depths: &[((usize, usize), usize)],
+    title: Option<&str>,
+) -> String {
+    draw(
+        sequence,
+        structure,
+        None,
+        depth_color(formation_depths),
+        title,
+    )
+}
+
+/// Draw `structure` of `sequence` like [`svg_with_formation_depths()`], placing the nucleotides at `coordinates`
+/// instead of on a circle, e.g. as returned by [`plot_coordinates()`](crate::plot_coordinates) to share the layout
+/// of `RNAfold` figures. Coordinates are scaled to fit the image and pairs are drawn as straight lines.
+/// Panics if `structure` or `coordinates` do not match the length of `sequence`.
+pub fn svg_with_layout(
+    sequence: &str,
+    structure: &PairTable,
+    coordinates: &[(f64, f64)],
+    formation_depths: &[((usize, usize), usize)],
+    title: Option<&str>,
+) -> String {
+    draw(
+        sequence,
+        structure,
+        Some(coordinates),
+        depth_color(formation_depths),
+        title,
+    )
+}
+
+// Color pairs by the depth at which they were formed, or gray if it is unknown.
+fn depth_color(
+    formation_depths: &[((usize, usize), usize)],
+) -> impl Fn(usize, usize) -> &'static str + '_ {
+    |i: usize, j: usize| {
+        formation_depths
+            .iter()
+            .find(|(pair, _)| *pair == (i, j))
+            .map_or("gray", |(_, depth)| {
+                PALETTE[depth.saturating_sub(1) % PALETTE.len()]
+            })
+    }
+}
+
+// Scale `coordinates` uniformly into the unit square around `(0, 0)`, i.e. the bounding box of the circle.
+fn fitted(coordinates: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    let (min_x, max_x, min_y, max_y) = coordinates.iter().fold(
+        (
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+        ),
+        |(min_x, max_x, min_y, max_y), &(x, y)| {
+            (min_x.min(x), max_x.max(x), min_y.min(y), max_y.max(y))
+        },
+    );
+    let scale = (max_x - min_x).max(max_y - min_y).max(f64::EPSILON) / 2.0;
+
+    coordinates
+        .iter()
+        .map(|(x, y)| {
+            (
+                (x - (min_x + max_x) / 2.0) / scale,
+                (y - (min_y + max_y) / 2.0) / scale,
+            )
+        })
+        .collect()
+}
+
+fn draw<'a, F>(
+    sequence: &str,
+    structure: &PairTable,
+    layout: Option<&[(f64, f64)]>,
+    pair_color: F,
+    title: Option<&str>,
+) -> String
+where
+    F: Fn(usize, usize) -> &'a str,
+{
+    let nucleotides: Vec<char> = sequence.chars().filter(|&c| c != '&').collect();
+    assert_eq!(nucleotides.len(), structure.len());
+
+    let center = RADIUS + MARGIN;
+    let size = 2.0 * center;
+    let point = |(x, y): (f64, f64)| (center + RADIUS * x, center + RADIUS * y);
+    let coordinates: Vec<(f64, f64)> = match layout {
+        Some(layout) => {
+            assert_eq!(layout.len(), nucleotides.len());
+            fitted(layout)
+        }
+        None => circular_layout(nucleotides.len()),
+    }
+    .into_iter()
+    .map(point)
+    .collect();
+
+    let mut svg = String::new();
+    // writing to a String never fails
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{size:.0}" height="{height:.0}" viewBox="0 0 {size:.0} {height:.0}" font-family="monospace" font-size="10">"#,
+        height = size + if title.is_some() { MARGIN } else { 0.0 }
+    );
+
+    // backbone
+    let backbone = coordinates
+        .iter()
+        .map(|(x, y)| format!("{:.2},{:.2}", x, y))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let _ = writeln!(
+        svg,
+        r#"<polyline points="{}" fill="none" stroke="gray" stroke-width="1"/>"#,
+        backbone
+    );
+
+    // base pairs as quadratic curves with their control point between the pair's midpoint and the center,
+    // or as straight lines for other layouts
+    for (i, j) in structure.paired() {
+        let ((xi, yi), (xj, yj)) = (coordinates[i - 1], coordinates[j - 1]);
+        let (cx, cy) = if layout.is_some() {
+            ((xi + xj) / 2.0, (yi + yj) / 2.0)
+        } else {
+            (
+                (xi + xj) / 4.0 + center / 2.0,
+                (yi + yj) / 4.0 + center / 2.0,
+            )
+        };
+        let _ = writeln!(
+            svg,
+            r#"<path d="M {:.2} {:.2} Q {:.2} {:.2} {:.2} {:.2}" fill="none" stroke="{}" stroke-width="1.5"/>"#,
+            xi,
+            yi,
+            cx,
+            cy,
+            xj,
+            yj,
+            pair_color(i, j)
+        );
+    }
+
+    // nucleotides
+    for (&(x, y), c) in coordinates.iter().zip(&nucleotides) {
+        let _ = writeln!(
+            svg,
+            r#"<circle cx="{:.2}" cy="{:.2}" r="6" fill="white" stroke="black" stroke-width="0.5"/><text x="{:.2}" y="{:.2}" text-anchor="middle" dominant-baseline="central">{}</text>"#,
+            x, y, x, y, c
+        );
+    }
+
+    if let Some((x, y)) = coordinates.first() {
+        let _ = writeln!(
+            svg,
+            r#"<text x="{:.2}" y="{:.2}" text-anchor="end">5'</text>"#,
+            x - 10.0,
+            y - 10.0
+        );
+    }
+
+    if let Some(title) = title {
+        let _ = writeln!(
+            svg,
+            r#"<text x="{:.2}" y="{:.2}" text-anchor="middle">{}</text>"#,
+            center,
+            size,
+            escape(title)
+        );
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_svg() {
+        let layout = circular_layout(3);
+        assert_eq!(layout.len(), 3);
+        assert!(layout
+            .iter()
+            .all(|(x, y)| ((x * x + y * y).sqrt() - 1.0).abs() < 1e-12));
+        // the 5' end is right of the top, the 3' end left of it
+        assert!(layout[0].0 > 0.0 && layout[2].0 < 0.0);
+
+        let pt: PairTable = "((((....))))".parse().unwrap();
+        let drawing = svg("GGGGAAAACCCC", &pt, Some("-5.00 kcal/mol <best>"));
+
+        assert!(drawing.starts_with("<svg"));
+        assert!(drawing.trim_end().ends_with("</svg>"));
+        assert_eq!(drawing.matches("<path").count(), 4);
+        assert_eq!(drawing.matches("<circle").count(), 12);
+        assert!(drawing.contains("&lt;best&gt;"));
+
+        let depths = [((1, 12), 2), ((2, 11), 2), ((3, 10), 1)];
+        let drawing = svg_with_formation_depths("GGGGAAAACCCC", &pt, &depths, None);
+        assert_eq!(drawing.matches(PALETTE[1]).count(), 2);
+        assert_eq!(drawing.matches(PALETTE[0]).count(), 1);
+        assert_eq!(
+            drawing
+                .matches(r#"stroke="gray" stroke-width="1.5""#)
+                .count(),
+            1
+        );
+
+        // a hairpin drawn vertically, pairs become straight lines
+        let layout: Vec<(f64, f64)> = (0..12)
+            .map(|i| match i {
+                0..=3 => (0.0, 10.0 * (4 - i) as f64),
+                8..=11 => (5.0, 10.0 * (i - 7) as f64),
+                _ => (2.5, 0.0),
+            })
+            .collect();
+        let drawing = svg_with_layout("GGGGAAAACCCC", &pt, &layout, &depths, None);
+        assert_eq!(drawing.matches("<path").count(), 4);
+        assert_eq!(drawing.matches("<circle").count(), 12);
+        assert_eq!(drawing.matches(PALETTE[1]).count(), 2);
+
+        let fitted = fitted(&layout);
+        assert!(fitted
+            .iter()
+            .all(|(x, y)| x.abs() <= 1.0 + 1e-12 && y.abs() <= 1.0 + 1e-12));
+        assert!(fitted.iter().any(|(_, y)| (y.abs() - 1.0).abs() < 1e-12));
+    }
+}
@@ -7,6 +7,13 @@
 //! - `G = (0, 0, 1, 0)`
 //! - `U = (0, 0, 0, 1)`
 //!
+//! DNA sequences are supported as well, `T` is encoded like `U`.
+//! Gaps (`-` or `.`, see [`GAP_SYMBOLS`]), e.g. of alignment columns, are not encoded and contribute nothing
+//! to pairing scores. They are skipped when numbering positions, such that all positions refer to the
+//! [`ungapped()`] sequence, and structures can be mapped back using [`PairTable::to_string_with_gaps()`].
+//! Complexes of several strands are encoded as their concatenation, where strands are separated
+//! by `&` in the sequence string (as in ViennaRNA) and the resulting cut points are retained.
+//!
 //! Additionally, a _mirrored_ copy of the sequence is encoded in reverse using a complementary (in a sense)
 //! alphabet, effectively carrying information about the strength of legal base pairs:
 //!
@@ -18,15 +25,42 @@
 //! where `AU`, `GC`, `GU` are weights of the base pairs.
 
 use ndarray::{arr1, s, Array1, Array2, ArrayView1, Axis};
+use std::cmp::Ordering;
 use std::convert::TryInto;
+use std::fmt::{self, Write};
+use std::ops::Range;
+use std::str::FromStr;
 use thiserror::Error;
 
 /// Error type representing errors that may arise during sequence parsing or encoding.
 #[derive(Error, Debug)]
 pub enum Error {
     /// Error variant corresponding to invalid nucleotides in the supplied sequence string.
-    #[error("invalid nucleotide (expected one of [A, C, G, U], found {0:?})")]
+    #[error("invalid nucleotide (expected one of [A, C, G, U, T] or a gap, found {0:?})")]
     InvalidNucleotide(char),
+    /// Error variant corresponding to invalid symbols in the supplied dot-bracket notation.
+    #[error("invalid structure symbol (expected one of [., (, )], found {0:?})")]
+    InvalidStructureSymbol(char),
+    /// Error variant corresponding to unmatched brackets in the supplied dot-bracket notation.
+    #[error("unbalanced dot-bracket notation at position {0}")]
+    UnbalancedStructure(usize),
+    /// Error variant corresponding to invalid entries of a pair table in the layout of ViennaRNA.
+    #[error("invalid pair table entry at position {0}")]
+    InvalidPairTable(usize),
+    /// Error variant corresponding to mutations of [masked](EncodedSequence::mask()) positions.
+    #[error("cannot mutate masked position {0}")]
+    MaskedPosition(usize),
+}
+
+/// Symbols denoting gaps in sequences, which are skipped by the encoding.
+pub const GAP_SYMBOLS: [char; 2] = ['-', '.'];
+
+/// Return `sequence` without gaps (see [`GAP_SYMBOLS`]), e.g. to evaluate structures of a gapped sequence.
+pub fn ungapped(sequence: &str) -> String {
+    sequence
+        .chars()
+        .filter(|c| !GAP_SYMBOLS.contains(c))
+        .collect()
 }
 
 // emulating an enum with array variants
@@ -41,12 +75,64 @@ mod Alphabet {
 /// See the [module-level description](crate::encoding).
 #[allow(missing_docs)]
 #[allow(non_snake_case)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct BasePairWeights {
     pub AU: f64,
     pub GC: f64,
     pub GU: f64,
 }
 
+impl BasePairWeights {
+    /// Return whether all weights are non-negative integers, such that pairing scores can be accumulated
+    /// exactly using integer arithmetic, see [`StackConstraints::integer_scores`].
+    pub fn is_integral(&self) -> bool {
+        [self.AU, self.GC, self.GU].into_iter().all(is_integral)
+    }
+}
+
+// Whether `weight` (or a product of weights) is scored exactly by integer accumulation.
+fn is_integral(weight: f64) -> bool {
+    weight >= 0.0 && weight.fract() == 0.0 && weight <= u32::MAX as f64
+}
+
+// Scores accumulated by the sliding-window search of consecutive pairs.
+// The score of a run of consecutive pairs with weights `w` follows `s = w * (s' + w)` with `s' = 0` initially.
+trait PairingScore: Copy + PartialOrd + Default {
+    fn from_weight(weight: f64) -> Self;
+    // Return the score of the run continued by a pair of score (i.e. weight) `self` after a run of score `prev`.
+    fn accumulate(self, prev: Self) -> Self;
+    fn to_f64(self) -> f64;
+}
+
+impl PairingScore for f64 {
+    fn from_weight(weight: f64) -> Self {
+        weight
+    }
+
+    fn accumulate(self, prev: Self) -> Self {
+        self * (prev + self)
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+}
+
+// Exact for integer weights; scores saturate for runs of roughly 80 or more pairs of maximum weight 3
+impl PairingScore for i128 {
+    fn from_weight(weight: f64) -> Self {
+        weight as i128
+    }
+
+    fn accumulate(self, prev: Self) -> Self {
+        self.saturating_mul(prev.saturating_add(self))
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
 #[allow(non_snake_case)]
 struct MirrorAlphabet {
     A: Array1<f64>,
@@ -66,6 +152,19 @@ impl MirrorAlphabet {
     }
 }
 
+impl MirrorAlphabet {
+    // Return the forward and mirrored encoding of nucleotide `c`.
+    fn encode(&self, c: char) -> Result<([f64; 4], &Array1<f64>), Error> {
+        match c {
+            'A' => Ok((Alphabet::A, &self.A)),
+            'C' => Ok((Alphabet::C, &self.C)),
+            'G' => Ok((Alphabet::G, &self.G)),
+            'U' | 'T' => Ok((Alphabet::U, &self.U)),
+            _ => Err(Error::InvalidNucleotide(c)),
+        }
+    }
+}
+
 impl Default for MirrorAlphabet {
     fn default() -> Self {
         Self {
@@ -77,78 +176,107 @@ impl Default for MirrorAlphabet {
     }
 }
 
+/// A `0`-indexed sequence position, as used for local positions of an [`EncodedSequence`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SeqPos0(pub usize);
+
+/// A `1`-indexed sequence position, as used by [`PairTable`], cut points and parent positions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SeqPos1(pub usize);
+
+impl SeqPos0 {
+    /// Return the same position `1`-indexed.
+    pub fn to_one_based(self) -> SeqPos1 {
+        SeqPos1(self.0 + 1)
+    }
+}
+
+impl SeqPos1 {
+    /// Return the same position `0`-indexed.
+    /// Panics if the position is `0`, which is not a valid `1`-indexed position.
+    pub fn to_zero_based(self) -> SeqPos0 {
+        assert!(self.0 > 0, "1-indexed positions start at 1");
+        SeqPos0(self.0 - 1)
+    }
+}
+
+impl From<SeqPos0> for SeqPos1 {
+    fn from(position: SeqPos0) -> Self {
+        position.to_one_based()
+    }
+}
+
+impl From<SeqPos1> for SeqPos0 {
+    fn from(position: SeqPos1) -> Self {
+        position.to_zero_based()
+    }
+}
+
+impl fmt::Display for SeqPos0 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl fmt::Display for SeqPos1 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 /// An [`EncodedSequence`] consists of a _forward_ encoding and a _mirrored_ encoding.
 /// See the [module-level description](crate::encoding) for details.
-#[derive(Debug, Clone)]
+///
+/// Encoded sequences are equal if they encode the same nucleotides with the same weights at the same
+/// parent positions. `Eq` and `Hash` are not implemented due to the floating-point encoding.
+#[derive(Clone, PartialEq)]
 pub struct EncodedSequence {
     pub(crate) forward: Array2<f64>,
     pub(crate) mirrored: Array2<f64>,
     //subsequences will carry information about the positions of their parent sequence
     pub(crate) parent_indices: Array1<usize>,
+    // 1-indexed parent positions starting a new strand
+    pub(crate) cut_points: Vec<usize>,
 }
 
 impl EncodedSequence {
     /// Encode an RNA sequence with given [`BasePairWeights`] being stored in the mirrored encoded sequence.
+    /// Strands of a complex are separated by `&`, see [`EncodedSequence::cut_points()`].
     pub fn with_basepair_weights(sequence: &str, weights: &BasePairWeights) -> Result<Self, Error> {
         let mirrored_alphabet = MirrorAlphabet::new(weights);
 
-        let length = sequence.len();
+        let mut cut_points = vec![];
+        let mut length = 0;
+
+        for c in sequence.chars().filter(|c| !GAP_SYMBOLS.contains(c)) {
+            if c == '&' {
+                cut_points.push(length + 1);
+            } else {
+                length += 1;
+            }
+        }
 
         let mut forward = Array2::default((4, length));
         let mut mirrored = Array2::default((4, length));
         // 1-indexed for convenience
         let parent_indices = Array1::from_iter(1..=length);
 
-        match sequence.chars().enumerate().try_for_each(|(i, c)| match c {
-            'A' => {
-                forward
-                    .column_mut(i)
-                    .zip_mut_with(&arr1(&Alphabet::A), |ci, ni| *ci = *ni);
-                mirrored
-                    .column_mut(i)
-                    .zip_mut_with(&mirrored_alphabet.A.view(), |ci, ni| *ci = *ni);
-
-                Ok(())
-            }
-            'C' => {
-                forward
-                    .column_mut(i)
-                    .zip_mut_with(&arr1(&Alphabet::C), |ci, ni| *ci = *ni);
-                mirrored
-                    .column_mut(i)
-                    .zip_mut_with(&mirrored_alphabet.C.view(), |ci, ni| *ci = *ni);
-
-                Ok(())
-            }
-            'G' => {
-                forward
-                    .column_mut(i)
-                    .zip_mut_with(&arr1(&Alphabet::G), |ci, ni| *ci = *ni);
-                mirrored
-                    .column_mut(i)
-                    .zip_mut_with(&mirrored_alphabet.G.view(), |ci, ni| *ci = *ni);
-
-                Ok(())
-            }
-            'U' => {
-                forward
-                    .column_mut(i)
-                    .zip_mut_with(&arr1(&Alphabet::U), |ci, ni| *ci = *ni);
-                mirrored
-                    .column_mut(i)
-                    .zip_mut_with(&mirrored_alphabet.U.view(), |ci, ni| *ci = *ni);
-
-                Ok(())
-            }
-            _ => Err(Error::InvalidNucleotide(c)),
-        }) {
-            Err(e) => Err(e),
-            _ => Ok(Self {
-                forward,
-                mirrored,
-                parent_indices,
-            }),
+        for (i, c) in sequence
+            .chars()
+            .filter(|c| *c != '&' && !GAP_SYMBOLS.contains(c))
+            .enumerate()
+        {
+            let (fwd, mrrd) = mirrored_alphabet.encode(c)?;
+            forward.column_mut(i).assign(&arr1(&fwd));
+            mirrored.column_mut(i).assign(mrrd);
         }
+
+        Ok(Self {
+            forward,
+            mirrored,
+            parent_indices,
+            cut_points,
+        })
     }
 
     /// Encode an RNA sequence with equal [`BasePairWeights`].
@@ -168,16 +296,212 @@ impl EncodedSequence {
         self.forward.len_of(Axis(1))
     }
 
+    // Return the number of bytes allocated on the heap by the encoding.
+    pub(crate) fn heap_size(&self) -> usize {
+        (self.forward.len() + self.mirrored.len()) * std::mem::size_of::<f64>()
+            + (self.parent_indices.len() + self.cut_points.capacity())
+                * std::mem::size_of::<usize>()
+    }
+
     /// Return whether the encoded sequence is empty.
     pub fn is_empty(&self) -> bool {
         self.forward.is_empty()
     }
 
+    /// Return the `1`-indexed positions of the parent sequence at which a new strand starts,
+    /// i.e. the positions following each `&` in the sequence string.
+    /// The cut points are empty for single-stranded sequences and retained by subsequences.
+    pub fn cut_points(&self) -> &[usize] {
+        &self.cut_points
+    }
+
+    /// Return the local ranges of positions belonging to the same strand, in order.
+    /// Single-stranded sequences consist of a single range.
+    pub fn strand_segments(&self) -> Vec<Range<usize>> {
+        let mut segments = vec![];
+        let mut start = 0;
+
+        for k in 1..self.len() {
+            if self.cut_points.contains(&self.parent_indices[k]) {
+                segments.push(start..k);
+                start = k;
+            }
+        }
+
+        if start < self.len() {
+            segments.push(start..self.len());
+        }
+
+        segments
+    }
+
+    // Return the parent position of the `local` position, panicking if it is out of range.
+    pub(crate) fn parent_position(&self, local: SeqPos0) -> SeqPos1 {
+        SeqPos1(self.parent_indices[local.0])
+    }
+
+    /// Map a `local` position of this sequence to its position in the parent sequence,
+    /// or return `None` if it is out of range.
+    /// For concatenated fragments (see [`EncodedSequence::subsequence()`]), local positions are contiguous,
+    /// while the parent positions skip the omitted region at the concatenation site.
+    /// Parent positions of the original sequence are not affected by `&` (see [`EncodedSequence::cut_points()`]).
+    pub fn to_parent(&self, local: SeqPos0) -> Option<SeqPos1> {
+        self.parent_indices.get(local.0).map(|&p| SeqPos1(p))
+    }
+
+    /// Map a position of the parent sequence to the local position of this sequence,
+    /// or return `None` if the parent position is not covered, e.g. if it was omitted by a concatenation.
+    /// This is the inverse of [`EncodedSequence::to_parent()`].
+    pub fn from_parent(&self, parent: SeqPos1) -> Option<SeqPos0> {
+        self.parent_indices
+            .iter()
+            .position(|&p| p == parent.0)
+            .map(SeqPos0)
+    }
+
+    /// Return an iterator over all `(local, parent)` positions in order of the local positions.
+    pub fn parent_positions(&self) -> impl Iterator<Item = (SeqPos0, SeqPos1)> + '_ {
+        self.parent_indices
+            .iter()
+            .enumerate()
+            .map(|(k, &p)| (SeqPos0(k), SeqPos1(p)))
+    }
+
+    // Return whether the local positions `k - 1` and `k` are adjacent positions of the same strand.
+    fn adjacent(&self, k: usize) -> bool {
+        let (a, b) = (
+            self.parent_position(SeqPos0(k - 1)),
+            self.parent_position(SeqPos0(k)),
+        );
+
+        b.0 - a.0 == 1 && !self.cut_points.contains(&b.0)
+    }
+
+    // Return the distance of the parent positions of the local positions `lower <= upper`.
+    fn pair_span(&self, lower: usize, upper: usize) -> usize {
+        self.parent_position(SeqPos0(upper)).0 - self.parent_position(SeqPos0(lower)).0
+    }
+
+    // Return whether a pair of the local positions `lower` and `upper` encloses enough unpaired positions.
+    // Loops containing a cut point are exterior loops and therefore not restricted.
+    fn encloses_loop(&self, lower: usize, upper: usize, minimal_hairpin: usize) -> bool {
+        let (SeqPos1(a), SeqPos1(b)) = (
+            self.parent_position(SeqPos0(lower)),
+            self.parent_position(SeqPos0(upper)),
+        );
+
+        b - a > minimal_hairpin || self.cut_points.iter().any(|&c| a < c && c <= b)
+    }
+
+    /// Forbid pairing of the positions in `region` (`0`-indexed, exclusive end) by clearing their encoding.
+    /// Masked positions neither contribute to the autocorrelation nor form stacks, and the mask is
+    /// retained by subsequences. Positions beyond the end of the sequence are ignored.
+    pub fn mask(&mut self, region: Range<usize>) {
+        let region = region.start.min(self.len())..region.end.min(self.len());
+
+        self.forward.slice_mut(s![.., region.clone()]).fill(0.0);
+        self.mirrored.slice_mut(s![.., region]).fill(0.0);
+    }
+
+    /// Return the nucleotides recovered from the (one-hot) forward encoding, without strand separators.
+    /// `T` is recovered as `U` and [masked](EncodedSequence::mask()) positions as `N`.
+    pub fn decode(&self) -> String {
+        self.forward
+            .columns()
+            .into_iter()
+            .map(|column| match column.iter().position(|&x| x == 1.0) {
+                Some(k) => ['A', 'C', 'G', 'U'][k],
+                None => 'N',
+            })
+            .collect()
+    }
+
+    /// Return the contiguous intervals `(first, last)` of parent positions (`1`-indexed, inclusive)
+    /// covered by this sequence, in order.
+    /// Subsequences of the same parent sequence with equal intervals encode the same fragment.
+    pub fn parent_intervals(&self) -> Vec<(usize, usize)> {
+        let mut intervals: Vec<(usize, usize)> = vec![];
+
+        for &i in self.parent_indices.iter() {
+            match intervals.last_mut() {
+                Some((_, last)) if *last + 1 == i => *last = i,
+                _ => intervals.push((i, i)),
+            }
+        }
+
+        intervals
+    }
+
+    /// Replace the nucleotide at the `local` position by `nucleotide` in place, e.g. to scan point mutations
+    /// by mutating, folding and reverting a single sequence. Only the affected column of each encoding is
+    /// rewritten, while parent positions and cut points are retained without parsing the sequence again.
+    /// The [`BasePairWeights`] are recovered from the mirrored encoding of the other positions.
+    /// Weights of pairs whose nucleotides occur nowhere in the sequence cannot be recovered and default to `1`,
+    /// as for [`EncodedSequence::new()`].
+    /// [Masked](EncodedSequence::mask()) positions are rejected and stay masked.
+    /// Panics if `local` is out of range.
+    pub fn mutate(&mut self, local: SeqPos0, nucleotide: char) -> Result<(), Error> {
+        assert!(local.0 < self.len(), "position out of range");
+
+        if self.forward.column(local.0).iter().all(|&x| x == 0.0) {
+            return Err(Error::MaskedPosition(local.0));
+        }
+
+        let equal_weights = MirrorAlphabet::default();
+        let (fwd, default) = equal_weights.encode(nucleotide)?;
+        let k = fwd
+            .iter()
+            .position(|&x| x == 1.0)
+            .expect("one-hot encoding");
+        let mrrd: Array1<f64> = (0..4)
+            .map(|partner| {
+                self.nucleotide_pair_weight(k, partner)
+                    .unwrap_or(default[partner])
+            })
+            .collect();
+
+        self.forward.column_mut(local.0).assign(&arr1(&fwd));
+        self.mirrored.column_mut(local.0).assign(&mrrd);
+
+        Ok(())
+    }
+
+    /// Return a copy of this sequence with the nucleotide at the `local` position replaced,
+    /// see [`EncodedSequence::mutate()`]. This sequence is left unchanged.
+    pub fn with_mutation(&self, local: SeqPos0, nucleotide: char) -> Result<Self, Error> {
+        let mut mutant = self.clone();
+        mutant.mutate(local, nucleotide)?;
+        Ok(mutant)
+    }
+
+    // Return the weight of pairs of the nucleotides `a` and `b` (indices of the forward encoding), read from the
+    // mirrored encoding of the first unmasked position encoding either of them.
+    fn nucleotide_pair_weight(&self, a: usize, b: usize) -> Option<f64> {
+        self.forward
+            .columns()
+            .into_iter()
+            .zip(self.mirrored.columns())
+            .find_map(|(fwd, mrrd)| {
+                if fwd[a] == 1.0 {
+                    Some(mrrd[b])
+                } else if fwd[b] == 1.0 {
+                    Some(mrrd[a])
+                } else {
+                    None
+                }
+            })
+    }
+
     /// Create a subsequence from an `EncodedSequence`.
     /// Currently, this allocates new memory instead of slicing or some copy-on-write behaviour.
     /// The range defined by `start` and `end` is exclusive.
     /// If `start >= end`, a contiguous [`EncodedSequence`] is newly created, omitting the inner
     /// region enclosed by both parameters.
+    ///
+    /// Subsequences can be nested arbitrarily, e.g. to fold the exterior loop of an exterior loop.
+    /// `start` and `end` always refer to local positions, while the positions of the result still map to the
+    /// original sequence (see [`EncodedSequence::to_parent()`]). Every omitted region adds a concatenation site,
+    /// which is retained by nested subsequences (see [`EncodedSequence::parent_intervals()`]).
     pub fn subsequence(&self, start: usize, end: usize) -> Self {
         if start < end {
             let sub_fwd = self.forward.slice(s![.., start..end]);
@@ -188,53 +512,429 @@ impl EncodedSequence {
                 forward: sub_fwd.to_owned(),
                 mirrored: sub_mrrd.to_owned(),
                 parent_indices: sub_indices.to_owned(),
+                cut_points: self.cut_points.clone(),
             }
         } else {
-            // let indices: Vec<usize> = (0..end).chain(start..self.len())
-            // should work as well since it does not change pairing
-            // in which case `end` should be stored as concatenation site
-            let indices: Vec<usize> = (0..end).chain(start..self.len()).collect();
-            //let indices: Vec<usize> = (start..self.len()).chain(0..end).collect();
-
-            // double-select to force C standard layout
-            // this is hacky and not as efficient as possible but should suffice for now
-            let sub_fwd = self
-                .forward
-                .select(Axis(1), &indices)
-                .select(Axis(0), &[0, 1, 2, 3]);
-            let sub_mrrd = self
-                .mirrored
-                .select(Axis(1), &indices)
-                .select(Axis(0), &[0, 1, 2, 3]);
-
-            let sub_indices = self.parent_indices.select(Axis(0), &indices);
+            self.concatenation(&[0..end, start..self.len()])
+        }
+    }
 
-            Self {
-                forward: sub_fwd,
-                mirrored: sub_mrrd,
-                parent_indices: sub_indices,
+    /// Create a subsequence by concatenating the local `intervals` (exclusive ends) in order,
+    /// e.g. to fold the unpaired regions of a multiloop jointly.
+    /// Like [`EncodedSequence::subsequence()`], every omitted region adds a concatenation site.
+    /// Empty intervals are ignored.
+    /// Panics if the intervals are out of range, overlap or are not in ascending order.
+    pub fn concatenation(&self, intervals: &[Range<usize>]) -> Self {
+        let mut indices: Vec<usize> = vec![];
+
+        for interval in intervals.iter().filter(|interval| !interval.is_empty()) {
+            assert!(interval.end <= self.len(), "interval out of range");
+            assert!(
+                !matches!(indices.last(), Some(&last) if last >= interval.start),
+                "intervals overlap or are not ordered"
+            );
+            indices.extend(interval.clone());
+        }
+
+        // concatenation sites are recovered from gaps in the parent indices
+        // double-select to force C standard layout
+        // this is hacky and not as efficient as possible but should suffice for now
+        let sub_fwd = self
+            .forward
+            .select(Axis(1), &indices)
+            .select(Axis(0), &[0, 1, 2, 3]);
+        let sub_mrrd = self
+            .mirrored
+            .select(Axis(1), &indices)
+            .select(Axis(0), &[0, 1, 2, 3]);
+
+        let sub_indices = self.parent_indices.select(Axis(0), &indices);
+
+        Self {
+            forward: sub_fwd,
+            mirrored: sub_mrrd,
+            parent_indices: sub_indices,
+            cut_points: self.cut_points.clone(),
+        }
+    }
+}
+
+impl fmt::Display for EncodedSequence {
+    /// Write the decoded nucleotides (see [`EncodedSequence::decode()`]), separating strands by `&`.
+    /// Concatenation sites of subsequences are not marked.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for ((_, parent), c) in self.parent_positions().zip(self.decode().chars()) {
+            if parent.0 != self.parent_indices[0] && self.cut_points.contains(&parent.0) {
+                f.write_char('&')?;
+            }
+            f.write_char(c)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for EncodedSequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncodedSequence")
+            .field("sequence", &self.to_string())
+            .field("parent_intervals", &self.parent_intervals())
+            .field("cut_points", &self.cut_points)
+            .finish()
+    }
+}
+
+/// Normalization of the pairing score of a [`Stack`], see [`EncodedSequence::stack_score()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScoreNormalization {
+    /// The score accumulated during the sliding-window search, as in the reference implementation.
+    #[default]
+    Raw,
+    /// The sum of [`BasePairWeights`] of the stack divided by its number of pairs.
+    PerPair,
+    /// The sum of log-odds of the [`BasePairWeights`] of the stack against the expected weight
+    /// of a random pair given the nucleotide composition of the sequence.
+    LogOdds,
+}
+
+/// Summation of floating-point pair weights, e.g. by [`EncodedSequence::stack_score_with()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Summation {
+    /// Plain left-to-right summation.
+    #[default]
+    Naive,
+    /// Neumaier's compensated summation in a fixed order, such that sums are exact up to the final rounding
+    /// in practice and therefore do not depend on how the summands were grouped, e.g. by SIMD or parallel code.
+    Compensated,
+}
+
+impl Summation {
+    /// Return the sum of `values`.
+    pub fn sum<I: IntoIterator<Item = f64>>(self, values: I) -> f64 {
+        match self {
+            Summation::Naive => values.into_iter().sum(),
+            Summation::Compensated => {
+                let (mut sum, mut compensation) = (0.0f64, 0.0f64);
+
+                for value in values {
+                    let total = sum + value;
+                    compensation += if sum.abs() >= value.abs() {
+                        (sum - total) + value
+                    } else {
+                        (value - total) + sum
+                    };
+                    sum = total;
+                }
+
+                sum + compensation
             }
         }
     }
 }
 
+/// Enforcement of the minimum number of unpaired positions in hairpin loops by the stack search,
+/// see [`StackConstraints`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HairpinEnforcement {
+    /// Runs of consecutive pairs continuing into a too small hairpin loop are truncated to the pairs
+    /// enclosing enough unpaired positions, as in the reference implementation.
+    #[default]
+    Truncate,
+    /// Runs of consecutive pairs continuing into a too small hairpin loop are rejected entirely,
+    /// such that no stack closes a hairpin loop next to unpaired complementary positions.
+    Reject,
+}
+
+impl FromStr for HairpinEnforcement {
+    type Err = String;
+
+    fn from_str(enforcement: &str) -> Result<Self, Self::Err> {
+        match enforcement.to_lowercase().as_str() {
+            "truncate" => Ok(Self::Truncate),
+            "reject" => Ok(Self::Reject),
+            _ => Err(format!(
+                "unknown hairpin enforcement {:?} (expected one of truncate, reject)",
+                enforcement
+            )),
+        }
+    }
+}
+
+/// Constraints on the pairs considered by the stack search,
+/// see [`EncodedSequence::consecutive_pairs_at_lag_with()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackConstraints {
+    /// minimum number of unpaired positions enclosed by a hairpin loop
+    pub minimal_hairpin: usize,
+    /// handling of runs of consecutive pairs continuing into too small hairpin loops
+    pub hairpin_enforcement: HairpinEnforcement,
+    /// maximum distance `j - i` of the parent positions of pairs, e.g. to fold long sequences locally
+    pub max_pair_span: Option<usize>,
+    /// accumulate pairing scores using exact integer arithmetic if the pair weights at the lag are integers,
+    /// e.g. for integral [`BasePairWeights`] (see [`BasePairWeights::is_integral()`]); otherwise,
+    /// floating-point arithmetic is used regardless
+    pub integer_scores: bool,
+    /// normalization of the scores of the stacks, which are ranked by the normalized scores
+    pub score_normalization: ScoreNormalization,
+    /// summation of the pair weights of normalized scores
+    pub summation: Summation,
+}
+
+impl Default for StackConstraints {
+    fn default() -> Self {
+        Self {
+            minimal_hairpin: 3,
+            hairpin_enforcement: HairpinEnforcement::default(),
+            max_pair_span: None,
+            integer_scores: false,
+            score_normalization: ScoreNormalization::default(),
+            summation: Summation::default(),
+        }
+    }
+}
+
+/// A stack of consecutive base pairs found by [`EncodedSequence::stack_at_lag()`].
+///
+/// Positions are `0`-indexed and local to the searched sequence, i.e. they need to be mapped
+/// using its parent indices in case of subsequences.
+/// `(i, j)` is the innermost pair; the stack consists of the pairs `(i - k, j + k)` for `k < pairs`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stack {
+    /// number of consecutive pairs
+    pub pairs: usize,
+    /// lower position of the innermost pair
+    pub i: usize,
+    /// upper position of the innermost pair
+    pub j: usize,
+    /// pairing score, see [`ScoreNormalization`]
+    pub score: f64,
+}
+
+/// Reasons for a [`Stack`] to be inconsistent with the fragment it was found in,
+/// see [`EncodedSequence::validate_stack()`]. Positions are `1`-indexed parent positions.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum InvalidStack {
+    /// The stack extends beyond the fragment.
+    #[error("stack of {pairs} pairs with innermost pair ({i}, {j}) exceeds the fragment of length {length}")]
+    OutOfRange {
+        /// number of pairs
+        pairs: usize,
+        /// local lower position of the innermost pair
+        i: usize,
+        /// local upper position of the innermost pair
+        j: usize,
+        /// length of the fragment
+        length: usize,
+    },
+    /// Consecutive pairs of the stack are not adjacent in the parent sequence,
+    /// i.e. a side of the stack spans a concatenation site or a strand break.
+    #[error("pairs ({0}, {1}) and ({2}, {3}) are not stacked in the parent sequence")]
+    NotStacked(usize, usize, usize, usize),
+    /// A position of the stack is already paired.
+    #[error("position {0} is already paired")]
+    AlreadyPaired(usize),
+    /// A pair of the stack crosses a pair of the structure, i.e. it would form a pseudoknot.
+    #[error("pair ({0}, {1}) crosses pair ({2}, {3})")]
+    Crossing(usize, usize, usize, usize),
+}
+
 impl EncodedSequence {
+    /// Check that `stack` maps to a helix of consecutive pairs in the parent sequence that can be added to
+    /// `structure` (in parent positions), e.g. for stacks found in concatenated fragments whose sides
+    /// must neither span the concatenation site nor pair with positions of the omitted region.
+    pub fn validate_stack(&self, stack: &Stack, structure: &PairTable) -> Result<(), InvalidStack> {
+        if stack.pairs == 0 {
+            return Ok(());
+        }
+
+        if stack.i >= stack.j || stack.pairs > stack.i + 1 || stack.j + stack.pairs > self.len() {
+            return Err(InvalidStack::OutOfRange {
+                pairs: stack.pairs,
+                i: stack.i,
+                j: stack.j,
+                length: self.len(),
+            });
+        }
+
+        let parent_pair = |k: usize| {
+            (
+                self.parent_position(SeqPos0(stack.i - k)),
+                self.parent_position(SeqPos0(stack.j + k)),
+            )
+        };
+
+        for k in 0..stack.pairs {
+            let (SeqPos1(i), SeqPos1(j)) = parent_pair(k);
+
+            if k > 0 && !(self.adjacent(stack.i - k + 1) && self.adjacent(stack.j + k)) {
+                let (SeqPos1(inner_i), SeqPos1(inner_j)) = parent_pair(k - 1);
+                return Err(InvalidStack::NotStacked(i, j, inner_i, inner_j));
+            }
+
+            if let Some(position) = [i, j]
+                .into_iter()
+                .find(|&p| structure.partner(SeqPos1(p)).is_some())
+            {
+                return Err(InvalidStack::AlreadyPaired(position));
+            }
+
+            // pairs enclosed by (i, j) must not reach beyond it
+            for p in i + 1..j {
+                if let Some(SeqPos1(q)) = structure.partner(SeqPos1(p)) {
+                    if q < i || q > j {
+                        return Err(InvalidStack::Crossing(i, j, p.min(q), p.max(q)));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Return the weight of the pair `(i, j)` given the underlying [`BasePairWeights`].
+    /// Positions are `0`-indexed.
+    pub fn pair_weight(&self, i: usize, j: usize) -> f64 {
+        self.forward.column(i).dot(&self.mirrored.column(j))
+    }
+
+    /// Return the score of `stack` according to `normalization`.
+    /// [`ScoreNormalization::Raw`] returns the score of the sliding-window search unchanged.
+    pub fn stack_score(&self, stack: &Stack, normalization: ScoreNormalization) -> f64 {
+        self.stack_score_with(stack, normalization, Summation::default())
+    }
+
+    /// Return the score of `stack` according to `normalization`, where pair weights are summed using `summation`.
+    pub fn stack_score_with(
+        &self,
+        stack: &Stack,
+        normalization: ScoreNormalization,
+        summation: Summation,
+    ) -> f64 {
+        if stack.pairs == 0 {
+            return 0.0;
+        }
+
+        let weights = (0..stack.pairs).map(|k| self.pair_weight(stack.i - k, stack.j + k));
+
+        match normalization {
+            ScoreNormalization::Raw => stack.score,
+            ScoreNormalization::PerPair => summation.sum(weights) / stack.pairs as f64,
+            ScoreNormalization::LogOdds => {
+                let expected = self.expected_pair_weight(summation);
+                summation.sum(weights.map(|w| (w / expected).ln()))
+            }
+        }
+    }
+
+    // Return the expected weight of a pair of two random positions given the nucleotide composition.
+    fn expected_pair_weight(&self, summation: Summation) -> f64 {
+        let n = self.len() as f64;
+        let composition = |encoding: &Array2<f64>| {
+            encoding
+                .rows()
+                .into_iter()
+                .map(|row| summation.sum(row.iter().copied()))
+                .collect::<Vec<_>>()
+        };
+
+        summation.sum(
+            composition(&self.forward)
+                .into_iter()
+                .zip(composition(&self.mirrored))
+                .map(|(f, m)| f * m),
+        ) / (n * n)
+    }
+
     /// Search for the longest sequence of consecutive pairs of the encoded sequence and its (reversed) mirror
     /// offset-aligned by `positional_lag` using a sliding-window approach.
     ///
     /// Sequences of consecutive pairs are prohibited from spanning over concatenation sites.
     /// This may the case if `self` was constructed as a subsequence.
+    /// Likewise, stacks do not span over cut points of complexes.
     ///
     /// `minimal_hairpin` is the number of unpaired positions enclosed by a stack of consecutive pairs.
-    /// A sane default value is `3`.
+    /// A sane default value is `3`. Stacks enclosing a cut point are not restricted.
     ///
     /// Returns a quadruple containing the number of pairs in the sequence,
-    /// the first paired positions of both strands, and a score based on the underlying [`BasePairWeights`]
+    /// the innermost paired positions of both strands, and a score based on the underlying [`BasePairWeights`]
     pub fn consecutive_pairs_at_lag(
         &self,
         positional_lag: usize,
         minimal_hairpin: usize,
     ) -> (usize, usize, usize, f64) {
+        let stack = self.stack_at_lag(positional_lag, minimal_hairpin);
+        (stack.pairs, stack.i, stack.j, stack.score)
+    }
+
+    /// Search for the longest sequence of consecutive pairs like [`EncodedSequence::consecutive_pairs_at_lag()`],
+    /// but return it as a [`Stack`].
+    pub fn stack_at_lag(&self, positional_lag: usize, minimal_hairpin: usize) -> Stack {
+        self.consecutive_pairs_at_lag_with(
+            positional_lag,
+            StackConstraints {
+                minimal_hairpin,
+                ..StackConstraints::default()
+            },
+        )
+    }
+
+    /// Search for the longest sequence of consecutive pairs like [`EncodedSequence::consecutive_pairs_at_lag()`]
+    /// subject to `constraints`: pairs spanning more than the maximum span are not considered, and the
+    /// hairpin enforcement determines how runs of consecutive pairs continuing into hairpin loops with fewer
+    /// than the minimal number of unpaired positions are handled while sliding.
+    /// Unless the score normalization of `constraints` is [`ScoreNormalization::Raw`], the stack with the
+    /// best normalized score is returned, see [`EncodedSequence::stacks_at_lag_with()`].
+    pub fn consecutive_pairs_at_lag_with(
+        &self,
+        positional_lag: usize,
+        constraints: StackConstraints,
+    ) -> Stack {
+        if constraints.score_normalization != ScoreNormalization::Raw {
+            return self
+                .stacks_at_lag_with(positional_lag, 1, constraints)
+                .pop()
+                .unwrap_or(Stack {
+                    pairs: 0,
+                    i: 0,
+                    j: 0,
+                    score: 0.0,
+                });
+        }
+
+        let StackConstraints {
+            minimal_hairpin,
+            hairpin_enforcement: enforcement,
+            max_pair_span,
+            integer_scores,
+            ..
+        } = constraints;
+
+        let total_pairing_scores = self.total_pairing_scores(positional_lag, max_pair_span);
+
+        if integer_scores && total_pairing_scores.iter().all(|&w| is_integral(w)) {
+            self.slide_over_pairs(
+                total_pairing_scores.mapv(i128::from_weight),
+                positional_lag,
+                minimal_hairpin,
+                enforcement,
+            )
+        } else {
+            self.slide_over_pairs(
+                total_pairing_scores,
+                positional_lag,
+                minimal_hairpin,
+                enforcement,
+            )
+        }
+    }
+
+    // Return the pair weights of the positions aligned at `positional_lag`, i.e. the weight of the `t`-th pair
+    // of the (lower) half of the window, with pairs spanning more than `max_pair_span` scored `0`.
+    fn total_pairing_scores(
+        &self,
+        positional_lag: usize,
+        max_pair_span: Option<usize>,
+    ) -> Array1<f64> {
         // Slicing this way since self.mirrored is stored in the same direction as self.forward
         let (fwd_sliceinfo, mrrd_sliceinfo) = if positional_lag < self.len() {
             (s![.., ..=positional_lag], s![.., ..=positional_lag;-1])
@@ -258,12 +958,49 @@ impl EncodedSequence {
             * mrrd_slice.slice(s![.., ..halved_length]))
         .sum_axis(Axis(0));
 
+        if let Some(max_pair_span) = max_pair_span {
+            let offset = positional_lag.saturating_sub(self.len() - 1);
+
+            for (t, score) in total_pairing_scores.iter_mut().enumerate() {
+                if self.pair_span(offset + t, positional_lag - offset - t) > max_pair_span {
+                    *score = 0.0;
+                }
+            }
+        }
+
+        total_pairing_scores
+    }
+
+    // Replace the raw score of `stack` by its score according to the normalization of `constraints`.
+    fn normalized(&self, mut stack: Stack, constraints: StackConstraints) -> Stack {
+        stack.score = self.stack_score_with(
+            &stack,
+            constraints.score_normalization,
+            constraints.summation,
+        );
+        stack
+    }
+
+    // Accumulate the total pairing scores per position of the offset-aligned sequences and return the best stack.
+    fn slide_over_pairs<T: PairingScore>(
+        &self,
+        mut total_pairing_scores: Array1<T>,
+        positional_lag: usize,
+        minimal_hairpin: usize,
+        enforcement: HairpinEnforcement,
+    ) -> Stack {
+        let zero = T::default();
+
         // not very idiomatic but I'm trying to stay close to the reference implementation
         // the essential functionality could be done simpler but I want to reproduce intermediate results
         let mut i = 0;
         let mut max_i = 0;
-        let mut max_score = 0.0;
-        let mut acc_pairs = if total_pairing_scores[0] == 0.0 { 0 } else { 1 };
+        let mut max_score = zero;
+        let mut acc_pairs = if total_pairing_scores[0] == zero {
+            0
+        } else {
+            1
+        };
         let mut max_pairs = 0;
 
         let (mut max_lower, mut max_upper) = if positional_lag < self.len() {
@@ -272,8 +1009,14 @@ impl EncodedSequence {
             (positional_lag - self.len() + 1, self.len() - 1)
         };
 
-        if total_pairing_scores[0] >= 0.0
-            && self.parent_indices[max_upper] - self.parent_indices[max_lower] > minimal_hairpin
+        // the best stack found before the current run of consecutive pairs, restored if the run is rejected
+        let mut before_run = (zero, 0, 0, 0, 0);
+        let mut run_rejected = enforcement == HairpinEnforcement::Reject
+            && acc_pairs > 0
+            && !self.encloses_loop(max_lower, max_upper, minimal_hairpin);
+
+        if total_pairing_scores[0] >= zero
+            && self.encloses_loop(max_lower, max_upper, minimal_hairpin)
         {
             max_score = total_pairing_scores[0];
             max_pairs = acc_pairs;
@@ -282,7 +1025,7 @@ impl EncodedSequence {
             max_upper = 0;
         }
 
-        let accumulate_scores = |&prev: &f64, curr: &mut f64| {
+        let accumulate_scores = |&prev: &T, curr: &mut T| {
             i += 1;
 
             let (lower_position, upper_position) = if positional_lag < self.len() {
@@ -291,25 +1034,35 @@ impl EncodedSequence {
                 (positional_lag - self.len() + 1 + i, self.len() - i - 1)
             };
 
-            if self.parent_indices[lower_position] - self.parent_indices[lower_position - 1] == 1
-                && self.parent_indices[upper_position + 1] - self.parent_indices[upper_position]
-                    == 1
-            {
-                *curr *= prev + *curr;
+            let contiguous = self.adjacent(lower_position) && self.adjacent(upper_position + 1);
+
+            if contiguous {
+                *curr = curr.accumulate(prev);
             }
 
-            if *curr > 0.0 {
+            if *curr > zero {
                 acc_pairs += 1;
             } else {
                 acc_pairs = 0;
             }
 
-            let distance =
-                self.parent_indices[upper_position] - self.parent_indices[lower_position];
+            // runs are also separated by concatenation sites, where scores are not accumulated
+            if acc_pairs == 1 || (acc_pairs > 0 && !contiguous) {
+                before_run = (max_score, max_i, max_lower, max_upper, max_pairs);
+                run_rejected = false;
+            }
+
+            let encloses_loop = self.encloses_loop(lower_position, upper_position, minimal_hairpin);
 
-            if *curr >= max_score
+            if enforcement == HairpinEnforcement::Reject && acc_pairs > 0 && !encloses_loop {
+                if !run_rejected {
+                    (max_score, max_i, max_lower, max_upper, max_pairs) = before_run;
+                    run_rejected = true;
+                }
+            } else if *curr >= max_score
             // check if there are at least 3 unpaired positions between paired positions of stack
-            && distance > minimal_hairpin
+            && encloses_loop
+            && !run_rejected
             {
                 max_score = *curr;
                 max_i = i;
@@ -321,22 +1074,207 @@ impl EncodedSequence {
 
         total_pairing_scores.accumulate_axis_inplace(Axis(0), accumulate_scores);
 
-        (max_pairs, max_lower, max_upper, max_score)
+        Stack {
+            pairs: max_pairs,
+            i: max_lower,
+            j: max_upper,
+            score: max_score.to_f64(),
+        }
     }
-}
-
-/// A wrapper type for pair tables in `ViennaRNA`.
-/// This struct stores `i16` internally and is `1`-indexed.
-///
-/// Refer to the [upstream API](https://www.tbi.univie.ac.at/RNA/ViennaRNA/doc/html/group__struct__utils__pair__table.html) for details.
-// Why is Array1<i16> not Copy?
-#[derive(Clone, PartialEq, Eq)]
-pub struct PairTable(Array1<i16>);
 
-impl PairTable {
-    /// Create a new [`PairTable`].
-    pub fn new(length: usize) -> Self {
-        let mut inner = Array1::zeros(length + 1);
+    /// Search for up to `k` disjoint stacks of consecutive pairs at `positional_lag`, ranked by decreasing score.
+    ///
+    /// Runs of consecutive pairs are separated by unpaired positions or concatenation sites and each run
+    /// contributes its best-scoring stack, scored independently of preceding runs.
+    /// For `k == 1`, this delegates to [`EncodedSequence::stack_at_lag()`] (if a stack was found)
+    /// to retain the behavior of the reference implementation.
+    /// Stacks with equal scores are ranked by decreasing `i`, i.e. inner stacks first.
+    pub fn stacks_at_lag(
+        &self,
+        positional_lag: usize,
+        minimal_hairpin: usize,
+        k: usize,
+    ) -> Vec<Stack> {
+        self.stacks_at_lag_with(
+            positional_lag,
+            k,
+            StackConstraints {
+                minimal_hairpin,
+                ..StackConstraints::default()
+            },
+        )
+    }
+
+    /// Search for up to `k` disjoint stacks like [`EncodedSequence::stacks_at_lag()`] subject to `constraints`,
+    /// see [`EncodedSequence::consecutive_pairs_at_lag_with()`].
+    /// Unless the normalization is [`ScoreNormalization::Raw`], the best stack of each run is selected by its
+    /// normalized score, e.g. the strongest pairs of a run by their score per pair, and the stacks are ranked by
+    /// their normalized scores, so the normalization determines which `k` stacks are kept.
+    pub fn stacks_at_lag_with(
+        &self,
+        positional_lag: usize,
+        k: usize,
+        constraints: StackConstraints,
+    ) -> Vec<Stack> {
+        if k <= 1 && constraints.score_normalization == ScoreNormalization::Raw {
+            let stack = self.consecutive_pairs_at_lag_with(positional_lag, constraints);
+            return if stack.pairs > 0 && k == 1 {
+                vec![stack]
+            } else {
+                vec![]
+            };
+        }
+
+        let total_pairing_scores =
+            self.total_pairing_scores(positional_lag, constraints.max_pair_span);
+
+        if constraints.integer_scores && total_pairing_scores.iter().all(|&w| is_integral(w)) {
+            self.disjoint_stacks(
+                total_pairing_scores.mapv(i128::from_weight),
+                positional_lag,
+                k,
+                constraints,
+            )
+        } else {
+            self.disjoint_stacks(total_pairing_scores, positional_lag, k, constraints)
+        }
+    }
+
+    // Return up to `k` disjoint stacks at `positional_lag`, the best-scoring stack of each run of consecutive pairs.
+    fn disjoint_stacks<T: PairingScore>(
+        &self,
+        total_pairing_scores: Array1<T>,
+        positional_lag: usize,
+        k: usize,
+        constraints: StackConstraints,
+    ) -> Vec<Stack> {
+        let StackConstraints {
+            minimal_hairpin,
+            hairpin_enforcement: enforcement,
+            score_normalization: normalization,
+            ..
+        } = constraints;
+        let zero = T::default();
+
+        // the normalized score of a stack is the sum of its terms, divided by its pairs for `PerPair`
+        let expected = match normalization {
+            ScoreNormalization::LogOdds => self.expected_pair_weight(constraints.summation),
+            _ => 1.0,
+        };
+        let term = |weight: T| match normalization {
+            ScoreNormalization::LogOdds => (weight.to_f64() / expected).ln(),
+            _ => weight.to_f64(),
+        };
+        let normalized_score = |terms: f64, pairs: usize| match normalization {
+            ScoreNormalization::PerPair => terms / pairs as f64,
+            _ => terms,
+        };
+
+        let (offset, window) = if positional_lag < self.len() {
+            (0, positional_lag + 1)
+        } else {
+            (
+                positional_lag - self.len() + 1,
+                2 * self.len() - 1 - positional_lag,
+            )
+        };
+        let halved_length = window / 2 + window % 2;
+
+        // stacks with their exact scores
+        let mut stacks: Vec<(Stack, T)> = vec![];
+        // best stack of the current run with its normalized score
+        let mut current: Option<(Stack, T, f64)> = None;
+        let (mut score, mut pairs, mut terms) = (zero, 0, 0.0);
+        let mut run_rejected = false;
+
+        for t in 0..halved_length {
+            let (lower, upper) = (offset + t, offset + window - 1 - t);
+            let weight = total_pairing_scores[t];
+
+            let contiguous = t > 0 && self.adjacent(lower) && self.adjacent(upper + 1);
+
+            if weight > zero && contiguous && pairs > 0 {
+                score = weight.accumulate(score);
+                pairs += 1;
+                terms += term(weight);
+            } else {
+                stacks.extend(current.take().map(|(stack, score, _)| (stack, score)));
+                score = weight;
+                pairs = if weight > zero { 1 } else { 0 };
+                terms = if weight > zero { term(weight) } else { 0.0 };
+                run_rejected = false;
+            }
+
+            let encloses_loop = self.encloses_loop(lower, upper, minimal_hairpin);
+
+            if enforcement == HairpinEnforcement::Reject && pairs > 0 && !encloses_loop {
+                current = None;
+                run_rejected = true;
+            } else if pairs > 0 && encloses_loop && !run_rejected {
+                let normalized = normalized_score(terms, pairs);
+                let improved = match (normalization, &current) {
+                    (_, None) => true,
+                    (ScoreNormalization::Raw, Some((_, best, _))) => *best <= score,
+                    (_, Some((_, _, best))) => *best <= normalized,
+                };
+
+                if improved {
+                    current = Some((
+                        Stack {
+                            pairs,
+                            i: lower,
+                            j: upper,
+                            score: score.to_f64(),
+                        },
+                        score,
+                        normalized,
+                    ));
+                }
+            }
+        }
+        stacks.extend(current.map(|(stack, score, _)| (stack, score)));
+
+        if normalization != ScoreNormalization::Raw {
+            let mut stacks: Vec<Stack> = stacks
+                .into_iter()
+                .map(|(stack, _)| self.normalized(stack, constraints))
+                .collect();
+
+            // normalized scores are never NaN since all pair weights of a stack are positive
+            stacks.sort_by(|a, b| {
+                b.score
+                    .partial_cmp(&a.score)
+                    .unwrap_or(Ordering::Equal)
+                    .then_with(|| b.i.cmp(&a.i))
+            });
+            stacks.truncate(k);
+            return stacks;
+        }
+
+        // scores are never NaN
+        stacks.sort_by(|(a, a_score), (b, b_score)| {
+            b_score
+                .partial_cmp(a_score)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| b.i.cmp(&a.i))
+        });
+        stacks.truncate(k);
+        stacks.into_iter().map(|(stack, _)| stack).collect()
+    }
+}
+
+/// A wrapper type for pair tables in `ViennaRNA`.
+/// This struct stores `i16` internally and is `1`-indexed.
+///
+/// Refer to the [upstream API](https://www.tbi.univie.ac.at/RNA/ViennaRNA/doc/html/group__struct__utils__pair__table.html) for details.
+// Why is Array1<i16> not Copy?
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PairTable(pub(crate) Array1<i16>);
+
+impl PairTable {
+    /// Create a new [`PairTable`].
+    pub fn new(length: usize) -> Self {
+        let mut inner = Array1::zeros(length + 1);
         inner[0] = length.try_into().unwrap();
         PairTable(inner)
     }
@@ -347,6 +1285,11 @@ impl PairTable {
         self.0[0] as usize
     }
 
+    // Return the number of bytes allocated on the heap by the pair table.
+    pub(crate) fn heap_size(&self) -> usize {
+        self.0.len() * std::mem::size_of::<i16>()
+    }
+
     /// Return whether the represented structure is empty.
     pub fn is_empty(&self) -> bool {
         self.len() == 0
@@ -374,6 +1317,169 @@ impl PairTable {
         self.paired().count()
     }
 
+    /// Return the number of helices, i.e. maximal runs of stacked pairs `(i, j), (i + 1, j - 1), ...`.
+    /// Helices interrupted by bulges or interior loops are counted separately.
+    pub fn helices(&self) -> usize {
+        self.paired()
+            .filter(|&(i, j)| i == 1 || self.partner(SeqPos1(i - 1)) != Some(SeqPos1(j + 1)))
+            .count()
+    }
+
+    /// Return the abstract shape of the structure at `level` (`1` to `5`) as defined by RNAshapes,
+    /// where helices are written as `[]` and unpaired regions as `_`:
+    ///
+    /// 1. all loops and all unpaired regions
+    /// 2. all loops, but only the unpaired regions of the exterior loop and multiloops
+    /// 3. all loops, but no unpaired regions
+    /// 4. helices (i.e. bulges and interior loops are ignored) and the unpaired regions of the exterior loop
+    ///    and multiloops
+    /// 5. helices only
+    ///
+    /// Unpaired nucleotides of hairpin loops are never written, and the shape of the open chain is `_`.
+    /// Panics if `level` is not in `1..=5`.
+    pub fn abstract_shape(&self, level: u8) -> String {
+        assert!((1..=5).contains(&level), "invalid shape level {}", level);
+
+        let mut shape = String::new();
+        if !self.is_empty() && self.pairs() == 0 {
+            shape.push('_');
+        } else {
+            self.push_loop_shape(1, self.len(), level, &mut shape);
+        }
+
+        shape
+    }
+
+    // Append the shape of the exterior loop or multiloop formed by the positions `l..=r` to `shape`.
+    fn push_loop_shape(&self, l: usize, r: usize, level: u8, shape: &mut String) {
+        let mut k = l;
+
+        while k <= r {
+            match self.partner(SeqPos1(k)) {
+                Some(SeqPos1(p)) => {
+                    self.push_helix_shape(k, p, level, shape);
+                    k = p + 1;
+                }
+                None => {
+                    if level != 3 && level != 5 && !shape.ends_with('_') {
+                        shape.push('_');
+                    }
+                    k += 1;
+                }
+            }
+        }
+    }
+
+    // Append the shape of the helix closed by the pair `(i, j)` and of the loops it encloses to `shape`.
+    fn push_helix_shape(&self, mut i: usize, mut j: usize, level: u8, shape: &mut String) {
+        shape.push('[');
+
+        loop {
+            let enclosed = self.enclosed_pairs(i, j);
+
+            match enclosed[..] {
+                // stacked pairs extend the helix, as do bulges and interior loops at levels 4 and 5
+                [(p, q)] if (p == i + 1 && q == j - 1) || level >= 4 => (i, j) = (p, q),
+                [(p, q)] => {
+                    if level == 1 && p > i + 1 {
+                        shape.push('_');
+                    }
+                    self.push_helix_shape(p, q, level, shape);
+                    if level == 1 && q < j - 1 {
+                        shape.push('_');
+                    }
+                    break;
+                }
+                [] => break,
+                _ => {
+                    self.push_loop_shape(i + 1, j - 1, level, shape);
+                    break;
+                }
+            }
+        }
+
+        shape.push(']');
+    }
+
+    // Return the pairs directly enclosed by the pair `(i, j)`.
+    fn enclosed_pairs(&self, i: usize, j: usize) -> Vec<(usize, usize)> {
+        self.loop_members(i + 1, j - 1).1
+    }
+
+    // Return the unpaired positions and the pairs of the loop formed by the positions `l..=r`,
+    // i.e. skipping the positions enclosed by its pairs.
+    pub(crate) fn loop_members(&self, l: usize, r: usize) -> (Vec<usize>, Vec<(usize, usize)>) {
+        let (mut unpaired, mut pairs) = (vec![], vec![]);
+        let mut k = l;
+
+        while k <= r {
+            match self.partner(SeqPos1(k)) {
+                Some(SeqPos1(p)) => {
+                    pairs.push((k, p));
+                    k = p + 1;
+                }
+                None => {
+                    unpaired.push(k);
+                    k += 1;
+                }
+            }
+        }
+
+        (unpaired, pairs)
+    }
+
+    /// Return the element string of the structure as used by [forgi](https://viennarna.github.io/forgi/),
+    /// annotating each position with the type of its structural element:
+    /// `s` for stems (paired positions), `h` for hairpin loops, `i` for interior loops and bulges,
+    /// `m` for multiloops and unpaired regions of the exterior loop between stems, and `f` and `t` for the
+    /// unpaired 5' and 3' ends. All positions of the open chain are annotated with `f`.
+    pub fn element_string(&self) -> String {
+        let mut elements = vec![b's'; self.len()];
+        let (exterior, mut pairs) = self.loop_members(1, self.len());
+        let first = pairs.first().map_or(usize::MAX, |&(i, _)| i);
+        let last = pairs.last().map_or(0, |&(_, j)| j);
+
+        for k in exterior {
+            elements[k - 1] = match k {
+                k if k < first => b'f',
+                k if k > last => b't',
+                _ => b'm',
+            };
+        }
+
+        while let Some((i, j)) = pairs.pop() {
+            let (unpaired, enclosed) = self.loop_members(i + 1, j - 1);
+            let element = match enclosed.len() {
+                0 => b'h',
+                1 => b'i',
+                _ => b'm',
+            };
+
+            for k in unpaired {
+                elements[k - 1] = element;
+            }
+            pairs.extend(enclosed);
+        }
+
+        String::from_utf8(elements).expect("ASCII elements")
+    }
+
+    /// Return the base pair distance to `other`, i.e. the number of pairs contained in only one of both structures.
+    /// Panics if the structures differ in length.
+    pub fn distance(&self, other: &PairTable) -> usize {
+        assert_eq!(self.len(), other.len());
+
+        self.0
+            .iter()
+            .zip(other.0.iter())
+            .enumerate()
+            .skip(1)
+            .map(|(i, (&u, &v))| {
+                (u != v && (u as usize) > i) as usize + (u != v && (v as usize) > i) as usize
+            })
+            .sum()
+    }
+
     /// Insert a new pair into the [`PairTable`].
     /// Does not check for crossing pairs.
     /// Panics if supplied positions are out of range or inserting `i` or `j` would conflict with other pairs.
@@ -393,12 +1499,69 @@ impl PairTable {
         self.0[j as usize] = i;
     }
 
+    /// Return the partner of position `i`, or `None` if it is unpaired or out of range.
+    pub fn partner(&self, i: SeqPos1) -> Option<SeqPos1> {
+        match self.0.get(i.0) {
+            Some(&j) if i.0 > 0 && j > 0 => Some(SeqPos1(j as usize)),
+            _ => None,
+        }
+    }
+
+    /// Insert the pair `(i, j)` like [`PairTable::insert()`], using typed positions.
+    pub fn insert_pair(&mut self, i: SeqPos1, j: SeqPos1) {
+        self.insert(i.0.try_into().unwrap(), j.0.try_into().unwrap());
+    }
+
     /// Return a view of the inner array.
     pub fn view(&self) -> ArrayView1<i16> {
         self.0.view()
     }
 }
 
+impl PairTable {
+    /// Return the dot-bracket notation of the PairTable, where strands of a complex are separated by `&`
+    /// in front of each of the `1`-indexed `cut_points` (see [`EncodedSequence::cut_points()`]).
+    pub fn to_string_with_cut_points(&self, cut_points: &[usize]) -> String {
+        let mut dot_bracket = String::with_capacity(self.len() + cut_points.len());
+
+        for (i, c) in self.to_string().chars().enumerate() {
+            if i > 0 && cut_points.contains(&(i + 1)) {
+                dot_bracket.push('&');
+            }
+            dot_bracket.push(c);
+        }
+
+        dot_bracket
+    }
+
+    /// Return the dot-bracket notation of the PairTable aligned to the columns of `gapped_sequence`,
+    /// which contains gaps (see [`GAP_SYMBOLS`]) and strand separators `&` in addition to the positions of the structure.
+    /// Gap columns are written using the gap symbol of the sequence.
+    /// Panics if `gapped_sequence` does not match the length of the structure.
+    pub fn to_string_with_gaps(&self, gapped_sequence: &str) -> String {
+        let mut structure = self.to_string().into_bytes().into_iter();
+
+        let aligned = gapped_sequence
+            .chars()
+            .map(|c| {
+                if c == '&' || GAP_SYMBOLS.contains(&c) {
+                    c
+                } else {
+                    structure
+                        .next()
+                        .expect("sequence longer than the structure") as char
+                }
+            })
+            .collect();
+        assert!(
+            structure.next().is_none(),
+            "sequence shorter than the structure"
+        );
+
+        aligned
+    }
+}
+
 impl ToString for PairTable {
     /// Return the dot-bracket notation of the PairTable.
     fn to_string(&self) -> String {
@@ -418,10 +1581,138 @@ impl ToString for PairTable {
     }
 }
 
+impl FromStr for PairTable {
+    type Err = Error;
+
+    /// Parse a [`PairTable`] from dot-bracket notation.
+    /// Strand separators `&` are skipped, i.e. positions refer to the concatenated strands.
+    fn from_str(structure: &str) -> Result<Self, Self::Err> {
+        let mut pt = PairTable::new(structure.chars().filter(|&c| c != '&').count());
+        let mut stack = Vec::new();
+
+        for (i, c) in structure.chars().filter(|&c| c != '&').enumerate() {
+            match c {
+                '.' => (),
+                '(' => stack.push(i + 1),
+                ')' => {
+                    let j = stack.pop().ok_or(Error::UnbalancedStructure(i + 1))?;
+                    pt.insert(j as i16, (i + 1) as i16);
+                }
+                _ => return Err(Error::InvalidStructureSymbol(c)),
+            }
+        }
+
+        match stack.pop() {
+            Some(j) => Err(Error::UnbalancedStructure(j)),
+            None => Ok(pt),
+        }
+    }
+}
+
+/// The unpaired fragments of a structure, i.e. the unpaired positions of the exterior loop and of the
+/// interior of each pair, each concatenated into a subsequence ready to be folded (see [`EncodedSequence::concatenation()`]).
+/// These are the fragments RAFFT searches for new stacks after forming the structure.
+#[derive(Debug, Clone)]
+pub struct ExteriorFragments {
+    fragments: Vec<(ClosingPair, EncodedSequence)>,
+}
+
+// `None` for the exterior loop, otherwise the closing pair (1-indexed)
+type ClosingPair = Option<(usize, usize)>;
+
+impl ExteriorFragments {
+    /// Split `encoded` into the unpaired fragments of `structure`. Loops without unpaired positions are omitted.
+    /// Panics if `structure` does not match the length of `encoded` or contains crossing pairs.
+    pub fn new(encoded: &EncodedSequence, structure: &PairTable) -> Self {
+        assert_eq!(encoded.len(), structure.len());
+
+        // closing pair and 0-indexed unpaired positions of each loop
+        let mut loops: Vec<(ClosingPair, Vec<usize>)> = vec![(None, vec![])];
+        // indices into `loops` of the enclosing pairs
+        let mut enclosing = vec![0];
+        let view = structure.view();
+
+        for i in 1..=structure.len() {
+            let j = view[i] as usize;
+            let current = *enclosing.last().expect("the exterior loop is never closed");
+
+            if j == 0 {
+                loops[current].1.push(SeqPos1(i).to_zero_based().0);
+            } else if i < j {
+                loops.push((Some((i, j)), vec![]));
+                enclosing.push(loops.len() - 1);
+            } else {
+                assert_eq!(loops[current].0, Some((j, i)), "crossing pairs");
+                enclosing.pop();
+            }
+        }
+
+        let fragments = loops
+            .into_iter()
+            .filter(|(_, positions)| !positions.is_empty())
+            .map(|(pair, positions)| {
+                let mut intervals: Vec<Range<usize>> = vec![];
+                for k in positions {
+                    match intervals.last_mut() {
+                        Some(interval) if interval.end == k => interval.end += 1,
+                        _ => intervals.push(k..k + 1),
+                    }
+                }
+                (pair, encoded.concatenation(&intervals))
+            })
+            .collect();
+
+        Self { fragments }
+    }
+
+    /// Return the number of fragments.
+    pub fn len(&self) -> usize {
+        self.fragments.len()
+    }
+
+    /// Return whether there are no unpaired positions.
+    pub fn is_empty(&self) -> bool {
+        self.fragments.is_empty()
+    }
+
+    /// Return the fragment of the exterior loop, if it has unpaired positions.
+    pub fn exterior(&self) -> Option<&EncodedSequence> {
+        self.fragments
+            .first()
+            .filter(|(pair, _)| pair.is_none())
+            .map(|(_, fragment)| fragment)
+    }
+
+    /// Return the fragment enclosed by the pair `(i, j)` (`1`-indexed), if it has unpaired positions.
+    pub fn enclosed_by(&self, i: usize, j: usize) -> Option<&EncodedSequence> {
+        self.fragments
+            .iter()
+            .find(|(pair, _)| *pair == Some((i, j)))
+            .map(|(_, fragment)| fragment)
+    }
+
+    /// Return an iterator over all fragments together with their closing pair (`None` for the exterior loop),
+    /// ordered by the `5'` end of the closing pair.
+    pub fn iter(&self) -> impl Iterator<Item = (Option<(usize, usize)>, &EncodedSequence)> + '_ {
+        self.fragments
+            .iter()
+            .map(|(pair, fragment)| (*pair, fragment))
+    }
+
+    /// Return the fragments without their closing pairs.
+    pub fn into_fragments(self) -> Vec<EncodedSequence> {
+        self.fragments
+            .into_iter()
+            .map(|(_, fragment)| fragment)
+            .collect()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use ndarray::Array;
+    use std::collections::HashSet;
 
     #[test]
     fn test_encoding() {
@@ -497,6 +1788,85 @@ mod tests {
         assert_eq!(encoded.mirrored, mrrd);
     }
 
+    #[test]
+    fn test_dna_encoding() {
+        let rna = EncodedSequence::new("GGGAAACCCUUU").unwrap();
+        let dna = EncodedSequence::new("GGGAAACCCTTT").unwrap();
+
+        assert_eq!(rna.forward, dna.forward);
+        assert_eq!(rna.mirrored, dna.mirrored);
+        assert!(matches!(
+            EncodedSequence::new("GGGAAXCCC"),
+            Err(Error::InvalidNucleotide('X'))
+        ));
+    }
+
+    #[test]
+    fn test_gaps() {
+        let gapped = EncodedSequence::new("GG-GA.AA--CC&CU").unwrap();
+        let reference = EncodedSequence::new("GGGAAACC&CU").unwrap();
+
+        assert!(gapped == reference);
+        assert_eq!(gapped.len(), 10);
+        assert_eq!(gapped.cut_points(), &[9]);
+        assert_eq!(ungapped("GG-GA.AA--CC&CU"), "GGGAAACC&CU");
+
+        let pt: PairTable = "((....))..".parse().unwrap();
+        assert_eq!(pt.to_string_with_gaps("GG-GA.AA--CC&CU"), "((-.....--))&..");
+        assert!(std::panic::catch_unwind(|| pt.to_string_with_gaps("GG-GA")).is_err());
+    }
+
+    #[test]
+    fn test_mutation() {
+        let bpw = BasePairWeights {
+            AU: 2.0,
+            GC: 3.0,
+            GU: 1.0,
+        };
+        let encoded = EncodedSequence::with_basepair_weights("GGGAAA&CCCUUU", &bpw).unwrap();
+        let mutant = encoded.with_mutation(SeqPos0(4), 'G').unwrap();
+        let reference = EncodedSequence::with_basepair_weights("GGGAGA&CCCUUU", &bpw).unwrap();
+
+        assert!(mutant == reference);
+        assert_eq!(mutant.to_string(), "GGGAGA&CCCUUU");
+        assert_eq!(encoded.decode(), "GGGAAACCCUUU");
+
+        // local positions of subsequences keep their parent positions
+        let sub = encoded
+            .subsequence(6, 12)
+            .with_mutation(SeqPos0(0), 'T')
+            .unwrap();
+        assert_eq!(sub.to_string(), "UCCUUU");
+        assert_eq!(sub.parent_intervals(), vec![(7, 12)]);
+
+        // mutating and reverting in place restores the sequence
+        let mut scanned = encoded.clone();
+        scanned.mutate(SeqPos0(1), 'A').unwrap();
+        assert_eq!(scanned.to_string(), "GAGAAA&CCCUUU");
+        scanned.mutate(SeqPos0(1), 'G').unwrap();
+        assert!(scanned == encoded);
+
+        // weights are recovered from the other positions, also for nucleotides not occurring before
+        let mut scanned = EncodedSequence::with_basepair_weights("GGAAA", &bpw).unwrap();
+        scanned.mutate(SeqPos0(4), 'U').unwrap();
+        scanned.mutate(SeqPos0(3), 'C').unwrap();
+        assert!(scanned == EncodedSequence::with_basepair_weights("GGACU", &bpw).unwrap());
+
+        assert!(matches!(
+            encoded.with_mutation(SeqPos0(0), 'X'),
+            Err(Error::InvalidNucleotide('X'))
+        ));
+
+        // masked positions are rejected and stay masked
+        let mut masked = encoded.clone();
+        masked.mask(2..4);
+        assert!(matches!(
+            masked.mutate(SeqPos0(3), 'G'),
+            Err(Error::MaskedPosition(3))
+        ));
+        assert_eq!(masked.to_string(), "GGNNAA&CCCUUU");
+    }
+
     #[test]
     fn test_subsequence() {
         let sequence =
@@ -526,6 +1896,10 @@ mod tests {
 
         assert_eq!(concat_oligo.forward, encoded_oligo.forward);
         assert_eq!(concat_oligo.mirrored, encoded_oligo.mirrored);
+
+        assert_eq!(encoded.parent_intervals(), vec![(1, 82)]);
+        assert_eq!(sub.parent_intervals(), vec![(1, 5)]);
+        assert_eq!(concat_oligo.parent_intervals(), vec![(1, 3), (81, 82)]);
     }
 
     #[test]
@@ -561,4 +1935,644 @@ mod tests {
         assert_eq!(splitenc.consecutive_pairs_at_lag(6, 3), (1, 1, 5, 9.0));
         assert_eq!(splitenc.consecutive_pairs_at_lag(11, 3), (1, 4, 7, 1.0));
     }
+
+    #[test]
+    fn test_hairpin_enforcement() {
+        // at lag 8, the pairs (0, 8), (1, 7), (2, 6) and (3, 5) are consecutive
+        let encoded = EncodedSequence::new("GGGGACCCC").unwrap();
+        let search = |minimal_hairpin, hairpin_enforcement| {
+            encoded.consecutive_pairs_at_lag_with(
+                8,
+                StackConstraints {
+                    minimal_hairpin,
+                    hairpin_enforcement,
+                    ..StackConstraints::default()
+                },
+            )
+        };
+
+        for enforcement in [HairpinEnforcement::Truncate, HairpinEnforcement::Reject] {
+            let stack = search(1, enforcement);
+            assert_eq!((stack.pairs, stack.i, stack.j), (4, 3, 5));
+        }
+
+        // (3, 5) encloses a single position, (2, 6) three positions
+        for minimal_hairpin in [2, 3] {
+            let stack = search(minimal_hairpin, HairpinEnforcement::Truncate);
+            assert_eq!((stack.pairs, stack.i, stack.j), (3, 2, 6));
+            assert_eq!(search(minimal_hairpin, HairpinEnforcement::Reject).pairs, 0);
+        }
+        assert_eq!(
+            encoded.stack_at_lag(8, 3),
+            search(3, HairpinEnforcement::Truncate)
+        );
+
+        // (1, 7) encloses five positions, (2, 6) three
+        let stack = search(4, HairpinEnforcement::Truncate);
+        assert_eq!((stack.pairs, stack.i, stack.j), (2, 1, 7));
+
+        // only the run continuing into the hairpin is rejected
+        let encoded = EncodedSequence::new("GGAAGGGGACCCCAACC").unwrap();
+        let reject = StackConstraints {
+            hairpin_enforcement: HairpinEnforcement::Reject,
+            ..StackConstraints::default()
+        };
+        let stacks = encoded.stacks_at_lag_with(16, 2, reject);
+        assert_eq!(stacks.len(), 1);
+        assert_eq!((stacks[0].pairs, stacks[0].i, stacks[0].j), (2, 1, 15));
+        let stacks = encoded.stacks_at_lag_with(16, 2, StackConstraints::default());
+        assert_eq!(stacks.len(), 2);
+        assert_eq!(
+            encoded.stacks_at_lag(16, 3, 2),
+            encoded.stacks_at_lag_with(16, 2, StackConstraints::default())
+        );
+
+        assert_eq!("reject".parse(), Ok(HairpinEnforcement::Reject));
+        assert!("strict".parse::<HairpinEnforcement>().is_err());
+    }
+
+    #[test]
+    fn test_max_pair_span() {
+        // at lag 18, the outer run spans 18 and 16 positions, the inner run 10 down to 4
+        let encoded = EncodedSequence::new("GGAAGGGGAAACCCCAACC").unwrap();
+        let local = |max_pair_span| StackConstraints {
+            max_pair_span: Some(max_pair_span),
+            ..StackConstraints::default()
+        };
+
+        let stack = encoded.consecutive_pairs_at_lag_with(18, StackConstraints::default());
+        assert_eq!((stack.pairs, stack.i, stack.j), (4, 7, 11));
+        let stack = encoded.consecutive_pairs_at_lag_with(18, local(8));
+        assert_eq!((stack.pairs, stack.i, stack.j), (3, 7, 11));
+        let stack = encoded.consecutive_pairs_at_lag_with(18, local(5));
+        assert_eq!((stack.pairs, stack.i, stack.j), (1, 7, 11));
+
+        assert_eq!(
+            encoded
+                .stacks_at_lag_with(18, 2, StackConstraints::default())
+                .len(),
+            2
+        );
+        assert_eq!(encoded.stacks_at_lag_with(18, 2, local(8)).len(), 1);
+        let stacks = encoded.stacks_at_lag_with(18, 2, local(16));
+        assert_eq!(stacks.len(), 2);
+        assert!(stacks
+            .iter()
+            .any(|stack| (stack.pairs, stack.i, stack.j) == (1, 1, 17)));
+
+        // spans refer to parent positions
+        let concatenated = encoded.concatenation(&[0..2, 17..19]);
+        assert_eq!(concatenated.stack_at_lag(3, 3).pairs, 2);
+        assert_eq!(
+            concatenated
+                .consecutive_pairs_at_lag_with(3, local(16))
+                .pairs,
+            1
+        );
+        assert_eq!(
+            concatenated
+                .consecutive_pairs_at_lag_with(3, local(8))
+                .pairs,
+            0
+        );
+    }
+
+    #[test]
+    fn test_integer_scores() {
+        let sequence =
+            "GGGUUUGCGGUGUAAGUGCAGCCCGUCUUACACCGUGCGGCACAGGCACUAGUACUGAUGUCGUAUACAGGGCUUUUGACAU";
+        let encoded = EncodedSequence::new(sequence).unwrap();
+        let exact = StackConstraints {
+            integer_scores: true,
+            ..StackConstraints::default()
+        };
+
+        // small integer scores are represented exactly by floats as well
+        for lag in 0..2 * encoded.len() - 1 {
+            assert_eq!(
+                encoded.consecutive_pairs_at_lag_with(lag, exact),
+                encoded.consecutive_pairs_at_lag_with(lag, StackConstraints::default())
+            );
+            assert_eq!(
+                encoded.stacks_at_lag_with(lag, 3, exact),
+                encoded.stacks_at_lag_with(lag, 3, StackConstraints::default())
+            );
+        }
+
+        // an outer run of 35 GC pairs and a GU pair (following an unpaired position) outscores an inner run of 35 GC pairs by 1,
+        // which is lost in the rounding of floats, such that the inner run ties and is preferred
+        let (g, c) = ("G".repeat(35), "C".repeat(35));
+        let bpw = BasePairWeights {
+            AU: 2.0,
+            GC: 3.0,
+            GU: 1.0,
+        };
+        let encoded =
+            EncodedSequence::with_basepair_weights(&format!("A{g}GAA{g}AAAA{c}AAU{c}A"), &bpw)
+                .unwrap();
+        let lag = encoded.len() - 1;
+        let stack = encoded.consecutive_pairs_at_lag_with(lag, exact);
+        assert_eq!((stack.pairs, stack.i, stack.j), (36, 36, lag - 36));
+        let stack = encoded.consecutive_pairs_at_lag_with(lag, StackConstraints::default());
+        assert_eq!((stack.pairs, stack.i, stack.j), (35, 73, lag - 73));
+        assert_eq!(encoded.stacks_at_lag_with(lag, 2, exact)[0].i, 36);
+
+        assert!(bpw.is_integral());
+        assert!(!BasePairWeights {
+            AU: 2.5,
+            GC: 3.0,
+            GU: 1.0
+        }
+        .is_integral());
+    }
+
+    #[test]
+    fn test_stacks_at_lag() {
+        let bpw = BasePairWeights {
+            AU: 2.0,
+            GC: 3.0,
+            GU: 1.0,
+        };
+        // two runs of three GC pairs at the same lag, separated by unpaired A-A
+        let encoded =
+            EncodedSequence::with_basepair_weights("GGGAAGGGAAAAAAAACCCAACCC", &bpw).unwrap();
+
+        let best = encoded.stack_at_lag(23, 3);
+        let stacks = encoded.stacks_at_lag(23, 3, 2);
+
+        assert_eq!(stacks.len(), 2);
+        assert_eq!(
+            (stacks[0].pairs, stacks[0].i, stacks[0].j),
+            (best.pairs, best.i, best.j)
+        );
+        assert_eq!((stacks[0].pairs, stacks[0].i, stacks[0].j), (3, 7, 16));
+        // runs are scored independently of each other
+        assert_eq!(stacks[0].score, 63.0);
+        assert_eq!((stacks[1].pairs, stacks[1].i, stacks[1].j), (3, 2, 21));
+        assert_eq!(stacks[1].score, 63.0);
+
+        assert_eq!(encoded.stacks_at_lag(23, 3, 1), vec![best]);
+        assert_eq!(encoded.stacks_at_lag(23, 3, 5).len(), 2);
+        assert!(encoded.stacks_at_lag(2, 3, 3).is_empty());
+    }
+
+    #[test]
+    fn test_normalized_stacks() {
+        let bpw = BasePairWeights {
+            AU: 2.0,
+            GC: 3.0,
+            GU: 1.0,
+        };
+        // runs of five GU pairs, a single AU pair and a single GC pair at the same lag
+        let encoded =
+            EncodedSequence::with_basepair_weights("GGGGGAAAGAAAAAACAUAUUUUU", &bpw).unwrap();
+        let search = |score_normalization| {
+            encoded
+                .stacks_at_lag_with(
+                    23,
+                    2,
+                    StackConstraints {
+                        score_normalization,
+                        ..StackConstraints::default()
+                    },
+                )
+                .into_iter()
+                .map(|stack| (stack.pairs, stack.i, stack.score))
+                .collect::<Vec<_>>()
+        };
+        // the long run of weak pairs is kept by its raw score, but not by its score per pair
+        assert_eq!(
+            search(ScoreNormalization::Raw),
+            vec![(5, 4, 5.0), (1, 8, 3.0)]
+        );
+        assert_eq!(
+            search(ScoreNormalization::PerPair),
+            vec![(1, 8, 3.0), (1, 6, 2.0)]
+        );
+
+        // three GC pairs continued by three weaker AU pairs towards the hairpin loop
+        let encoded = EncodedSequence::with_basepair_weights("GGGAAAAAAAUUUCCC", &bpw).unwrap();
+        let search = |score_normalization| {
+            let stack = encoded.consecutive_pairs_at_lag_with(
+                15,
+                StackConstraints {
+                    score_normalization,
+                    ..StackConstraints::default()
+                },
+            );
+            (stack.pairs, stack.i, stack.j, stack.score)
+        };
+        // the whole run is selected by its raw score, but only the GC pairs by their score per pair
+        assert_eq!(search(ScoreNormalization::Raw).0, 6);
+        assert_eq!(search(ScoreNormalization::PerPair), (3, 2, 13, 3.0));
+        assert_eq!(search(ScoreNormalization::LogOdds).0, 6);
+    }
+
+    #[test]
+    fn test_cut_points() {
+        let duplex = EncodedSequence::new("GGGG&CCCC").unwrap();
+        let hairpin = EncodedSequence::new("GGGGCCCC").unwrap();
+
+        assert_eq!(duplex.len(), 8);
+        assert_eq!(duplex.cut_points(), &[5]);
+        assert_eq!(duplex.strand_segments(), vec![0..4, 4..8]);
+        assert_eq!(duplex.subsequence(2, 6).strand_segments(), vec![0..2, 2..4]);
+        assert_eq!(hairpin.strand_segments(), vec![0..8]);
+
+        // the cut point allows the inner pairs to close an exterior loop
+        assert_eq!(duplex.stack_at_lag(7, 3).pairs, 4);
+        assert_eq!(hairpin.stack_at_lag(7, 3).pairs, 2);
+
+        let pt = "((((&))))".parse::<PairTable>().unwrap();
+        assert_eq!(pt.to_string(), "(((())))");
+        assert_eq!(
+            pt.to_string_with_cut_points(duplex.cut_points()),
+            "((((&))))"
+        );
+    }
+
+    #[test]
+    fn test_positions() {
+        assert_eq!(SeqPos0(0).to_one_based(), SeqPos1(1));
+        assert_eq!(SeqPos0::from(SeqPos1(7)), SeqPos0(6));
+        assert_eq!(SeqPos1::from(SeqPos0::from(SeqPos1(3))), SeqPos1(3));
+        assert!(std::panic::catch_unwind(|| SeqPos1(0).to_zero_based()).is_err());
+
+        let encoded = EncodedSequence::new("GGGG&AAAACCCC").unwrap();
+        let inner = encoded.subsequence(3, 10).subsequence(2, 5);
+        assert_eq!(inner.parent_position(SeqPos0(0)), SeqPos1(6));
+        assert_eq!(inner.parent_position(SeqPos0(2)), SeqPos1(8));
+
+        let mut pt = PairTable::new(8);
+        pt.insert_pair(SeqPos1(1), SeqPos1(8));
+        assert_eq!(pt.partner(SeqPos1(1)), Some(SeqPos1(8)));
+        assert_eq!(pt.partner(SeqPos1(8)), Some(SeqPos1(1)));
+        assert_eq!(pt.partner(SeqPos1(2)), None);
+        // index 0 stores the length and is not a position
+        assert_eq!(pt.partner(SeqPos1(0)), None);
+        assert_eq!(pt.partner(SeqPos1(9)), None);
+    }
+
+    #[test]
+    fn test_parent_mapping() {
+        let encoded = EncodedSequence::new("GGGAAACCCAUAUGGGAAACCC").unwrap();
+        // concatenation of 0..3 and 19..22, omitting the inner region
+        let exterior = encoded.subsequence(19, 3);
+
+        assert_eq!(exterior.to_parent(SeqPos0(2)), Some(SeqPos1(3)));
+        assert_eq!(exterior.to_parent(SeqPos0(3)), Some(SeqPos1(20)));
+        assert_eq!(exterior.to_parent(SeqPos0(6)), None);
+        assert_eq!(exterior.from_parent(SeqPos1(20)), Some(SeqPos0(3)));
+        assert_eq!(exterior.from_parent(SeqPos1(10)), None);
+        assert_eq!(
+            exterior.parent_positions().collect::<Vec<_>>(),
+            [1, 2, 3, 20, 21, 22]
+                .iter()
+                .enumerate()
+                .map(|(k, &p)| (SeqPos0(k), SeqPos1(p)))
+                .collect::<Vec<_>>()
+        );
+        assert!(exterior
+            .parent_positions()
+            .all(|(local, parent)| exterior.from_parent(parent) == Some(local)));
+    }
+
+    #[test]
+    fn test_nested_subsequence() {
+        let sequence = "GGGAAACCCAUAUGGGAAACCCUAUAGGGAAACCC";
+        let encoded = EncodedSequence::new(sequence).unwrap();
+
+        // omit the hairpin 13..22, then the hairpin 0..9 of the exterior loop
+        let exterior = encoded.subsequence(22, 13);
+        assert_eq!(exterior.parent_intervals(), vec![(1, 13), (23, 35)]);
+        let nested = exterior.subsequence(9, 0);
+        assert_eq!(nested.parent_intervals(), vec![(10, 13), (23, 35)]);
+        // omit the inner part of the remaining hairpin, across both previous concatenations
+        let twice = nested.subsequence(14, 7);
+        assert_eq!(twice.parent_intervals(), vec![(10, 13), (23, 25), (33, 35)]);
+        assert_eq!(
+            twice
+                .parent_positions()
+                .map(|(_, p)| p.0)
+                .collect::<Vec<_>>(),
+            vec![10, 11, 12, 13, 23, 24, 25, 33, 34, 35]
+        );
+
+        // encodings follow the parent positions
+        assert!(twice.parent_positions().all(|(local, parent)| {
+            let original = encoded.from_parent(parent).unwrap();
+            twice.forward.column(local.0) == encoded.forward.column(original.0)
+        }));
+
+        // stacks do not span concatenation sites
+        for lag in 0..2 * twice.len() - 1 {
+            let stack = twice.stack_at_lag(lag, 0);
+            assert!((0..stack.pairs).all(|k| {
+                k == 0 || (twice.adjacent(stack.i - k + 1) && twice.adjacent(stack.j + k))
+            }));
+        }
+    }
+
+    #[test]
+    fn test_validate_stack() {
+        // the exterior loop of ((((...))))..((((...)))) omitting the first hairpin
+        let encoded = EncodedSequence::new("GGGGAAACCCCAAGGGGAAACCCC").unwrap();
+        let structure = PairTable::from_str("((((...)))).............").unwrap();
+        let exterior = encoded.subsequence(11, 0);
+        let stack = |pairs, i, j| Stack {
+            pairs,
+            i,
+            j,
+            score: 0.0,
+        };
+
+        // the second hairpin
+        assert_eq!(exterior.validate_stack(&stack(4, 5, 9), &structure), Ok(()));
+        assert_eq!(exterior.validate_stack(&stack(0, 0, 0), &structure), Ok(()));
+        assert!(matches!(
+            exterior.validate_stack(&stack(4, 2, 12), &structure),
+            Err(InvalidStack::OutOfRange { .. })
+        ));
+
+        // pairs of the omitted region are not covered by the fragment
+        let whole = encoded.subsequence(0, encoded.len());
+        assert_eq!(
+            whole.validate_stack(&stack(1, 3, 12), &structure),
+            Err(InvalidStack::AlreadyPaired(4))
+        );
+        assert_eq!(
+            whole.validate_stack(&stack(1, 5, 12), &structure),
+            Err(InvalidStack::Crossing(6, 13, 4, 8))
+        );
+
+        // both sides of a stack must be contiguous in the parent sequence
+        let concatenated = encoded.concatenation(&[0..2, 4..7, 20..24]);
+        assert_eq!(
+            concatenated.validate_stack(&stack(2, 2, 6), &PairTable::new(encoded.len())),
+            Err(InvalidStack::NotStacked(2, 23, 5, 22))
+        );
+        assert_eq!(
+            concatenated.validate_stack(&stack(1, 2, 6), &PairTable::new(encoded.len())),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_concatenation() {
+        let sequence = "GGGAAACCCAUAUGGGAAACCCUAUAGGGAAACCC";
+        let encoded = EncodedSequence::new(sequence).unwrap();
+
+        // the unpaired regions of a multiloop closed by 1-35 and enclosing two hairpins
+        let multiloop = encoded.concatenation(&[1..3, 9..13, 22..26, 35..35, 33..34]);
+        assert_eq!(multiloop.len(), 11);
+        assert_eq!(
+            multiloop.parent_intervals(),
+            vec![(2, 3), (10, 13), (23, 26), (34, 34)]
+        );
+        assert!(multiloop.parent_positions().all(|(local, parent)| {
+            let original = encoded.from_parent(parent).unwrap();
+            multiloop.forward.column(local.0) == encoded.forward.column(original.0)
+        }));
+
+        // two intervals are equivalent to the concatenating subsequence
+        assert_eq!(
+            encoded.concatenation(&[0..4, 30..35]).parent_intervals(),
+            encoded.subsequence(30, 4).parent_intervals()
+        );
+
+        assert!(std::panic::catch_unwind(|| encoded.concatenation(&[4..8, 2..6])).is_err());
+        assert!(std::panic::catch_unwind(|| encoded.concatenation(&[0..4, 30..36])).is_err());
+    }
+
+    #[test]
+    fn test_exterior_fragments() {
+        let encoded = EncodedSequence::new("GGGAAACCCAUAUGGGAAACCCUAUAGGGAAACCC").unwrap();
+        let pt: PairTable = ".(((...))).(((((...)))).)...((...))".parse().unwrap();
+        let fragments = ExteriorFragments::new(&encoded, &pt);
+
+        assert_eq!(fragments.len(), 5);
+        assert_eq!(
+            fragments.exterior().unwrap().parent_intervals(),
+            vec![(1, 1), (11, 11), (26, 28)]
+        );
+        assert_eq!(
+            fragments.enclosed_by(4, 8).unwrap().parent_intervals(),
+            vec![(5, 7)]
+        );
+        // the bulge of the second helix is part of the loop enclosed by the pair (12, 25)
+        assert_eq!(
+            fragments.enclosed_by(12, 25).unwrap().parent_intervals(),
+            vec![(24, 24)]
+        );
+        assert!(fragments.enclosed_by(2, 10).is_none());
+        assert_eq!(
+            fragments.iter().map(|(pair, _)| pair).collect::<Vec<_>>(),
+            vec![
+                None,
+                Some((4, 8)),
+                Some((12, 25)),
+                Some((16, 20)),
+                Some((30, 34))
+            ]
+        );
+        assert_eq!(
+            fragments
+                .into_fragments()
+                .iter()
+                .map(EncodedSequence::len)
+                .sum::<usize>(),
+            pt.unpaired().count()
+        );
+
+        let unpaired = ExteriorFragments::new(&encoded, &PairTable::new(35));
+        assert_eq!(unpaired.len(), 1);
+        assert_eq!(unpaired.exterior().unwrap().len(), 35);
+    }
+
+    #[test]
+    fn test_decode() {
+        let encoded = EncodedSequence::new("GGGAAACCCAUAUGGGAAACCC").unwrap();
+        assert_eq!(encoded.decode(), "GGGAAACCCAUAUGGGAAACCC");
+        assert_eq!(encoded.subsequence(19, 3).to_string(), "GGGCCC");
+
+        let mut duplex = EncodedSequence::new("GGTTAG&CCAAUC").unwrap();
+        assert_eq!(duplex.decode(), "GGUUAGCCAAUC");
+        assert_eq!(duplex.to_string(), "GGUUAG&CCAAUC");
+        // a subsequence starting at the cut point is a single strand
+        assert_eq!(duplex.subsequence(6, 10).to_string(), "CCAA");
+        assert_eq!(duplex.subsequence(4, 8).to_string(), "AG&CC");
+
+        duplex.mask(0..2);
+        assert_eq!(duplex.to_string(), "NNUUAG&CCAAUC");
+        assert_eq!(
+            format!("{:?}", duplex.subsequence(8, 4)),
+            r#"EncodedSequence { sequence: "NNUUAAUC", parent_intervals: [(1, 4), (9, 12)], cut_points: [7] }"#
+        );
+    }
+
+    #[test]
+    fn test_mask() {
+        let mut encoded = EncodedSequence::new("GGGGAAAACCCCAUAUGGGGAAAACCCC").unwrap();
+        assert!(encoded.stack_at_lag(11, 3).pairs > 0);
+
+        encoded.mask(8..12);
+        encoded.mask(26..40);
+
+        assert_eq!(encoded.len(), 28);
+        assert_eq!(encoded.stack_at_lag(11, 3).pairs, 0);
+        assert!((8..12).all(|j| (0..28).all(|i| encoded.pair_weight(i, j) == 0.0)));
+        assert!(encoded
+            .subsequence(0, 12)
+            .autocorrelation(1.0)
+            .into_inner()
+            .iter()
+            .all(|&c| c.abs() < 1e-9));
+    }
+
+    #[test]
+    fn test_stack_score() {
+        let sequence = "UGCGGUGUAAGUGC";
+        let bpw = BasePairWeights {
+            AU: 2.0,
+            GC: 3.0,
+            GU: 1.0,
+        };
+        let encoded = EncodedSequence::with_basepair_weights(sequence, &bpw).unwrap();
+
+        // C3-G11, G2-U12, U1-G13 (1-indexed)
+        let stack = encoded.stack_at_lag(12, 3);
+        assert_eq!(encoded.pair_weight(2, 10), 3.0);
+
+        assert_eq!(encoded.stack_score(&stack, ScoreNormalization::Raw), 15.0);
+        assert_eq!(
+            encoded.stack_score(&stack, ScoreNormalization::PerPair),
+            5.0 / 3.0
+        );
+
+        let log_odds = encoded.stack_score(&stack, ScoreNormalization::LogOdds);
+        // A: 2, C: 2, G: 6, U: 4 nucleotides; expected pair weight is 152 / 196
+        let expected = 152.0 / 196.0;
+        let reference = (3.0f64 / expected).ln() + 2.0 * (1.0f64 / expected).ln();
+        assert!((log_odds - reference).abs() < 1e-12);
+        assert!(
+            (encoded.stack_score_with(&stack, ScoreNormalization::LogOdds, Summation::Compensated)
+                - reference)
+                .abs()
+                < 1e-12
+        );
+    }
+
+    #[test]
+    fn test_summation() {
+        let values = [1.0, 1e100, 1.0, -1e100];
+        assert_eq!(Summation::Naive.sum(values), 0.0);
+        assert_eq!(Summation::Compensated.sum(values), 2.0);
+
+        // the compensated sum does not depend on the order of summands
+        let values: Vec<f64> = (1..1000).map(|k| 1.0 / k as f64).collect();
+        let reversed: Vec<f64> = values.iter().rev().copied().collect();
+        assert_eq!(
+            Summation::Compensated.sum(values.iter().copied()),
+            Summation::Compensated.sum(reversed)
+        );
+        assert_eq!(Summation::Compensated.sum([]), 0.0);
+    }
+
+    #[test]
+    fn test_hashable() {
+        let mut pt = PairTable::new(40);
+        pt.insert(1, 40);
+        pt.insert(2, 39);
+        pt.insert(5, 20);
+        pt.insert(6, 19);
+        pt.insert(25, 36);
+
+        assert_eq!(pt.helices(), 3);
+        assert_eq!(PairTable::new(5).helices(), 0);
+
+        // pair tables can be used as keys
+        let mut other = pt.clone();
+        other.insert(8, 12);
+        let set: HashSet<PairTable> = [pt.clone(), other, pt.clone()].into_iter().collect();
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&pt));
+
+        let encoded = EncodedSequence::new("GGGAAACCC").unwrap();
+        assert_eq!(encoded.clone(), encoded);
+        assert_eq!(encoded.subsequence(0, 3), encoded.subsequence(0, 3));
+        assert_ne!(encoded.subsequence(0, 3), encoded.subsequence(6, 9));
+    }
+
+    #[test]
+    fn test_dot_bracket() {
+        let structure = "((((((....)).))..(((...))).))..";
+        let pt = structure.parse::<PairTable>().unwrap();
+
+        assert_eq!(pt.len(), structure.len());
+        assert_eq!(pt.pairs(), 9);
+        assert_eq!(pt.to_string(), structure);
+
+        assert!(matches!(
+            "((..)".parse::<PairTable>(),
+            Err(Error::UnbalancedStructure(1))
+        ));
+        assert!(matches!(
+            "(..))".parse::<PairTable>(),
+            Err(Error::UnbalancedStructure(5))
+        ));
+        assert!(matches!(
+            "(..]".parse::<PairTable>(),
+            Err(Error::InvalidStructureSymbol(']'))
+        ));
+
+        let other = "(((((......).))..((.....)).)).."
+            .parse::<PairTable>()
+            .unwrap();
+        assert_eq!(pt.distance(&other), 2);
+        assert_eq!(other.distance(&pt), 2);
+        assert_eq!(pt.distance(&pt), 0);
+        assert_eq!(pt.distance(&PairTable::new(pt.len())), 9);
+    }
+
+    #[test]
+    fn test_abstract_shape() {
+        let shapes = |structure: &str| {
+            let pt = structure.parse::<PairTable>().unwrap();
+            (1..=5)
+                .map(|level| pt.abstract_shape(level))
+                .collect::<Vec<_>>()
+        };
+
+        // interior loop, bulge and multiloop
+        assert_eq!(
+            shapes("..((..((...))..))..((.((...))))..((...))."),
+            vec![
+                "_[_[]_]_[_[]]_[]_",
+                "_[[]]_[[]]_[]_",
+                "[[]][[]][]",
+                "_[]_[]_[]_",
+                "[][][]"
+            ]
+        );
+        assert_eq!(
+            shapes("((..((...))((...))..))"),
+            vec!["[_[][]_]", "[_[][]_]", "[[][]]", "[_[][]_]", "[[][]]"]
+        );
+        assert_eq!(shapes("((((....))))"), vec!["[]"; 5]);
+        assert_eq!(shapes("....."), vec!["_"; 5]);
+        assert_eq!(PairTable::new(0).abstract_shape(5), "");
+    }
+
+    #[test]
+    fn test_element_string() {
+        let elements = |structure: &str| structure.parse::<PairTable>().unwrap().element_string();
+
+        assert_eq!(
+            elements("..((..((...))..))..((.((...)))).."),
+            "ffssiisshhhssiissmmssisshhhsssstt"
+        );
+        assert_eq!(elements("((..((...))((...))..))"), "ssmmsshhhsssshhhssmmss");
+        assert_eq!(elements("....."), "fffff");
+        assert_eq!(elements(""), "");
+    }
 }
@@ -0,0 +1,391 @@
+//! This module provides exports of structures for visualization and other tools:
+//!
+//! - [forna](http://rna.tbi.univie.ac.at/forna/): sequence, structure and custom colors in the format of
+//!   `fornac`'s `addRNA()` and `addCustomColors()`, which can be pasted into forna's JSON import.
+//! - [R2DT](https://www.ebi.ac.uk/Tools/rna/r2dt/): a submission payload for the REST API of the EBI job dispatcher,
+//!   where only the `email` parameter has to be added.
+//! - Connectivity tables (CT), e.g. for RNAstructure or mfold, see [`write_ct()`].
+//! - The complete fast folding graph as JSON, DOT (Graphviz) or GraphML (e.g. for Gephi or Cytoscape),
+//!   see [`graph_json()`], [`write_dot()`] and [`write_graphml()`].
+//!
+//! Positions are annotated with the depth at which they were first paired along the trajectory
+//! towards the exported structure (see [`RafftGraph::first_pairing_depths()`]).
+//! Structures in JSON exports are annotated with the element strings of forgi (see
+//! [`PairTable::element_string()`](crate::encoding::PairTable::element_string)), such that they can be
+//! consumed by tools of the forgi ecosystem.
+//! Nodes of exported graphs can be annotated with populations, e.g. the Boltzmann probabilities
+//! or time-resolved populations of [`kinetics`](crate::kinetics), to size or color them by population.
+
+use crate::analysis;
+use crate::folding_graph::{RafftGraph, RafftNodeId};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// Return the forna JSON of the structure `node` of `graph`, constructed for `sequence`.
+/// Positions are colored by their depth of first pairing (unpaired positions are not colored),
+/// and the depths are also included as `annotations` (`null` for unpaired positions) together with the forgi `elements`.
+/// Returns `None` if `node` is not part of the graph.
+pub fn forna_json(
+    graph: &RafftGraph,
+    sequence: &str,
+    name: &str,
+    node: RafftNodeId,
+) -> Option<Value> {
+    let info = graph.node(node)?;
+    let depths = graph.first_pairing_depths(node);
+
+    // 1-indexed positions as keys
+    let color_values: Map<String, Value> = depths
+        .iter()
+        .enumerate()
+        .filter_map(|(i, depth)| depth.map(|depth| ((i + 1).to_string(), json!(depth))))
+        .collect();
+
+    Some(json!({
+        "name": name,
+        "sequence": sequence.replace('&', ""),
+        "structure": info.structure.to_string(),
+        "energy": info.energy as f64 * 0.01,
+        "custom_colors": {
+            "domain": [1, info.depth.max(1)],
+            "color_values": { "": color_values },
+        },
+        "annotations": {
+            "first_pairing_depth": depths,
+            "elements": info.structure.pair_table().element_string(),
+        },
+    }))
+}
+
+/// Write the structure `node` of `graph`, constructed for `sequence`, as connectivity table (CT).
+/// In addition to the six standard columns, a seventh column contains the depth at which each pair
+/// was formed (see [`RafftGraph::pair_formation_depths()`]) or `-` for unpaired positions.
+/// Nothing is written if `node` is not part of the graph.
+pub fn write_ct<W: Write>(
+    graph: &RafftGraph,
+    sequence: &str,
+    name: &str,
+    node: RafftNodeId,
+    writer: &mut W,
+) -> io::Result<()> {
+    let info = match graph.node(node) {
+        Some(info) => info,
+        None => return Ok(()),
+    };
+    let pt = info.structure.pair_table();
+    let depths = graph.first_pairing_depths(node);
+    let nucleotides: Vec<char> = sequence.chars().filter(|&c| c != '&').collect();
+
+    writeln!(
+        writer,
+        "{}\tENERGY = {:.2}\t{}",
+        nucleotides.len(),
+        info.energy as f64 * 0.01,
+        name
+    )?;
+
+    for (i, c) in nucleotides.iter().enumerate() {
+        let next = if i + 1 < nucleotides.len() { i + 2 } else { 0 };
+        let depth = depths[i].map_or_else(|| "-".to_string(), |depth| depth.to_string());
+
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            i + 1,
+            c,
+            i,
+            next,
+            pt.view()[i + 1],
+            i + 1,
+            depth
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Return an R2DT submission payload for `sequence`, lacking the mandatory `email` parameter.
+pub fn r2dt_payload(sequence: &str, name: &str) -> Value {
+    json!({
+        "title": name,
+        "sequence": format!(">{}\n{}", name, sequence.replace('&', "")),
+    })
+}
+
+/// Return the structures, edges and trajectories of `graph` as JSON. Nodes are indexed in the order of [`RafftGraph::iter()`],
+/// contain the forgi `elements` of their structure and the attribute `population` if `populations` (in the same order) are given.
+/// Each trajectory (see [`analysis::trajectories()`]) lists the indices of its structures and their
+/// `energy_profile`, `pairing_fraction_profile` and `helix_profile`.
+/// Panics if the number of populations does not match the number of structures.
+pub fn graph_json(graph: &RafftGraph, populations: Option<&[f64]>) -> Value {
+    check_populations(graph, populations);
+
+    let nodes: Vec<Value> = graph
+        .iter()
+        .enumerate()
+        .map(|(index, node)| {
+            let mut value = json!({
+                "id": index,
+                "depth": node.depth,
+                "structure": node.structure.to_string(),
+                "elements": node.structure.pair_table().element_string(),
+                "energy": node.energy as f64 * 0.01,
+            });
+            if let Some(populations) = populations {
+                value["population"] = json!(populations[index]);
+            }
+            value
+        })
+        .collect();
+
+    let indices: HashMap<RafftNodeId, usize> = graph
+        .iter()
+        .enumerate()
+        .map(|(index, node)| (node.id, index))
+        .collect();
+    let trajectories: Vec<Value> = analysis::trajectories(graph)
+        .iter()
+        .map(|trajectory| {
+            json!({
+                "nodes": trajectory
+                    .nodes()
+                    .iter()
+                    .map(|id| indices[id])
+                    .collect::<Vec<_>>(),
+                "energy_profile": trajectory.energy_profile(),
+                "pairing_fraction_profile": trajectory.pairing_fraction_profile(),
+                "helix_profile": trajectory.helix_profile(),
+            })
+        })
+        .collect();
+
+    json!({
+        "nodes": nodes,
+        "edges": graph.adjacent_indices().collect::<Vec<_>>(),
+        "trajectories": trajectories,
+    })
+}
+
+/// Write `graph` in the DOT language of Graphviz. Nodes are named by their index in the order of
+/// [`RafftGraph::iter()`] and labeled with their structure and free energy.
+/// If `populations` are given, they are added as attribute `population` and the width of each node
+/// grows with the square root of its population, such that the area is proportional to it.
+/// Panics if the number of populations does not match the number of structures.
+pub fn write_dot<W: Write>(
+    graph: &RafftGraph,
+    populations: Option<&[f64]>,
+    writer: &mut W,
+) -> io::Result<()> {
+    check_populations(graph, populations);
+
+    writeln!(writer, "digraph rafft {{")?;
+    writeln!(writer, "  node [shape=box, fontname=monospace];")?;
+
+    for (index, node) in graph.iter().enumerate() {
+        write!(
+            writer,
+            "  {} [label=\"{}\\n{:.2}\", depth={}, energy={:.2}",
+            index,
+            node.structure,
+            node.energy as f64 * 0.01,
+            node.depth,
+            node.energy as f64 * 0.01
+        )?;
+        if let Some(populations) = populations {
+            write!(
+                writer,
+                ", population={:.6e}, width={:.3}",
+                populations[index],
+                0.5 + 2.0 * populations[index].sqrt()
+            )?;
+        }
+        writeln!(writer, "];")?;
+    }
+
+    for (i, j) in graph.adjacent_indices() {
+        writeln!(writer, "  {} -> {};", i, j)?;
+    }
+
+    writeln!(writer, "}}")
+}
+
+/// Write `graph` as GraphML with the node attributes `structure`, `energy`, `depth` and, if `populations`
+/// are given, `population`. Nodes are named `n<index>` in the order of [`RafftGraph::iter()`].
+/// Panics if the number of populations does not match the number of structures.
+pub fn write_graphml<W: Write>(
+    graph: &RafftGraph,
+    populations: Option<&[f64]>,
+    writer: &mut W,
+) -> io::Result<()> {
+    check_populations(graph, populations);
+
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        writer,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+    )?;
+    writeln!(
+        writer,
+        r#"  <key id="structure" for="node" attr.name="structure" attr.type="string"/>"#
+    )?;
+    writeln!(
+        writer,
+        r#"  <key id="energy" for="node" attr.name="energy" attr.type="double"/>"#
+    )?;
+    writeln!(
+        writer,
+        r#"  <key id="depth" for="node" attr.name="depth" attr.type="int"/>"#
+    )?;
+    if populations.is_some() {
+        writeln!(
+            writer,
+            r#"  <key id="population" for="node" attr.name="population" attr.type="double"/>"#
+        )?;
+    }
+    writeln!(writer, r#"  <graph id="rafft" edgedefault="directed">"#)?;
+
+    for (index, node) in graph.iter().enumerate() {
+        writeln!(writer, r#"    <node id="n{}">"#, index)?;
+        writeln!(
+            writer,
+            r#"      <data key="structure">{}</data>"#,
+            node.structure
+        )?;
+        writeln!(
+            writer,
+            r#"      <data key="energy">{:.2}</data>"#,
+            node.energy as f64 * 0.01
+        )?;
+        writeln!(writer, r#"      <data key="depth">{}</data>"#, node.depth)?;
+        if let Some(populations) = populations {
+            writeln!(
+                writer,
+                r#"      <data key="population">{:.6e}</data>"#,
+                populations[index]
+            )?;
+        }
+        writeln!(writer, "    </node>")?;
+    }
+
+    for (i, j) in graph.adjacent_indices() {
+        writeln!(writer, r#"    <edge source="n{}" target="n{}"/>"#, i, j)?;
+    }
+
+    writeln!(writer, "  </graph>")?;
+    writeln!(writer, "</graphml>")
+}
+
+fn check_populations(graph: &RafftGraph, populations: Option<&[f64]>) {
+    if let Some(populations) = populations {
+        assert_eq!(
+            populations.len(),
+            graph.iter().count(),
+            "expected one population per structure"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fast_folding::RafftConfig;
+    use crate::kinetics::{boltzmann_probabilities, ArrheniusModel};
+
+    #[test]
+    fn test_forna_json() {
+        let sequence = "GGGGAAAACCCCAUAUAUGGGGAAAACCCC";
+        let mut graph = RafftConfig::new().folding_graph(sequence);
+        graph.construct_trajectories();
+
+        let best = graph.iter().min_by_key(|node| node.energy).unwrap();
+        let forna = forna_json(&graph, sequence, "test", best.id).unwrap();
+
+        assert_eq!(forna["structure"], best.structure.to_string());
+        let depths = forna["annotations"]["first_pairing_depth"]
+            .as_array()
+            .unwrap();
+        assert_eq!(depths.len(), sequence.len());
+        assert_eq!(
+            depths.iter().filter(|depth| !depth.is_null()).count(),
+            2 * best.structure.pairs()
+        );
+        let elements = forna["annotations"]["elements"].as_str().unwrap();
+        assert_eq!(elements.len(), sequence.len());
+        assert_eq!(elements.matches('s').count(), 2 * best.structure.pairs());
+        assert_eq!(
+            forna["custom_colors"]["color_values"][""]
+                .as_object()
+                .unwrap()
+                .len(),
+            2 * best.structure.pairs()
+        );
+
+        let mut ct = vec![];
+        write_ct(&graph, sequence, "test", best.id, &mut ct).unwrap();
+        let ct = String::from_utf8(ct).unwrap();
+        assert_eq!(ct.lines().count(), sequence.len() + 1);
+        assert!(ct.lines().nth(1).unwrap().starts_with(&format!(
+            "1\tG\t0\t2\t{}\t1\t",
+            best.structure.pair_table().view()[1]
+        )));
+
+        let payload = r2dt_payload(sequence, "test");
+        assert_eq!(payload["sequence"], format!(">test\n{}", sequence));
+    }
+
+    #[test]
+    fn test_graph_exports() {
+        let sequence = "GGGGAAAACCCCAUAUAUGGGGAAAACCCC";
+        let mut graph = RafftConfig::new().folding_graph(sequence);
+        graph.construct_trajectories();
+
+        let n = graph.iter().count();
+        let edges = graph.adjacent_indices().count();
+        let populations = boltzmann_probabilities(&graph, ArrheniusModel::default().kt);
+
+        let value = graph_json(&graph, Some(&populations));
+        assert_eq!(value["nodes"].as_array().unwrap().len(), n);
+        assert_eq!(value["edges"].as_array().unwrap().len(), edges);
+        assert_eq!(value["nodes"][0]["population"], json!(populations[0]));
+        assert!(value["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .zip(graph.iter())
+            .all(|(node, info)| node["elements"]
+                == json!(info.structure.pair_table().element_string())));
+        assert_eq!(
+            value["nodes"][0]["elements"],
+            json!("f".repeat(sequence.len()))
+        );
+        let trajectories = value["trajectories"].as_array().unwrap();
+        assert_eq!(trajectories.len(), graph.leaves().len());
+        assert!(trajectories.iter().all(|trajectory| {
+            trajectory["nodes"][0] == json!(0)
+                && trajectory["nodes"].as_array().unwrap().len()
+                    == trajectory["energy_profile"].as_array().unwrap().len()
+        }));
+        assert!(graph_json(&graph, None)["nodes"][0]
+            .get("population")
+            .is_none());
+
+        let mut dot = vec![];
+        write_dot(&graph, Some(&populations), &mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.starts_with("digraph rafft {"));
+        assert_eq!(dot.matches("population=").count(), n);
+        assert_eq!(dot.matches(" -> ").count(), edges);
+
+        let mut graphml = vec![];
+        write_graphml(&graph, Some(&populations), &mut graphml).unwrap();
+        let graphml = String::from_utf8(graphml).unwrap();
+        assert_eq!(graphml.matches("<node ").count(), n);
+        assert_eq!(graphml.matches("<edge ").count(), edges);
+        assert_eq!(graphml.matches(r#"<data key="population">"#).count(), n);
+
+        let mut graphml = vec![];
+        write_graphml(&graph, None, &mut graphml).unwrap();
+        assert!(!String::from_utf8(graphml).unwrap().contains("population"));
+    }
+}
@@ -1,20 +1,85 @@
 //! This module provides `RafftConfig`, a convenient wrapper type to construct [`crate::folding_graph::RafftGraph`]s.
 //! Note that energy parameters and temperature are set globally (available via CLI, crate root and python bindings)
 
-use crate::encoding::{BasePairWeights, EncodedSequence};
+use crate::analysis::{shape_groups, ShapeGroups};
+use crate::autocorrelation::CorrelationKernel;
+use crate::cancel::CancelToken;
+use crate::encoding::{
+    self, BasePairWeights, EncodedSequence, HairpinEnforcement, PairTable, ScoreNormalization,
+    SeqPos1, StackConstraints, Summation,
+};
+use crate::fft::{self, FftBackend};
 use crate::folding_graph::*;
-use crate::vienna::VCompound;
+use crate::motifs::{self, find_motif, ElementAnnotation, ElementClassifier, MotifMatch};
+#[cfg(feature = "viennarna")]
+use crate::vienna::{ModelOptions, VCompound};
+use rayon::{ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Error type representing errors that may arise while setting up a fast folding graph for a region of a sequence.
+#[derive(Error, Debug)]
+pub enum ContextError {
+    /// The sequence could not be encoded.
+    #[error(transparent)]
+    Encoding(#[from] encoding::Error),
+    /// The region is empty or exceeds the sequence.
+    #[error("invalid region {0:?} for a sequence of length {1}")]
+    InvalidRegion(Range<usize>, usize),
+    /// The frozen structure does not match the length of the sequence.
+    #[error("frozen structure has length {0}, but the sequence has length {1}")]
+    LengthMismatch(usize, usize),
+    /// A pair of the frozen structure involves a position of the region (`1`-indexed).
+    #[error("frozen pair ({0}, {1}) involves the folded region")]
+    PairInRegion(usize, usize),
+}
+
+/// State of the sequence flanking a region folded by [`RafftConfig::folding_graph_in_context()`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlankingContext {
+    /// The flanks remain single-stranded.
+    Unpaired,
+    /// The flanks are frozen in the pairs of the given structure of the complete sequence.
+    /// Pairs must not involve positions of the folded region.
+    Frozen(PairTable),
+}
 
 /// A builder type for [`RafftGraph`] allowing to adjust parameters as necessary and to finally construct
 /// the graph type per individual RNA sequence.
 /// A single `RafftConfig` can be re-used to construct `RafftGraph`s for different sequences.
+#[derive(Clone)]
 pub struct RafftConfig {
-    basepair_weights: BasePairWeights,
-    min_unpaired: usize,
-    min_loop_energy: f64,
-    number_of_lags: usize,
-    number_of_branches: usize,
-    saved_trajectories: usize,
+    pub(crate) basepair_weights: BasePairWeights,
+    pub(crate) min_unpaired: usize,
+    pub(crate) hairpin_enforcement: HairpinEnforcement,
+    pub(crate) max_pair_span: Option<usize>,
+    pub(crate) strict_stacks: bool,
+    pub(crate) integer_scores: bool,
+    pub(crate) min_loop_energy: f64,
+    pub(crate) energy_free: bool,
+    pub(crate) number_of_lags: usize,
+    pub(crate) number_of_branches: usize,
+    pub(crate) saved_trajectories: usize,
+    pub(crate) compact_structures: bool,
+    pub(crate) cache_size: usize,
+    pub(crate) seed: u64,
+    pub(crate) correlation_kernel: CorrelationKernel,
+    pub(crate) fft_backend: Arc<dyn FftBackend>,
+    pub(crate) score_normalization: ScoreNormalization,
+    pub(crate) summation: Summation,
+    pub(crate) stacks_per_lag: usize,
+    pub(crate) branch_ranking: BranchRanking,
+    pub(crate) minimum_distance: usize,
+    pub(crate) trajectory_selection: TrajectorySelection,
+    pub(crate) tracked_target: Option<PairTable>,
+    pub(crate) exhaustive_length: Option<usize>,
+    pub(crate) sampling_temperature: Option<f64>,
+    pub(crate) time_limit: Option<Duration>,
+    pub(crate) cancel_token: Option<CancelToken>,
+    pub(crate) pool: Option<Arc<ThreadPool>>,
+    pub(crate) blocked_regions: Vec<Range<usize>>,
 }
 
 impl Default for RafftConfig {
@@ -26,10 +91,33 @@ impl Default for RafftConfig {
                 GU: 1.0,
             },
             min_unpaired: 3,
+            hairpin_enforcement: HairpinEnforcement::default(),
+            max_pair_span: None,
+            strict_stacks: false,
+            integer_scores: false,
             min_loop_energy: 0.0,
+            energy_free: false,
             number_of_lags: 100,
             number_of_branches: 1000,
             saved_trajectories: 1,
+            compact_structures: false,
+            cache_size: 1024,
+            seed: 0,
+            correlation_kernel: CorrelationKernel::default(),
+            fft_backend: fft::default_backend(),
+            score_normalization: ScoreNormalization::default(),
+            summation: Summation::default(),
+            stacks_per_lag: 1,
+            branch_ranking: BranchRanking::default(),
+            minimum_distance: 0,
+            trajectory_selection: TrajectorySelection::default(),
+            tracked_target: None,
+            exhaustive_length: None,
+            sampling_temperature: None,
+            time_limit: None,
+            cancel_token: None,
+            pool: None,
+            blocked_regions: vec![],
         }
     }
 }
@@ -58,12 +146,68 @@ impl RafftConfig {
         self
     }
 
+    /// Set how candidate stacks continuing into hairpin loops smaller than the
+    /// [minimum](RafftConfig::minimum_unpaired_in_hairpins()) are handled by the stack search.
+    /// By default, they are truncated as in the reference implementation, see [`HairpinEnforcement`].
+    pub fn hairpin_enforcement(mut self, enforcement: HairpinEnforcement) -> Self {
+        self.hairpin_enforcement = enforcement;
+        self
+    }
+
+    /// Restrict the stack search to pairs `(i, j)` with `j - i <= max_pair_span` (local folding).
+    /// Similar to RNALfold's maximum base pair span, this drastically reduces the candidate stacks
+    /// of long sequences, which are otherwise dominated by long-range pairs. By default, spans are not restricted.
+    pub fn max_pair_span(mut self, max_pair_span: usize) -> Self {
+        self.max_pair_span = Some(max_pair_span);
+        self
+    }
+
+    /// Stop the construction of fast folding graphs at the first candidate stack that is inconsistent
+    /// with its fragment, e.g. spanning a concatenation site (see [`EncodedSequence::validate_stack()`]),
+    /// instead of silently skipping it. The error is returned by [`RafftGraph::try_construct_trajectories()`].
+    pub fn strict_stacks(mut self, strict: bool) -> Self {
+        self.strict_stacks = strict;
+        self
+    }
+
+    // Constraints of the stack search per positional lag.
+    pub(crate) fn stack_constraints(&self) -> StackConstraints {
+        StackConstraints {
+            minimal_hairpin: self.min_unpaired,
+            hairpin_enforcement: self.hairpin_enforcement,
+            max_pair_span: self.max_pair_span,
+            integer_scores: self.integer_scores,
+            score_normalization: self.score_normalization,
+            summation: self.summation,
+        }
+    }
+
+    /// Accumulate pairing scores of consecutive pairs in exact integer arithmetic after the positional lags
+    /// were selected, if the base pair weights are integers (as by default).
+    /// This avoids rounding of the scores of long stacks, which may tie or reorder candidates otherwise.
+    pub fn integer_scores(mut self, integer_scores: bool) -> Self {
+        self.integer_scores = integer_scores;
+        self
+    }
+
     /// Set the minimum energy value new loops have to contribute in order to be formed.
     pub fn minimum_loop_energy(mut self, min_loop_energy: f64) -> Self {
         self.min_loop_energy = min_loop_energy;
         self
     }
 
+    /// Construct fast folding graphs without thermodynamic evaluation ("maximum weighted pairing" mode).
+    /// Free energies are replaced by the negative sum of [`BasePairWeights`] of all pairs formed by RAFFT
+    /// (in units of `0.01`, like energies in `dcal/mol`), which are used for acceptance
+    /// (see [`RafftConfig::minimum_loop_energy()`]) and ranking of structures instead.
+    /// This is much faster and sufficient for coarse screens, but local minima and loop decompositions
+    /// still refer to the Nearest-Neighbor model.
+    /// Without the `viennarna` feature, graphs are always constructed energy-free.
+    pub fn energy_free(mut self, energy_free: bool) -> Self {
+        self.energy_free = energy_free;
+        self
+    }
+
     /// Set the number of positional lags between forward and mirrored encoded RNA sequence strands
     /// that should be searched for base pair stacks.
     pub fn positional_lags(mut self, number_of_lags: usize) -> Self {
@@ -71,6 +215,13 @@ impl RafftConfig {
         self
     }
 
+    /// Set the number of disjoint base pair stacks considered per positional lag.
+    /// The default of `1` only considers the best stack per lag, as in the reference implementation.
+    pub fn stacks_per_lag(mut self, stacks_per_lag: usize) -> Self {
+        self.stacks_per_lag = stacks_per_lag.max(1);
+        self
+    }
+
     /// Set the number of branches to be explored during construction of the fast folding graph.
     pub fn maximum_branches(mut self, number_of_branches: usize) -> Self {
         self.number_of_branches = number_of_branches;
@@ -83,27 +234,452 @@ impl RafftConfig {
         self
     }
 
+    /// Set the criterion used to rank the structures of each depth before only the best
+    /// trajectories are kept. See [`BranchRanking`].
+    pub fn branch_ranking(mut self, ranking: BranchRanking) -> Self {
+        self.branch_ranking = ranking;
+        self
+    }
+
+    /// Set the minimum base pair distance between structures kept per depth.
+    /// Structures too similar to a better-ranked structure are discarded, such that the saved
+    /// trajectories cover different basins instead of variants of a single fold.
+    /// Depths may then contain fewer than the number of saved trajectories.
+    /// The default of `0` disables the filter.
+    pub fn minimum_distance(mut self, distance: usize) -> Self {
+        self.minimum_distance = distance;
+        self
+    }
+
+    /// Set additional criteria to select the structures kept per depth, e.g. to maximize their diversity
+    /// or to retain trajectories towards a target structure. See [`TrajectorySelection`].
+    pub fn trajectory_selection(mut self, selection: TrajectorySelection) -> Self {
+        self.trajectory_selection = selection;
+        self
+    }
+
+    /// Track the distance of the search to `target`, e.g. a known native structure parsed from its
+    /// dot-bracket notation. For every depth, the minimum base pair distance of the new structures to the target
+    /// and whether the target itself was reached are recorded in the [statistics](RafftGraph::stats()),
+    /// see also [`RafftGraph::target_reached()`]. The search itself is not affected.
+    /// Targets differing in length from the sequence are ignored.
+    pub fn track_target(mut self, target: PairTable) -> Self {
+        self.tracked_target = Some(target);
+        self
+    }
+
+    /// Construct fast folding graphs of sequences of at most `max_length` nucleotides exhaustively:
+    /// all positional lags and all maximal stacks per lag are searched, and all structures of each depth are kept
+    /// instead of the best [branches](RafftConfig::maximum_branches()) and [trajectories](RafftConfig::maximum_trajectories()).
+    /// This yields a reference result to validate heuristic settings against, see [`RafftGraph::is_exhaustive()`].
+    /// Longer sequences are folded using the heuristic settings. The effort grows exponentially with the length.
+    pub fn exhaustive(mut self, max_length: usize) -> Self {
+        self.exhaustive_length = Some(max_length);
+        self
+    }
+
+    // Return the settings used for a sequence of `length` nucleotides, i.e. without truncation of the search
+    // if it is folded exhaustively.
+    pub(crate) fn for_length(self, length: usize) -> Self {
+        match self.exhaustive_length {
+            Some(max_length) if length <= max_length => Self {
+                number_of_lags: usize::MAX,
+                stacks_per_lag: usize::MAX,
+                number_of_branches: usize::MAX,
+                saved_trajectories: usize::MAX,
+                minimum_distance: 0,
+                trajectory_selection: TrajectorySelection::default(),
+                ..self
+            },
+            _ => self,
+        }
+    }
+
+    /// Sample the structures kept per depth with probabilities proportional to `exp(-dG / kT)` instead
+    /// of keeping the best-ranked ones, where `kt` is given in `kcal/mol` (`0.6163` at 37°C).
+    /// Sampling is reproducible for a given [`seed`](RafftConfig::seed()); constructing graphs for
+    /// different seeds yields an ensemble of RAFFT trajectories.
+    pub fn boltzmann_sampling(mut self, kt: f64) -> Self {
+        self.sampling_temperature = Some(kt);
+        self
+    }
+
+    /// Stop the construction of fast folding graphs once `time_limit` has elapsed.
+    /// Structures found until then are kept and the graph is marked as truncated,
+    /// see [`RafftGraph::is_truncated()`].
+    pub fn time_limit(mut self, time_limit: Duration) -> Self {
+        self.time_limit = Some(time_limit);
+        self
+    }
+
+    /// Stop the construction of fast folding graphs once `token` is cancelled, e.g. from another thread.
+    /// Like for the [time limit](RafftConfig::time_limit()), structures found until then are kept,
+    /// see [`RafftGraph::is_cancelled()`]. Clones of the configuration share the token.
+    pub fn cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel_token = Some(token);
+        self
+    }
+
+    /// Run batch computations (e.g. [`crate::screening::screen_all()`] or [`crate::sweep::sweep()`])
+    /// on a thread pool of `threads` threads instead of the global rayon pool.
+    /// The pool is created once and shared by all clones of the configuration.
+    /// By default, the pool of the caller is used, i.e. the global pool unless called from within another pool,
+    /// such that applications managing their own pools are not affected.
+    /// Returns an error if the pool cannot be created.
+    pub fn threads(mut self, threads: usize) -> Result<Self, ThreadPoolBuildError> {
+        self.pool = Some(Arc::new(
+            ThreadPoolBuilder::new().num_threads(threads).build()?,
+        ));
+        Ok(self)
+    }
+
+    /// Run `op` on the thread pool configured by [`RafftConfig::threads()`], or in the current pool.
+    pub(crate) fn install<R, F>(&self, op: F) -> R
+    where
+        F: FnOnce() -> R + Send,
+        R: Send,
+    {
+        match &self.pool {
+            Some(pool) => pool.install(op),
+            None => op(),
+        }
+    }
+
+    /// Forbid any pairing of the positions in `region` (`0`-indexed, exclusive end), e.g. to simulate
+    /// the footprint of a bound protein or ligand. May be called repeatedly to block several regions.
+    /// Blocked positions are masked in the encoding (see [`EncodedSequence::mask()`]), such that
+    /// no stacks involving them are ever proposed.
+    pub fn block_region(mut self, region: Range<usize>) -> Self {
+        self.blocked_regions.push(region);
+        self
+    }
+
+    /// Store the structures of the fast folding graph as the pairs added to the structures of their parents
+    /// (see [`StoredStructure::Delta`]). This reduces the memory of the structures of long sequences by up to
+    /// an order of magnitude, at the cost of reconstructing pair tables on demand.
+    pub fn compact_structures(mut self, compact: bool) -> Self {
+        self.compact_structures = compact;
+        self
+    }
+
+    /// Set the maximum number of fragments whose stack search results are cached during construction
+    /// of the fast folding graph. Identical fragments recur across branches, e.g. the exterior loop.
+    /// A value of `0` disables caching.
+    pub fn cache_size(mut self, cache_size: usize) -> Self {
+        self.cache_size = cache_size;
+        self
+    }
+
+    /// Set the transformation applied to the autocorrelation of each fragment before positional lags are ranked.
+    /// The choice of lags strongly affects which stacks are found, see [`CorrelationKernel`].
+    pub fn correlation_kernel(mut self, kernel: CorrelationKernel) -> Self {
+        self.correlation_kernel = kernel;
+        self
+    }
+
+    /// Set the FFT backend used to compute (auto)correlations of fragments, see [`crate::fft`].
+    /// Backends only differ in performance and rounding of the correlations.
+    pub fn fft_backend(mut self, backend: Arc<dyn FftBackend>) -> Self {
+        self.fft_backend = backend;
+        self
+    }
+
+    /// Set the normalization of pairing scores of candidate stacks, such that stacks of different lengths
+    /// can be ranked comparably. See [`ScoreNormalization`].
+    /// The normalized scores select the stacks kept per positional lag (see [`RafftConfig::stacks_per_lag()`]).
+    pub fn score_normalization(mut self, normalization: ScoreNormalization) -> Self {
+        self.score_normalization = normalization;
+        self
+    }
+
+    /// Set the summation of pair weights in stack scores and pseudo-energies, see [`Summation`].
+    /// [`Summation::Compensated`] yields identical results across platforms and thread counts,
+    /// which is recommended for published analyses.
+    pub fn summation(mut self, summation: Summation) -> Self {
+        self.summation = summation;
+        self
+    }
+
+    /// Set the seed of the random number generator used by randomized search strategies.
+    /// Construction of the fast folding graph is deterministic otherwise: candidates with equal
+    /// scores or energies are ordered by positional lag or dot-bracket notation, respectively.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Encode `sequence` using the configured base pair weights and blocked regions.
+    pub(crate) fn encode(&self, sequence: &str) -> Result<EncodedSequence, encoding::Error> {
+        let mut encoded = EncodedSequence::with_basepair_weights(sequence, &self.basepair_weights)?;
+
+        for region in &self.blocked_regions {
+            encoded.mask(region.clone());
+        }
+
+        Ok(encoded)
+    }
+
     /// Return an empty [`RafftGraph`] that can be used to construct fast folding trajectories.
+    /// Without the `viennarna` feature, the graph is constructed energy-free (see [`RafftConfig::energy_free()`]).
     pub fn folding_graph(&self, sequence: &str) -> RafftGraph {
-        let fc = VCompound::new(sequence);
+        self.clone()
+            .graph(sequence)
+            .expect("Not a valid RNA Sequence!")
+    }
+
+    // Return an empty graph of `sequence` like `folding_graph()`, reporting invalid sequences.
+    fn graph(self, sequence: &str) -> Result<RafftGraph, encoding::Error> {
+        let encoded = self.encode(sequence)?;
+        let root_structure = PairTable::new(encoded.len());
+
+        Ok(RafftGraph::with_root(
+            vec![encoded],
+            root_structure,
+            fold_compound(sequence),
+            self,
+        ))
+    }
 
-        let encoded = EncodedSequence::with_basepair_weights(sequence, &self.basepair_weights)
-            .expect("Not a valid RNA Sequence!");
+    /// Return an empty [`RafftGraph`] for `sequence`, where stacks are only formed within `region`
+    /// (`0`-indexed, exclusive end) and the flanking sequence is kept single-stranded or frozen in a
+    /// given structure. Free energies are evaluated for the complete sequence, i.e. including the context.
+    /// This allows to study local elements, e.g. riboswitch aptamers, in their native context.
+    pub fn folding_graph_in_context(
+        &self,
+        sequence: &str,
+        region: Range<usize>,
+        context: &FlankingContext,
+    ) -> Result<RafftGraph, ContextError> {
+        let encoded = self.encode(sequence)?;
 
-        RafftGraph::new(
-            encoded,
-            fc,
-            self.min_unpaired,
-            self.min_loop_energy,
-            self.number_of_lags,
-            self.number_of_branches,
-            self.saved_trajectories,
-        )
+        if region.start >= region.end || region.end > encoded.len() {
+            return Err(ContextError::InvalidRegion(region, encoded.len()));
+        }
+
+        let root_structure = match context {
+            FlankingContext::Unpaired => PairTable::new(encoded.len()),
+            FlankingContext::Frozen(frozen) => {
+                if frozen.len() != encoded.len() {
+                    return Err(ContextError::LengthMismatch(frozen.len(), encoded.len()));
+                }
+
+                // 1-indexed pairs vs. 0-indexed region
+                let in_region = |i: usize| region.contains(&SeqPos1(i).to_zero_based().0);
+                if let Some((i, j)) = frozen.paired().find(|&(i, j)| in_region(i) || in_region(j)) {
+                    return Err(ContextError::PairInRegion(i, j));
+                }
+
+                frozen.clone()
+            }
+        };
+
+        let fragment = encoded.subsequence(region.start, region.end);
+
+        Ok(RafftGraph::with_root(
+            vec![fragment],
+            root_structure,
+            fold_compound(sequence),
+            self.clone(),
+        ))
     }
 }
 
+/// Outcome of [`fold_sequence()`]: the constructed fast folding graph and its structure of lowest free energy.
+pub struct RafftResult {
+    sequence: String,
+    graph: RafftGraph,
+    best: RafftNodeId,
+}
+
+impl RafftResult {
+    /// Wrap a constructed fast folding graph, selecting its structure of lowest free energy.
+    pub(crate) fn from_graph(sequence: &str, graph: RafftGraph) -> Self {
+        let best = graph
+            .iter()
+            .min_by_key(|node| node.energy)
+            .expect("the root is always present")
+            .id;
+
+        Self {
+            sequence: sequence.to_string(),
+            graph,
+            best,
+        }
+    }
+
+    /// Return the folded sequence.
+    pub fn sequence(&self) -> &str {
+        &self.sequence
+    }
+
+    /// Return the complete fast folding graph, e.g. to inspect trajectories or export it.
+    pub fn graph(&self) -> &RafftGraph {
+        &self.graph
+    }
+
+    /// Return the fast folding graph, consuming `self`.
+    pub fn into_graph(self) -> RafftGraph {
+        self.graph
+    }
+
+    /// Return the structure of lowest free energy found along all trajectories.
+    pub fn best(&self) -> &RafftNodeInfo {
+        self.graph
+            .node(self.best)
+            .expect("the best structure is part of the graph")
+    }
+
+    /// Return the structure of lowest free energy in dot-bracket notation,
+    /// where strands of complexes are separated by `&`.
+    pub fn structure(&self) -> String {
+        self.best()
+            .structure
+            .pair_table()
+            .to_string_with_cut_points(self.graph.cut_points())
+    }
+
+    /// Return the free energy of the structure of lowest free energy in `kcal/mol`.
+    pub fn energy(&self) -> f64 {
+        self.best().energy as f64 * 0.01
+    }
+
+    /// Return the trajectories grouped by the abstract shapes at `level` (`1` to `5`) of their final structures,
+    /// see [`crate::analysis::shape_groups()`].
+    pub fn shape_groups(&self, level: u8) -> ShapeGroups {
+        shape_groups(&self.graph, level)
+    }
+
+    /// Return the regions of the structures of the graph matching `motif`, e.g. `((((....))))` or `hairpin:4:GNRA`,
+    /// together with the structures and trajectories containing them, see [`crate::motifs`].
+    pub fn find_motif(&self, motif: &str) -> Result<Vec<MotifMatch>, motifs::Error> {
+        Ok(find_motif(&self.graph, &self.sequence, &motif.parse()?))
+    }
+
+    /// Return the structural elements of `classifier` formed by each structure of the graph,
+    /// see [`ElementClassifier::annotate()`].
+    pub fn classify(&self, classifier: &ElementClassifier) -> Vec<ElementAnnotation> {
+        classifier.annotate(&self.graph)
+    }
+
+    /// Re-evaluate all structures of the graph under the energy model of `options`, e.g. at another temperature
+    /// or using DNA parameters, without repeating the search.
+    /// Structures are ranked by free energy (rank `1` is the lowest, ties keep the order of the graph) and
+    /// returned in the order of their new ranks.
+    #[cfg(feature = "viennarna")]
+    pub fn rerank_with(&self, options: &ModelOptions) -> Vec<RerankedStructure> {
+        let nodes: Vec<&RafftNodeInfo> = self.graph.iter().collect();
+        let structures: Vec<PairTable> = nodes
+            .iter()
+            .map(|node| node.structure.pair_table().into_owned())
+            .collect();
+        let energies = VCompound::with_options(&self.sequence, options).eval_many(&structures);
+
+        let old_ranks = energy_ranks(nodes.iter().map(|node| node.energy));
+        let new_ranks = energy_ranks(energies.iter().copied());
+
+        let mut reranked: Vec<RerankedStructure> = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| RerankedStructure {
+                id: node.id,
+                energy: node.energy as f64 * 0.01,
+                rank: old_ranks[i],
+                reranked_energy: energies[i] as f64 * 0.01,
+                new_rank: new_ranks[i],
+            })
+            .collect();
+        reranked.sort_by_key(|structure| structure.new_rank);
+        reranked
+    }
+
+    /// Return a standalone HTML page summarizing the fold, see [`crate::report::html_report()`].
+    #[cfg(feature = "drawing")]
+    pub fn to_html_report(&self) -> String {
+        crate::report::html_report(&self.graph, &self.sequence)
+    }
+}
+
+/// A structure of a fast folding graph re-evaluated under another energy model, see [`RafftResult::rerank_with()`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct RerankedStructure {
+    /// the structure in the graph
+    pub id: RafftNodeId,
+    /// free energy in `kcal/mol` found during the search
+    pub energy: f64,
+    /// rank by `energy`, starting at `1`
+    pub rank: usize,
+    /// free energy in `kcal/mol` under the other energy model
+    pub reranked_energy: f64,
+    /// rank by `reranked_energy`, starting at `1`
+    pub new_rank: usize,
+}
+
+impl RerankedStructure {
+    /// Return the number of ranks the structure gained under the other energy model, negative if it lost ranks.
+    pub fn rank_change(&self) -> isize {
+        self.rank as isize - self.new_rank as isize
+    }
+}
+
+// Rank free energies in ascending order starting at `1`, ties keep their order.
+fn energy_ranks(energies: impl Iterator<Item = i32>) -> Vec<usize> {
+    let mut order: Vec<(usize, i32)> = energies.enumerate().collect();
+    order.sort_by_key(|&(_, energy)| energy);
+
+    let mut ranks = vec![0; order.len()];
+    for (rank, (i, _)) in order.into_iter().enumerate() {
+        ranks[i] = rank + 1;
+    }
+    ranks
+}
+
+/// Fold `sequence` using the default [`RafftConfig`] and return the constructed graph together with
+/// its structure of lowest free energy. This is the shortest way to predict a structure:
+///
+/// ```no_run
+/// let result = rafft::fold_sequence("GGGGAAAACCCC").unwrap();
+/// println!("{} {:.2}", result.structure(), result.energy());
+/// ```
+pub fn fold_sequence(sequence: &str) -> Result<RafftResult, encoding::Error> {
+    fold_sequence_with(sequence, |config| config)
+}
+
+/// Fold `sequence` like [`fold_sequence()`], using the default [`RafftConfig`] modified by `configure`:
+///
+/// ```no_run
+/// let result = rafft::fold_sequence_with("GGGGAAAACCCC", |config| {
+///     config.positional_lags(50).maximum_trajectories(10)
+/// })
+/// .unwrap();
+/// ```
+pub fn fold_sequence_with<F>(sequence: &str, configure: F) -> Result<RafftResult, encoding::Error>
+where
+    F: FnOnce(RafftConfig) -> RafftConfig,
+{
+    let mut graph = configure(RafftConfig::new()).graph(sequence)?;
+    graph.construct_trajectories();
+
+    Ok(RafftResult::from_graph(sequence, graph))
+}
+
+/// Return the ranked candidate stacks RAFFT considers in the first step of folding `sequence` (or a subsequence
+/// of interest) using `config`, without constructing a fast folding graph, see [`RafftGraph::candidate_stacks()`].
+///
+/// ```no_run
+/// for stack in rafft::candidate_stacks("GGGGAAAACCCC", rafft::fast_folding::RafftConfig::new()).unwrap() {
+///     println!("{} ({}, {}) x{} {:.2}", stack.lag, stack.i, stack.j, stack.pairs, stack.delta_energy as f64 * 0.01);
+/// }
+/// ```
+pub fn candidate_stacks(
+    sequence: &str,
+    config: RafftConfig,
+) -> Result<Vec<CandidateStack>, encoding::Error> {
+    Ok(config.graph(sequence)?.candidate_stacks())
+}
+
 mod tests {
     #[test]
+    #[cfg(feature = "viennarna")]
     fn test_folding() {
         use super::RafftConfig;
         let sequence =
@@ -125,4 +701,196 @@ mod tests {
             "..((((((((((((((.((.....))))))))))))).))).(((.........)))((((((.............))))))"
         );
     }
+
+    #[test]
+    fn test_threads() {
+        use super::RafftConfig;
+
+        let config = RafftConfig::new().threads(3).unwrap();
+        assert_eq!(config.install(rayon::current_num_threads), 3);
+
+        // clones share the pool
+        let clone = config.clone().maximum_trajectories(3);
+        assert!(std::sync::Arc::ptr_eq(
+            config.pool.as_ref().unwrap(),
+            clone.pool.as_ref().unwrap()
+        ));
+
+        // the pool of the caller is used by default
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        assert_eq!(
+            pool.install(|| RafftConfig::new().install(rayon::current_num_threads)),
+            2
+        );
+        assert_eq!(
+            pool.install(|| config.install(rayon::current_num_threads)),
+            3
+        );
+    }
+
+    #[test]
+    fn test_block_region() {
+        use super::RafftConfig;
+
+        let sequence = crate::testing::SMALL_SEQUENCE;
+        let mut ffgraph = RafftConfig::new()
+            .maximum_trajectories(3)
+            .block_region(0..4)
+            .block_region(24..26)
+            .folding_graph(sequence);
+        ffgraph.construct_trajectories();
+
+        assert!(ffgraph.iter().count() > 1);
+        assert!(ffgraph.iter().all(|node| node
+            .structure
+            .pair_table()
+            .paired()
+            .all(|(i, j)| i > 4 && !(25..=26).contains(&j))));
+    }
+
+    #[test]
+    fn test_folding_in_context() {
+        use super::{ContextError, FlankingContext, RafftConfig};
+        use crate::encoding::PairTable;
+
+        let sequence = crate::testing::SMALL_SEQUENCE;
+        let config = RafftConfig::new().maximum_trajectories(3);
+
+        let mut ffgraph = config
+            .folding_graph_in_context(sequence, 16..28, &FlankingContext::Unpaired)
+            .unwrap();
+        ffgraph.construct_trajectories();
+
+        assert!(ffgraph.iter().count() > 1);
+        assert!(ffgraph.iter().all(|node| node
+            .structure
+            .pair_table()
+            .paired()
+            .all(|(i, j)| i > 16 && j <= 28)));
+
+        let frozen = "((((....))))................".parse::<PairTable>().unwrap();
+        let mut ffgraph = config
+            .folding_graph_in_context(sequence, 16..28, &FlankingContext::Frozen(frozen.clone()))
+            .unwrap();
+        ffgraph.construct_trajectories();
+
+        assert!(ffgraph.iter().all(|node| frozen.paired().all(|(i, j)| node
+            .structure
+            .pair_table()
+            .view()[i]
+            == j as i16)));
+        assert!(ffgraph
+            .iter()
+            .skip(1)
+            .all(|node| node.energy < ffgraph.iter().next().unwrap().energy));
+
+        assert!(matches!(
+            config.folding_graph_in_context(sequence, 8..20, &FlankingContext::Frozen(frozen)),
+            Err(ContextError::PairInRegion(1, 12))
+        ));
+        assert!(matches!(
+            config.folding_graph_in_context(sequence, 20..40, &FlankingContext::Unpaired),
+            Err(ContextError::InvalidRegion(_, 28))
+        ));
+    }
+
+    #[test]
+    fn test_fold_sequence() {
+        use super::{fold_sequence, fold_sequence_with, RafftConfig};
+        let sequence = crate::testing::SMALL_SEQUENCE;
+
+        let result = fold_sequence(sequence).unwrap();
+        let mut ffgraph = RafftConfig::new().folding_graph(sequence);
+        ffgraph.construct_trajectories();
+        let best = ffgraph.iter().min_by_key(|node| node.energy).unwrap();
+
+        assert_eq!(result.structure(), best.structure.to_string());
+        assert_eq!(result.energy(), best.energy as f64 * 0.01);
+        assert_eq!(result.graph().iter().count(), ffgraph.iter().count());
+
+        let result = fold_sequence_with(sequence, |config| config.maximum_trajectories(1)).unwrap();
+        assert!(result
+            .graph()
+            .iter()
+            .all(|node| node.energy >= result.best().energy));
+
+        let complex = fold_sequence("GGGGAAAA&UUUUCCCC").unwrap();
+        assert_eq!(complex.structure().len(), 17);
+        assert_eq!(complex.structure().find('&'), Some(8));
+
+        assert!(fold_sequence("ACGUX").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "viennarna")]
+    fn test_rerank_with() {
+        use super::fold_sequence;
+        use crate::vienna::{evaluate_structure, ModelOptions, ParameterSet, VCompound};
+        let sequence = crate::testing::SMALL_SEQUENCE;
+        let result = fold_sequence(sequence).unwrap();
+        let structures = result.graph().iter().count();
+
+        let unchanged = result.rerank_with(&ModelOptions::default());
+        assert_eq!(unchanged.len(), structures);
+        assert!(unchanged
+            .iter()
+            .all(|structure| structure.reranked_energy == structure.energy
+                && structure.rank_change() == 0));
+        assert_eq!(unchanged[0].id, result.best().id);
+
+        let options = ModelOptions {
+            temperature: Some(60.0),
+            parameter_set: Some(ParameterSet::DnaMathews2004),
+        };
+        let reranked = result.rerank_with(&options);
+        let vc = VCompound::with_options(sequence, &options);
+        for (new_rank, structure) in reranked.iter().enumerate() {
+            let node = result.graph().node(structure.id).unwrap();
+            let energy = vc.evaluate_structure_f64(node.structure.pair_table().view());
+
+            assert_eq!(structure.new_rank, new_rank + 1);
+            assert_eq!(structure.reranked_energy, energy);
+            assert_eq!(structure.energy, node.energy as f64 * 0.01);
+        }
+        let mut ranks: Vec<usize> = reranked.iter().map(|structure| structure.rank).collect();
+        ranks.sort_unstable();
+        assert_eq!(ranks, (1..=structures).collect::<Vec<_>>());
+
+        // the global parameters are restored
+        let best = result.best().structure.pair_table();
+        assert_eq!(evaluate_structure(sequence, &best), result.best().energy);
+    }
+
+    #[test]
+    #[cfg(feature = "viennarna")]
+    fn test_concurrent_rerank() {
+        use super::{fold_sequence, RafftResult};
+        use crate::vienna::{ModelOptions, ParameterSet};
+        let sequence = crate::testing::SMALL_SEQUENCE;
+        let energies = |result: &RafftResult| -> Vec<i32> {
+            result.graph().iter().map(|node| node.energy).collect()
+        };
+        let expected = energies(&fold_sequence(sequence).unwrap());
+
+        let options = ModelOptions {
+            temperature: None,
+            parameter_set: Some(ParameterSet::DnaMathews2004),
+        };
+        // folds running while DNA parameters are loaded temporarily still use the global RNA parameters
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let result = fold_sequence(sequence).unwrap();
+                let reranked = result.rerank_with(&options);
+                for _ in 0..20 {
+                    assert_eq!(result.rerank_with(&options), reranked);
+                }
+            });
+            for _ in 0..20 {
+                assert_eq!(energies(&fold_sequence(sequence).unwrap()), expected);
+            }
+        });
+    }
 }
@@ -0,0 +1,464 @@
+//! This module abstracts the FFT used to compute (auto)correlations, see [`crate::autocorrelation`],
+//! behind the [`FftBackend`] trait.
+//!
+//! The pure-Rust [`RustFft`] backend (using `realfft`) is always available and the default.
+//! [`PlannedRustFft`] computes the same transforms, but keeps its plans across convolutions, e.g. owned by a
+//! [`RafftSession`](crate::session::RafftSession).
+//! The [`Fftw`] backend links the FFTW library (located by the build script using `pkg-config`) and is
+//! available using the `fftw` feature, e.g. on HPC systems with an optimized FFTW installation.
+//! It caches its plans like [`PlannedRustFft`].
+//! Backends can be selected at runtime by name, see [`backend_by_name()`].
+//!
+//! Sequences are zero-padded to FFT-friendly lengths, see [`padded_length()`], which only affects the
+//! performance: convolutions are truncated to their linear length, such that lags are interpreted as before.
+
+use ndarray::Array1;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// A backend computing linear convolutions of real-valued sequences using FFT.
+pub trait FftBackend: Send + Sync {
+    /// Return the name of the backend, e.g. to report it in results.
+    fn name(&self) -> &'static str;
+
+    /// Return the linear convolution of `a` and `b` of length `a.len() + b.len() - 1`.
+    /// Panics if either sequence is empty.
+    fn convolution(&self, a: &[f64], b: &[f64]) -> Array1<f64>;
+}
+
+impl fmt::Debug for dyn FftBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FftBackend({})", self.name())
+    }
+}
+
+/// Names of the backends available in this build, see [`backend_by_name()`].
+pub const BACKENDS: &[&str] = &[
+    "rustfft",
+    #[cfg(feature = "fftw")]
+    "fftw",
+];
+
+/// Return the backend called `name`, or `None` if it is unknown or not available in this build.
+pub fn backend_by_name(name: &str) -> Option<Arc<dyn FftBackend>> {
+    match name {
+        "rustfft" => Some(Arc::new(RustFft)),
+        #[cfg(feature = "fftw")]
+        "fftw" => Some(Arc::new(Fftw::new())),
+        _ => None,
+    }
+}
+
+/// Return the default backend, i.e. [`RustFft`].
+pub fn default_backend() -> Arc<dyn FftBackend> {
+    Arc::new(RustFft)
+}
+
+/// Return the length transforms of at least `length` values are padded to, i.e. the smallest 5-smooth number
+/// (whose only prime factors are 2, 3 and 5) not less than `length`.
+/// This avoids slow transforms of awkward (e.g. prime) lengths, while padding by a few percent at most
+/// for lengths of sequences.
+pub fn padded_length(length: usize) -> usize {
+    // `usize::is_multiple_of` requires Rust 1.87
+    #[allow(clippy::manual_is_multiple_of)]
+    let smooth = |mut n: usize| {
+        for p in [2, 3, 5] {
+            while n % p == 0 {
+                n /= p;
+            }
+        }
+        n == 1
+    };
+
+    (length.max(1)..)
+        .find(|&n| smooth(n))
+        .expect("5-smooth numbers are unbounded")
+}
+
+// Return `a` zero-padded to `length`.
+fn padded(a: &[f64], length: usize) -> Vec<f64> {
+    a.iter()
+        .cloned()
+        .chain(std::iter::repeat(0f64))
+        .take(length)
+        .collect()
+}
+
+/// The pure-Rust backend using `realfft` (and thereby `rustfft`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RustFft;
+
+impl FftBackend for RustFft {
+    fn name(&self) -> &'static str {
+        "rustfft"
+    }
+
+    fn convolution(&self, a: &[f64], b: &[f64]) -> Array1<f64> {
+        let mut planner = RealFftPlanner::<f64>::new();
+
+        realfft_convolution(a, b, |length| {
+            (
+                planner.plan_fft_forward(length),
+                planner.plan_fft_inverse(length),
+            )
+        })
+    }
+}
+
+/// The pure-Rust backend like [`RustFft`], re-using the plans of transforms of the same length across
+/// convolutions instead of planning each transform anew. Plans are cached until the backend is dropped.
+#[derive(Default)]
+pub struct PlannedRustFft {
+    planner: Mutex<RealFftPlanner<f64>>,
+}
+
+impl PlannedRustFft {
+    /// Create a backend without any plans.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl fmt::Debug for PlannedRustFft {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PlannedRustFft")
+    }
+}
+
+impl FftBackend for PlannedRustFft {
+    fn name(&self) -> &'static str {
+        "rustfft"
+    }
+
+    fn convolution(&self, a: &[f64], b: &[f64]) -> Array1<f64> {
+        realfft_convolution(a, b, |length| {
+            // only planning is serialized, plans are executed concurrently
+            let mut planner = self
+                .planner
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            (
+                planner.plan_fft_forward(length),
+                planner.plan_fft_inverse(length),
+            )
+        })
+    }
+}
+
+type RealFftPlans = (Arc<dyn RealToComplex<f64>>, Arc<dyn ComplexToReal<f64>>);
+
+// Return the linear convolution of `a` and `b` using the forward and inverse plans of a length returned by `plan`.
+fn realfft_convolution<F>(a: &[f64], b: &[f64], plan: F) -> Array1<f64>
+where
+    F: FnOnce(usize) -> RealFftPlans,
+{
+    assert_ne!(a.len(), 0);
+    assert_ne!(b.len(), 0);
+
+    let linear = a.len() + b.len() - 1;
+    let length = padded_length(linear);
+    let (fft, ifft) = plan(length);
+
+    let mut in_a = padded(a, length);
+    let mut in_b = padded(b, length);
+
+    let mut out_a = fft.make_output_vec();
+    let mut out_b = fft.make_output_vec();
+
+    fft.process(&mut in_a, &mut out_a).unwrap();
+    fft.process(&mut in_b, &mut out_b).unwrap();
+
+    let mut in_ab = out_a
+        .iter()
+        .zip(out_b.iter())
+        .map(|(ai, bi)| ai * bi / length as f64)
+        .collect::<Vec<_>>();
+    let mut out_ab = ifft.make_output_vec();
+
+    // For some sequences (like GUGCCUUGCGCCGGGAAACCACGCAAGGGGCGUAUGGCGCGCCGAUGAAGGUGUAGA)
+    // the forward real-to-complex FFT produces non-zero imaginary parts in the first vector component.
+    // This error appears to originate in rustfft or realfft.
+    // However, the non-zero imaginary parts seem to be very small (i.e. approximately zero).
+    // Therefore, we consider this to be non-critical and proceed with the computation.
+    //
+    // Nevertheless, we still want to report on these for now, might be relevent at some point.
+    // This should probably be reported using the `log` crate but the warnings can be piped away using
+    // `2> /dev/null` on the commandline.
+    //
+    // See also https://github.com/HEnquist/realfft/issues/11
+    match ifft.process(&mut in_ab, &mut out_ab) {
+        Ok(()) => (),
+        Err(error) => eprintln!("[fft.rs] INFO: {}", error),
+    }
+
+    out_ab.truncate(linear);
+    Array1::from_vec(out_ab)
+}
+
+#[cfg(feature = "fftw")]
+pub use self::fftw::Fftw;
+
+#[cfg(feature = "fftw")]
+mod fftw {
+    use super::{padded, padded_length, FftBackend};
+    use ndarray::Array1;
+    use std::collections::HashMap;
+    use std::fmt;
+    use std::os::raw::{c_int, c_uint, c_void};
+    use std::sync::{Arc, Mutex};
+
+    // fftw_complex is `double[2]`
+    type Complex = [f64; 2];
+
+    const FFTW_UNALIGNED: c_uint = 1 << 1;
+    const FFTW_ESTIMATE: c_uint = 1 << 6;
+
+    // libfftw3 is linked by the build script
+    extern "C" {
+        fn fftw_plan_dft_r2c_1d(
+            n: c_int,
+            input: *mut f64,
+            output: *mut Complex,
+            flags: c_uint,
+        ) -> *mut c_void;
+        fn fftw_plan_dft_c2r_1d(
+            n: c_int,
+            input: *mut Complex,
+            output: *mut f64,
+            flags: c_uint,
+        ) -> *mut c_void;
+        fn fftw_execute_dft_r2c(plan: *const c_void, input: *mut f64, output: *mut Complex);
+        fn fftw_execute_dft_c2r(plan: *const c_void, input: *mut Complex, output: *mut f64);
+        fn fftw_destroy_plan(plan: *mut c_void);
+    }
+
+    // Only the execution of plans is thread-safe in FFTW, planning and destroying plans are not.
+    static PLANNER: Mutex<()> = Mutex::new(());
+
+    // The forward and inverse plans of transforms of `length`.
+    struct Plans {
+        length: usize,
+        forward: *mut c_void,
+        inverse: *mut c_void,
+    }
+
+    // SAFETY: plans are only executed concurrently, which FFTW supports, and destroyed under the `PLANNER` lock.
+    unsafe impl Send for Plans {}
+    unsafe impl Sync for Plans {}
+
+    impl Plans {
+        fn new(length: usize) -> Self {
+            let mut real = vec![0.0; length];
+            let mut complex = vec![[0.0; 2]; length / 2 + 1];
+
+            let _guard = PLANNER
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            // SAFETY: both buffers are sized as required by FFTW, and FFTW_ESTIMATE does not access them
+            // during planning. The plans are executed on other buffers, whose alignment may differ.
+            let (forward, inverse) = unsafe {
+                (
+                    fftw_plan_dft_r2c_1d(
+                        length as c_int,
+                        real.as_mut_ptr(),
+                        complex.as_mut_ptr(),
+                        FFTW_ESTIMATE | FFTW_UNALIGNED,
+                    ),
+                    fftw_plan_dft_c2r_1d(
+                        length as c_int,
+                        complex.as_mut_ptr(),
+                        real.as_mut_ptr(),
+                        FFTW_ESTIMATE | FFTW_UNALIGNED,
+                    ),
+                )
+            };
+            assert!(
+                !forward.is_null() && !inverse.is_null(),
+                "FFTW failed to plan a transform"
+            );
+
+            Self {
+                length,
+                forward,
+                inverse,
+            }
+        }
+
+        // Return the unnormalized real-to-complex transform of `input`.
+        fn forward(&self, mut input: Vec<f64>) -> Vec<Complex> {
+            assert_eq!(input.len(), self.length);
+            let mut output = vec![[0.0; 2]; self.length / 2 + 1];
+
+            // SAFETY: both buffers are sized like the buffers the plan was created with.
+            unsafe { fftw_execute_dft_r2c(self.forward, input.as_mut_ptr(), output.as_mut_ptr()) };
+
+            output
+        }
+
+        // Return the unnormalized complex-to-real transform of `input`.
+        fn inverse(&self, mut input: Vec<Complex>) -> Vec<f64> {
+            assert_eq!(input.len(), self.length / 2 + 1);
+            let mut output = vec![0.0; self.length];
+
+            // SAFETY: see `Plans::forward()`; the input is destroyed by the transform but not used afterwards.
+            unsafe { fftw_execute_dft_c2r(self.inverse, input.as_mut_ptr(), output.as_mut_ptr()) };
+
+            output
+        }
+    }
+
+    impl Drop for Plans {
+        fn drop(&mut self) {
+            let _guard = PLANNER
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            // SAFETY: the plans are no longer shared and not used afterwards.
+            unsafe {
+                fftw_destroy_plan(self.forward);
+                fftw_destroy_plan(self.inverse);
+            }
+        }
+    }
+
+    /// The backend linking the FFTW library (`libfftw3`), available using the `fftw` feature.
+    /// Like [`PlannedRustFft`](super::PlannedRustFft), it re-uses the plans of transforms of the same length
+    /// across convolutions. Plans are cached until the backend is dropped.
+    #[derive(Default)]
+    pub struct Fftw {
+        plans: Mutex<HashMap<usize, Arc<Plans>>>,
+    }
+
+    impl Fftw {
+        /// Create a backend without any plans.
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        // Return the plans of transforms of `length`, planning them on first use.
+        fn plans(&self, length: usize) -> Arc<Plans> {
+            self.plans
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .entry(length)
+                .or_insert_with(|| Arc::new(Plans::new(length)))
+                .clone()
+        }
+    }
+
+    impl fmt::Debug for Fftw {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("Fftw")
+        }
+    }
+
+    impl FftBackend for Fftw {
+        fn name(&self) -> &'static str {
+            "fftw"
+        }
+
+        fn convolution(&self, a: &[f64], b: &[f64]) -> Array1<f64> {
+            assert_ne!(a.len(), 0);
+            assert_ne!(b.len(), 0);
+
+            let linear = a.len() + b.len() - 1;
+            let length = padded_length(linear);
+            let plans = self.plans(length);
+            let (out_a, out_b) = (
+                plans.forward(padded(a, length)),
+                plans.forward(padded(b, length)),
+            );
+
+            let product = out_a
+                .iter()
+                .zip(out_b.iter())
+                .map(|([ar, ai], [br, bi])| [ar * br - ai * bi, ar * bi + ai * br])
+                .collect();
+
+            let mut convolution = plans.inverse(product);
+            convolution.truncate(linear);
+            Array1::from_vec(convolution) / length as f64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Return the linear convolution of `a` and `b` computed directly.
+    fn direct_convolution(a: &[f64], b: &[f64]) -> Vec<f64> {
+        let mut result = vec![0.0; a.len() + b.len() - 1];
+
+        for (i, x) in a.iter().enumerate() {
+            for (j, y) in b.iter().enumerate() {
+                result[i + j] += x * y;
+            }
+        }
+
+        result
+    }
+
+    #[test]
+    fn test_padded_length() {
+        assert_eq!(padded_length(0), 1);
+        assert_eq!(
+            (1..=16).map(padded_length).collect::<Vec<_>>(),
+            vec![1, 2, 3, 4, 5, 6, 8, 8, 9, 10, 12, 12, 15, 15, 15, 16]
+        );
+        assert_eq!(padded_length(163), 180);
+        assert_eq!(padded_length(1024), 1024);
+        assert_eq!(padded_length(1999), 2000);
+    }
+
+    #[test]
+    fn test_backends() {
+        let a = [0., 1., 0., 0., 2., 3., 0., 1.];
+        let b = [1., 0., 2., 0., 0., 3., 1.];
+        let expected = direct_convolution(&a, &b);
+
+        for name in BACKENDS {
+            let backend = backend_by_name(name).unwrap();
+            assert_eq!(&backend.name(), name);
+
+            let convolution = backend.convolution(&a, &b);
+            assert_eq!(convolution.len(), a.len() + b.len() - 1);
+            assert!(convolution
+                .iter()
+                .zip(&expected)
+                .all(|(x, y)| (x - y).abs() < 1e-9));
+            assert_eq!(backend.convolution(&[2.0], &[3.0]).to_vec(), vec![6.0]);
+        }
+
+        // 7 + 11 - 1 = 17 is prime and padded to 18
+        let (a, b) = ([1.0; 7], [2.0; 11]);
+        assert_eq!(padded_length(a.len() + b.len() - 1), 18);
+        assert!(RustFft
+            .convolution(&a, &b)
+            .iter()
+            .zip(direct_convolution(&a, &b))
+            .all(|(x, y)| (x - y).abs() < 1e-9));
+
+        // cached plans yield the same results
+        let planned = PlannedRustFft::new();
+        for _ in 0..2 {
+            assert_eq!(planned.convolution(&a, &b), RustFft.convolution(&a, &b));
+        }
+        assert_eq!(planned.name(), RustFft.name());
+
+        #[cfg(feature = "fftw")]
+        {
+            let fftw = Fftw::new();
+            for _ in 0..2 {
+                assert!(fftw
+                    .convolution(&a, &b)
+                    .iter()
+                    .zip(RustFft.convolution(&a, &b))
+                    .all(|(x, y)| (x - y).abs() < 1e-9));
+            }
+        }
+
+        assert!(backend_by_name("unknown").is_none());
+        assert_eq!(default_backend().name(), "rustfft");
+    }
+}
@@ -1,19 +1,536 @@
 //! This module provides the core algorithm of RAFFT to construct fast folding graphs.
-use crate::encoding::{EncodedSequence, PairTable};
+use crate::autocorrelation::{correlation_fft_length, FFTS_PER_CORRELATION};
+use crate::encoding::{
+    EncodedSequence, InvalidStack, PairTable, ScoreNormalization, SeqPos0, SeqPos1, Stack,
+};
+use crate::fast_folding::RafftConfig;
+use crate::kinetics::{self, ArrheniusModel, CsrMatrix};
+#[cfg(feature = "viennarna")]
 use crate::vienna::VCompound;
 use itertools::Itertools;
-use petgraph::graph::DiGraph;
-use std::collections::{HashMap, HashSet};
+#[cfg(not(feature = "viennarna"))]
+use ndarray::ArrayView1;
+use petgraph::graph::{DiGraph, Edge, Node, NodeIndex};
+use petgraph::Direction;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::mem::size_of;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-pub use petgraph::graph::NodeIndex;
+/// Structure stored per node in a `RafftGraph`, either as a full [`PairTable`] or as the pairs added to the
+/// structure of its parent. See [`RafftConfig::compact_structures()`].
+/// Stored structures are compared and hashed by their pairs, regardless of their representation.
+#[derive(Clone, Debug)]
+pub enum StoredStructure {
+    /// An uncompressed pair table.
+    Full(PairTable),
+    /// The pairs added to the structure of the parent, reconstructed on demand.
+    Delta(Arc<StructureDelta>),
+}
+
+impl StoredStructure {
+    /// Return the [`PairTable`] of this structure, reconstructing it if necessary.
+    pub fn pair_table(&self) -> Cow<'_, PairTable> {
+        match self {
+            Self::Full(pt) => Cow::Borrowed(pt),
+            Self::Delta(delta) => Cow::Owned(delta.to_pair_table()),
+        }
+    }
+
+    /// Return the number of pairs in the structure.
+    pub fn pairs(&self) -> usize {
+        match self {
+            Self::Full(pt) => pt.pairs(),
+            Self::Delta(delta) => delta.pairs,
+        }
+    }
+
+    // Return the number of bytes allocated on the heap by the stored structure.
+    // Parents of deltas are allocated by their own nodes.
+    fn heap_size(&self) -> usize {
+        match self {
+            Self::Full(pt) => pt.heap_size(),
+            Self::Delta(delta) => {
+                // the strong and weak counts of the `Arc`
+                2 * size_of::<usize>()
+                    + size_of::<StructureDelta>()
+                    + delta.added.len() * size_of::<(i16, i16)>()
+            }
+        }
+    }
+}
+
+impl PartialEq for StoredStructure {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Full(a), Self::Full(b)) => a == b,
+            (Self::Delta(a), Self::Delta(b)) if Arc::ptr_eq(a, b) => true,
+            _ => self.pairs() == other.pairs() && self.pair_table() == other.pair_table(),
+        }
+    }
+}
+
+impl Eq for StoredStructure {}
+
+impl Hash for StoredStructure {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.pair_table().hash(state);
+    }
+}
+
+impl fmt::Display for StoredStructure {
+    /// Write the dot-bracket notation of the structure.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pair_table().to_string())
+    }
+}
+
+/// Pairs of a structure in addition to those of the structure of its parent, see [`StoredStructure::Delta`].
+/// The structures of parents are shared by their children.
+#[derive(Debug)]
+pub struct StructureDelta {
+    // `None` for the root
+    parent: Option<Arc<StructureDelta>>,
+    added: Box<[(i16, i16)]>,
+    length: usize,
+    pairs: usize,
+}
+
+impl StructureDelta {
+    // Store `structure` as the pairs added to `parent`, which are all retained by `structure`.
+    fn new(parent: Option<Arc<StructureDelta>>, structure: &PairTable) -> Self {
+        let parent_structure = parent.as_ref().map(|parent| parent.to_pair_table());
+        let added: Box<[(i16, i16)]> = structure
+            .paired()
+            .filter(|&(i, j)| {
+                !matches!(&parent_structure, Some(pt) if pt.partner(SeqPos1(i)) == Some(SeqPos1(j)))
+            })
+            .map(|(i, j)| (i as i16, j as i16))
+            .collect();
+        let pairs = parent.as_ref().map_or(0, |parent| parent.pairs) + added.len();
+        debug_assert_eq!(pairs, structure.pairs(), "pairs of the parent were removed");
+
+        Self {
+            parent,
+            added,
+            length: structure.len(),
+            pairs,
+        }
+    }
+
+    /// Reconstruct the [`PairTable`] of the structure from the pairs added along its ancestors.
+    pub fn to_pair_table(&self) -> PairTable {
+        let mut pt = PairTable::new(self.length);
+        let mut delta = Some(self);
+
+        while let Some(current) = delta {
+            current.added.iter().for_each(|&(i, j)| pt.insert(i, j));
+            delta = current.parent.as_deref();
+        }
+
+        pt
+    }
+}
+
+// Lookup of the nodes of a graph by their structures, keyed by the hashes of the pair tables instead of the
+// structures themselves. Colliding structures are stored at the next free key, which is sound since entries are
+// never removed (the table is rebuilt instead).
+#[derive(Default)]
+struct NodeTable(HashMap<u64, NodeIndex>);
+
+impl NodeTable {
+    fn key(structure: &PairTable) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        structure.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn get(&self, inner: &DiGraph<RafftNodeInfo, ()>, structure: &PairTable) -> Option<NodeIndex> {
+        let mut key = Self::key(structure);
+
+        while let Some(&index) = self.0.get(&key) {
+            let stored = &inner[index].structure;
+            if stored.pairs() == structure.pairs() && *stored.pair_table() == *structure {
+                return Some(index);
+            }
+            key = key.wrapping_add(1);
+        }
+
+        None
+    }
+
+    // Insert the node `index` of `structure`, which must not be contained yet.
+    fn insert(&mut self, structure: &PairTable, index: NodeIndex) {
+        let mut key = Self::key(structure);
+
+        while self.0.contains_key(&key) {
+            key = key.wrapping_add(1);
+        }
+
+        self.0.insert(key, index);
+    }
+}
+
+/// Criterion to rank the structures of a depth before all but the best
+/// [`saved trajectories`](RafftConfig::maximum_trajectories()) are discarded.
+/// Lower values rank better. Ties are broken by free energy and dot-bracket notation.
+///
+/// Ranking by raw free energy favors structures that formed many pairs early on, even if other
+/// branches would lead to more stable structures eventually.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BranchRanking {
+    /// Free energy of the structure, as in the reference implementation.
+    #[default]
+    Energy,
+    /// Free energy of the structure per paired nucleotide.
+    EnergyPerPairedNucleotide,
+    /// Free energy difference to the parent structure.
+    Improvement,
+    /// Weighted sum of the other criteria.
+    Weighted {
+        /// weight of the free energy
+        energy: f64,
+        /// weight of the free energy per paired nucleotide
+        density: f64,
+        /// weight of the free energy difference to the parent structure
+        improvement: f64,
+    },
+}
+
+impl BranchRanking {
+    /// Return the rank value of a structure with free energy `energy` and `pairs` base pairs
+    /// whose parent structure has free energy `parent_energy` (both in `dcal/mol`).
+    pub fn rank(&self, energy: i32, parent_energy: i32, pairs: usize) -> f64 {
+        let density = if pairs > 0 {
+            energy as f64 / (2 * pairs) as f64
+        } else {
+            0.0
+        };
+        let improvement = (energy - parent_energy) as f64;
+
+        match *self {
+            Self::Energy => energy as f64,
+            Self::EnergyPerPairedNucleotide => density,
+            Self::Improvement => improvement,
+            Self::Weighted {
+                energy: w_energy,
+                density: w_density,
+                improvement: w_improvement,
+            } => w_energy * energy as f64 + w_density * density + w_improvement * improvement,
+        }
+    }
+}
+
+impl FromStr for BranchRanking {
+    type Err = String;
+
+    fn from_str(ranking: &str) -> Result<Self, Self::Err> {
+        match ranking.to_lowercase().as_str() {
+            "energy" => Ok(Self::Energy),
+            "density" => Ok(Self::EnergyPerPairedNucleotide),
+            "improvement" => Ok(Self::Improvement),
+            _ => Err(format!(
+                "unknown branch ranking {:?} (expected one of energy, density, improvement)",
+                ranking
+            )),
+        }
+    }
+}
+
+/// Additional criteria to select the structures kept per depth when it is truncated to the
+/// [saved trajectories](RafftConfig::maximum_trajectories()), see [`RafftConfig::trajectory_selection()`].
+/// By default, the best-ranked structures are kept (see [`BranchRanking`]).
+///
+/// Structures kept by `lowest_energy` or `target` are always included, even beyond the number of saved trajectories.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TrajectorySelection {
+    /// Fill the saved trajectories by maximizing the structural diversity, i.e. by repeatedly adding the structure of
+    /// the largest base pair distance to the structures kept so far (starting from the best-ranked one).
+    pub diversity: bool,
+    /// Always keep the structure of lowest free energy, such that the shallowest trajectory towards low free energies
+    /// is retained regardless of the ranking, sampling or diversity.
+    pub lowest_energy: bool,
+    /// Always keep the best-ranked structure that can still reach this target structure, i.e. whose pairs are all
+    /// contained in the target, such that a trajectory towards the target is retained if one is found.
+    pub target: Option<PairTable>,
+}
+
+impl TrajectorySelection {
+    /// Return whether `structure` can still reach the target structure by forming further stacks.
+    pub fn leads_to_target(&self, structure: &PairTable) -> bool {
+        self.target.as_ref().is_some_and(|target| {
+            target.len() == structure.len()
+                && structure
+                    .paired()
+                    .all(|(i, j)| target.partner(SeqPos1(i)) == Some(SeqPos1(j)))
+        })
+    }
+}
+
+/// Result of a gradient walk from a structure of a [`RafftGraph`] to a local minimum of the
+/// energy landscape, see [`RafftGraph::gradient_walks()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct GradientWalk {
+    /// structure the walk started from
+    pub node: RafftNodeId,
+    /// local minimum reached by the walk
+    pub relaxed: PairTable,
+    /// free energy of the local minimum in `dcal/mol`
+    pub relaxed_energy: i32,
+}
+
+/// A candidate stack considered by the first step of the construction of a [`RafftGraph`],
+/// see [`RafftGraph::candidate_stacks()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CandidateStack {
+    /// positional lag the stack was found at, i.e. the sum of the `0`-indexed positions of its pairs
+    pub lag: usize,
+    /// lower position of the outermost pair (`1`-indexed)
+    pub i: usize,
+    /// upper position of the outermost pair (`1`-indexed)
+    pub j: usize,
+    /// number of consecutive pairs
+    pub pairs: usize,
+    /// pairing score used to select the stack, see [`ScoreNormalization`](crate::encoding::ScoreNormalization)
+    pub score: f64,
+    /// free energy change of forming the stack in `dcal/mol`
+    pub delta_energy: i32,
+    /// whether the stack contributes at least the [minimum loop energy](RafftConfig::minimum_loop_energy()),
+    /// i.e. whether it would be formed
+    pub accepted: bool,
+}
+
+/// Comparison of the structures of two fast folding graphs constructed for sequences of the same length,
+/// e.g. a wild type and a mutant, see [`RafftGraph::diff()`].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct GraphDiff {
+    /// structures present in both graphs as `(node, other_node)`, in the order of [`RafftGraph::iter()`]
+    pub shared: Vec<(RafftNodeId, RafftNodeId)>,
+    /// change of the free energy in `dcal/mol` of each shared structure, from the first to the other graph
+    pub energy_shifts: Vec<i32>,
+    /// structures only present in the first graph
+    pub lost: Vec<RafftNodeId>,
+    /// structures only present in the other graph
+    pub gained: Vec<RafftNodeId>,
+}
+
+/// Search statistics of a single depth of a [`RafftGraph`], see [`RafftGraph::stats()`].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DepthStats {
+    /// depth of the new structures
+    pub depth: usize,
+    /// number of candidate stacks whose energy was evaluated
+    pub stacks_evaluated: usize,
+    /// number of candidate stacks that contributed at least the minimum loop energy
+    pub stacks_accepted: usize,
+    /// number of combined structures that were skipped because they were already known
+    pub duplicates: usize,
+    /// number of FFTs computed for (auto)correlations of fragments
+    pub ffts: usize,
+    /// theoretical cost of the FFTs, i.e. the sum of `n log2(n)` over their lengths `n`
+    pub fft_work: f64,
+    /// number of searches for stacks at a positional lag
+    pub stack_searches: usize,
+    /// number of fragments whose candidate stacks were re-used from the cache
+    pub cache_hits: usize,
+    /// number of free energy evaluations of candidate stacks and combined structures
+    pub energy_evaluations: usize,
+    /// number of new structures inserted into the graph
+    pub structures: usize,
+    /// estimated memory usage of the graph in bytes after this depth, see [`RafftGraph::memory_usage()`]
+    pub memory_bytes: usize,
+    /// lowest free energy of the new structures in `kcal/mol`
+    pub best_energy: Option<f64>,
+    /// median free energy of the new structures in `kcal/mol`
+    pub median_energy: Option<f64>,
+    /// time spent constructing this depth
+    pub elapsed: Duration,
+    /// minimum base pair distance of the new structures to the [tracked target](RafftConfig::track_target())
+    pub target_distance: Option<usize>,
+    /// whether the tracked target is among the new structures
+    pub target_reached: bool,
+}
+
+impl DepthStats {
+    /// Column names of [`RafftGraph::write_stats()`].
+    pub const TSV_HEADER: &'static str = "depth\tstacks_evaluated\tstacks_accepted\tduplicates\tstructures\tbest_energy\tmedian_energy\tseconds\tffts\tfft_work\tstack_searches\tcache_hits\tenergy_evaluations\tmemory_bytes\ttarget_distance\ttarget_reached";
+}
+
+/// Estimated memory usage of a [`RafftGraph`] in bytes, see [`RafftGraph::memory_usage()`].
+/// Allocator overhead and the spare capacity of hash tables are not accounted for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryUsage {
+    /// nodes including their structures
+    pub nodes: usize,
+    /// edges between nodes
+    pub edges: usize,
+    /// encoded fragments of the nodes that remain to be folded
+    pub encodings: usize,
+    /// lookup tables of structures and identifiers
+    pub lookup: usize,
+    /// cached stack search results of fragments, see [`RafftConfig::cache_size()`]
+    pub cache: usize,
+}
+
+impl MemoryUsage {
+    /// Return the total number of bytes.
+    pub fn total(&self) -> usize {
+        self.nodes + self.edges + self.encodings + self.lookup + self.cache
+    }
+}
+
+/// Iterator constructing a [`RafftGraph`] one depth at a time, see [`RafftGraph::construct_iter()`].
+pub struct ConstructIter<'a> {
+    graph: &'a mut RafftGraph,
+    current_nodes: Vec<NodeIndex>,
+    depth: usize,
+    started: bool,
+}
+
+impl ConstructIter<'_> {
+    /// Return the graph constructed so far.
+    pub fn graph(&self) -> &RafftGraph {
+        self.graph
+    }
+}
+
+impl Iterator for ConstructIter<'_> {
+    type Item = (usize, Vec<RafftNodeId>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let graph = &mut *self.graph;
+
+        if !self.started {
+            self.started = true;
+            self.current_nodes = vec![graph.root];
+            return Some((self.depth, vec![graph.inner[graph.root].id]));
+        }
+
+        if self.current_nodes.is_empty() || graph.stop_requested() {
+            self.current_nodes.clear();
+            return None;
+        }
+
+        self.current_nodes = graph.breadth_first_search(&self.current_nodes);
+        self.depth += 1;
+
+        if self.current_nodes.is_empty() {
+            None
+        } else {
+            Some((
+                self.depth,
+                self.current_nodes
+                    .iter()
+                    .map(|&index| graph.inner[index].id)
+                    .collect(),
+            ))
+        }
+    }
+}
+
+/// Progress of the construction of a [`RafftGraph`] after each depth,
+/// see [`RafftGraph::construct_trajectories_with_progress()`].
+#[derive(Debug, Clone, Copy)]
+pub struct DepthProgress<'a> {
+    /// the completed depth
+    pub depth: usize,
+    /// the new structures of this depth in insertion order
+    pub nodes: &'a [&'a RafftNodeInfo],
+    /// estimated memory usage of the graph so far
+    pub memory: MemoryUsage,
+}
+
+/// Results of the stack search for a single fragment for each of the best positional lags of its autocorrelation.
+type StackCandidates = Vec<Stack>;
+
+/// A child of a fragment formed by a single stack: the enclosed and the outer fragment (if any),
+/// the structure and its free energy.
+type FragmentChild = (
+    Option<EncodedSequence>,
+    Option<EncodedSequence>,
+    PairTable,
+    i32,
+);
+
+/// A bounded memoization cache for autocorrelation-based stack searches keyed by the parent
+/// coordinates of a fragment (see [`EncodedSequence::parent_intervals()`]).
+/// Fragments with identical coordinates recur across branches, e.g. the exterior loop.
+/// When full, the oldest entries are evicted first.
+struct FragmentCache {
+    capacity: usize,
+    entries: HashMap<Vec<(usize, usize)>, Arc<StackCandidates>>,
+    order: VecDeque<Vec<(usize, usize)>>,
+}
+
+impl FragmentCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn get(&self, key: &[(usize, usize)]) -> Option<Arc<StackCandidates>> {
+        self.entries.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: Vec<(usize, usize)>, stacks: Arc<StackCandidates>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.order.push_back(key.clone());
+        self.entries.insert(key, stacks);
+    }
+}
+
+/// Opaque identifier of a structure in a [`RafftGraph`].
+/// Identifiers are assigned in insertion order and remain valid when the graph is modified,
+/// e.g. by pruning. Constructing a graph with the same configuration again assigns the same identifiers.
+/// They can be stored using their [`Display`](fmt::Display) representation and parsed back using [`FromStr`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RafftNodeId(u64);
+
+impl fmt::Display for RafftNodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for RafftNodeId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        id.parse().map(Self)
+    }
+}
 
 /// Information stored per Node in a `RafftGraph`
-//#[derive(Clone, Eq, Hash, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct RafftNodeInfo {
+    /// stable identifier of this node
+    pub id: RafftNodeId,
     /// Encoded subsequences for this structure,
     pub sub_nodes: Vec<EncodedSequence>,
     /// structure of this node, corresponds to its parent's structure + stack gained through the corresponding edge
-    pub structure: PairTable,
+    pub structure: StoredStructure,
     /// cached free energy of the structure
     pub energy: i32,
     /// depth of the node, i.e. number of edges starting from the root node
@@ -22,67 +539,148 @@ pub struct RafftNodeInfo {
 
 /// Fast-folding graph containing the folding trajectories and associated information.
 pub struct RafftGraph {
-    pub(crate) inner: DiGraph<RafftNodeInfo, ()>,
-    node_table: HashMap<String, NodeIndex>,
+    inner: DiGraph<RafftNodeInfo, ()>,
+    node_table: NodeTable,
+    id_table: HashMap<RafftNodeId, NodeIndex>,
+    next_id: u64,
     root: NodeIndex,
-    fc: VCompound,
-    min_unpaired: usize,
-    min_loop_energy: f64,
-    number_of_lags: usize,
-    number_of_branches: usize,
-    saved_trajectories: usize,
+    length: usize,
+    // `None` for energy-free graphs constructed without ViennaRNA
+    fc: Option<VCompound>,
+    config: RafftConfig,
+    cache: RefCell<FragmentCache>,
+    rng: StdRng,
+    deadline: Option<Instant>,
+    truncated: bool,
+    cancelled: bool,
+    invalid_stack: Option<InvalidStack>,
+    stats: Vec<DepthStats>,
+    cut_points: Vec<usize>,
+    exhaustive: bool,
+}
+
+// Stand-in for the fold compounds of ViennaRNA, which cannot be constructed without the `viennarna` feature.
+#[cfg(not(feature = "viennarna"))]
+pub(crate) enum VCompound {}
+
+#[cfg(not(feature = "viennarna"))]
+impl VCompound {
+    fn evaluate_structure(&self, _structure: ArrayView1<i16>) -> i32 {
+        match *self {}
+    }
+}
+
+// Return the fold compound evaluating the free energies of `sequence`, `None` without ViennaRNA.
+#[cfg(feature = "viennarna")]
+pub(crate) fn fold_compound(sequence: &str) -> Option<VCompound> {
+    Some(VCompound::new(sequence))
+}
+
+#[cfg(not(feature = "viennarna"))]
+pub(crate) fn fold_compound(_sequence: &str) -> Option<VCompound> {
+    None
 }
 
+// Upper bound of the capacity reserved for the branches of a depth.
+const MAX_BRANCH_CAPACITY: usize = 1 << 12;
+
 impl RafftGraph {
     /// Construct new graph containing only the root node
-    pub fn new(
-        root: EncodedSequence,
-        fold_compound: VCompound,
-        min_unpaired: usize,
-        min_loop_energy: f64,
-        number_of_lags: usize,
-        number_of_branches: usize,
-        saved_trajectories: usize,
+    #[cfg(feature = "viennarna")]
+    pub fn new(root: EncodedSequence, fold_compound: VCompound, config: RafftConfig) -> Self {
+        let root_structure = PairTable::new(root.len());
+        Self::with_root(vec![root], root_structure, Some(fold_compound), config)
+    }
+
+    /// Construct new graph containing only a root node with the given structure, where stacks are
+    /// only searched for within `fragments`.
+    /// Without a fold compound, the graph is constructed energy-free (see [`RafftConfig::energy_free()`]).
+    pub(crate) fn with_root(
+        fragments: Vec<EncodedSequence>,
+        root_structure: PairTable,
+        fold_compound: Option<VCompound>,
+        config: RafftConfig,
     ) -> Self {
+        let exhaustive = matches!(config.exhaustive_length, Some(max_length) if root_structure.len() <= max_length);
+        let mut config = config.for_length(root_structure.len());
+        config.energy_free |= fold_compound.is_none();
         let mut inner = DiGraph::new();
-        let mut node_table = HashMap::new();
+        let mut node_table = NodeTable::default();
+        let mut id_table = HashMap::new();
 
-        let root_structure = PairTable::new(root.len());
-        let root_string = root_structure.to_string();
+        let cut_points = fragments
+            .first()
+            .map(|fragment| fragment.cut_points.clone())
+            .unwrap_or_default();
+        let energy = match &fold_compound {
+            Some(fc) if root_structure.pairs() > 0 && !config.energy_free => {
+                fc.evaluate_structure(root_structure.view())
+            }
+            _ => 0,
+        };
+        let length = root_structure.len();
+        let structure = if config.compact_structures {
+            StoredStructure::Delta(Arc::new(StructureDelta::new(None, &root_structure)))
+        } else {
+            StoredStructure::Full(root_structure.clone())
+        };
 
         let root_info = RafftNodeInfo {
-            sub_nodes: vec![root],
-            structure: root_structure,
-            energy: 0,
+            id: RafftNodeId(0),
+            sub_nodes: fragments,
+            structure,
+            energy,
             depth: 0,
         };
 
         let _root = inner.add_node(root_info);
-        node_table.insert(root_string, _root);
+        node_table.insert(&root_structure, _root);
+        id_table.insert(RafftNodeId(0), _root);
 
         Self {
             inner,
             node_table,
+            id_table,
+            next_id: 1,
             root: _root,
+            length,
             fc: fold_compound,
-            min_unpaired,
-            min_loop_energy,
-            number_of_lags,
-            number_of_branches,
-            saved_trajectories,
+            cache: RefCell::new(FragmentCache::new(config.cache_size)),
+            rng: StdRng::seed_from_u64(config.seed),
+            deadline: None,
+            truncated: false,
+            cancelled: false,
+            invalid_stack: None,
+            stats: vec![],
+            cut_points,
+            exhaustive,
+            config,
         }
     }
 
-    /// Return the `NodeIndex` of the root node.
-    pub fn root(&self) -> NodeIndex {
-        self.root
+    /// Return the fold compound used to evaluate structures, consuming `self`, e.g. to re-use it for another graph.
+    #[cfg(feature = "viennarna")]
+    pub(crate) fn into_fold_compound(self) -> Option<VCompound> {
+        self.fc
+    }
+
+    // Return the fold compound of graphs that are not energy-free.
+    fn fold_compound(&self) -> &VCompound {
+        self.fc
+            .as_ref()
+            .expect("graphs without a fold compound are energy-free")
+    }
+
+    /// Return the [`RafftNodeId`] of the root node.
+    pub fn root(&self) -> RafftNodeId {
+        self.inner[self.root].id
     }
 
     /// Insert a new structure as child of `parent`.
     /// If the structure is already present, the `NodeIndex` of the existing node is returned.
     /// A new edge is added anyway if there was not already an edge starting from `parent`.
     /// Therefore, a `RafftGraph` is usually not a tree.
-    pub fn insert(
+    fn insert(
         &mut self,
         parent: NodeIndex,
         sub_nodes: Vec<EncodedSequence>,
@@ -91,20 +689,36 @@ impl RafftGraph {
     ) -> NodeIndex {
         let depth = self.inner[parent].depth + 1;
 
-        let structure_string = structure.to_string();
+        let node_index = if let Some(index) = self.node_table.get(&self.inner, &structure) {
+            index
+        } else {
+            // nodes are indexed densely in insertion order
+            self.node_table
+                .insert(&structure, NodeIndex::new(self.inner.node_count()));
+
+            let structure = if self.config.compact_structures {
+                let parent = match &self.inner[parent].structure {
+                    StoredStructure::Delta(delta) => Some(Arc::clone(delta)),
+                    StoredStructure::Full(_) => None,
+                };
+                StoredStructure::Delta(Arc::new(StructureDelta::new(parent, &structure)))
+            } else {
+                StoredStructure::Full(structure)
+            };
 
-        let info = RafftNodeInfo {
-            sub_nodes,
-            structure,
-            energy,
-            depth,
-        };
+            let id = RafftNodeId(self.next_id);
+            self.next_id += 1;
+
+            let info = RafftNodeInfo {
+                id,
+                sub_nodes,
+                structure,
+                energy,
+                depth,
+            };
 
-        let node_index = if let Some(index) = self.node_table.get(&structure_string) {
-            *index
-        } else {
             let index = self.inner.add_node(info);
-            self.node_table.insert(structure_string, index);
+            self.id_table.insert(id, index);
 
             index
         };
@@ -113,272 +727,2363 @@ impl RafftGraph {
         node_index
     }
 
-    /// Return whether the fast folding graph already contains a structure with the provided dot-bracket notation.
-    pub fn contains(&self, structure: &str) -> bool {
-        self.node_table.get(structure).is_some()
+    /// Return whether the construction of the fast folding graph was stopped early because the
+    /// [time limit](RafftConfig::time_limit()) was exceeded, it was [cancelled](RafftConfig::cancel_token())
+    /// or an invalid candidate stack was found in [strict mode](RafftConfig::strict_stacks()).
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
     }
-}
 
-impl RafftGraph {
-    /// Construct folding trajectories recursively in a breadth-first fashion, starting from the root.
-    pub fn construct_trajectories(&mut self) {
-        let current_nodes = vec![self.root()];
-        self.breadth_first_search(&current_nodes);
+    /// Return whether the fast folding graph is constructed [exhaustively](RafftConfig::exhaustive()),
+    /// i.e. the sequence is short enough for the search not to be truncated.
+    pub fn is_exhaustive(&self) -> bool {
+        self.exhaustive
     }
 
-    /// Return an iterator over all structures represented as references to [`RafftNodeInfo`] and
-    /// in insertion order (i.e. breadth-first and sorted by energy).
-    pub fn iter(&self) -> impl Iterator<Item = &RafftNodeInfo> + '_ {
-        self.inner.node_weights()
+    /// Return whether the construction of the fast folding graph was stopped early because its
+    /// [cancel token](RafftConfig::cancel_token()) was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled
     }
 
-    /// Return the (Metropolis) transition rates `r(i->j) = min(1, exp(-beta * (dGj - dGi)))` between each pair of connected structures,
-    /// with `beta = k * T`, where `k` is the Boltzmann constant and `T` the absolute temperature.
-    /// The output is in COO format (`(r, i, j)`) and should work nicely with [`scipy.sparse.coo_matrix()`](https://docs.scipy.org/doc/scipy/reference/generated/scipy.sparse.coo_matrix.html#scipy.sparse.coo_matrix).
-    /// The indices correspond to the order of [`fn@iter()`] and the output of `rufft` as well as the python bindings.
-    ///
-    /// Try `beta = 0.61`.
-    #[allow(non_snake_case)]
-    pub fn transition_rates(&self, beta: f64) -> (Vec<f64>, Vec<usize>, Vec<usize>) {
-        // TODO need to store diaganol entries such that rows sum to 0
-        let nnz = self.inner.edge_count() + self.inner.node_count();
-        let mut data: Vec<f64> = Vec::with_capacity(nnz);
-        let mut is: Vec<usize> = Vec::with_capacity(nnz);
-        let mut js: Vec<usize> = Vec::with_capacity(nnz);
+    /// Return the search statistics of each constructed depth, starting at depth `1`.
+    pub fn stats(&self) -> &[DepthStats] {
+        &self.stats
+    }
 
-        // diagonal entries
-        for i in 0..self.inner.node_count() {
-            data.push(0.0);
-            is.push(i);
-            js.push(i);
-        }
+    /// Return the first depth at which the [tracked target](RafftConfig::track_target()) was reached,
+    /// or `None` if it was not reached or no target is tracked.
+    pub fn target_reached(&self) -> Option<usize> {
+        let target = self.tracked_target()?;
 
-        for edge in self.inner.raw_edges() {
-            let dGi = self.inner[edge.source()].energy;
-            let dGj = self.inner[edge.target()].energy;
+        if self.inner[self.root]
+            .structure
+            .pair_table()
+            .distance(target)
+            == 0
+        {
+            return Some(0);
+        }
 
-            let ddG = (dGj - dGi) as f64;
+        self.stats
+            .iter()
+            .find(|stats| stats.target_reached)
+            .map(|stats| stats.depth)
+    }
 
-            let rate = 1.0f64.min((-beta * ddG).exp());
-            let rev_rate = 1.0f64.min((beta * ddG).exp());
+    // The tracked target, if it matches the length of the sequence.
+    fn tracked_target(&self) -> Option<&PairTable> {
+        self.config
+            .tracked_target
+            .as_ref()
+            .filter(|target| target.len() == self.inner[self.root].structure.pair_table().len())
+    }
 
-            data.push(rate);
-            is.push(edge.source().index());
-            js.push(edge.target().index());
+    /// Write the search statistics of each depth as tab-separated values with a header line
+    /// (see [`DepthStats::TSV_HEADER`]). Missing energies and target distances are written as `NA`.
+    pub fn write_stats<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "{}", DepthStats::TSV_HEADER)?;
 
-            data.push(rev_rate);
-            is.push(edge.target().index());
-            js.push(edge.source().index());
+        let format_energy = |energy: Option<f64>| {
+            energy.map_or_else(|| "NA".to_string(), |energy| format!("{:.2}", energy))
+        };
 
-            // diagonal entries
-            data[edge.source().index()] -= rate;
-            data[edge.target().index()] -= rev_rate;
+        for stats in &self.stats {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.6}\t{}\t{:.0}\t{}\t{}\t{}\t{}\t{}\t{}",
+                stats.depth,
+                stats.stacks_evaluated,
+                stats.stacks_accepted,
+                stats.duplicates,
+                stats.structures,
+                format_energy(stats.best_energy),
+                format_energy(stats.median_energy),
+                stats.elapsed.as_secs_f64(),
+                stats.ffts,
+                stats.fft_work,
+                stats.stack_searches,
+                stats.cache_hits,
+                stats.energy_evaluations,
+                stats.memory_bytes,
+                stats
+                    .target_distance
+                    .map_or_else(|| "NA".to_string(), |distance| distance.to_string()),
+                stats.target_reached as u8
+            )?;
         }
 
-        (data, is, js)
+        Ok(())
     }
 
-    /// Return the directed edges `(i, j)` of the fast folding graph, where `i`, `j` are
-    /// the indices of the participating structures.
-    /// This is the sparse COO format with empty weights.
-    /// The indices correspond to the order of [`fn@iter()`] and the output of `rufft` as well as the python bindings.
-    pub fn adjacent_indices(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
-        self.inner
-            .raw_edges()
-            .iter()
-            .map(|edge| (edge.source().index(), edge.target().index()))
+    /// Return the `1`-indexed positions at which a new strand starts if the graph was constructed for a complex,
+    /// see [`EncodedSequence::cut_points()`].
+    pub fn cut_points(&self) -> &[usize] {
+        &self.cut_points
     }
 
-    /// Recursively construct the fast folding graph layer-per-layer.
-    ///
-    /// _Implementation Detail_: This does not need to be done recursively. In fact, this
-    /// implementation is easily translated into an iterative style as the underlying `Vec`-backed
-    /// graph structure is not closely tied to the algorithm.
-    #[allow(clippy::type_complexity)]
-    fn breadth_first_search(&mut self, nodes: &[NodeIndex]) {
-        // Using iterators nested in a for-loop because
-        // nested iterators and borrowing still is elusive to me.
-        // Also, triple-nested Vec is probably not very efficient
-        // `all_children` is a Vec containing all children of the current step
-        // (i.e. for all `structure_id`s' sub-nodes all the inner and outer fragments
-        // that were generated based on global parameters
-        let mut all_children: Vec<
-            Vec<
-                Vec<(
-                    Option<EncodedSequence>,
-                    Option<EncodedSequence>,
-                    PairTable,
-                    i32,
-                )>,
-            >,
-        > = Vec::with_capacity(nodes.len());
+    /// Return whether the fast folding graph already contains a structure with the provided dot-bracket notation.
+    /// Strand separators `&` are ignored.
+    pub fn contains(&self, structure: &str) -> bool {
+        self.find_node(structure).is_some()
+    }
 
-        for structure_id in nodes {
-            let energy = self.inner[*structure_id].energy;
-            let pt = self.inner[*structure_id].structure.clone();
+    /// Return the [`RafftNodeId`] of the structure with the provided dot-bracket notation, if present.
+    /// Strand separators `&` are ignored.
+    pub fn find_node(&self, structure: &str) -> Option<RafftNodeId> {
+        let structure: PairTable = structure.replace('&', "").parse().ok()?;
 
-            all_children.push(
-                self.inner[*structure_id]
-                    .sub_nodes
-                    .iter()
-                    .filter_map(|encoded| {
-                        let children = self.create_children(encoded, energy, &pt);
-                        match children.len() {
-                            0 => None,
-                            _ => Some(children),
-                        }
-                    })
-                    .collect::<Vec<_>>(),
-            );
-        }
+        self.node_table
+            .get(&self.inner, &structure)
+            .map(|index| self.inner[index].id)
+    }
 
-        let mut i_branch = 0;
+    /// Align the structures of this graph with those of `other` by their pairs and report the structures
+    /// that are lost or gained in `other`, and the energy shifts of the shared structures.
+    /// Panics if the graphs were constructed for sequences of different lengths.
+    pub fn diff(&self, other: &RafftGraph) -> GraphDiff {
+        assert_eq!(
+            self.inner[self.root].structure.pair_table().len(),
+            other.inner[other.root].structure.pair_table().len()
+        );
 
-        // unfortunately I seem to need this because I don't want to insert first and then remove unnecessary nodes?
-        // in the reference implementation this gets passed down during recursion
-        // but I think I can leave it locally for now
-        let mut seen: HashSet<String> = HashSet::with_capacity(self.number_of_branches);
+        let mut diff = GraphDiff::default();
 
-        // parent, sub_nodes, structure, energy
-        let mut new_children: Vec<(NodeIndex, Vec<EncodedSequence>, PairTable, i32)> =
-            Vec::with_capacity(self.number_of_branches + nodes.len());
+        for node in self.iter() {
+            match other.find_node(&node.structure.to_string()) {
+                Some(other_id) => {
+                    diff.shared.push((node.id, other_id));
+                    diff.energy_shifts
+                        .push(other.node(other_id).unwrap().energy - node.energy);
+                }
+                None => diff.lost.push(node.id),
+            }
+        }
 
-        for (structure_id, node_children) in nodes.iter().zip(all_children.iter()) {
-            for combined_helix in node_children
-                .iter()
-                .map(|inner| inner.iter())
-                .multi_cartesian_product()
-            {
-                let mut sub_nodes: Vec<EncodedSequence> = vec![];
-                let mut pt = PairTable::new(self.fc.len());
+        diff.gained = other
+            .iter()
+            .filter(|node| !self.contains(&node.structure.to_string()))
+            .map(|node| node.id)
+            .collect();
 
-                for helix_part in combined_helix {
-                    helix_part
-                        .2
-                        .paired()
-                        .for_each(|(i, j)| pt.insert(i as i16, j as i16));
+        diff
+    }
 
-                    if let Some(inner) = &helix_part.0 {
-                        sub_nodes.push(inner.clone());
-                    }
+    /// Return the [`RafftNodeInfo`] of `node`, if present.
+    pub fn node(&self, node: RafftNodeId) -> Option<&RafftNodeInfo> {
+        self.id_table.get(&node).map(|&index| &self.inner[index])
+    }
 
-                    if let Some(outer) = &helix_part.1 {
-                        sub_nodes.push(outer.clone());
-                    }
-                }
+    /// Return an iterator over the structures that were formed by adding a stack to `node`.
+    /// A structure carried over unchanged to the next depth is connected to itself by an edge
+    /// (see [`fn@adjacent_indices()`]), but is not its own child.
+    pub fn children(&self, node: RafftNodeId) -> impl Iterator<Item = RafftNodeId> + '_ {
+        self.neighbors(node, Direction::Outgoing)
+    }
 
-                let structure_string = pt.to_string();
+    /// Return an iterator over the structures `node` was formed from.
+    /// All nodes except the root have at least one parent.
+    pub fn parents(&self, node: RafftNodeId) -> impl Iterator<Item = RafftNodeId> + '_ {
+        self.neighbors(node, Direction::Incoming)
+    }
 
-                if !self.contains(&structure_string) && seen.insert(structure_string) {
-                    i_branch += 1;
+    /// Return a shortest path from `node` to the root, starting with `node` and ending with the root.
+    /// If a structure has several parents, the one of lowest depth is followed and ties are broken
+    /// by insertion order.
+    /// The path is empty if `node` is not part of the graph.
+    pub fn path_to_root(&self, node: RafftNodeId) -> Vec<RafftNodeId> {
+        let mut path = vec![];
+        let mut current = self.id_table.get(&node).copied();
 
-                    let energy = self.fc.evaluate_structure(pt.view());
-                    new_children.push((*structure_id, sub_nodes, pt, energy));
+        while let Some(index) = current {
+            path.push(self.inner[index].id);
+            current = self
+                .parent_indices(index)
+                .min_by_key(|&parent| (self.inner[parent].depth, self.inner[parent].id));
+        }
+
+        path
+    }
+
+    /// Return for each position (`0`-indexed) the depth at which it was first paired along the trajectory
+    /// from the root to `node` (see [`RafftGraph::path_to_root()`]), or `None` if it is unpaired in `node`.
+    /// The result is empty if `node` is not part of the graph.
+    pub fn first_pairing_depths(&self, node: RafftNodeId) -> Vec<Option<usize>> {
+        let mut depths = match self.node(node) {
+            Some(info) => vec![None; info.structure.pair_table().len()],
+            None => return vec![],
+        };
+
+        // pairs are only added along a trajectory, so the first structure containing a pair determines its depth
+        for id in self.path_to_root(node).into_iter().rev() {
+            let info = &self.inner[self.id_table[&id]];
+            for (i, j) in info.structure.pair_table().paired() {
+                depths[SeqPos1(i).to_zero_based().0].get_or_insert(info.depth);
+                depths[SeqPos1(j).to_zero_based().0].get_or_insert(info.depth);
+            }
+        }
+
+        depths
+    }
+
+    /// Return the pairs `(i, j)` (`1`-indexed) of `node` together with the depth at which each pair was formed
+    /// along the trajectory from the root to `node`, i.e. the folding order of the structure.
+    /// The result is empty if `node` is not part of the graph.
+    pub fn pair_formation_depths(&self, node: RafftNodeId) -> Vec<((usize, usize), usize)> {
+        let depths = self.first_pairing_depths(node);
+
+        match self.node(node) {
+            Some(info) => info
+                .structure
+                .pair_table()
+                .paired()
+                .map(|(i, j)| {
+                    (
+                        (i, j),
+                        depths[SeqPos1(i).to_zero_based().0]
+                            .expect("paired positions have a depth"),
+                    )
+                })
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// Return the structures without children, in insertion order.
+    pub fn leaves(&self) -> Vec<RafftNodeId> {
+        self.inner
+            .node_indices()
+            .filter(|&index| self.child_indices(index).next().is_none())
+            .map(|index| self.inner[index].id)
+            .collect()
+    }
+
+    /// Return the local minima of the fast folding graph together with the sizes of their basins, sorted by energy.
+    /// A structure is a local minimum if none of its children has a lower free energy, i.e. adding any of
+    /// the stacks found by RAFFT does not stabilize it. In particular, all leaves are local minima.
+    /// Each structure belongs to the basin of the local minimum reached by repeatedly moving to its
+    /// child of lowest energy (ties are broken by insertion order) as long as this lowers the energy.
+    /// Basin sizes include the local minimum itself.
+    pub fn local_minima(&self) -> Vec<(RafftNodeId, usize)> {
+        let mut basins: HashMap<NodeIndex, usize> = HashMap::new();
+        let mut minima: Vec<Option<NodeIndex>> = vec![None; self.inner.node_count()];
+
+        for start in self.inner.node_indices() {
+            let mut path = vec![];
+            let mut current = start;
+
+            let minimum = loop {
+                if let Some(minimum) = minima[current.index()] {
+                    break minimum;
                 }
 
-                if i_branch >= self.number_of_branches {
-                    break;
+                path.push(current);
+
+                match self
+                    .child_indices(current)
+                    .min_by_key(|&child| (self.inner[child].energy, self.inner[child].id))
+                {
+                    Some(child) if self.inner[child].energy < self.inner[current].energy => {
+                        current = child;
+                    }
+                    _ => break current,
                 }
+            };
+
+            for index in path {
+                minima[index.index()] = Some(minimum);
             }
         }
 
-        // The reference implementation carries _all_ the best structures till the end
-        // Therefore we're adding the previous nodes to the new children
-        for structure_id in nodes {
-            new_children.push((
-                *structure_id,
-                self.inner[*structure_id].sub_nodes.clone(), //vec![],
-                self.inner[*structure_id].structure.clone(),
-                self.inner[*structure_id].energy,
-            ));
+        for minimum in minima.into_iter().flatten() {
+            *basins.entry(minimum).or_insert(0) += 1;
         }
 
-        // sort by energy
-        new_children.sort_by_key(|child| child.3);
-        new_children = new_children[..self.saved_trajectories.min(new_children.len())].to_vec();
+        let mut basins: Vec<_> = basins.into_iter().collect();
+        basins.sort_by_key(|&(index, _)| (self.inner[index].energy, self.inner[index].id));
 
-        let new_nodes: Vec<NodeIndex> = new_children
+        basins
             .into_iter()
-            //.map(|(parent, sub_nodes, pt, energy)| self.insert(parent, sub_nodes, pt, energy))
-            .filter_map(|(parent, sub_nodes, pt, energy)| {
-                let id = self.insert(parent, sub_nodes, pt, energy);
+            .map(|(index, size)| (self.inner[index].id, size))
+            .collect()
+    }
 
-                // We added the previous nodes to new_children.
-                // However, to save some work, we only want to return "real" new children
-                // This .filter_map() does this
-                if id == parent {
-                    None
-                } else {
-                    Some(id)
-                }
+    fn neighbors(
+        &self,
+        node: RafftNodeId,
+        direction: Direction,
+    ) -> impl Iterator<Item = RafftNodeId> + '_ {
+        self.id_table
+            .get(&node)
+            .into_iter()
+            .flat_map(move |&index| {
+                self.inner
+                    .neighbors_directed(index, direction)
+                    .filter(move |&neighbor| neighbor != index)
             })
+            .map(move |index| self.inner[index].id)
+    }
+
+    // Return the children of `index` except itself, i.e. without the self-loop of a structure carried over
+    // unchanged to the next depth.
+    fn child_indices(&self, index: NodeIndex) -> impl Iterator<Item = NodeIndex> + '_ {
+        self.inner
+            .neighbors_directed(index, Direction::Outgoing)
+            .filter(move |&child| child != index)
+    }
+
+    // Return the parents of `index` except itself, see `child_indices()`.
+    fn parent_indices(&self, index: NodeIndex) -> impl Iterator<Item = NodeIndex> + '_ {
+        self.inner
+            .neighbors_directed(index, Direction::Incoming)
+            .filter(move |&parent| parent != index)
+    }
+}
+
+impl RafftGraph {
+    /// Remove all structures with a free energy above `energy` (in `kcal/mol`), except for the root,
+    /// as well as all structures that are no longer connected to the root.
+    /// Returns the number of removed structures.
+    pub fn prune_above(&mut self, energy: f64) -> usize {
+        let mut mapping: Vec<Option<NodeIndex>> = vec![None; self.inner.node_count()];
+        let mut queue = VecDeque::from([self.root]);
+        mapping[self.root.index()] = Some(self.root);
+
+        while let Some(index) = queue.pop_front() {
+            for child in self.inner.neighbors_directed(index, Direction::Outgoing) {
+                if mapping[child.index()].is_none()
+                    && self.inner[child].energy as f64 * 0.01 <= energy
+                {
+                    mapping[child.index()] = Some(child);
+                    queue.push_back(child);
+                }
+            }
+        }
+
+        self.rebuild(mapping, vec![])
+    }
+
+    /// Merge chains of structures with equal free energy into their first structure.
+    /// A structure is merged into its parent if it has no other parent and both have the same energy.
+    /// The children of merged structures become children of the remaining structure.
+    /// Returns the number of merged structures.
+    pub fn merge_isoenergetic_chains(&mut self) -> usize {
+        let mut mapping: Vec<Option<NodeIndex>> = vec![None; self.inner.node_count()];
+
+        // parents are inserted before their children, i.e. representatives are always known
+        for index in self.inner.node_indices() {
+            let mut parents = self.parent_indices(index);
+
+            mapping[index.index()] = match (parents.next(), parents.next()) {
+                (Some(parent), None) if self.inner[parent].energy == self.inner[index].energy => {
+                    mapping[parent.index()]
+                }
+                _ => Some(index),
+            };
+        }
+
+        self.rebuild(mapping, vec![])
+    }
+
+    /// Remove all structures with exactly one parent and one child and connect their parent to their child instead.
+    /// The root and all leaves are retained. Depths of the remaining structures are not changed.
+    /// Returns the number of removed structures.
+    pub fn condense_linear_paths(&mut self) -> usize {
+        let is_linear = |index: NodeIndex| {
+            index != self.root
+                && self.parent_indices(index).count() == 1
+                && self.child_indices(index).count() == 1
+        };
+
+        let mapping: Vec<Option<NodeIndex>> = self
+            .inner
+            .node_indices()
+            .map(|index| if is_linear(index) { None } else { Some(index) })
             .collect();
 
-        if !new_nodes.is_empty() {
-            self.breadth_first_search(&new_nodes);
+        let mut bypasses = vec![];
+        for edge in self.inner.raw_edges() {
+            let (source, mut target) = (edge.source(), edge.target());
+
+            if is_linear(source) || !is_linear(target) {
+                continue;
+            }
+
+            while is_linear(target) {
+                target = self.child_indices(target).next().unwrap();
+            }
+
+            bypasses.push((source, target));
         }
+
+        self.rebuild(mapping, bypasses)
     }
 
-    fn create_children(
-        &self,
-        parent_fragment: &EncodedSequence,
-        reference_energy: i32,
-        parent_structure: &PairTable,
-    ) -> Vec<(
-        Option<EncodedSequence>,
-        Option<EncodedSequence>,
-        PairTable,
-        i32,
-    )> {
-        let corr = parent_fragment.autocorrelation(1.0);
-        let mut corr = corr.iter().enumerate().collect::<Vec<_>>();
-        corr.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap()); // swapping a and b saves me from using `corr.reverse();`
-
-        let mut children: Vec<_> = corr
-            .iter()
-            .take(self.number_of_lags)
-            .filter_map(|(lag, _)| {
-                let (bp, mi, mj, _score) =
-                    parent_fragment.consecutive_pairs_at_lag(*lag, self.min_unpaired);
+    /// Release spare capacity of the nodes, edges, lookup tables and statistics of the graph.
+    pub fn shrink_to_fit(&mut self) {
+        self.inner.shrink_to_fit();
+        self.node_table.0.shrink_to_fit();
+        self.id_table.shrink_to_fit();
+        self.stats.shrink_to_fit();
+    }
 
-                if bp > 0 {
-                    let mut pt = parent_structure.clone();
+    /// Drop all data only needed to construct the graph, i.e. the encoded fragments of the structures and the
+    /// cached stack searches, and release spare capacity (see [`RafftGraph::shrink_to_fit()`]).
+    /// Nodes are always indexed densely in insertion order, such that indices and identifiers are unchanged.
+    /// This reduces the memory footprint of graphs kept for later analysis or serialization,
+    /// see [`RafftGraph::memory_usage()`]. Compacted graphs cannot be extended any further.
+    /// Returns the number of bytes released according to [`RafftGraph::memory_usage()`].
+    pub fn compact(&mut self) -> usize {
+        let before = self.memory_usage().total();
 
-                    (0..bp).for_each(|i| {
-                        pt.insert(
-                            parent_fragment.parent_indices[mi - i] as i16,
-                            parent_fragment.parent_indices[mj + i] as i16,
-                        );
-                    });
+        for node in self.inner.node_weights_mut() {
+            node.sub_nodes = vec![];
+        }
+        *self.cache.get_mut() = FragmentCache::new(self.cache.get_mut().capacity);
+        self.shrink_to_fit();
 
-                    let energy = self.fc.evaluate_structure(pt.view());
+        before - self.memory_usage().total()
+    }
 
-                    if (energy - reference_energy) as f64 * 0.01 < self.min_loop_energy {
-                        let inner = if mj - mi > 1 {
-                            Some(parent_fragment.subsequence(mi + 1, mj))
-                        } else {
-                            None
-                        };
+    /// Rebuild the graph after simplification. Each node is either retained (mapped to itself), merged into
+    /// another retained node or removed (`None`). Edges are re-attached accordingly and `extra_edges` are added.
+    /// Insertion order and node identifiers of retained nodes are preserved.
+    fn rebuild(
+        &mut self,
+        mapping: Vec<Option<NodeIndex>>,
+        extra_edges: Vec<(NodeIndex, NodeIndex)>,
+    ) -> usize {
+        let (nodes, edges) = std::mem::take(&mut self.inner).into_nodes_edges();
+        let mut new_indices: Vec<Option<NodeIndex>> = vec![None; nodes.len()];
 
-                        let outer = if mi + 1 > bp || mj + bp < parent_fragment.len() {
-                            Some(parent_fragment.subsequence(mj + bp, mi + 1 - bp))
-                        } else {
-                            None
-                        };
+        for (index, node) in nodes.into_iter().enumerate() {
+            if mapping[index] == Some(NodeIndex::new(index)) {
+                new_indices[index] = Some(self.inner.add_node(node.weight));
+            }
+        }
 
-                        Some((inner, outer, pt, energy))
-                    } else {
-                        None
-                    }
-                } else {
-                    None
+        let removed = new_indices.len() - self.inner.node_count();
+        let resolve =
+            |index: NodeIndex| mapping[index.index()].and_then(|rep| new_indices[rep.index()]);
+
+        for (source, target) in edges
+            .iter()
+            .map(|edge| (edge.source(), edge.target()))
+            .chain(extra_edges)
+        {
+            if let (Some(new_source), Some(new_target)) = (resolve(source), resolve(target)) {
+                // self-loops of carried over structures are retained, but merging does not add any
+                if new_source != new_target || source == target {
+                    self.inner.update_edge(new_source, new_target, ());
                 }
-            })
+            }
+        }
+
+        self.root = new_indices[self.root.index()].expect("the root is always retained");
+        self.node_table = NodeTable::default();
+        for index in self.inner.node_indices() {
+            self.node_table
+                .insert(&self.inner[index].structure.pair_table(), index);
+        }
+        self.id_table = self
+            .inner
+            .node_indices()
+            .map(|index| (self.inner[index].id, index))
             .collect();
-        // Usually, sorting children seems to be unnecessary
-        // but there are cases where it makes a difference.
-        children.sort_by_key(|child| child.3);
 
-        children
+        removed
+    }
+}
+
+impl RafftGraph {
+    /// Construct folding trajectories recursively in a breadth-first fashion, starting from the root.
+    pub fn construct_trajectories(&mut self) {
+        self.construct_trajectories_with(|_, _| ());
+    }
+
+    /// Construct folding trajectories like [`RafftGraph::construct_trajectories()`] and call `on_depth`
+    /// with the depth and the new structures (in insertion order) as soon as each depth is completed,
+    /// starting with the root at depth `0`.
+    /// This allows to stream partial results of long-running constructions.
+    pub fn construct_trajectories_with<F>(&mut self, mut on_depth: F)
+    where
+        F: FnMut(usize, &[&RafftNodeInfo]),
+    {
+        self.construct_trajectories_with_progress(|progress| {
+            on_depth(progress.depth, progress.nodes)
+        });
+    }
+
+    /// Construct folding trajectories like [`RafftGraph::construct_trajectories_with()`], additionally
+    /// reporting the estimated memory usage of the graph after each depth, see [`DepthProgress`].
+    /// This allows to tune the number of branches and saved trajectories to the available memory.
+    pub fn construct_trajectories_with_progress<F>(&mut self, mut on_depth: F)
+    where
+        F: FnMut(&DepthProgress),
+    {
+        let mut steps = self.construct_iter();
+
+        while let Some((depth, ids)) = steps.next() {
+            let graph = steps.graph();
+            let layer: Vec<&RafftNodeInfo> = ids
+                .iter()
+                .map(|&id| &graph.inner[graph.id_table[&id]])
+                .collect();
+
+            on_depth(&DepthProgress {
+                depth,
+                nodes: &layer,
+                memory: graph.memory_usage(),
+            });
+        }
+    }
+
+    /// Return an iterator constructing folding trajectories like [`RafftGraph::construct_trajectories()`]
+    /// one depth at a time. Each step yields the depth and the identifiers of its new structures in insertion order,
+    /// starting with the root at depth `0`, and the graph can be inspected in between using [`ConstructIter::graph()`].
+    /// This allows to interleave the construction with other work or to stop early on custom criteria
+    /// by dropping the iterator, which keeps the structures found so far.
+    pub fn construct_iter(&mut self) -> ConstructIter<'_> {
+        self.deadline = self
+            .config
+            .time_limit
+            .map(|time_limit| Instant::now() + time_limit);
+
+        ConstructIter {
+            current_nodes: vec![],
+            depth: self.inner[self.root].depth,
+            started: false,
+            graph: self,
+        }
+    }
+
+    /// Construct folding trajectories like [`RafftGraph::construct_trajectories()`], returning the first
+    /// candidate stack that is inconsistent with its fragment if [strict](RafftConfig::strict_stacks()).
+    /// The graph retains the structures found before the invalid stack.
+    pub fn try_construct_trajectories(&mut self) -> Result<(), InvalidStack> {
+        self.try_construct_trajectories_with(|_, _| ())
+    }
+
+    /// Construct folding trajectories like [`RafftGraph::construct_trajectories_with()`], returning the first
+    /// invalid candidate stack like [`RafftGraph::try_construct_trajectories()`].
+    pub fn try_construct_trajectories_with<F>(&mut self, on_depth: F) -> Result<(), InvalidStack>
+    where
+        F: FnMut(usize, &[&RafftNodeInfo]),
+    {
+        self.construct_trajectories_with(on_depth);
+
+        match self.invalid_stack.take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Construct folding trajectories like [`RafftGraph::construct_trajectories_with_progress()`], returning the
+    /// first invalid candidate stack like [`RafftGraph::try_construct_trajectories()`].
+    pub fn try_construct_trajectories_with_progress<F>(
+        &mut self,
+        on_depth: F,
+    ) -> Result<(), InvalidStack>
+    where
+        F: FnMut(&DepthProgress),
+    {
+        self.construct_trajectories_with_progress(on_depth);
+
+        match self.invalid_stack.take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Return the estimated memory usage of the graph, i.e. of its nodes, edges, the encoded fragments
+    /// of its nodes and the cache of stack searches.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let nodes = self.inner.node_count() * size_of::<Node<RafftNodeInfo>>()
+            + self
+                .iter()
+                .map(|node| {
+                    node.structure.heap_size()
+                        + node.sub_nodes.capacity() * size_of::<EncodedSequence>()
+                })
+                .sum::<usize>();
+        let encodings = self
+            .iter()
+            .flat_map(|node| &node.sub_nodes)
+            .map(EncodedSequence::heap_size)
+            .sum();
+        let lookup = self.node_table.0.len() * size_of::<(u64, NodeIndex)>()
+            + self.id_table.len() * size_of::<(RafftNodeId, NodeIndex)>();
+
+        let cache = self.cache.borrow();
+        let key_size = |key: &Vec<(usize, usize)>| {
+            size_of::<Vec<(usize, usize)>>() + key.capacity() * size_of::<(usize, usize)>()
+        };
+        let cache = cache
+            .entries
+            .iter()
+            .map(|(key, stacks)| {
+                key_size(key)
+                    + size_of::<Arc<StackCandidates>>()
+                    + size_of::<StackCandidates>()
+                    + stacks.capacity() * size_of::<Stack>()
+            })
+            .sum::<usize>()
+            + cache.order.iter().map(key_size).sum::<usize>();
+
+        MemoryUsage {
+            nodes,
+            edges: self.inner.edge_count() * size_of::<Edge<()>>(),
+            encodings,
+            lookup,
+            cache,
+        }
+    }
+
+    /// Return an iterator over all structures represented as references to [`RafftNodeInfo`] and
+    /// in insertion order (i.e. breadth-first and sorted by energy).
+    pub fn iter(&self) -> impl Iterator<Item = &RafftNodeInfo> + '_ {
+        self.inner.node_weights()
+    }
+
+    /// Return the (Metropolis) transition rates `r(i->j) = min(1, exp(-beta * (dGj - dGi)))` between each pair of connected structures,
+    /// with `beta = k * T`, where `k` is the Boltzmann constant and `T` the absolute temperature.
+    /// The output is in COO format (`(r, i, j)`) and should work nicely with [`scipy.sparse.coo_matrix()`](https://docs.scipy.org/doc/scipy/reference/generated/scipy.sparse.coo_matrix.html#scipy.sparse.coo_matrix).
+    /// The indices correspond to the order of [`fn@iter()`] and the output of `rufft` as well as the python bindings.
+    ///
+    /// Try `beta = 0.61`.
+    #[allow(non_snake_case)]
+    pub fn transition_rates(&self, beta: f64) -> (Vec<f64>, Vec<usize>, Vec<usize>) {
+        // TODO need to store diaganol entries such that rows sum to 0
+        let nnz = self.inner.edge_count() + self.inner.node_count();
+        let mut data: Vec<f64> = Vec::with_capacity(nnz);
+        let mut is: Vec<usize> = Vec::with_capacity(nnz);
+        let mut js: Vec<usize> = Vec::with_capacity(nnz);
+
+        // diagonal entries
+        for i in 0..self.inner.node_count() {
+            data.push(0.0);
+            is.push(i);
+            js.push(i);
+        }
+
+        for edge in self.inner.raw_edges() {
+            let dGi = self.inner[edge.source()].energy;
+            let dGj = self.inner[edge.target()].energy;
+
+            let ddG = (dGj - dGi) as f64;
+
+            let rate = 1.0f64.min((-beta * ddG).exp());
+            let rev_rate = 1.0f64.min((beta * ddG).exp());
+
+            data.push(rate);
+            is.push(edge.source().index());
+            js.push(edge.target().index());
+
+            data.push(rev_rate);
+            is.push(edge.target().index());
+            js.push(edge.source().index());
+
+            // diagonal entries
+            data[edge.source().index()] -= rate;
+            data[edge.target().index()] -= rev_rate;
+        }
+
+        (data, is, js)
+    }
+
+    /// Return the directed edges `(i, j)` of the fast folding graph, where `i`, `j` are
+    /// the indices of the participating structures.
+    /// This is the sparse COO format with empty weights.
+    /// The indices correspond to the order of [`fn@iter()`] and the output of `rufft` as well as the python bindings.
+    pub fn adjacent_indices(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.inner
+            .raw_edges()
+            .iter()
+            .map(|edge| (edge.source().index(), edge.target().index()))
+    }
+
+    /// Return the adjacency matrix of the directed edges of the fast folding graph, i.e. the entry in row `i`
+    /// and column `j` is `1` if structure `j` is a child of structure `i`, see [`fn@adjacent_indices()`].
+    pub fn adjacency_matrix(&self) -> CsrMatrix {
+        let n = self.inner.node_count();
+
+        CsrMatrix::from_triplets((n, n), self.adjacent_indices().map(|(i, j)| (i, j, 1.0)))
+    }
+
+    /// Return the generator (rate matrix) of the Markov chain with Metropolis rates at `temperature` (in °C)
+    /// along the edges in both directions, see [`kinetics::rates()`]. The diagonal entries are the negative
+    /// total outgoing rates, such that every row sums to `0`.
+    /// The free energies of the structures are not re-evaluated at `temperature`,
+    /// see [`kinetics::temperature_protocol()`] for that.
+    pub fn rate_matrix(&self, temperature: f64) -> CsrMatrix {
+        let n = self.inner.node_count();
+        let rates = kinetics::rates(self, &ArrheniusModel::at_temperature(temperature));
+
+        let mut outflow = vec![0.0; n];
+        for &(i, _, rate) in &rates {
+            outflow[i] -= rate;
+        }
+
+        CsrMatrix::from_triplets(
+            (n, n),
+            rates.into_iter().chain(
+                outflow
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, rate)| (i, i, rate)),
+            ),
+        )
+    }
+
+    /// Return the structures grouped by depth in the layout of the reference implementation of RAFFT.
+    /// Layers containing fewer than the number of saved trajectories are padded with the best
+    /// structures of the previous layer, i.e. duplicate structures are included.
+    /// Each layer is sorted by energy.
+    pub fn compat_layers(&self) -> Vec<(usize, Vec<&RafftNodeInfo>)> {
+        let saved_trajectories = self.config.saved_trajectories;
+        let mut grouped: Vec<(usize, Vec<&RafftNodeInfo>)> = vec![];
+
+        for (depth, nodes) in &self.iter().group_by(|node| node.depth) {
+            let mut nodes = nodes.collect::<Vec<_>>();
+
+            if nodes.len() < saved_trajectories {
+                // layers cannot be padded if all structures are kept, see `RafftConfig::exhaustive()`
+                if let Some(previous) = grouped
+                    .last()
+                    .filter(|previous| previous.1.len() >= saved_trajectories - nodes.len())
+                {
+                    let mut missing_previous =
+                        previous.1[..saved_trajectories - nodes.len()].to_vec();
+
+                    nodes.append(&mut missing_previous);
+                    assert_eq!(nodes.len(), saved_trajectories);
+
+                    nodes.sort_by_key(|node| node.energy);
+                }
+            }
+            grouped.push((depth, nodes));
+        }
+
+        grouped
+    }
+
+    /// Return the candidate stacks the first step of the construction considers for the root,
+    /// without constructing the graph, e.g. to debug unexpected predictions.
+    /// Stacks are ranked by their free energy change like the structures they form.
+    /// Stacks inconsistent with the sequence (see [`EncodedSequence::validate_stack()`]) are not included.
+    pub fn candidate_stacks(&self) -> Vec<CandidateStack> {
+        let root = &self.inner[self.root];
+        let structure = root.structure.pair_table();
+        let mut stats = DepthStats::default();
+
+        let mut candidates: Vec<_> = root
+            .sub_nodes
+            .iter()
+            .flat_map(|fragment| {
+                self.stack_candidates(fragment, &mut stats)
+                    .iter()
+                    .filter(|stack| {
+                        stack.pairs > 0 && fragment.validate_stack(stack, &structure).is_ok()
+                    })
+                    .map(|stack| {
+                        let (pt, energy) =
+                            self.form_stack(fragment, stack, root.energy, &structure, &mut stats);
+                        let (i, j) = (
+                            fragment.parent_position(SeqPos0(stack.i + 1 - stack.pairs)),
+                            fragment.parent_position(SeqPos0(stack.j + stack.pairs - 1)),
+                        );
+                        let delta_energy = energy - root.energy;
+
+                        (
+                            CandidateStack {
+                                lag: stack.i + stack.j,
+                                i: i.0,
+                                j: j.0,
+                                pairs: stack.pairs,
+                                score: stack.score,
+                                delta_energy,
+                                accepted: (delta_energy as f64 * 0.01)
+                                    < self.config.min_loop_energy,
+                            },
+                            pt.to_string(),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        // ties are broken as for structures, see `create_children()`
+        if self.config.score_normalization == ScoreNormalization::Raw {
+            candidates.sort_by(|a, b| (a.0.delta_energy, &a.1).cmp(&(b.0.delta_energy, &b.1)));
+        } else {
+            candidates.sort_by_key(|candidate| candidate.0.delta_energy);
+        }
+        candidates
+            .into_iter()
+            .map(|(candidate, _)| candidate)
+            .collect()
+    }
+
+    /// Relax each structure of the fast folding graph to its nearest local minimum of the energy landscape
+    /// by a steepest descent gradient walk using the move set of ViennaRNA (insertion and deletion of single pairs).
+    /// The results are in the order of [`RafftGraph::iter()`].
+    #[cfg(feature = "viennarna")]
+    pub fn gradient_walks(&self) -> Vec<GradientWalk> {
+        self.iter()
+            .map(|node| {
+                let minimum = self
+                    .fold_compound()
+                    .gradient_walk(node.structure.pair_table().view());
+
+                let mut relaxed = PairTable::new(node.structure.pair_table().len());
+                minimum
+                    .indexed_iter()
+                    .skip(1)
+                    .filter(|(i, &j)| *i < j as usize)
+                    .for_each(|(i, &j)| relaxed.insert(i as i16, j));
+
+                GradientWalk {
+                    node: node.id,
+                    relaxed_energy: self.fold_compound().evaluate_structure(relaxed.view()),
+                    relaxed,
+                }
+            })
+            .collect()
+    }
+
+    /// Construct the next layer of the fast folding graph from the structures `nodes` of the current layer.
+    /// Returns the structures that were newly inserted, which form the next layer.
+    #[allow(clippy::type_complexity)]
+    fn breadth_first_search(&mut self, nodes: &[NodeIndex]) -> Vec<NodeIndex> {
+        let start = Instant::now();
+        let mut stats = DepthStats {
+            depth: self.inner[nodes[0]].depth + 1,
+            ..DepthStats::default()
+        };
+
+        // Using iterators nested in a for-loop because
+        // nested iterators and borrowing still is elusive to me.
+        // Also, triple-nested Vec is probably not very efficient
+        // `all_children` is a Vec containing all children of the current step
+        // (i.e. for all `structure_id`s' sub-nodes all the inner and outer fragments
+        // that were generated based on global parameters
+        let mut all_children: Vec<
+            Vec<
+                Vec<(
+                    Option<EncodedSequence>,
+                    Option<EncodedSequence>,
+                    PairTable,
+                    i32,
+                )>,
+            >,
+        > = Vec::with_capacity(nodes.len());
+
+        for structure_id in nodes {
+            let energy = self.inner[*structure_id].energy;
+            let pt = self.inner[*structure_id]
+                .structure
+                .pair_table()
+                .into_owned();
+            let mut node_children = vec![];
+
+            for encoded in &self.inner[*structure_id].sub_nodes {
+                match self.create_children(encoded, energy, &pt, &mut stats) {
+                    Ok(children) if children.is_empty() => (),
+                    Ok(children) => node_children.push(children),
+                    Err(error) => {
+                        // strict mode: keep the structures found so far
+                        self.invalid_stack = Some(error);
+                        self.truncated = true;
+                        return vec![];
+                    }
+                }
+            }
+
+            all_children.push(node_children);
+        }
+
+        let mut i_branch = 0;
+
+        // unfortunately I seem to need this because I don't want to insert first and then remove unnecessary nodes?
+        // in the reference implementation this gets passed down during recursion
+        // but I think I can leave it locally for now
+        let capacity = self.config.number_of_branches.min(MAX_BRANCH_CAPACITY);
+        let mut seen: HashSet<String> = HashSet::with_capacity(capacity);
+
+        // parent, sub_nodes, structure, energy
+        let mut new_children: Vec<(NodeIndex, Vec<EncodedSequence>, PairTable, i32)> =
+            Vec::with_capacity(capacity + nodes.len());
+
+        for (structure_id, node_children) in nodes.iter().zip(all_children.iter()) {
+            for combined_helix in node_children
+                .iter()
+                .map(|inner| inner.iter())
+                .multi_cartesian_product()
+            {
+                // keep the structures found so far and finish the current depth gracefully
+                if self.stop_requested() {
+                    break;
+                }
+
+                let mut sub_nodes: Vec<EncodedSequence> = vec![];
+                let mut pt = PairTable::new(self.length);
+                let parent_energy = self.inner[*structure_id].energy;
+                // pairing energies of the helices of different fragments are additive
+                let mut pairing_energy = parent_energy;
+
+                for helix_part in combined_helix {
+                    helix_part
+                        .2
+                        .paired()
+                        .for_each(|(i, j)| pt.insert(i as i16, j as i16));
+
+                    if let Some(inner) = &helix_part.0 {
+                        sub_nodes.push(inner.clone());
+                    }
+
+                    if let Some(outer) = &helix_part.1 {
+                        sub_nodes.push(outer.clone());
+                    }
+
+                    pairing_energy += helix_part.3 - parent_energy;
+                }
+
+                if self.node_table.get(&self.inner, &pt).is_none() && seen.insert(pt.to_string()) {
+                    i_branch += 1;
+
+                    let energy = if self.config.energy_free {
+                        pairing_energy
+                    } else {
+                        stats.energy_evaluations += 1;
+                        self.fold_compound().evaluate_structure(pt.view())
+                    };
+                    new_children.push((*structure_id, sub_nodes, pt, energy));
+                } else {
+                    stats.duplicates += 1;
+                }
+
+                if i_branch >= self.config.number_of_branches {
+                    break;
+                }
+            }
+        }
+
+        // The reference implementation carries _all_ the best structures till the end
+        // Therefore we're adding the previous nodes to the new children
+        for structure_id in nodes {
+            new_children.push((
+                *structure_id,
+                self.inner[*structure_id].sub_nodes.clone(), //vec![],
+                self.inner[*structure_id]
+                    .structure
+                    .pair_table()
+                    .into_owned(),
+                self.inner[*structure_id].energy,
+            ));
+        }
+
+        // sort by rank, ties are broken by energy and dot-bracket notation to not depend on iteration order
+        let ranking = self.config.branch_ranking;
+        let mut ranked: Vec<_> = new_children
+            .into_iter()
+            .map(|child| {
+                let rank = ranking.rank(child.3, self.inner[child.0].energy, child.2.pairs());
+                (rank, child.3, child.2.to_string(), child)
+            })
+            .collect();
+        ranked.sort_by(|a, b| {
+            a.0.total_cmp(&b.0)
+                .then_with(|| a.1.cmp(&b.1))
+                .then_with(|| a.2.cmp(&b.2))
+        });
+        new_children = ranked.into_iter().map(|(_, _, _, child)| child).collect();
+
+        if let Some(kt) = self.config.sampling_temperature {
+            // Weighted sampling without replacement (Efraimidis & Spirakis):
+            // sorting by u^(1/w) with weights w = exp(-dE / kT) relative to the lowest energy
+            let minimum_energy = new_children.iter().map(|child| child.3).min().unwrap_or(0);
+            let mut keyed: Vec<_> = new_children
+                .into_iter()
+                .map(|child| {
+                    let u: f64 = 1.0 - self.rng.gen::<f64>();
+                    let delta = (child.3 - minimum_energy) as f64 * 0.01;
+                    (u.ln() * (delta / kt).exp(), child)
+                })
+                .collect();
+            keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+            new_children = keyed.into_iter().map(|(_, child)| child).collect();
+        }
+
+        new_children = self.select_saved(new_children);
+
+        if self.config.sampling_temperature.is_some() {
+            // keep the layer sorted by energy regardless of sampling order
+            new_children.sort_by_cached_key(|child| (child.3, child.2.to_string()));
+        }
+
+        let new_nodes: Vec<NodeIndex> = new_children
+            .into_iter()
+            //.map(|(parent, sub_nodes, pt, energy)| self.insert(parent, sub_nodes, pt, energy))
+            .filter_map(|(parent, sub_nodes, pt, energy)| {
+                let id = self.insert(parent, sub_nodes, pt, energy);
+
+                // We added the previous nodes to new_children.
+                // However, to save some work, we only want to return "real" new children
+                // This .filter_map() does this
+                if id == parent {
+                    None
+                } else {
+                    Some(id)
+                }
+            })
+            .collect();
+
+        let mut energies: Vec<i32> = new_nodes
+            .iter()
+            .map(|&index| self.inner[index].energy)
+            .collect();
+        energies.sort_unstable();
+
+        stats.structures = new_nodes.len();
+        stats.best_energy = energies.first().map(|&energy| energy as f64 * 0.01);
+        stats.median_energy = match energies.len() {
+            0 => None,
+            n if n % 2 == 1 => Some(energies[n / 2] as f64 * 0.01),
+            n => Some((energies[n / 2 - 1] + energies[n / 2]) as f64 * 0.005),
+        };
+        stats.target_distance = self.tracked_target().and_then(|target| {
+            new_nodes
+                .iter()
+                .map(|&index| self.inner[index].structure.pair_table().distance(target))
+                .min()
+        });
+        stats.target_reached = stats.target_distance == Some(0);
+        stats.elapsed = start.elapsed();
+        stats.memory_bytes = self.memory_usage().total();
+        self.stats.push(stats);
+
+        new_nodes
+    }
+
+    // Select the saved trajectories among the ordered candidates of a depth according to the minimum distance
+    // and the trajectory selection, retaining their order.
+    fn select_saved<T>(
+        &self,
+        candidates: Vec<(NodeIndex, T, PairTable, i32)>,
+    ) -> Vec<(NodeIndex, T, PairTable, i32)> {
+        let selection = &self.config.trajectory_selection;
+        let saved = self.config.saved_trajectories;
+        let minimum_distance = self.config.minimum_distance;
+
+        if !selection.diversity
+            && !selection.lowest_energy
+            && selection.target.is_none()
+            && minimum_distance == 0
+        {
+            let mut candidates = candidates;
+            candidates.truncate(saved);
+            return candidates;
+        }
+
+        // indices of the kept candidates
+        let mut kept: Vec<usize> = vec![];
+
+        let distant = |kept: &[usize], k: usize| {
+            kept.iter()
+                .all(|&l| candidates[l].2.distance(&candidates[k].2) >= minimum_distance)
+        };
+
+        if selection.diversity {
+            // farthest-point selection starting from the best-ranked structure, ties are broken by order
+            while kept.len() < saved {
+                let farthest = (0..candidates.len())
+                    .filter(|&k| !kept.contains(&k) && distant(&kept, k))
+                    .map(|k| {
+                        let distance = kept
+                            .iter()
+                            .map(|&l| candidates[l].2.distance(&candidates[k].2))
+                            .min()
+                            .unwrap_or(usize::MAX);
+                        (distance, std::cmp::Reverse(k))
+                    })
+                    .max();
+
+                match farthest {
+                    Some((_, std::cmp::Reverse(k))) => kept.push(k),
+                    None => break,
+                }
+            }
+        } else {
+            // greedily keep the best structures that are sufficiently distant from all better ones
+            for k in 0..candidates.len() {
+                if kept.len() >= saved {
+                    break;
+                }
+
+                if distant(&kept, k) {
+                    kept.push(k);
+                }
+            }
+        }
+
+        // structures that are always kept, in addition to the saved ones
+        if selection.lowest_energy {
+            kept.extend(
+                candidates
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, child)| child.3)
+                    .map(|(k, _)| k),
+            );
+        }
+        kept.extend(
+            candidates
+                .iter()
+                .position(|child| selection.leads_to_target(&child.2)),
+        );
+
+        kept.sort_unstable();
+        kept.dedup();
+        let mut candidates: Vec<_> = candidates.into_iter().map(Some).collect();
+        kept.into_iter()
+            .filter_map(|k| candidates[k].take())
+            .collect()
+    }
+
+    // Check the cancel token and the time limit, and mark the graph as truncated if either applies.
+    fn stop_requested(&mut self) -> bool {
+        if matches!(&self.config.cancel_token, Some(token) if token.is_cancelled()) {
+            self.cancelled = true;
+        }
+
+        if self.cancelled || matches!(self.deadline, Some(deadline) if Instant::now() >= deadline) {
+            self.truncated = true;
+        }
+
+        self.truncated
+    }
+
+    fn create_children(
+        &self,
+        parent_fragment: &EncodedSequence,
+        reference_energy: i32,
+        parent_structure: &PairTable,
+        stats: &mut DepthStats,
+    ) -> Result<Vec<FragmentChild>, InvalidStack> {
+        let stacks = self.stack_candidates(parent_fragment, stats);
+
+        // stacks inconsistent with the fragment are skipped unless in strict mode
+        let mut valid_stacks = Vec::with_capacity(stacks.len());
+        for stack in stacks.iter() {
+            match parent_fragment.validate_stack(stack, parent_structure) {
+                Ok(()) => valid_stacks.push(stack),
+                Err(error) if self.config.strict_stacks => return Err(error),
+                Err(_) => (),
+            }
+        }
+
+        let mut children: Vec<_> = valid_stacks
+            .into_iter()
+            .filter_map(|stack| {
+                let (bp, mi, mj) = (stack.pairs, stack.i, stack.j);
+
+                if bp > 0 {
+                    let (pt, energy) = self.form_stack(
+                        parent_fragment,
+                        stack,
+                        reference_energy,
+                        parent_structure,
+                        stats,
+                    );
+
+                    if (energy - reference_energy) as f64 * 0.01 < self.config.min_loop_energy {
+                        stats.stacks_accepted += 1;
+
+                        let inner = if mj - mi > 1 {
+                            Some(parent_fragment.subsequence(mi + 1, mj))
+                        } else {
+                            None
+                        };
+
+                        let outer = if mi + 1 > bp || mj + bp < parent_fragment.len() {
+                            Some(parent_fragment.subsequence(mj + bp, mi + 1 - bp))
+                        } else {
+                            None
+                        };
+
+                        Some((inner, outer, pt, energy))
+                    } else {
+                        None
+                    }
+                } else {
+                    None
+                }
+            })
+            .collect();
+        // Usually, sorting children seems to be unnecessary
+        // but there are cases where it makes a difference.
+        // Ties are broken by dot-bracket notation, or by the normalized scores of the stacks
+        // ranked across lags (see `stack_candidates()`).
+        if self.config.score_normalization == ScoreNormalization::Raw {
+            children.sort_by_cached_key(|child| (child.3, child.2.to_string()));
+        } else {
+            children.sort_by_key(|child| child.3);
+        }
+
+        Ok(children)
+    }
+
+    /// Return the structure formed by adding `stack` of `fragment` to `parent_structure` and its free energy
+    /// (or the negative pair weights in energy-free mode, relative to `reference_energy`).
+    fn form_stack(
+        &self,
+        fragment: &EncodedSequence,
+        stack: &Stack,
+        reference_energy: i32,
+        parent_structure: &PairTable,
+        stats: &mut DepthStats,
+    ) -> (PairTable, i32) {
+        let (bp, mi, mj) = (stack.pairs, stack.i, stack.j);
+        let mut pt = parent_structure.clone();
+
+        (0..bp).for_each(|i| {
+            pt.insert_pair(
+                fragment.parent_position(SeqPos0(mi - i)),
+                fragment.parent_position(SeqPos0(mj + i)),
+            );
+        });
+
+        let energy = if self.config.energy_free {
+            // negative pair weights of the stack in units of 0.01
+            let weight = self
+                .config
+                .summation
+                .sum((0..bp).map(|i| fragment.pair_weight(mi - i, mj + i)));
+            reference_energy - (weight * 100.0).round() as i32
+        } else {
+            stats.energy_evaluations += 1;
+            self.fold_compound().evaluate_structure(pt.view())
+        };
+        stats.stacks_evaluated += 1;
+
+        (pt, energy)
+    }
+
+    /// Search for stacks at the best positional lags of the fragment's autocorrelation,
+    /// re-using cached results for fragments that have been searched before.
+    /// Normalized scores are comparable across lags, so stacks are ranked by them unless they are raw.
+    /// For fragments spanning several strands of a complex, the best lags of the cross-correlations
+    /// of each pair of strands are searched as well to select inter-strand helices.
+    fn stack_candidates(
+        &self,
+        fragment: &EncodedSequence,
+        stats: &mut DepthStats,
+    ) -> Arc<StackCandidates> {
+        let key = fragment.parent_intervals();
+
+        if let Some(stacks) = self.cache.borrow().get(&key) {
+            stats.cache_hits += 1;
+            return stacks;
+        }
+
+        let mut count_ffts = |a: usize, b: usize| {
+            let n = correlation_fft_length(a, b) as f64;
+            stats.ffts += FFTS_PER_CORRELATION;
+            stats.fft_work += FFTS_PER_CORRELATION as f64 * n * n.log2();
+        };
+
+        count_ffts(fragment.len(), fragment.len());
+        let kernel = &self.config.correlation_kernel;
+        let mut lags = kernel
+            .apply(&fragment.autocorrelation_with(1.0, &*self.config.fft_backend))
+            .top_lags(self.config.number_of_lags);
+
+        for (a, b) in fragment.strand_segments().into_iter().tuple_combinations() {
+            let offset = a.start + b.start;
+            let (a, b) = (
+                fragment.subsequence(a.start, a.end),
+                fragment.subsequence(b.start, b.end),
+            );
+            count_ffts(a.len(), b.len());
+
+            lags.extend(
+                kernel
+                    .apply(&a.cross_correlation_with(&b, 1.0, &*self.config.fft_backend))
+                    .top_lags(self.config.number_of_lags)
+                    .into_iter()
+                    .map(|lag| lag + offset),
+            );
+        }
+
+        let lags: Vec<usize> = lags.into_iter().unique().collect();
+        stats.stack_searches += lags.len();
+
+        let mut stacks: StackCandidates = lags
+            .into_iter()
+            .flat_map(|lag| {
+                if self.config.stacks_per_lag == 1 {
+                    vec![fragment
+                        .consecutive_pairs_at_lag_with(lag, self.config.stack_constraints())]
+                } else {
+                    fragment.stacks_at_lag_with(
+                        lag,
+                        self.config.stacks_per_lag,
+                        self.config.stack_constraints(),
+                    )
+                }
+            })
+            .collect();
+
+        if self.config.score_normalization != ScoreNormalization::Raw {
+            // normalized scores are never NaN, ties keep the order of the lags
+            stacks.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+        }
+
+        let stacks = Arc::new(stacks);
+        self.cache.borrow_mut().insert(key, stacks.clone());
+        stacks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BranchRanking, DepthStats, StoredStructure};
+    use crate::autocorrelation::{correlation_fft_length, FFTS_PER_CORRELATION};
+    use crate::cancel::CancelToken;
+    use crate::encoding::{EncodedSequence, ExteriorFragments};
+    use crate::fast_folding::RafftConfig;
+    use itertools::Itertools;
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    #[test]
+    fn test_reproducibility() {
+        let sequence =
+            "GGGUUUGCGGUGUAAGUGCAGCCCGUCUUACACCGUGCGGCACAGGCACUAGUACUGAUGUCGUAUACAGGGCUUUUGACAU";
+        let config = RafftConfig::new().maximum_trajectories(10);
+
+        let trajectories = || {
+            let mut ffgraph = config.folding_graph(sequence);
+            ffgraph.construct_trajectories();
+            ffgraph
+                .iter()
+                .map(|node| (node.structure.to_string(), node.energy))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(trajectories(), trajectories());
+    }
+
+    #[test]
+    fn test_untied_order() {
+        let sequence =
+            "GGGUUUGCGGUGUAAGUGCAGCCCGUCUUACACCGUGCGGCACAGGCACUAGUACUGAUGUCGUAUACAGGGCUUUUGACAU";
+
+        // lags with distinct correlations are ranked as by the former stable sort by decreasing correlation
+        let correlation = EncodedSequence::new(sequence).unwrap().autocorrelation(1.0);
+        let mut reference: Vec<(usize, f64)> =
+            correlation.view().iter().copied().enumerate().collect();
+        reference.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        assert_eq!(
+            correlation.ranked_lags(),
+            reference
+                .into_iter()
+                .map(|(lag, _)| lag)
+                .collect::<Vec<_>>()
+        );
+
+        // structures of a depth are ordered by energy as before, dot-bracket notation only breaks ties
+        let config = RafftConfig::new().maximum_trajectories(10);
+        let mut ffgraph = config.folding_graph(sequence);
+        ffgraph.construct_trajectories();
+        for (_, layer) in &ffgraph.iter().group_by(|node| node.depth) {
+            let layer: Vec<_> = layer
+                .map(|node| (node.energy, node.structure.to_string()))
+                .collect();
+            assert!(layer.windows(2).all(|pair| pair[0] <= pair[1]));
+        }
+
+        // the seed only affects randomized strategies, e.g. sampling
+        let structures = |config: RafftConfig| {
+            let mut ffgraph = config.folding_graph(sequence);
+            ffgraph.construct_trajectories();
+            ffgraph
+                .iter()
+                .map(|node| node.structure.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            structures(config.clone().seed(1)),
+            structures(config.clone().seed(2))
+        );
+    }
+
+    #[test]
+    fn test_queries() {
+        let sequence =
+            "GGGUUUGCGGUGUAAGUGCAGCCCGUCUUACACCGUGCGGCACAGGCACUAGUACUGAUGUCGUAUACAGGGCUUUUGACAU";
+        let mut ffgraph = RafftConfig::new()
+            .maximum_trajectories(5)
+            .folding_graph(sequence);
+        ffgraph.construct_trajectories();
+
+        let root = ffgraph.root();
+        assert_eq!(ffgraph.find_node(&".".repeat(sequence.len())), Some(root));
+        assert_eq!(ffgraph.find_node("((...))"), None);
+        assert_eq!(ffgraph.parents(root).count(), 0);
+
+        for node in ffgraph.children(root) {
+            assert!(ffgraph.parents(node).any(|parent| parent == root));
+        }
+
+        let deepest = ffgraph
+            .iter()
+            .map(|node| node.structure.to_string())
+            .last()
+            .and_then(|structure| ffgraph.find_node(&structure))
+            .unwrap();
+        assert_eq!(deepest.to_string().parse(), Ok(deepest));
+        let path = ffgraph.path_to_root(deepest);
+
+        assert_eq!(path.first(), Some(&deepest));
+        assert_eq!(path.last(), Some(&root));
+        assert_eq!(path.len(), ffgraph.node(deepest).unwrap().depth + 1);
+        assert!(path
+            .windows(2)
+            .all(|edge| ffgraph.children(edge[1]).any(|child| child == edge[0])));
+
+        let depths = ffgraph.first_pairing_depths(deepest);
+        let deepest = ffgraph.node(deepest).unwrap();
+        assert_eq!(depths.len(), sequence.len());
+        assert!(depths
+            .iter()
+            .flatten()
+            .all(|&d| d >= 1 && d <= deepest.depth));
+        assert!(depths.contains(&Some(deepest.depth)));
+        assert_eq!(
+            depths.iter().flatten().count(),
+            2 * deepest.structure.pairs()
+        );
+
+        let formation = ffgraph.pair_formation_depths(deepest.id);
+        assert_eq!(formation.len(), deepest.structure.pairs());
+        assert!(formation
+            .iter()
+            .all(|&((i, j), depth)| depths[i - 1] == Some(depth) && depths[j - 1] == Some(depth)));
+
+        // the fragments of each node are unpaired fragments of its structure,
+        // except those where no stack has been found before
+        let encoded = RafftConfig::new().encode(sequence).unwrap();
+        for node in ffgraph.iter() {
+            let intervals = |fragments: &[EncodedSequence]| {
+                fragments
+                    .iter()
+                    .map(EncodedSequence::parent_intervals)
+                    .sorted()
+                    .collect::<Vec<_>>()
+            };
+            let fragments =
+                ExteriorFragments::new(&encoded, &node.structure.pair_table()).into_fragments();
+            let all = intervals(&fragments);
+            assert!(intervals(&node.sub_nodes)
+                .iter()
+                .all(|fragment| all.contains(fragment)));
+        }
+    }
+
+    #[test]
+    fn test_stored_structure() {
+        let sequence = "GGGGAAAACCCCAUAUAUGGGGAAAACCCC";
+        let mut full = RafftConfig::new().folding_graph(sequence);
+        let mut compact = RafftConfig::new()
+            .compact_structures(true)
+            .folding_graph(sequence);
+        full.construct_trajectories();
+        compact.construct_trajectories();
+
+        // structures are equal regardless of their representation
+        assert!(full
+            .iter()
+            .zip(compact.iter())
+            .all(|(a, b)| a.structure == b.structure));
+        let structures: HashSet<&StoredStructure> = full
+            .iter()
+            .chain(compact.iter())
+            .map(|node| &node.structure)
+            .collect();
+        assert_eq!(structures.len(), full.iter().count());
+
+        // deltas only store the pairs added to their parents and are found by their structures
+        for node in compact.iter() {
+            let StoredStructure::Delta(delta) = &node.structure else {
+                panic!("structures of compact graphs are stored as deltas");
+            };
+            let parent_pairs = delta.parent.as_ref().map_or(0, |parent| parent.pairs);
+            assert_eq!(delta.added.len(), node.structure.pairs() - parent_pairs);
+            assert_eq!(node.structure.pairs(), node.structure.pair_table().pairs());
+            assert_eq!(
+                compact.find_node(&node.structure.to_string()),
+                Some(node.id)
+            );
+        }
+        assert!(!compact.contains("(..."));
+
+        let root = full.node(full.root()).unwrap();
+        assert_eq!(&root.clone(), root);
+    }
+
+    #[test]
+    fn test_node_table_collisions() {
+        use super::{NodeTable, RafftNodeInfo};
+        use crate::encoding::PairTable;
+        use petgraph::graph::{DiGraph, NodeIndex};
+
+        let structures: Vec<PairTable> = ["((....))", "(......)"]
+            .iter()
+            .map(|structure| structure.parse().unwrap())
+            .collect();
+        let mut inner = DiGraph::<RafftNodeInfo, ()>::new();
+        for (id, structure) in structures.iter().enumerate() {
+            inner.add_node(RafftNodeInfo {
+                id: super::RafftNodeId(id as u64),
+                sub_nodes: vec![],
+                structure: StoredStructure::Full(structure.clone()),
+                energy: 0,
+                depth: 0,
+            });
+        }
+
+        // the first structure occupies the key of the second one
+        let mut table = NodeTable::default();
+        table
+            .0
+            .insert(NodeTable::key(&structures[1]), NodeIndex::new(0));
+        assert_eq!(table.get(&inner, &structures[1]), None);
+
+        table.insert(&structures[1], NodeIndex::new(1));
+        assert_eq!(table.get(&inner, &structures[1]), Some(NodeIndex::new(1)));
+        assert_eq!(table.0.len(), 2);
+    }
+
+    #[test]
+    fn test_energy_free() {
+        use super::RafftGraph;
+        use crate::encoding::PairTable;
+
+        let sequence = "GGGGAAAACCCCAUAUAUGGGGAAAACCCC";
+        let mut graph = RafftConfig::new()
+            .energy_free(true)
+            .maximum_trajectories(3)
+            .folding_graph(sequence);
+        graph.construct_trajectories();
+
+        let encoded = RafftConfig::new().encode(sequence).unwrap();
+        assert!(graph.iter().count() > 1);
+        // pseudo-energies are the negative pair weights of all pairs
+        for node in graph.iter() {
+            let weight: f64 = node
+                .structure
+                .pair_table()
+                .paired()
+                .map(|(i, j)| encoded.pair_weight(i - 1, j - 1))
+                .sum();
+            assert_eq!(node.energy, -(weight * 100.0).round() as i32);
+        }
+
+        let best = graph.iter().min_by_key(|node| node.energy).unwrap();
+        assert_eq!(best.structure.to_string(), "((((....(((((....)))))....))))");
+
+        // graphs without a fold compound (e.g. without ViennaRNA) are constructed energy-free
+        let mut unevaluated = RafftGraph::with_root(
+            vec![encoded.clone()],
+            PairTable::new(encoded.len()),
+            None,
+            RafftConfig::new().maximum_trajectories(3),
+        );
+        unevaluated.construct_trajectories();
+        let nodes = |graph: &RafftGraph| {
+            graph
+                .iter()
+                .map(|node| (node.structure.to_string(), node.energy))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(nodes(&unevaluated), nodes(&graph));
+    }
+
+    #[test]
+    fn test_local_minima() {
+        let sequence =
+            "GGGUUUGCGGUGUAAGUGCAGCCCGUCUUACACCGUGCGGCACAGGCACUAGUACUGAUGUCGUAUACAGGGCUUUUGACAU";
+        let mut ffgraph = RafftConfig::new()
+            .maximum_trajectories(5)
+            .folding_graph(sequence);
+        ffgraph.construct_trajectories();
+
+        let leaves = ffgraph.leaves();
+        let minima = ffgraph.local_minima();
+
+        assert!(!leaves.is_empty());
+        assert!(leaves
+            .iter()
+            .all(|leaf| ffgraph.children(*leaf).next().is_none()));
+        assert!(leaves
+            .iter()
+            .all(|leaf| minima.iter().any(|(minimum, _)| minimum == leaf)));
+
+        // every structure belongs to exactly one basin
+        assert_eq!(
+            minima.iter().map(|(_, size)| size).sum::<usize>(),
+            ffgraph.iter().count()
+        );
+
+        for (minimum, _) in &minima {
+            let energy = ffgraph.node(*minimum).unwrap().energy;
+            assert!(ffgraph
+                .children(*minimum)
+                .all(|child| ffgraph.node(child).unwrap().energy >= energy));
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let sequence = "GGGGAAAACCCCAUAUAUGGGGAAAACCCC";
+        let mutant = "GGGGAAAACCCCAUAUAUGGGGAAAAGCCC";
+        let config = RafftConfig::new().maximum_trajectories(5);
+
+        let mut wild_type = config.folding_graph(sequence);
+        wild_type.construct_trajectories();
+        let mut other = config.folding_graph(mutant);
+        other.construct_trajectories();
+
+        let same = wild_type.diff(&wild_type);
+        assert_eq!(same.shared.len(), wild_type.iter().count());
+        assert!(same.energy_shifts.iter().all(|&shift| shift == 0));
+        assert!(same.lost.is_empty() && same.gained.is_empty());
+
+        let diff = wild_type.diff(&other);
+        assert_eq!(diff.shared.len(), diff.energy_shifts.len());
+        assert_eq!(
+            diff.shared.len() + diff.lost.len(),
+            wild_type.iter().count()
+        );
+        assert_eq!(diff.shared.len() + diff.gained.len(), other.iter().count());
+        // the root is always shared and has no energy
+        assert_eq!(diff.shared[0], (wild_type.root(), other.root()));
+        assert_eq!(diff.energy_shifts[0], 0);
+
+        for (&(node, other_node), &shift) in diff.shared.iter().zip(&diff.energy_shifts) {
+            let (node, other_node) = (
+                wild_type.node(node).unwrap(),
+                other.node(other_node).unwrap(),
+            );
+            assert_eq!(node.structure, other_node.structure);
+            assert_eq!(other_node.energy - node.energy, shift);
+        }
+    }
+
+    #[test]
+    fn test_simplification() {
+        let sequence =
+            "GGGUUUGCGGUGUAAGUGCAGCCCGUCUUACACCGUGCGGCACAGGCACUAGUACUGAUGUCGUAUACAGGGCUUUUGACAU";
+        let config = RafftConfig::new().maximum_trajectories(5);
+
+        let mut ffgraph = config.folding_graph(sequence);
+        ffgraph.construct_trajectories();
+
+        let count = ffgraph.iter().count();
+        let threshold = ffgraph.iter().map(|node| node.energy).min().unwrap() / 2;
+        let below: Vec<_> = ffgraph
+            .iter()
+            .filter(|node| node.energy <= threshold)
+            .map(|node| (node.id, node.structure.to_string()))
+            .collect();
+
+        let removed = ffgraph.prune_above(threshold as f64 * 0.01);
+        assert_eq!(ffgraph.iter().count(), count - removed);
+        assert!(ffgraph
+            .iter()
+            .all(|node| node.id == ffgraph.root() || node.energy <= threshold));
+
+        // identifiers are retained and all structures are still connected to the root
+        for node in ffgraph.iter() {
+            assert!(below.contains(&(node.id, node.structure.to_string())) || node.depth == 0);
+            assert_eq!(
+                ffgraph.find_node(&node.structure.to_string()),
+                Some(node.id)
+            );
+            assert_eq!(ffgraph.path_to_root(node.id).last(), Some(&ffgraph.root()));
+        }
+
+        let mut ffgraph = config.folding_graph(sequence);
+        ffgraph.construct_trajectories();
+        let leaves = ffgraph.leaves();
+
+        ffgraph.condense_linear_paths();
+        assert_eq!(ffgraph.leaves(), leaves);
+        for node in ffgraph.iter().filter(|node| node.id != ffgraph.root()) {
+            assert!(
+                ffgraph.parents(node.id).count() != 1 || ffgraph.children(node.id).count() != 1
+            );
+        }
+
+        ffgraph.merge_isoenergetic_chains();
+        for node in ffgraph.iter() {
+            let mut parents = ffgraph.parents(node.id);
+            if let (Some(parent), None) = (parents.next(), parents.next()) {
+                assert_ne!(ffgraph.node(parent).unwrap().energy, node.energy);
+            }
+        }
+    }
+
+    #[test]
+    fn test_self_loops() {
+        // the hairpin is formed at depth 1 and carried over unchanged to depth 2
+        let mut ffgraph = RafftConfig::new().folding_graph("GGGGAAAACCCC");
+        ffgraph.construct_trajectories();
+        let hairpin = ffgraph.find_node("((((....))))").unwrap();
+
+        // the carried over hairpin is connected to itself, but is neither its own child nor parent
+        assert_eq!(
+            ffgraph.adjacent_indices().collect::<Vec<_>>(),
+            vec![(0, 1), (1, 1)]
+        );
+        assert_eq!(ffgraph.children(hairpin).count(), 0);
+        assert_eq!(
+            ffgraph.parents(hairpin).collect::<Vec<_>>(),
+            vec![ffgraph.root()]
+        );
+        assert_eq!(ffgraph.leaves(), vec![hairpin]);
+        assert_eq!(ffgraph.path_to_root(hairpin), vec![hairpin, ffgraph.root()]);
+
+        // simplification retains the self-loop
+        assert_eq!(ffgraph.condense_linear_paths(), 0);
+        assert_eq!(ffgraph.merge_isoenergetic_chains(), 0);
+        assert_eq!(ffgraph.adjacent_indices().count(), 2);
+    }
+
+    #[test]
+    fn test_streaming() {
+        let sequence =
+            "GGGUUUGCGGUGUAAGUGCAGCCCGUCUUACACCGUGCGGCACAGGCACUAGUACUGAUGUCGUAUACAGGGCUUUUGACAU";
+        let mut ffgraph = RafftConfig::new()
+            .maximum_trajectories(5)
+            .folding_graph(sequence);
+
+        let mut streamed = vec![];
+        ffgraph.construct_trajectories_with(|depth, nodes| {
+            streamed.extend(nodes.iter().map(|node| {
+                assert_eq!(node.depth, depth);
+                (node.structure.to_string(), node.energy)
+            }))
+        });
+
+        // streamed structures arrive in insertion order
+        assert_eq!(
+            streamed,
+            ffgraph
+                .iter()
+                .map(|node| (node.structure.to_string(), node.energy))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_time_limit() {
+        let sequence =
+            "GGGUUUGCGGUGUAAGUGCAGCCCGUCUUACACCGUGCGGCACAGGCACUAGUACUGAUGUCGUAUACAGGGCUUUUGACAU";
+
+        let mut ffgraph = RafftConfig::new()
+            .time_limit(Duration::ZERO)
+            .folding_graph(sequence);
+        ffgraph.construct_trajectories();
+
+        assert!(ffgraph.is_truncated());
+        assert_eq!(ffgraph.iter().count(), 1);
+
+        let mut ffgraph = RafftConfig::new()
+            .time_limit(Duration::from_secs(3600))
+            .folding_graph(sequence);
+        ffgraph.construct_trajectories();
+
+        assert!(!ffgraph.is_truncated());
+        assert!(ffgraph.iter().count() > 1);
+    }
+
+    #[test]
+    fn test_cancel_token() {
+        let sequence =
+            "GGGUUUGCGGUGUAAGUGCAGCCCGUCUUACACCGUGCGGCACAGGCACUAGUACUGAUGUCGUAUACAGGGCUUUUGACAU";
+
+        let token = CancelToken::new();
+        let mut ffgraph = RafftConfig::new()
+            .cancel_token(token.clone())
+            .folding_graph(sequence);
+        let mut depths = 0;
+        ffgraph.construct_trajectories_with(|depth, _| {
+            depths += 1;
+            if depth == 1 {
+                token.cancel();
+            }
+        });
+
+        assert!(ffgraph.is_cancelled());
+        assert!(ffgraph.is_truncated());
+        assert_eq!(depths, 2);
+
+        let mut ffgraph = RafftConfig::new()
+            .cancel_token(CancelToken::new())
+            .folding_graph(sequence);
+        ffgraph.construct_trajectories();
+
+        assert!(!ffgraph.is_cancelled());
+        assert!(!ffgraph.is_truncated());
+    }
+
+    #[test]
+    fn test_stats() {
+        let sequence =
+            "GGGUUUGCGGUGUAAGUGCAGCCCGUCUUACACCGUGCGGCACAGGCACUAGUACUGAUGUCGUAUACAGGGCUUUUGACAU";
+        let mut ffgraph = RafftConfig::new()
+            .maximum_trajectories(5)
+            .folding_graph(sequence);
+        ffgraph.construct_trajectories();
+
+        let stats = ffgraph.stats();
+        let depth = ffgraph.iter().map(|node| node.depth).max().unwrap();
+
+        // the last depth does not yield any new structures
+        assert_eq!(stats.len(), depth + 1);
+        assert_eq!(
+            stats.iter().map(|stats| stats.structures).sum::<usize>(),
+            ffgraph.iter().count() - 1
+        );
+        assert!(stats
+            .iter()
+            .enumerate()
+            .all(|(i, stats)| stats.depth == i + 1
+                && stats.stacks_accepted <= stats.stacks_evaluated
+                && stats.best_energy <= stats.median_energy));
+
+        let mut tsv = vec![];
+        ffgraph.write_stats(&mut tsv).unwrap();
+        let tsv = String::from_utf8(tsv).unwrap();
+
+        assert_eq!(tsv.lines().next(), Some(DepthStats::TSV_HEADER));
+        assert_eq!(tsv.lines().count(), stats.len() + 1);
+        assert!(tsv.lines().last().unwrap().contains("\tNA\tNA\t"));
+        assert!(tsv
+            .lines()
+            .all(|line| line.split('\t').count() == DepthStats::TSV_HEADER.split('\t').count()));
+
+        // the root is searched once, after which the exterior loop of structures recurs in the cache
+        let n = correlation_fft_length(sequence.len(), sequence.len()) as f64;
+        assert_eq!(stats[0].ffts, FFTS_PER_CORRELATION);
+        assert_eq!(
+            stats[0].fft_work,
+            FFTS_PER_CORRELATION as f64 * n * n.log2()
+        );
+        assert!(stats[0].stack_searches > 0 && stats[0].stack_searches <= 100);
+        // candidate stacks and combined structures are evaluated before the best branches are selected,
+        // while graphs are constructed energy-free without ViennaRNA
+        #[cfg(feature = "viennarna")]
+        assert!(stats
+            .iter()
+            .all(|stats| stats.energy_evaluations >= stats.stacks_evaluated + stats.structures));
+        assert!(stats
+            .iter()
+            .all(|stats| stats.ffts % FFTS_PER_CORRELATION == 0));
+        assert!(stats.iter().skip(1).any(|stats| stats.cache_hits > 0));
+        assert_eq!(
+            stats.last().unwrap().memory_bytes,
+            ffgraph.memory_usage().total()
+        );
+    }
+
+    #[test]
+    fn test_construct_iter() {
+        let sequence = "GGGGAAAACCCCAUAUAUGGGGAAAACCCC";
+        let config = RafftConfig::new().maximum_trajectories(3);
+
+        let mut expected = config.folding_graph(sequence);
+        let mut layers = vec![];
+        expected.construct_trajectories_with(|depth, nodes| {
+            layers.push((depth, nodes.iter().map(|node| node.id).collect::<Vec<_>>()))
+        });
+
+        let mut ffgraph = config.folding_graph(sequence);
+        let mut steps = ffgraph.construct_iter();
+        let mut iterated = vec![];
+        while let Some((depth, ids)) = steps.next() {
+            assert!(ids
+                .iter()
+                .all(|&id| steps.graph().node(id).unwrap().depth == depth));
+            iterated.push((depth, ids));
+        }
+        assert_eq!(iterated, layers);
+        assert!(ffgraph
+            .iter()
+            .zip(expected.iter())
+            .all(|(a, b)| a.structure == b.structure && a.energy == b.energy));
+
+        // stopping early keeps the structures of the completed depths
+        let mut ffgraph = config.folding_graph(sequence);
+        let steps: Vec<_> = ffgraph.construct_iter().take(2).collect();
+        assert_eq!(steps, layers[..2]);
+        assert_eq!(ffgraph.iter().count(), 1 + layers[1].1.len());
+        assert!(!ffgraph.is_truncated());
+    }
+
+    #[test]
+    fn test_compact() {
+        let sequence = "GGGGAAAACCCCAUAUAUGGGGAAAACCCC";
+        let mut ffgraph = RafftConfig::new()
+            .maximum_trajectories(3)
+            .folding_graph(sequence);
+        ffgraph.construct_trajectories();
+
+        let structures: Vec<_> = ffgraph
+            .iter()
+            .map(|node| (node.id, node.structure.to_string(), node.energy))
+            .collect();
+        let usage = ffgraph.memory_usage();
+        let released = ffgraph.compact();
+
+        let compacted = ffgraph.memory_usage();
+        assert_eq!(released, usage.total() - compacted.total());
+        assert_eq!((compacted.encodings, compacted.cache), (0, 0));
+        assert!(compacted.nodes < usage.nodes);
+        assert_eq!(
+            ffgraph
+                .iter()
+                .map(|node| (node.id, node.structure.to_string(), node.energy))
+                .collect::<Vec<_>>(),
+            structures
+        );
+        assert!(structures
+            .iter()
+            .all(|(id, structure, _)| ffgraph.node(*id).is_some() && ffgraph.contains(structure)));
+
+        // nothing left to release
+        assert_eq!(ffgraph.compact(), 0);
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let sequence =
+            "GGGUUUGCGGUGUAAGUGCAGCCCGUCUUACACCGUGCGGCACAGGCACUAGUACUGAUGUCGUAUACAGGGCUUUUGACAU";
+        let config = RafftConfig::new().maximum_trajectories(5);
+
+        let mut ffgraph = config.folding_graph(sequence);
+        let mut memory = vec![];
+        ffgraph.construct_trajectories_with_progress(|progress| {
+            assert_eq!(progress.depth, memory.len());
+            memory.push(progress.memory);
+        });
+
+        let usage = ffgraph.memory_usage();
+        // the last depth does not yield any new structures, but its search results are cached
+        assert_eq!(memory.last().unwrap().nodes, usage.nodes);
+        assert_eq!(ffgraph.stats().last().unwrap().memory_bytes, usage.total());
+        assert!(memory[0].cache == 0 && memory[0].edges == 0);
+        // nodes are never removed during construction
+        assert!(memory.windows(2).all(|w| w[0].nodes < w[1].nodes));
+        assert!(usage.cache > 0 && usage.encodings > 0);
+        assert_eq!(
+            usage.total(),
+            usage.nodes + usage.edges + usage.encodings + usage.lookup + usage.cache
+        );
+
+        let mut compact = config
+            .clone()
+            .compact_structures(true)
+            .cache_size(0)
+            .folding_graph(sequence);
+        compact.construct_trajectories();
+        let compact_usage = compact.memory_usage();
+        assert_eq!(compact.iter().count(), ffgraph.iter().count());
+        assert_eq!(compact_usage.cache, 0);
+        assert!(compact_usage.nodes < usage.nodes);
+        // the lookup stores hashes instead of structures
+        assert_eq!(compact_usage.lookup, usage.lookup);
+    }
+
+    #[test]
+    #[cfg(feature = "viennarna")]
+    fn test_gradient_walks() {
+        use crate::testing::{small_graph, SMALL_SEQUENCE};
+
+        let sequence = SMALL_SEQUENCE;
+        let ffgraph = small_graph();
+
+        let walks = ffgraph.gradient_walks();
+        assert_eq!(walks.len(), ffgraph.iter().count());
+
+        for (walk, node) in walks.iter().zip(ffgraph.iter()) {
+            assert_eq!(walk.node, node.id);
+            assert_eq!(walk.relaxed.len(), sequence.len());
+            assert!(walk.relaxed_energy <= node.energy);
+        }
+    }
+
+    #[test]
+    fn test_sampling() {
+        let sequence =
+            "GGGUUUGCGGUGUAAGUGCAGCCCGUCUUACACCGUGCGGCACAGGCACUAGUACUGAUGUCGUAUACAGGGCUUUUGACAU";
+
+        let trajectories = |seed| {
+            let mut ffgraph = RafftConfig::new()
+                .maximum_trajectories(5)
+                .boltzmann_sampling(0.6)
+                .seed(seed)
+                .folding_graph(sequence);
+            ffgraph.construct_trajectories();
+            ffgraph
+                .iter()
+                .map(|node| (node.structure.to_string(), node.depth, node.energy))
+                .collect::<Vec<_>>()
+        };
+
+        let sampled = trajectories(42);
+        assert_eq!(sampled, trajectories(42));
+
+        // every depth is sorted by energy and contains at most the saved trajectories
+        for (_, layer) in &sampled.iter().group_by(|node| node.1) {
+            let energies: Vec<_> = layer.map(|node| node.2).collect();
+            assert!(energies.len() <= 5);
+            assert!(energies.windows(2).all(|pair| pair[0] <= pair[1]));
+        }
+    }
+
+    #[test]
+    fn test_trajectory_selection() {
+        use super::TrajectorySelection;
+        use crate::encoding::PairTable;
+        use petgraph::graph::NodeIndex;
+
+        // candidates in order of their rank
+        let candidates = || {
+            [
+                ("((((....))))........", -300),
+                ("(((......)))........", -200),
+                ("((((....)))).((...))", -500),
+                ("........((((....))))", -250),
+                ("...(((....))).......", -100),
+            ]
+            .iter()
+            .map(|&(structure, energy)| {
+                let structure = structure.parse::<PairTable>().unwrap();
+                (NodeIndex::new(0), (), structure, energy)
+            })
+            .collect::<Vec<_>>()
+        };
+        let select = |selection: TrajectorySelection, saved| {
+            let ffgraph = RafftConfig::new()
+                .maximum_trajectories(saved)
+                .trajectory_selection(selection)
+                .folding_graph("GGGGAAAACCCCAGGGAAAC");
+            ffgraph
+                .select_saved(candidates())
+                .into_iter()
+                .map(|child| child.3)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(select(TrajectorySelection::default(), 2), vec![-300, -200]);
+        assert_eq!(
+            select(
+                TrajectorySelection {
+                    lowest_energy: true,
+                    ..TrajectorySelection::default()
+                },
+                2
+            ),
+            vec![-300, -200, -500]
+        );
+
+        // the structure farthest from the best-ranked one is added first
+        assert_eq!(
+            select(
+                TrajectorySelection {
+                    diversity: true,
+                    ..TrajectorySelection::default()
+                },
+                2
+            ),
+            vec![-300, -250]
+        );
+
+        // kept in addition to the saved trajectories
+        let target = TrajectorySelection {
+            target: Some("...(((....)))((...))".parse().unwrap()),
+            ..TrajectorySelection::default()
+        };
+        assert!(target.leads_to_target(&candidates()[4].2));
+        assert!(!target.leads_to_target(&candidates()[0].2));
+        assert_eq!(select(target.clone(), 1), vec![-300, -100]);
+
+        // the target is reached on a trajectory even if it is not among the best structures
+        let sequence = "GGGGAAAACCCCAUAUAUGGGGAAAACCCC";
+        let mut ffgraph = RafftConfig::new()
+            .maximum_trajectories(20)
+            .folding_graph(sequence);
+        ffgraph.construct_trajectories();
+        let mut best = RafftConfig::new()
+            .maximum_trajectories(1)
+            .folding_graph(sequence);
+        best.construct_trajectories();
+        let target = ffgraph
+            .iter()
+            .filter(|node| !best.contains(&node.structure.to_string()))
+            .max_by_key(|node| (node.depth, -node.energy))
+            .unwrap()
+            .structure
+            .pair_table()
+            .into_owned();
+
+        let mut targeted = RafftConfig::new()
+            .maximum_trajectories(1)
+            .trajectory_selection(TrajectorySelection {
+                target: Some(target.clone()),
+                ..TrajectorySelection::default()
+            })
+            .folding_graph(sequence);
+        targeted.construct_trajectories();
+        assert!(targeted.contains(&target.to_string()));
+        assert!(best
+            .iter()
+            .all(|node| targeted.contains(&node.structure.to_string())));
+    }
+
+    #[test]
+    fn test_candidate_stacks() {
+        use crate::encoding::PairTable;
+
+        let sequence = "GGGGAAAACCCCAUAUAUGGGGAAAACCCC";
+        let config = RafftConfig::new().maximum_trajectories(100);
+        let candidates = crate::candidate_stacks(sequence, config.clone()).unwrap();
+        assert!(!candidates.is_empty());
+        assert!(candidates
+            .windows(2)
+            .all(|pair| pair[0].delta_energy <= pair[1].delta_energy));
+        assert!(candidates
+            .iter()
+            .all(|stack| stack.lag + 2 == stack.i + stack.j
+                && stack.i + 2 * (stack.pairs - 1) < stack.j));
+
+        // the accepted candidates form the first depth
+        let mut ffgraph = config.folding_graph(sequence);
+        ffgraph.construct_trajectories();
+        let mut expected: Vec<_> = ffgraph
+            .iter()
+            .filter(|node| node.depth == 1)
+            .map(|node| (node.structure.to_string(), node.energy))
+            .collect();
+        let mut formed: Vec<_> = candidates
+            .iter()
+            .filter(|stack| stack.accepted)
+            .map(|stack| {
+                let mut pt = PairTable::new(sequence.len());
+                (0..stack.pairs)
+                    .for_each(|k| pt.insert((stack.i + k) as i16, (stack.j - k) as i16));
+                (pt.to_string(), stack.delta_energy)
+            })
+            .collect();
+        expected.sort();
+        formed.sort();
+        assert_eq!(formed, expected);
+
+        // nothing is accepted if loops have to contribute more than any stack
+        let strict = RafftConfig::new().minimum_loop_energy(-100.0);
+        assert!(crate::candidate_stacks(sequence, strict)
+            .unwrap()
+            .iter()
+            .all(|stack| !stack.accepted));
+        assert!(crate::candidate_stacks("GGXA", RafftConfig::new()).is_err());
+    }
+
+    #[test]
+    fn test_normalized_candidates() {
+        use crate::encoding::ScoreNormalization;
+
+        let sequence =
+            "GGGUUUGCGGUGUAAGUGCAGCCCGUCUUACACCGUGCGGCACAGGCACUAGUACUGAUGUCGUAUACAGGGCUUUUGACAU";
+        let ffgraph = RafftConfig::new()
+            .stacks_per_lag(3)
+            .score_normalization(ScoreNormalization::PerPair)
+            .folding_graph(sequence);
+
+        // the stacks of all lags are ranked by their normalized scores
+        let root = &ffgraph.inner[ffgraph.root];
+        let stacks = ffgraph.stack_candidates(&root.sub_nodes[0], &mut DepthStats::default());
+        assert!(stacks.len() > 3);
+        assert!(stacks.windows(2).all(|pair| pair[0].score >= pair[1].score));
+    }
+
+    #[test]
+    fn test_exhaustive() {
+        let sequence = "GGGGAAAACCCCAUAUAUGGGGAAAACCCC";
+        let mut exhaustive = RafftConfig::new()
+            .exhaustive(sequence.len())
+            .folding_graph(sequence);
+        exhaustive.construct_trajectories();
+        assert!(exhaustive.is_exhaustive());
+
+        // heuristic settings only find a subset of the structures
+        for (saved, lags, stacks) in [(1, 100, 1), (5, 100, 1), (5, 10, 2), (20, 5, 3)] {
+            let mut ffgraph = RafftConfig::new()
+                .maximum_trajectories(saved)
+                .positional_lags(lags)
+                .stacks_per_lag(stacks)
+                .folding_graph(sequence);
+            ffgraph.construct_trajectories();
+            assert!(!ffgraph.is_exhaustive());
+
+            assert!(ffgraph
+                .iter()
+                .all(|node| exhaustive.contains(&node.structure.to_string())));
+            assert!(
+                exhaustive.iter().map(|node| node.energy).min()
+                    <= ffgraph.iter().map(|node| node.energy).min()
+            );
+            assert!(exhaustive.iter().count() >= ffgraph.iter().count());
+        }
+        assert!(!exhaustive.compat_layers().is_empty());
+
+        // longer sequences are folded using the heuristic settings
+        let mut heuristic = RafftConfig::new()
+            .exhaustive(sequence.len() - 1)
+            .folding_graph(sequence);
+        heuristic.construct_trajectories();
+        let mut default = RafftConfig::new().folding_graph(sequence);
+        default.construct_trajectories();
+        assert!(!heuristic.is_exhaustive());
+        assert_eq!(
+            heuristic
+                .iter()
+                .map(|node| node.structure.to_string())
+                .collect::<Vec<_>>(),
+            default
+                .iter()
+                .map(|node| node.structure.to_string())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_track_target() {
+        let sequence = "GGGGAAAACCCCAUAUAUGGGGAAAACCCC";
+        let mut ffgraph = RafftConfig::new()
+            .maximum_trajectories(5)
+            .folding_graph(sequence);
+        ffgraph.construct_trajectories();
+        assert_eq!(ffgraph.target_reached(), None);
+        assert!(ffgraph
+            .stats()
+            .iter()
+            .all(|stats| stats.target_distance.is_none() && !stats.target_reached));
+
+        let best = ffgraph.iter().min_by_key(|node| node.energy).unwrap();
+        let target = best.structure.pair_table().into_owned();
+
+        let mut tracked = RafftConfig::new()
+            .maximum_trajectories(5)
+            .track_target(target.clone())
+            .folding_graph(sequence);
+        tracked.construct_trajectories();
+
+        // tracking does not affect the search
+        assert_eq!(tracked.iter().count(), ffgraph.iter().count());
+        assert_eq!(tracked.target_reached(), Some(best.depth));
+        let stats = tracked.stats();
+        assert!(stats[..best.depth - 1]
+            .iter()
+            .all(|stats| stats.target_distance > Some(0) && !stats.target_reached));
+        assert!(stats[best.depth - 1].target_reached);
+        assert_eq!(stats[best.depth - 1].target_distance, Some(0));
+
+        let mut tsv = vec![];
+        tracked.write_stats(&mut tsv).unwrap();
+        let tsv = String::from_utf8(tsv).unwrap();
+        assert!(tsv.lines().nth(best.depth).unwrap().ends_with("\t0\t1"));
+
+        // the open chain is reached at the root, targets of other lengths are ignored
+        let open = RafftConfig::new()
+            .track_target(".".repeat(sequence.len()).parse().unwrap())
+            .folding_graph(sequence);
+        assert_eq!(open.target_reached(), Some(0));
+        let mut other = RafftConfig::new()
+            .track_target("(((....)))".parse().unwrap())
+            .folding_graph(sequence);
+        other.construct_trajectories();
+        assert_eq!(other.target_reached(), None);
+        assert!(other
+            .stats()
+            .iter()
+            .all(|stats| stats.target_distance.is_none()));
+    }
+
+    #[test]
+    fn test_branch_ranking() {
+        assert_eq!(BranchRanking::Energy.rank(-420, -100, 6), -420.0);
+        assert_eq!(
+            BranchRanking::EnergyPerPairedNucleotide.rank(-420, -100, 6),
+            -35.0
+        );
+        assert_eq!(BranchRanking::EnergyPerPairedNucleotide.rank(0, 0, 0), 0.0);
+        assert_eq!(BranchRanking::Improvement.rank(-420, -100, 6), -320.0);
+
+        let weighted = BranchRanking::Weighted {
+            energy: 0.5,
+            density: 2.0,
+            improvement: 1.0,
+        };
+        assert_eq!(weighted.rank(-420, -100, 6), -210.0 - 70.0 - 320.0);
+
+        assert_eq!(
+            "Density".parse::<BranchRanking>(),
+            Ok(BranchRanking::EnergyPerPairedNucleotide)
+        );
+        assert!("entropy".parse::<BranchRanking>().is_err());
+    }
+
+    #[test]
+    fn test_multistrand() {
+        let mut graph = RafftConfig::new().folding_graph("GGGGAAAA&UUUUCCCC&GGGGAAAA");
+        graph.construct_trajectories();
+
+        assert_eq!(graph.cut_points(), &[9, 17]);
+        // inter-strand helices are formed although they enclose less than 3 unpaired positions
+        assert!(graph.iter().any(|node| node
+            .structure
+            .pair_table()
+            .paired()
+            .any(|(i, j)| i < 9 && j >= 9)));
+        assert!(graph.contains("........&........&........"));
+    }
+
+    #[test]
+    fn test_strict_stacks() {
+        for sequence in [
+            "GGGGAAAACCCCAUGGGGAAAACCCC",
+            "GGGAAACCCAUAUGGGAAACCCUAUAGGGAAACCC",
+            "GGGGAAAA&UUUUCCCC&GGGGAAAA",
+        ] {
+            let mut lenient = RafftConfig::new()
+                .maximum_trajectories(5)
+                .folding_graph(sequence);
+            lenient.construct_trajectories();
+
+            // candidates of concatenated fragments never span the concatenation site
+            let mut strict = RafftConfig::new()
+                .maximum_trajectories(5)
+                .strict_stacks(true)
+                .folding_graph(sequence);
+            assert_eq!(strict.try_construct_trajectories(), Ok(()));
+            assert!(!strict.is_truncated());
+            assert!(lenient
+                .iter()
+                .zip(strict.iter())
+                .all(|(a, b)| a.structure == b.structure));
+        }
     }
 }
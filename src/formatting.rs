@@ -0,0 +1,162 @@
+//! This module provides human-readable terminal output of fast folding graphs.
+//! Structures are aligned under the sequence, the pairs added by the last stack of each structure
+//! can be highlighted using ANSI colors and the trajectory towards the structure of lowest free energy is marked:
+//!
+//! ```text
+//!     GGGGAAAACCCCAUAUAUGGGGAAAACCCC
+//! [0] ..............................  0.00 *
+//! [1] ........(((((....)))))........ -5.00 *
+//! [2] ((((....(((((....)))))....)))) -9.00 *
+//! ```
+
+use crate::encoding::SeqPos1;
+use crate::folding_graph::{RafftGraph, RafftNodeInfo};
+use std::collections::HashSet;
+use std::fmt;
+
+const NEW_PAIRS: &str = "\x1b[1;32m";
+const BEST: &str = "\x1b[1;33m";
+const RESET: &str = "\x1b[0m";
+
+/// Table of all structures of a [`RafftGraph`] in the order of [`RafftGraph::iter()`], implementing [`fmt::Display`].
+pub struct TrajectoryTable<'a> {
+    graph: &'a RafftGraph,
+    sequence: &'a str,
+    color: bool,
+}
+
+impl<'a> TrajectoryTable<'a> {
+    /// Create a table for `graph` constructed for `sequence`, without colors.
+    pub fn new(graph: &'a RafftGraph, sequence: &'a str) -> Self {
+        Self {
+            graph,
+            sequence,
+            color: false,
+        }
+    }
+
+    /// Set whether to highlight new pairs and the best trajectory using ANSI colors.
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    // pairs of `node` that are not present in its parent of lowest depth (1-indexed positions)
+    fn new_positions(&self, node: &RafftNodeInfo) -> HashSet<usize> {
+        let path = self.graph.path_to_root(node.id);
+        let pt = node.structure.pair_table();
+
+        match path.get(1).and_then(|&parent| self.graph.node(parent)) {
+            Some(parent) => {
+                let parent = parent.structure.pair_table();
+                pt.paired()
+                    .filter(|&(i, j)| parent.partner(SeqPos1(i)) != Some(SeqPos1(j)))
+                    .flat_map(|(i, j)| [i, j])
+                    .collect()
+            }
+            None => HashSet::new(),
+        }
+    }
+
+    fn write_structure(&self, f: &mut fmt::Formatter<'_>, node: &RafftNodeInfo) -> fmt::Result {
+        let pt = node.structure.pair_table();
+        let dot_bracket = pt.to_string_with_cut_points(self.graph.cut_points());
+
+        if !self.color {
+            return write!(f, "{}", dot_bracket);
+        }
+
+        let new_positions = self.new_positions(node);
+        let mut position = 0;
+
+        for c in dot_bracket.chars() {
+            if c != '&' {
+                position += 1;
+            }
+
+            if new_positions.contains(&position) && c != '&' {
+                write!(f, "{}{}{}", NEW_PAIRS, c, RESET)?;
+            } else {
+                write!(f, "{}", c)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for TrajectoryTable<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let best: HashSet<_> = self
+            .graph
+            .iter()
+            .min_by_key(|node| node.energy)
+            .map(|node| self.graph.path_to_root(node.id).into_iter().collect())
+            .unwrap_or_default();
+
+        let labels: Vec<String> = self
+            .graph
+            .iter()
+            .map(|node| format!("[{}]", node.depth))
+            .collect();
+        let label_width = labels.iter().map(String::len).max().unwrap_or(0);
+        let energies: Vec<String> = self
+            .graph
+            .iter()
+            .map(|node| format!("{:.2}", node.energy as f64 * 0.01))
+            .collect();
+        let energy_width = energies.iter().map(String::len).max().unwrap_or(0);
+
+        writeln!(f, "{:label_width$} {}", "", self.sequence)?;
+
+        for ((node, label), energy) in self.graph.iter().zip(&labels).zip(&energies) {
+            write!(f, "{:label_width$} ", label)?;
+            self.write_structure(f, node)?;
+            write!(f, " {:>energy_width$}", energy)?;
+
+            if best.contains(&node.id) {
+                if self.color {
+                    write!(f, " {}*{}", BEST, RESET)?;
+                } else {
+                    write!(f, " *")?;
+                }
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fast_folding::RafftConfig;
+
+    #[test]
+    fn test_trajectory_table() {
+        let sequence = "GGGGAAAACCCCAUAUAUGGGGAAAACCCC";
+        let mut graph = RafftConfig::new().folding_graph(sequence);
+        graph.construct_trajectories();
+
+        let plain = TrajectoryTable::new(&graph, sequence).to_string();
+        let lines: Vec<&str> = plain.lines().collect();
+
+        assert_eq!(lines.len(), graph.iter().count() + 1);
+        assert_eq!(lines[0].trim(), sequence);
+        // structures are aligned under the sequence
+        let offset = lines[0].find('G').unwrap();
+        assert!(lines[1..]
+            .iter()
+            .all(|line| line[offset..].starts_with(['.', '('])));
+        // the root is part of every trajectory
+        assert!(lines[1].ends_with(" *"));
+        assert!(!plain.contains('\x1b'));
+
+        let colored = TrajectoryTable::new(&graph, sequence)
+            .color(true)
+            .to_string();
+        assert!(colored.contains(NEW_PAIRS));
+        assert_eq!(colored.lines().count(), lines.len());
+    }
+}
@@ -0,0 +1,1173 @@
+//! This module exports fast folding graphs as continuous-time Markov chains for external kinetics tools
+//! such as [`treekin`](https://www.tbi.univie.ac.at/RNA/Treekin/) or `BarMap`.
+//!
+//! Transitions are only allowed along the edges of a [`RafftGraph`], in both directions.
+//! Structures are indexed in the order of [`RafftGraph::iter()`], starting at `1`
+//! as in the output of `barriers`.
+//!
+//! For custom analyses, e.g. spectral decompositions, committors or mean first-passage times in nalgebra or scipy,
+//! graphs can be exported as sparse [`CsrMatrix`] instances, see [`RafftGraph::adjacency_matrix()`]
+//! and [`RafftGraph::rate_matrix()`].
+//!
+//! Quantitative statements about folding pathways are given by [`committors()`] between the open chain and a set
+//! of target structures, and by [`mean_first_passage_times()`] into such a set.
+//!
+//! Graphs are coarse-grained into [`Macrostates`], e.g. the gradient basins of their local minima
+//! (see [`gradient_basins()`]), with aggregated rates between them.
+//!
+//! Temperature protocols such as a temperature jump `37 -> 55 -> 37 °C` are simulated on a fixed graph
+//! by re-evaluating the free energies of its structures at every temperature, see [`temperature_protocol()`].
+
+use crate::cancel::{CancelToken, Cancelled};
+use crate::folding_graph::RafftGraph;
+#[cfg(feature = "viennarna")]
+use crate::vienna::VCompound;
+use ndarray::Array2;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// Gas constant in `kcal/(mol K)`.
+pub const GAS_CONSTANT: f64 = 0.0019872;
+
+/// Arrhenius-type rate model `k(i -> j) = prefactor * exp(-(barrier + max(0, dG_j - dG_i)) / kT)`
+/// for transitions between adjacent structures.
+/// Without an additional barrier, this corresponds to Metropolis rates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArrheniusModel {
+    /// attempt frequency in `1/s` (or arbitrary time units)
+    pub prefactor: f64,
+    /// thermal energy `kT` in `kcal/mol`
+    pub kt: f64,
+    /// energy barrier in `kcal/mol` added to every transition
+    pub barrier: f64,
+}
+
+impl Default for ArrheniusModel {
+    /// Metropolis rates at 37°C.
+    fn default() -> Self {
+        Self::at_temperature(37.0)
+    }
+}
+
+impl ArrheniusModel {
+    /// Return the Metropolis rate model at `temperature` (in °C) with unit prefactor.
+    pub fn at_temperature(temperature: f64) -> Self {
+        Self {
+            prefactor: 1.0,
+            kt: GAS_CONSTANT * (temperature + 273.15),
+            barrier: 0.0,
+        }
+    }
+
+    /// Return the rate of the transition from a structure with free energy `from` to one with free energy `to`
+    /// (both in `kcal/mol`).
+    pub fn rate(&self, from: f64, to: f64) -> f64 {
+        self.prefactor * (-(self.barrier + (to - from).max(0.0)) / self.kt).exp()
+    }
+}
+
+/// A sparse matrix in the compressed sparse row (CSR) format, with the same layout as
+/// [`scipy.sparse.csr_matrix`](https://docs.scipy.org/doc/scipy/reference/generated/scipy.sparse.csr_matrix.html)
+/// and `nalgebra_sparse::CsrMatrix`: the column indices and values of row `i` are stored at
+/// `indptr[i]..indptr[i + 1]` of `indices` and `values`, sorted by column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsrMatrix {
+    /// the number of rows and columns
+    pub shape: (usize, usize),
+    /// the offsets of the rows in `indices` and `values`, of length `shape.0 + 1`
+    pub indptr: Vec<usize>,
+    /// the column indices of the stored entries
+    pub indices: Vec<usize>,
+    /// the values of the stored entries
+    pub values: Vec<f64>,
+}
+
+impl CsrMatrix {
+    /// Create a matrix of `shape` from triplets `(i, j, value)` in any order, summing duplicate entries.
+    /// Panics if an index is out of bounds.
+    pub fn from_triplets<I>(shape: (usize, usize), triplets: I) -> Self
+    where
+        I: IntoIterator<Item = (usize, usize, f64)>,
+    {
+        let mut triplets: Vec<_> = triplets.into_iter().collect();
+        triplets.sort_by_key(|&(i, j, _)| (i, j));
+
+        let mut indptr = vec![0; shape.0 + 1];
+        let mut indices: Vec<usize> = Vec::with_capacity(triplets.len());
+        let mut values: Vec<f64> = Vec::with_capacity(triplets.len());
+
+        for (index, &(i, j, value)) in triplets.iter().enumerate() {
+            assert!(
+                i < shape.0 && j < shape.1,
+                "index ({}, {}) out of bounds",
+                i,
+                j
+            );
+
+            if index > 0 && triplets[index - 1].0 == i && triplets[index - 1].1 == j {
+                *values.last_mut().expect("previous entry") += value;
+            } else {
+                indptr[i + 1] += 1;
+                indices.push(j);
+                values.push(value);
+            }
+        }
+
+        for i in 0..shape.0 {
+            indptr[i + 1] += indptr[i];
+        }
+
+        Self {
+            shape,
+            indptr,
+            indices,
+            values,
+        }
+    }
+
+    /// Return the number of stored entries.
+    pub fn nnz(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Return the entry in row `i` and column `j`, which is `0` if it is not stored.
+    pub fn get(&self, i: usize, j: usize) -> f64 {
+        let row = self.indptr[i]..self.indptr[i + 1];
+
+        self.indices[row.clone()]
+            .binary_search(&j)
+            .map_or(0.0, |offset| self.values[row.start + offset])
+    }
+
+    /// Return the stored entries as triplets `(i, j, value)` in row-major order,
+    /// i.e. in the COO format.
+    pub fn triplets(&self) -> impl Iterator<Item = (usize, usize, f64)> + '_ {
+        (0..self.shape.0).flat_map(move |i| {
+            (self.indptr[i]..self.indptr[i + 1]).map(move |k| (i, self.indices[k], self.values[k]))
+        })
+    }
+
+    /// Return the dense matrix. Note that its size grows quadratically with the number of structures.
+    pub fn to_dense(&self) -> Array2<f64> {
+        let mut dense = Array2::zeros(self.shape);
+
+        for (i, j, value) in self.triplets() {
+            dense[[i, j]] = value;
+        }
+
+        dense
+    }
+}
+
+/// Return the transition rates `(i, j, k(i -> j))` along all edges of `graph` in both directions.
+/// The self-loops of structures carried over unchanged to the next depth are skipped.
+/// Indices are `0`-based and correspond to the order of [`RafftGraph::iter()`].
+pub fn rates(graph: &RafftGraph, model: &ArrheniusModel) -> Vec<(usize, usize, f64)> {
+    let energies: Vec<f64> = graph.iter().map(|node| node.energy as f64 * 0.01).collect();
+
+    rates_with(graph, &energies, model)
+}
+
+// Transition rates along all edges of `graph` using `energies` (in `kcal/mol`) per structure.
+fn rates_with(
+    graph: &RafftGraph,
+    energies: &[f64],
+    model: &ArrheniusModel,
+) -> Vec<(usize, usize, f64)> {
+    graph
+        .adjacent_indices()
+        .filter(|&(i, j)| i != j)
+        .flat_map(|(i, j)| {
+            [
+                (i, j, model.rate(energies[i], energies[j])),
+                (j, i, model.rate(energies[j], energies[i])),
+            ]
+        })
+        .collect()
+}
+
+/// Return the populations of the structures of `graph` at `time`, starting with the complete population
+/// in the root (i.e. the open chain), by solving the master equation of the Markov chain defined by [`rates()`].
+/// The populations are in the order of [`RafftGraph::iter()`].
+///
+/// The solution is computed by uniformization, whose cost grows linearly with `time` times the maximum total
+/// outgoing rate of any structure.
+pub fn populations(graph: &RafftGraph, model: &ArrheniusModel, time: f64) -> Vec<f64> {
+    populations_until(graph, model, time, &CancelToken::new()).expect("never cancelled")
+}
+
+/// Like [`populations()`], but aborts once `token` is cancelled.
+pub fn populations_until(
+    graph: &RafftGraph,
+    model: &ArrheniusModel,
+    time: f64,
+    token: &CancelToken,
+) -> Result<Vec<f64>, Cancelled> {
+    let root = graph.root();
+    let initial: Vec<f64> = graph
+        .iter()
+        .map(|node| if node.id == root { 1.0 } else { 0.0 })
+        .collect();
+
+    propagate(initial, &rates(graph, model), time, token)
+}
+
+// Solve the master equation for `time`, starting from the populations `initial`.
+fn propagate(
+    initial: Vec<f64>,
+    rates: &[(usize, usize, f64)],
+    time: f64,
+    token: &CancelToken,
+) -> Result<Vec<f64>, Cancelled> {
+    let mut current = initial;
+    let mut outflow = vec![0.0; current.len()];
+    for &(i, _, rate) in rates {
+        outflow[i] += rate;
+    }
+
+    let lambda = outflow.iter().cloned().fold(0.0, f64::max);
+    if lambda == 0.0 || time <= 0.0 {
+        return Ok(current);
+    }
+
+    // p(t) = sum_k Poisson(k; lambda * t) * p(0) * P^k with P = I + Q / lambda
+    let mean = lambda * time;
+    let steps = (mean + 10.0 * mean.sqrt() + 20.0).ceil() as usize;
+    let mut result = vec![0.0; current.len()];
+    let mut log_weight = -mean;
+
+    for k in 0..=steps {
+        if token.is_cancelled() {
+            return Err(Cancelled);
+        }
+
+        let weight = log_weight.exp();
+        result
+            .iter_mut()
+            .zip(&current)
+            .for_each(|(r, p)| *r += weight * p);
+
+        let mut next: Vec<f64> = current
+            .iter()
+            .zip(&outflow)
+            .map(|(p, out)| p * (1.0 - out / lambda))
+            .collect();
+        for &(i, j, rate) in rates {
+            next[j] += current[i] * rate / lambda;
+        }
+
+        current = next;
+        log_weight += mean.ln() - ((k + 1) as f64).ln();
+    }
+
+    // account for the truncated tail of the Poisson distribution
+    let total: f64 = result.iter().sum();
+    result.iter_mut().for_each(|r| *r /= total);
+
+    Ok(result)
+}
+
+/// Return the committor probabilities of the structures of `graph`, i.e. the probability to reach any of the
+/// `targets` before returning to the root (i.e. the open chain), for the Markov chain defined by [`rates()`].
+/// The committors are `0` for the root and `1` for the targets, which take precedence if they include the root.
+/// Structures that can reach neither the targets nor the root, e.g. if uphill rates vanish at low temperatures,
+/// have a committor of `0`. Targets are indices in the order of [`RafftGraph::iter()`], as are the committors.
+///
+/// The committors are obtained by solving a dense linear system, whose cost grows cubically with the number of
+/// structures. Panics if a target is out of bounds.
+pub fn committors(graph: &RafftGraph, model: &ArrheniusModel, targets: &[usize]) -> Vec<f64> {
+    let root = graph.root();
+    let n = graph.iter().count();
+    let source = graph.iter().position(|node| node.id == root);
+
+    let mut committors = vec![0.0; n];
+    for &target in targets {
+        committors[target] = 1.0;
+    }
+
+    if targets.is_empty() {
+        return committors;
+    }
+
+    let rates = rates(graph, model);
+    let absorbing: Vec<usize> = targets.iter().copied().chain(source).collect();
+    let reaching = reaching(n, &rates, &absorbing);
+
+    // structures trapped away from the targets and the root keep a committor of 0
+    let unknown: Vec<usize> = (0..n)
+        .filter(|&i| committors[i] == 0.0 && Some(i) != source && reaching[i])
+        .collect();
+    let solution = solve_generator(n, &rates, &unknown, 0.0, |j| committors[j]);
+
+    for (i, q) in unknown.into_iter().zip(solution) {
+        committors[i] = q;
+    }
+
+    committors
+}
+
+/// Return the mean first-passage times of the structures of `graph` into the set of `targets`,
+/// i.e. the expected time until any of the `targets` is reached, in the time units of
+/// [`ArrheniusModel::prefactor`], for the Markov chain defined by [`rates()`].
+/// The time from the open chain is the entry of the root, targets have a time of `0`, and all times are infinite
+/// without targets. Times are also infinite for structures that may never reach the targets, i.e. that can reach
+/// a structure from which the targets are unreachable, e.g. if uphill rates vanish at low temperatures.
+/// Targets are indices in the order of [`RafftGraph::iter()`], as are the times.
+///
+/// See [`committors()`] for the cost. Panics if a target is out of bounds.
+pub fn mean_first_passage_times(
+    graph: &RafftGraph,
+    model: &ArrheniusModel,
+    targets: &[usize],
+) -> Vec<f64> {
+    let n = graph.iter().count();
+
+    if targets.is_empty() {
+        return vec![f64::INFINITY; n];
+    }
+
+    let rates = rates(graph, model);
+    let trapped: Vec<usize> = reaching(n, &rates, targets)
+        .into_iter()
+        .enumerate()
+        .filter(|&(_, reaching)| !reaching)
+        .map(|(i, _)| i)
+        .collect();
+    let infinite = reaching(n, &rates, &trapped);
+
+    let unknown: Vec<usize> = (0..n)
+        .filter(|&i| !infinite[i] && !targets.contains(&i))
+        .collect();
+    let mut times: Vec<f64> = (0..n)
+        .map(|i| match infinite[i] && !targets.contains(&i) {
+            true => f64::INFINITY,
+            false => 0.0,
+        })
+        .collect();
+    let solution = solve_generator(n, &rates, &unknown, -1.0, |_| 0.0);
+
+    for (i, t) in unknown.into_iter().zip(solution) {
+        times[i] = t;
+    }
+
+    times
+}
+
+// Return for each of the `n` structures whether any of the structures in `set` can be reached from it
+// along transitions with a positive rate.
+fn reaching(n: usize, rates: &[(usize, usize, f64)], set: &[usize]) -> Vec<bool> {
+    let mut predecessors = vec![vec![]; n];
+    for &(i, j, rate) in rates {
+        if rate > 0.0 {
+            predecessors[j].push(i);
+        }
+    }
+
+    let mut reaching = vec![false; n];
+    let mut stack = set.to_vec();
+    while let Some(j) = stack.pop() {
+        if !std::mem::replace(&mut reaching[j], true) {
+            stack.extend(predecessors[j].iter().filter(|&&i| !reaching[i]));
+        }
+    }
+
+    reaching
+}
+
+// Solve `sum_j k(i -> j) (x_j - x_i) = constant` for the values `x_i` of the `unknown` structures among all `n`
+// structures, where the values of all other structures are given by `known`.
+// Every unknown structure has to reach a known structure along positive `rates`, otherwise the system is singular.
+fn solve_generator<F>(
+    n: usize,
+    rates: &[(usize, usize, f64)],
+    unknown: &[usize],
+    constant: f64,
+    known: F,
+) -> Vec<f64>
+where
+    F: Fn(usize) -> f64,
+{
+    let m = unknown.len();
+    let mut position = vec![None; n];
+    for (row, &i) in unknown.iter().enumerate() {
+        position[i] = Some(row);
+    }
+
+    let mut matrix = Array2::<f64>::zeros((m, m));
+    let mut rhs = vec![constant; m];
+    for &(i, j, rate) in rates {
+        if let Some(row) = position[i] {
+            matrix[[row, row]] -= rate;
+            match position[j] {
+                Some(column) => matrix[[row, column]] += rate,
+                None => rhs[row] -= rate * known(j),
+            }
+        }
+    }
+
+    // Gaussian elimination with partial pivoting
+    for k in 0..m {
+        let pivot = (k..m)
+            .max_by(|&a, &b| matrix[[a, k]].abs().total_cmp(&matrix[[b, k]].abs()))
+            .expect("non-empty range");
+        assert!(
+            matrix[[pivot, k]] != 0.0,
+            "singular generator, an unknown structure cannot reach any known structure"
+        );
+        if pivot != k {
+            for column in k..m {
+                matrix.swap([k, column], [pivot, column]);
+            }
+            rhs.swap(k, pivot);
+        }
+
+        for row in k + 1..m {
+            let factor = matrix[[row, k]] / matrix[[k, k]];
+            if factor != 0.0 {
+                for column in k..m {
+                    matrix[[row, column]] -= factor * matrix[[k, column]];
+                }
+                rhs[row] -= factor * rhs[k];
+            }
+        }
+    }
+
+    let mut solution = vec![0.0; m];
+    for k in (0..m).rev() {
+        let sum: f64 = (k + 1..m)
+            .map(|column| matrix[[k, column]] * solution[column])
+            .sum();
+        solution[k] = (rhs[k] - sum) / matrix[[k, k]];
+    }
+
+    solution
+}
+
+/// Return the Boltzmann probabilities of the structures of `graph` at thermal energy `kt` (in `kcal/mol`),
+/// i.e. `exp(-dG_i / kT) / Z` where the partition function `Z` only contains the structures of `graph`.
+/// This is the steady state of the Markov chain defined by [`rates()`] if `graph` is connected.
+/// The probabilities are in the order of [`RafftGraph::iter()`].
+pub fn boltzmann_probabilities(graph: &RafftGraph, kt: f64) -> Vec<f64> {
+    let exponents: Vec<f64> = graph
+        .iter()
+        .map(|node| -(node.energy as f64 * 0.01) / kt)
+        .collect();
+
+    // shift by the maximum to avoid overflows
+    let maximum = exponents.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let weights: Vec<f64> = exponents.iter().map(|x| (x - maximum).exp()).collect();
+    let partition: f64 = weights.iter().sum();
+
+    weights.iter().map(|w| w / partition).collect()
+}
+
+/// A phase of a temperature protocol, see [`temperature_protocol()`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TemperatureStep {
+    /// temperature in °C
+    pub temperature: f64,
+    /// duration in the time units of [`ArrheniusModel::prefactor`]
+    pub duration: f64,
+}
+
+/// Populations of the structures of a graph at a point in time of a temperature protocol.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PopulationSnapshot {
+    /// time since the start of the protocol
+    pub time: f64,
+    /// temperature in °C at `time`
+    pub temperature: f64,
+    /// populations in the order of [`RafftGraph::iter()`]
+    pub populations: Vec<f64>,
+}
+
+/// Simulate the populations of the structures of `graph` under a temperature `protocol`,
+/// starting with the complete population in the root (i.e. the open chain).
+///
+/// The structures of `graph` are kept fixed, but their free energies are re-evaluated for `sequence`
+/// at the temperature of every step, and the rates are given by `model` at that temperature
+/// (i.e. with `kT` adjusted and the same prefactor and barrier).
+/// The populations at the end of a step are the initial populations of the next one, which allows to study
+/// refolding and hysteresis after temperature jumps.
+///
+/// Returns the initial populations and `samples` equidistant snapshots per step, the last of which is at the
+/// end of the step. Panics if `sequence` does not have the length of the structures of `graph`.
+#[cfg(feature = "viennarna")]
+pub fn temperature_protocol(
+    graph: &RafftGraph,
+    sequence: &str,
+    model: &ArrheniusModel,
+    protocol: &[TemperatureStep],
+    samples: usize,
+) -> Vec<PopulationSnapshot> {
+    temperature_protocol_until(
+        graph,
+        sequence,
+        model,
+        protocol,
+        samples,
+        &CancelToken::new(),
+    )
+    .expect("never cancelled")
+}
+
+/// Like [`temperature_protocol()`], but aborts once `token` is cancelled.
+#[cfg(feature = "viennarna")]
+pub fn temperature_protocol_until(
+    graph: &RafftGraph,
+    sequence: &str,
+    model: &ArrheniusModel,
+    protocol: &[TemperatureStep],
+    samples: usize,
+    token: &CancelToken,
+) -> Result<Vec<PopulationSnapshot>, Cancelled> {
+    let root = graph.root();
+    let mut current: Vec<f64> = graph
+        .iter()
+        .map(|node| if node.id == root { 1.0 } else { 0.0 })
+        .collect();
+
+    let mut time = 0.0;
+    let mut snapshots = vec![PopulationSnapshot {
+        time,
+        temperature: protocol.first().map_or(37.0, |step| step.temperature),
+        populations: current.clone(),
+    }];
+
+    for step in protocol {
+        let fc = VCompound::with_temperature(sequence, step.temperature);
+        let energies: Vec<f64> = graph
+            .iter()
+            .map(|node| fc.evaluate_structure_f64(node.structure.pair_table().view()))
+            .collect();
+
+        let model = ArrheniusModel {
+            kt: ArrheniusModel::at_temperature(step.temperature).kt,
+            ..*model
+        };
+        let rates = rates_with(graph, &energies, &model);
+
+        let interval = step.duration / samples.max(1) as f64;
+        for _ in 0..samples.max(1) {
+            current = propagate(current, &rates, interval, token)?;
+            time += interval;
+
+            if samples > 0 {
+                snapshots.push(PopulationSnapshot {
+                    time,
+                    temperature: step.temperature,
+                    populations: current.clone(),
+                });
+            }
+        }
+    }
+
+    Ok(snapshots)
+}
+
+/// Write the snapshots of a temperature protocol as tab-separated values with one line per snapshot,
+/// containing the time, the temperature and the populations of all structures.
+/// The header line names the structures by their `1`-based indices (see [`write_states()`]).
+pub fn write_protocol<W: Write>(
+    snapshots: &[PopulationSnapshot],
+    writer: &mut W,
+) -> io::Result<()> {
+    write!(writer, "time\ttemperature")?;
+    let n = snapshots.first().map_or(0, |s| s.populations.len());
+    for index in 1..=n {
+        write!(writer, "\t{}", index)?;
+    }
+    writeln!(writer)?;
+
+    for snapshot in snapshots {
+        write!(writer, "{:.6e}\t{}", snapshot.time, snapshot.temperature)?;
+        for population in &snapshot.populations {
+            write!(writer, "\t{:.6e}", population)?;
+        }
+        writeln!(writer)?;
+    }
+
+    Ok(())
+}
+
+/// Write the dense rate matrix in the format of `barriers --rates` (`rates.out`), which can be
+/// read by `treekin -m I`. The entry in row `i` and column `j` is the rate from structure `i` to `j`,
+/// diagonal entries are `0`. Note that the output grows quadratically with the number of structures.
+pub fn write_rate_matrix<W: Write>(
+    graph: &RafftGraph,
+    model: &ArrheniusModel,
+    writer: &mut W,
+) -> io::Result<()> {
+    write_dense_rates(graph.iter().count(), &rates(graph, model), writer)
+}
+
+// Write the dense matrix of `rates` between `n` states.
+fn write_dense_rates<W: Write>(
+    n: usize,
+    rates: &[(usize, usize, f64)],
+    writer: &mut W,
+) -> io::Result<()> {
+    let mut matrix = vec![0.0; n * n];
+
+    for &(i, j, rate) in rates {
+        matrix[i * n + j] = rate;
+    }
+
+    for row in matrix.chunks(n.max(1)).take(n) {
+        for rate in row {
+            write!(writer, "{:10.4e} ", rate)?;
+        }
+        writeln!(writer)?;
+    }
+
+    Ok(())
+}
+
+/// Write the non-zero rates as `i j k(i -> j)` per line, using `1`-based indices.
+pub fn write_sparse_rates<W: Write>(
+    graph: &RafftGraph,
+    model: &ArrheniusModel,
+    writer: &mut W,
+) -> io::Result<()> {
+    write_rate_triplets(&rates(graph, model), writer)
+}
+
+// Write `rates` as `i j k(i -> j)` per line, using `1`-based indices.
+fn write_rate_triplets<W: Write>(rates: &[(usize, usize, f64)], writer: &mut W) -> io::Result<()> {
+    for &(i, j, rate) in rates {
+        writeln!(writer, "{} {} {:.6e}", i + 1, j + 1, rate)?;
+    }
+
+    Ok(())
+}
+
+/// Write the states of the rate matrix as `index structure energy` per line, using `1`-based indices,
+/// similar to the output of `barriers`.
+pub fn write_states<W: Write>(graph: &RafftGraph, writer: &mut W) -> io::Result<()> {
+    for (index, node) in graph.iter().enumerate() {
+        writeln!(
+            writer,
+            "{:>4} {} {:6.2}",
+            index + 1,
+            node.structure,
+            node.energy as f64 * 0.01
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Return the local minimum reached from every structure of `graph` by gradient descent, i.e. by repeatedly moving
+/// to the adjacent structure of lowest free energy as long as it is lower, with ties broken by the order of
+/// [`RafftGraph::iter()`]. The structures with the same local minimum form its gradient basin.
+/// Both the structures and their minima are indices in the order of [`RafftGraph::iter()`].
+pub fn gradient_basins(graph: &RafftGraph) -> Vec<usize> {
+    let energies: Vec<i32> = graph.iter().map(|node| node.energy).collect();
+    let mut steepest: Vec<usize> = (0..energies.len()).collect();
+
+    for (i, j) in graph.adjacent_indices() {
+        for (from, to) in [(i, j), (j, i)] {
+            if (energies[to], to) < (energies[steepest[from]], steepest[from]) {
+                steepest[from] = to;
+            }
+        }
+    }
+
+    // descents only follow decreasing (energy, index) and therefore terminate
+    (0..energies.len())
+        .map(|mut i| {
+            while steepest[i] != i {
+                i = steepest[i];
+            }
+            i
+        })
+        .collect()
+}
+
+/// A coarse-graining of the structures of a graph into macrostates with aggregated transition rates,
+/// see [`Macrostates::new()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Macrostates {
+    /// the macrostate of every structure, in the order of [`RafftGraph::iter()`]
+    pub assignment: Vec<usize>,
+    /// the structure of lowest free energy per macrostate, e.g. the local minimum of a gradient basin
+    pub representatives: Vec<usize>,
+    /// the number of structures per macrostate
+    pub sizes: Vec<usize>,
+    /// the free energies `-kT ln(sum_i exp(-dG_i / kT))` of the macrostates in `kcal/mol`
+    pub energies: Vec<f64>,
+    /// the aggregated transition rates `(a, b, k(a -> b))` between distinct macrostates
+    pub rates: Vec<(usize, usize, f64)>,
+}
+
+impl Macrostates {
+    /// Coarse-grain `graph` into the macrostates given by `labels` per structure (in the order of
+    /// [`RafftGraph::iter()`]), e.g. the [`gradient_basins()`] or the clusters of a custom clustering.
+    /// Macrostates are numbered by the first occurrence of their labels, i.e. the macrostate of the root is `0`.
+    ///
+    /// Assuming local equilibrium within macrostates, the rate from macrostate `a` to `b` is the sum of the rates
+    /// of `model` from structures `i` in `a` to structures `j` in `b`, weighted by the Boltzmann probabilities
+    /// of the structures `i` within `a`. Panics if `labels` does not have one entry per structure.
+    pub fn new(graph: &RafftGraph, model: &ArrheniusModel, labels: &[usize]) -> Self {
+        let energies: Vec<f64> = graph.iter().map(|node| node.energy as f64 * 0.01).collect();
+        assert_eq!(labels.len(), energies.len(), "one label per structure");
+
+        let mut numbers = HashMap::new();
+        let assignment: Vec<usize> = labels
+            .iter()
+            .map(|label| {
+                let next = numbers.len();
+                *numbers.entry(label).or_insert(next)
+            })
+            .collect();
+        let m = numbers.len();
+
+        let mut representatives: Vec<Option<usize>> = vec![None; m];
+        let mut sizes = vec![0; m];
+        for (i, &a) in assignment.iter().enumerate() {
+            sizes[a] += 1;
+            // `Option::is_none_or` requires Rust 1.82
+            #[allow(clippy::unnecessary_map_or)]
+            let lower = representatives[a].map_or(true, |r| energies[i] < energies[r]);
+            if lower {
+                representatives[a] = Some(i);
+            }
+        }
+        let representatives: Vec<usize> = representatives.into_iter().flatten().collect();
+
+        // Boltzmann weights relative to the representatives to avoid overflows
+        let weights: Vec<f64> = energies
+            .iter()
+            .zip(&assignment)
+            .map(|(energy, &a)| (-(energy - energies[representatives[a]]) / model.kt).exp())
+            .collect();
+        let mut partitions = vec![0.0; m];
+        for (weight, &a) in weights.iter().zip(&assignment) {
+            partitions[a] += weight;
+        }
+
+        let mut aggregated: HashMap<(usize, usize), f64> = HashMap::new();
+        for (i, j, rate) in rates(graph, model) {
+            let (a, b) = (assignment[i], assignment[j]);
+            if a != b {
+                *aggregated.entry((a, b)).or_default() += weights[i] / partitions[a] * rate;
+            }
+        }
+        let mut aggregated: Vec<_> = aggregated
+            .into_iter()
+            .map(|((a, b), rate)| (a, b, rate))
+            .collect();
+        aggregated.sort_by_key(|&(a, b, _)| (a, b));
+
+        Self {
+            energies: representatives
+                .iter()
+                .zip(&partitions)
+                .map(|(&r, partition)| energies[r] - model.kt * partition.ln())
+                .collect(),
+            assignment,
+            representatives,
+            sizes,
+            rates: aggregated,
+        }
+    }
+
+    /// Coarse-grain `graph` into the gradient basins of its local minima, see [`gradient_basins()`].
+    pub fn gradient_basins(graph: &RafftGraph, model: &ArrheniusModel) -> Self {
+        Self::new(graph, model, &gradient_basins(graph))
+    }
+
+    /// Return the number of macrostates.
+    pub fn len(&self) -> usize {
+        self.representatives.len()
+    }
+
+    /// Return `true` if there are no macrostates, i.e. the graph has no structures.
+    pub fn is_empty(&self) -> bool {
+        self.representatives.is_empty()
+    }
+
+    /// Write the dense matrix of the aggregated rates in the format of `barriers --rates`,
+    /// see [`write_rate_matrix()`].
+    pub fn write_rate_matrix<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_dense_rates(self.len(), &self.rates, writer)
+    }
+
+    /// Write the aggregated rates as `a b k(a -> b)` per line, using `1`-based indices.
+    pub fn write_sparse_rates<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_rate_triplets(&self.rates, writer)
+    }
+
+    /// Write the macrostates as `index structure energy size` per line, using `1`-based indices,
+    /// where the structure is the representative in `graph` and the energy is the free energy of the macrostate.
+    pub fn write_states<W: Write>(&self, graph: &RafftGraph, writer: &mut W) -> io::Result<()> {
+        let structures: Vec<_> = graph.iter().collect();
+
+        for (index, (&representative, (energy, size))) in self
+            .representatives
+            .iter()
+            .zip(self.energies.iter().zip(&self.sizes))
+            .enumerate()
+        {
+            writeln!(
+                writer,
+                "{:>4} {} {:6.2} {}",
+                index + 1,
+                structures[representative].structure,
+                energy,
+                size
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fast_folding::RafftConfig;
+    use crate::testing::small_graph;
+
+    #[test]
+    fn test_rates() {
+        let model = ArrheniusModel {
+            prefactor: 2.0,
+            kt: 0.5,
+            barrier: 1.0,
+        };
+
+        assert_eq!(model.rate(0.0, -1.0), 2.0 * (-2.0f64).exp());
+        assert_eq!(model.rate(-1.0, 0.0), 2.0 * (-4.0f64).exp());
+        assert!((ArrheniusModel::default().kt - 0.6163).abs() < 1e-3);
+
+        let ffgraph = small_graph();
+
+        let n = ffgraph.iter().count();
+        let mut dense = vec![];
+        write_rate_matrix(&ffgraph, &model, &mut dense).unwrap();
+        let dense = String::from_utf8(dense).unwrap();
+
+        assert_eq!(dense.lines().count(), n);
+        assert!(dense
+            .lines()
+            .all(|line| line.split_whitespace().count() == n));
+
+        let mut sparse = vec![];
+        write_sparse_rates(&ffgraph, &model, &mut sparse).unwrap();
+        assert_eq!(
+            String::from_utf8(sparse).unwrap().lines().count(),
+            2 * ffgraph.adjacent_indices().filter(|(i, j)| i != j).count()
+        );
+    }
+
+    #[test]
+    fn test_matrices() {
+        let matrix =
+            CsrMatrix::from_triplets((3, 4), [(2, 0, 1.0), (0, 3, 2.0), (0, 1, 3.0), (2, 0, 0.5)]);
+        assert_eq!(matrix.indptr, vec![0, 2, 2, 3]);
+        assert_eq!(matrix.indices, vec![1, 3, 0]);
+        assert_eq!(matrix.values, vec![3.0, 2.0, 1.5]);
+        assert_eq!(matrix.get(2, 0), 1.5);
+        assert_eq!(matrix.get(1, 1), 0.0);
+        assert_eq!(
+            matrix.triplets().collect::<Vec<_>>(),
+            vec![(0, 1, 3.0), (0, 3, 2.0), (2, 0, 1.5)]
+        );
+        assert_eq!(matrix.to_dense().sum(), 6.5);
+
+        let ffgraph = small_graph();
+        let n = ffgraph.iter().count();
+
+        let adjacency = ffgraph.adjacency_matrix();
+        assert_eq!(adjacency.shape, (n, n));
+        assert_eq!(adjacency.nnz(), ffgraph.adjacent_indices().count());
+        assert!(ffgraph
+            .adjacent_indices()
+            .all(|(i, j)| adjacency.get(i, j) == 1.0 && (i == j || adjacency.get(j, i) == 0.0)));
+
+        // off-diagonal entries are the rates, rows of the generator sum to 0
+        let model = ArrheniusModel::at_temperature(37.0);
+        let generator = ffgraph.rate_matrix(37.0);
+        for (i, j, rate) in rates(&ffgraph, &model) {
+            assert_eq!(generator.get(i, j), rate);
+        }
+        assert!(generator
+            .to_dense()
+            .rows()
+            .into_iter()
+            .all(|row| row.sum().abs() < 1e-12));
+        assert!(ffgraph.rate_matrix(60.0).get(1, 0) > generator.get(1, 0));
+    }
+
+    #[test]
+    fn test_committors() {
+        let ffgraph = small_graph();
+
+        let model = ArrheniusModel::default();
+        let rates = rates(&ffgraph, &model);
+        let n = ffgraph.iter().count();
+        let minimum = ffgraph.iter().map(|node| node.energy).min().unwrap();
+        let targets: Vec<usize> = ffgraph
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.energy == minimum)
+            .map(|(index, _)| index)
+            .collect();
+
+        // the residuals sum_j k(i -> j) (x_j - x_i) vanish for committors and are -1 for passage times
+        let residuals = |values: &[f64]| {
+            let mut residuals = vec![0.0; n];
+            for &(i, j, rate) in &rates {
+                residuals[i] += rate * (values[j] - values[i]);
+            }
+            residuals
+        };
+
+        let q = committors(&ffgraph, &model, &targets);
+        assert_eq!(q[0], 0.0);
+        assert!(targets.iter().all(|&target| q[target] == 1.0));
+        assert!(q.iter().all(|&p| (-1e-9..=1.0 + 1e-9).contains(&p)));
+        assert!(residuals(&q)
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != 0 && !targets.contains(i))
+            .all(|(_, r)| r.abs() < 1e-9));
+
+        let times = mean_first_passage_times(&ffgraph, &model, &targets);
+        assert!(times[0] > 0.0);
+        assert!(targets.iter().all(|&target| times[target] == 0.0));
+        assert!(residuals(&times)
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !targets.contains(i))
+            .all(|(_, r)| (r + 1.0).abs() < 1e-6));
+
+        // slower transitions take longer
+        let slow = ArrheniusModel {
+            prefactor: 0.5,
+            ..model
+        };
+        let slow_times = mean_first_passage_times(&ffgraph, &slow, &targets);
+        assert!((slow_times[0] - 2.0 * times[0]).abs() < 1e-6 * times[0]);
+
+        assert!(committors(&ffgraph, &model, &[]).iter().all(|&p| p == 0.0));
+        assert!(mean_first_passage_times(&ffgraph, &model, &[])
+            .iter()
+            .all(|t| t.is_infinite()));
+        assert_eq!(committors(&ffgraph, &model, &[0])[0], 1.0);
+    }
+
+    #[test]
+    fn test_unreachable_targets() {
+        let sequence =
+            "GGGUUUGCGGUGUAAGUGCAGCCCGUCUUACACCGUGCGGCACAGGCACUAGUACUGAUGUCGUAUACAGGGCUUUUGACAU";
+        let mut ffgraph = RafftConfig::new()
+            .maximum_trajectories(5)
+            .folding_graph(sequence);
+        ffgraph.construct_trajectories();
+        // keep the branch of the lowest structure of the first depth
+        let threshold = ffgraph
+            .children(ffgraph.root())
+            .filter_map(|child| ffgraph.node(child))
+            .map(|node| node.energy)
+            .min()
+            .unwrap();
+        assert!(ffgraph.prune_above(threshold as f64 * 0.01) > 0);
+
+        // uphill rates vanish, so structures are trapped in the minima below them
+        let model = ArrheniusModel {
+            kt: 1e-4,
+            ..ArrheniusModel::default()
+        };
+        let rates = rates(&ffgraph, &model);
+        let n = ffgraph.iter().count();
+        let targets = [n - 1];
+        let reaching = reaching(n, &rates, &targets);
+        assert!(reaching.iter().any(|&reaching| !reaching));
+
+        let q = committors(&ffgraph, &model, &targets);
+        let times = mean_first_passage_times(&ffgraph, &model, &targets);
+        assert!(q.iter().all(|p| (0.0..=1.0 + 1e-9).contains(p)));
+        assert!(times.iter().all(|t| !t.is_nan() && *t >= 0.0));
+
+        for i in 0..n {
+            if !reaching[i] {
+                assert_eq!(q[i], 0.0);
+                assert!(times[i].is_infinite());
+            }
+            // structures that surely reach the target commit to it, unless they are the root
+            if times[i].is_finite() && i != 0 {
+                assert!((q[i] - 1.0).abs() < 1e-9);
+            }
+            if times[i].is_finite() && i != n - 1 {
+                let residual: f64 = rates
+                    .iter()
+                    .filter(|&&(from, _, rate)| from == i && rate > 0.0)
+                    .map(|&(_, j, rate)| rate * (times[j] - times[i]))
+                    .sum();
+                assert!((residual + 1.0).abs() < 1e-6);
+            }
+        }
+        assert_eq!(times[n - 1], 0.0);
+    }
+
+    #[test]
+    fn test_macrostates() {
+        let ffgraph = small_graph();
+
+        let model = ArrheniusModel::default();
+        let energies: Vec<i32> = ffgraph.iter().map(|node| node.energy).collect();
+        let basins = gradient_basins(&ffgraph);
+
+        // minima are their own basins and not higher than their members
+        assert!(basins.iter().all(|&minimum| basins[minimum] == minimum));
+        assert!(basins
+            .iter()
+            .enumerate()
+            .all(|(i, &minimum)| energies[minimum] <= energies[i]));
+
+        let macrostates = Macrostates::gradient_basins(&ffgraph, &model);
+        assert_eq!(macrostates.assignment.len(), energies.len());
+        assert_eq!(macrostates.sizes.iter().sum::<usize>(), energies.len());
+        assert!(macrostates.representatives.iter().all(|&r| basins[r] == r
+            && macrostates.energies[macrostates.assignment[r]] <= energies[r] as f64 * 0.01));
+        assert!(macrostates
+            .rates
+            .iter()
+            .all(|&(a, b, rate)| a != b && rate > 0.0));
+
+        // singleton macrostates reproduce the rates of the structures
+        let singletons =
+            Macrostates::new(&ffgraph, &model, &(0..energies.len()).collect::<Vec<_>>());
+        let mut expected = rates(&ffgraph, &model);
+        expected.sort_by_key(|&(i, j, _)| (i, j));
+        assert_eq!(singletons.len(), energies.len());
+        assert_eq!(singletons.rates.len(), expected.len());
+        assert!(singletons
+            .rates
+            .iter()
+            .zip(&expected)
+            .all(|(x, y)| x.0 == y.0 && x.1 == y.1 && (x.2 - y.2).abs() < 1e-12));
+
+        // a single macrostate has no transitions
+        let single = Macrostates::new(&ffgraph, &model, &vec![7; energies.len()]);
+        assert_eq!((single.len(), single.rates.len()), (1, 0));
+        assert_eq!(
+            single.representatives[0],
+            energies
+                .iter()
+                .position(|&e| e == *energies.iter().min().unwrap())
+                .unwrap()
+        );
+
+        let mut states = vec![];
+        macrostates.write_states(&ffgraph, &mut states).unwrap();
+        assert_eq!(
+            String::from_utf8(states).unwrap().lines().count(),
+            macrostates.len()
+        );
+        let mut dense = vec![];
+        macrostates.write_rate_matrix(&mut dense).unwrap();
+        assert_eq!(
+            String::from_utf8(dense).unwrap().lines().count(),
+            macrostates.len()
+        );
+    }
+
+    #[test]
+    fn test_populations() {
+        let ffgraph = small_graph();
+
+        let model = ArrheniusModel::default();
+        let initial = populations(&ffgraph, &model, 0.0);
+        assert_eq!(initial[0], 1.0);
+        assert_eq!(initial.iter().sum::<f64>(), 1.0);
+
+        let early = populations(&ffgraph, &model, 0.1);
+        let late = populations(&ffgraph, &model, 1000.0);
+        assert!((early.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!((late.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!(late[0] < early[0]);
+
+        // the lowest energy structures dominate eventually
+        let minimum = ffgraph.iter().map(|node| node.energy).min().unwrap();
+        let best: f64 = ffgraph
+            .iter()
+            .zip(&late)
+            .filter(|(node, _)| node.energy == minimum)
+            .map(|(_, p)| p)
+            .sum();
+        assert!(best > 0.5);
+
+        let token = CancelToken::new();
+        token.cancel();
+        assert_eq!(
+            populations_until(&ffgraph, &model, 1.0, &token),
+            Err(Cancelled)
+        );
+        assert_eq!(
+            populations_until(&ffgraph, &model, 0.0, &token).unwrap(),
+            initial
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "viennarna")]
+    fn test_temperature_protocol() {
+        use crate::testing::SMALL_SEQUENCE;
+
+        let sequence = SMALL_SEQUENCE;
+        let ffgraph = small_graph();
+
+        let model = ArrheniusModel::default();
+        let protocol = [
+            TemperatureStep {
+                temperature: 37.0,
+                duration: 10.0,
+            },
+            TemperatureStep {
+                temperature: 55.0,
+                duration: 10.0,
+            },
+            TemperatureStep {
+                temperature: 37.0,
+                duration: 10.0,
+            },
+        ];
+        let snapshots = temperature_protocol(&ffgraph, sequence, &model, &protocol, 4);
+
+        assert_eq!(snapshots.len(), 13);
+        assert_eq!(snapshots[0].time, 0.0);
+        assert!((snapshots[12].time - 30.0).abs() < 1e-9);
+        assert_eq!(snapshots[5].temperature, 55.0);
+        assert!(snapshots
+            .iter()
+            .all(|s| (s.populations.iter().sum::<f64>() - 1.0).abs() < 1e-9));
+
+        // a single step at the temperature of the graph reproduces the isothermal populations
+        let isothermal = temperature_protocol(&ffgraph, sequence, &model, &protocol[..1], 1);
+        populations(&ffgraph, &model, 10.0)
+            .iter()
+            .zip(&isothermal[1].populations)
+            .for_each(|(a, b)| assert!((a - b).abs() < 1e-9));
+
+        // the steady state is the Boltzmann distribution, a high temperature ensures fast mixing
+        let hot = ArrheniusModel { kt: 10.0, ..model };
+        let equilibrium = boltzmann_probabilities(&ffgraph, hot.kt);
+        assert!((equilibrium.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        populations(&ffgraph, &hot, 1e3)
+            .iter()
+            .zip(&equilibrium)
+            .for_each(|(a, b)| assert!((a - b).abs() < 1e-6));
+
+        let mut tsv = vec![];
+        write_protocol(&snapshots, &mut tsv).unwrap();
+        let tsv = String::from_utf8(tsv).unwrap();
+        assert_eq!(tsv.lines().count(), 14);
+        assert_eq!(
+            tsv.lines().next().unwrap().split('\t').count(),
+            ffgraph.iter().count() + 2
+        );
+    }
+}
@@ -2,27 +2,102 @@
 #![warn(rustdoc::missing_crate_level_docs)]
 
 //! Rust implementation of [`RAFFT`](https://www.biorxiv.org/content/10.1101/2021.07.02.450908v1.full)
+//!
+//! Fast folding graphs are evaluated using ViennaRNA, which is linked using the default `viennarna` feature.
+//! Without it, the encoding, autocorrelation and stack search (see [`encoding::EncodedSequence::stacks_at_lag()`]),
+//! the pre-screening of [`screening`] and energy-free fast folding graphs (see
+//! [`fast_folding::RafftConfig::energy_free()`]) are available, e.g. for lightweight consumers or WASM builds
+//! that only need pairing scores.
 
+/// Prediction accuracy on benchmark datasets of known structures
+#[cfg(feature = "viennarna")]
+pub mod accuracy;
+/// Comparisons across the trajectories of fast folding graphs
+pub mod analysis;
+/// Folding from asynchronous code on tokio's blocking thread pool
+#[cfg(feature = "async")]
+pub mod asynchronous;
 /// Autocorrelation of an encoded RNA sequence using FFT
 #[allow(dead_code)]
 pub mod autocorrelation;
 #[cfg(feature = "bindings")]
 #[allow(dead_code)]
 mod bindings;
+/// Cooperative cancellation of long-running computations
+pub mod cancel;
+/// Genomic coordinates of folded sequences, e.g. for genome browsers
+pub mod coordinates;
+/// SVG drawings of secondary structures
+#[cfg(feature = "drawing")]
+pub mod drawing;
 /// Encoding of RNA sequences using nucleotide representations suitable for FFT
 #[allow(dead_code)]
 pub mod encoding;
+/// Exports of structures for forna, R2DT and as connectivity tables, and of graphs as DOT and GraphML
+#[cfg(feature = "viennarna")]
+pub mod export;
 /// Implementation of the RAFFT fast-folding algorithm.
 #[allow(dead_code)]
 pub mod fast_folding;
+/// FFT backends used to compute autocorrelations
+pub mod fft;
 /// A graph structure used be the RAFFT fast-folding algorithm.
 #[allow(dead_code)]
 pub mod folding_graph;
+/// Human-readable terminal output of fast folding graphs
+#[cfg(feature = "viennarna")]
+pub mod formatting;
+/// Kinetics on fast folding graphs and export to external kinetics tools
+pub mod kinetics;
+/// Batch folding of jobs described by a manifest, e.g. for workflow managers
+#[cfg(feature = "viennarna")]
+pub mod manifest;
+/// Temperature scans producing melting curves
+#[cfg(feature = "viennarna")]
+pub mod melting;
+/// Search for secondary structure motifs among the structures of fast folding graphs
+pub mod motifs;
+/// Common scoring interface for sequence design and screening
+#[cfg(feature = "viennarna")]
+pub mod objective;
+/// Comparison against the reference implementation of RAFFT
+#[cfg(feature = "viennarna")]
+pub mod reference;
+/// Standalone HTML reports of fast folding graphs
+#[cfg(all(feature = "drawing", feature = "viennarna"))]
+pub mod report;
+/// Two-state analysis of fast folding graphs, e.g. for riboswitches
+#[cfg(feature = "viennarna")]
+pub mod riboswitch;
+/// Fast pre-screening of sequences by autocorrelation signal strength
+pub mod screening;
+/// Re-use of encodings and fold compounds across repeated folds of the same sequences
+#[cfg(feature = "viennarna")]
+pub mod session;
+/// Parallel parameter sweeps over grids of RAFFT parameters
+#[cfg(feature = "viennarna")]
+pub mod sweep;
+/// Strategies and invariants for property-based testing
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 /// Crate-specific bindings for ViennaRNA
+#[cfg(feature = "viennarna")]
 #[allow(dead_code)]
 mod vienna;
 
-pub use vienna::{set_global_energy_parameters, set_global_temperature, VIENNA_VERSION};
+#[cfg(feature = "async")]
+pub use asynchronous::fold_async;
+pub use cancel::CancelToken;
+pub use fast_folding::{candidate_stacks, fold_sequence, fold_sequence_with, RafftResult};
+#[cfg(feature = "viennarna")]
+pub use melting::melt;
+pub use motifs::LoopType;
+#[cfg(feature = "viennarna")]
+pub use vienna::{
+    evaluate_structure, loop_contributions, plot_coordinates, set_global_energy_parameters,
+    set_global_parameter_set, set_global_temperature, LoopContribution, ModelOptions, ParameterSet,
+    PlotLayout, VIENNA_VERSION,
+};
 
 #[cfg(feature = "bindings")]
 use pyo3::prelude::*;
@@ -42,6 +117,13 @@ fn librafft(py: Python, m: &PyModule) -> PyResult<()> {
         Ok(())
     }
 
+    #[pyfn(m)]
+    fn fold_sequence(sequence: &str) -> PyResult<(String, f64)> {
+        crate::fold_sequence(sequence)
+            .map(|result| (result.structure(), result.energy()))
+            .map_err(|error| pyo3::exceptions::PyValueError::new_err(error.to_string()))
+    }
+
     #[pyfn(m)]
     fn vienna_version() -> PyResult<String> {
         Ok(VIENNA_VERSION.to_string())
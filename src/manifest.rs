@@ -0,0 +1,410 @@
+//! This module provides batch folding driven by a manifest, e.g. for workflow managers such as Nextflow or Snakemake.
+//!
+//! A manifest is a tab-separated file with a header line. Each row describes a job: the column `input` contains
+//! the path of a sequence file (FASTA or plain sequence, only the first record is folded) and the column `output`
+//! the path the structures are written to. Relative paths are resolved against the directory of the manifest.
+//! All other columns override parameters of the [`RafftConfig`] for the row, empty cells keep the default:
+//!
+//! ```text
+//! input        output          positional_lags  saved_trajectories  temperature
+//! a.fa         out/a.tsv       100              5
+//! b.fa         out/b.tsv       50                                   25.0
+//! ```
+//!
+//! Jobs are folded in parallel and their status is summarized in a results manifest, see [`write_results()`].
+
+use crate::fast_folding::RafftConfig;
+use crate::folding_graph::RafftGraph;
+use crate::screening::read_fasta;
+use crate::session::RafftSession;
+use rayon::prelude::*;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Error type representing errors that may arise while reading manifests.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// The manifest could not be read.
+    #[error("failed to read manifest: {0}")]
+    Io(#[from] io::Error),
+    /// The header line lacks a required column.
+    #[error("manifest lacks the column {0:?}")]
+    MissingColumn(&'static str),
+    /// A row does not have as many cells as the header (`1`-indexed line number).
+    #[error("line {0} of the manifest has {1} columns, expected {2}")]
+    RowLength(usize, usize, usize),
+    /// A column does not name a parameter that can be overridden.
+    #[error("unknown parameter {0:?}")]
+    UnknownParameter(String),
+    /// The value of a parameter could not be parsed.
+    #[error("invalid value {1:?} for parameter {0:?}")]
+    InvalidValue(String, String),
+}
+
+/// Names of the parameters that can be overridden per job, see [`override_parameter()`].
+pub const PARAMETERS: &[&str] = &[
+    "positional_lags",
+    "stacks_per_lag",
+    "branches",
+    "saved_trajectories",
+    "min_unpaired",
+    "hairpin_enforcement",
+    "max_pair_span",
+    "minimum_helix_energy",
+    "basepair_weights",
+    "min_distance",
+    "seed",
+    "temperature",
+];
+
+/// A single row of a manifest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Job {
+    /// path of the sequence file
+    pub input: PathBuf,
+    /// path of the output file
+    pub output: PathBuf,
+    /// parameter overrides `(name, value)`, see [`PARAMETERS`]
+    pub overrides: Vec<(String, String)>,
+}
+
+/// Outcome of a [`Job`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobStatus {
+    /// The structures were written to the output file.
+    Done {
+        /// structure of lowest free energy in dot-bracket notation
+        best_structure: String,
+        /// lowest free energy in `kcal/mol`
+        best_energy: f64,
+        /// number of structures in the fast folding graph
+        structures: usize,
+    },
+    /// The job failed, e.g. because the input could not be read or contains an invalid sequence.
+    Failed(String),
+}
+
+/// Read the jobs of a manifest. Relative paths are resolved against `base`, e.g. the directory of the manifest.
+/// Parameter overrides are validated, such that jobs only fail because of their inputs and outputs.
+pub fn read_manifest<R: BufRead>(reader: R, base: &Path) -> Result<Vec<Job>, Error> {
+    let mut lines = reader
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()));
+
+    let header: Vec<String> = match lines.next() {
+        Some((_, line)) => line?.split('\t').map(|c| c.trim().to_string()).collect(),
+        None => return Ok(vec![]),
+    };
+
+    let column = |name: &'static str| {
+        header
+            .iter()
+            .position(|c| c == name)
+            .ok_or(Error::MissingColumn(name))
+    };
+    let (input, output) = (column("input")?, column("output")?);
+
+    if let Some(name) = header
+        .iter()
+        .enumerate()
+        .find(|&(k, name)| k != input && k != output && !PARAMETERS.contains(&name.as_str()))
+        .map(|(_, name)| name)
+    {
+        return Err(Error::UnknownParameter(name.clone()));
+    }
+
+    let mut jobs = vec![];
+    for (number, line) in lines {
+        let line = line?;
+        let cells: Vec<&str> = line.split('\t').map(str::trim).collect();
+
+        if cells.len() != header.len() {
+            return Err(Error::RowLength(number + 1, cells.len(), header.len()));
+        }
+
+        let overrides: Vec<(String, String)> = header
+            .iter()
+            .zip(&cells)
+            .enumerate()
+            .filter(|&(k, (_, value))| k != input && k != output && !value.is_empty())
+            .map(|(_, (name, value))| (name.clone(), value.to_string()))
+            .collect();
+
+        // validate overrides early
+        overrides
+            .iter()
+            .try_fold(RafftConfig::new(), |config, (name, value)| {
+                override_parameter(config, name, value)
+            })?;
+
+        jobs.push(Job {
+            input: base.join(cells[input]),
+            output: base.join(cells[output]),
+            overrides,
+        });
+    }
+
+    Ok(jobs)
+}
+
+/// Override the parameter `name` of `config` with `value`, see [`PARAMETERS`].
+/// Base pair weights are given as `AU,GC,GU`.
+/// The temperature is not part of [`RafftConfig`] and is only validated.
+pub fn override_parameter(
+    config: RafftConfig,
+    name: &str,
+    value: &str,
+) -> Result<RafftConfig, Error> {
+    let invalid = || Error::InvalidValue(name.to_string(), value.to_string());
+
+    fn parse<T: std::str::FromStr>(value: &str, invalid: impl Fn() -> Error) -> Result<T, Error> {
+        value.parse().map_err(|_| invalid())
+    }
+
+    Ok(match name {
+        "positional_lags" => config.positional_lags(parse(value, invalid)?),
+        "stacks_per_lag" => config.stacks_per_lag(parse(value, invalid)?),
+        "branches" => config.maximum_branches(parse(value, invalid)?),
+        "saved_trajectories" => config.maximum_trajectories(parse(value, invalid)?),
+        "min_unpaired" => config.minimum_unpaired_in_hairpins(parse(value, invalid)?),
+        "hairpin_enforcement" => config.hairpin_enforcement(parse(value, invalid)?),
+        "max_pair_span" => config.max_pair_span(parse(value, invalid)?),
+        "minimum_helix_energy" => config.minimum_loop_energy(parse(value, invalid)?),
+        "min_distance" => config.minimum_distance(parse(value, invalid)?),
+        "seed" => config.seed(parse(value, invalid)?),
+        "basepair_weights" => {
+            let weights = value
+                .split(',')
+                .map(|weight| parse::<f64>(weight.trim(), invalid))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            match weights[..] {
+                [au, gc, gu] => config.basepair_weights(au, gc, gu),
+                _ => return Err(invalid()),
+            }
+        }
+        "temperature" => {
+            parse::<f64>(value, invalid)?;
+            config
+        }
+        _ => return Err(Error::UnknownParameter(name.to_string())),
+    })
+}
+
+/// Run all `jobs` in parallel, starting from `config` and the temperature `temperature` (in °C) for rows
+/// that do not override them. The statuses are in the order of `jobs`.
+pub fn run(jobs: &[Job], config: &RafftConfig, temperature: f64) -> Vec<JobStatus> {
+    run_with(jobs, config, temperature, |_| ())
+}
+
+/// Like [`run()`], but calls `on_status` from the worker threads as soon as each job is finished,
+/// e.g. to report progress.
+pub fn run_with<F>(
+    jobs: &[Job],
+    config: &RafftConfig,
+    temperature: f64,
+    on_status: F,
+) -> Vec<JobStatus>
+where
+    F: Fn(&JobStatus) + Sync,
+{
+    // jobs of the same sequence share its encoding and fold compounds
+    let session = RafftSession::default();
+
+    config.install(|| {
+        jobs.par_iter()
+            .map(|job| {
+                let status =
+                    run_job(job, config, temperature, &session).unwrap_or_else(JobStatus::Failed);
+                on_status(&status);
+                status
+            })
+            .collect()
+    })
+}
+
+// Run a single job, see `RafftSession::fold_at_with()`.
+fn run_job(
+    job: &Job,
+    config: &RafftConfig,
+    temperature: f64,
+    session: &RafftSession,
+) -> Result<JobStatus, String> {
+    let mut config = config.clone();
+    let mut temperature = temperature;
+
+    for (name, value) in &job.overrides {
+        config = override_parameter(config, name, value).map_err(|error| error.to_string())?;
+        if name == "temperature" {
+            temperature = value.parse().expect("validated before");
+        }
+    }
+
+    let sequence = read_sequence(&job.input)
+        .map_err(|error| format!("failed to read {}: {}", job.input.display(), error))?;
+    session
+        .fold_at_with(&sequence, config, Some(temperature), |result| {
+            std::fs::File::create(&job.output)
+                .and_then(|mut file| write_structures(result.graph(), &mut file))
+                .map_err(|error| format!("failed to write {}: {}", job.output.display(), error))?;
+
+            Ok(JobStatus::Done {
+                best_structure: result.best().structure.to_string(),
+                best_energy: result.energy(),
+                structures: result.graph().iter().count(),
+            })
+        })
+        .map_err(|error| error.to_string())?
+}
+
+// Read the first record of a FASTA file, or a plain sequence (possibly spanning several lines).
+fn read_sequence(path: &Path) -> io::Result<String> {
+    let file = std::fs::File::open(path)?;
+
+    read_fasta(BufReader::new(file))?
+        .into_iter()
+        .map(|(_, sequence)| sequence)
+        .find(|sequence| !sequence.is_empty())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no sequence found"))
+}
+
+/// Write the structures of `graph` as tab-separated values `depth`, `energy` and `structure`
+/// with a header line, in the order of [`RafftGraph::iter()`].
+pub fn write_structures<W: Write>(graph: &RafftGraph, writer: &mut W) -> io::Result<()> {
+    writeln!(writer, "depth\tenergy\tstructure")?;
+
+    for node in graph.iter() {
+        writeln!(
+            writer,
+            "{}\t{:.2}\t{}",
+            node.depth,
+            node.energy as f64 * 0.01,
+            node.structure
+                .pair_table()
+                .to_string_with_cut_points(graph.cut_points())
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Column names of [`write_results()`].
+pub const RESULTS_HEADER: &str =
+    "input\toutput\tstatus\tbest_energy\tstructures\tbest_structure\tmessage";
+
+/// Write the statuses of `jobs` as tab-separated values with a header line (see [`RESULTS_HEADER`]).
+/// The status is `done` or `failed`, unavailable values are written as `NA`.
+pub fn write_results<W: Write>(
+    jobs: &[Job],
+    statuses: &[JobStatus],
+    writer: &mut W,
+) -> io::Result<()> {
+    writeln!(writer, "{}", RESULTS_HEADER)?;
+
+    for (job, status) in jobs.iter().zip(statuses) {
+        write!(
+            writer,
+            "{}\t{}\t",
+            job.input.display(),
+            job.output.display()
+        )?;
+
+        match status {
+            JobStatus::Done {
+                best_structure,
+                best_energy,
+                structures,
+            } => writeln!(
+                writer,
+                "done\t{:.2}\t{}\t{}\tNA",
+                best_energy, structures, best_structure
+            )?,
+            JobStatus::Failed(message) => writeln!(
+                writer,
+                "failed\tNA\tNA\tNA\t{}",
+                message.replace(['\t', '\n'], " ")
+            )?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let directory = std::env::temp_dir().join(format!("rafft-manifest-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("a.fa"),
+            ">a\nGGGGAAAACCCC\nAUAUGGGGAAAACCCC\n",
+        )
+        .unwrap();
+        std::fs::write(directory.join("b.txt"), "ACGUX\n").unwrap();
+
+        let manifest = "input\toutput\tpositional_lags\ttemperature\n\
+                        a.fa\ta.tsv\t50\t\n\
+                        \n\
+                        b.txt\tb.tsv\t\t25.0\n\
+                        missing.fa\tc.tsv\t\t\n";
+        let jobs = read_manifest(manifest.as_bytes(), &directory).unwrap();
+
+        assert_eq!(jobs.len(), 3);
+        assert_eq!(jobs[0].input, directory.join("a.fa"));
+        assert_eq!(
+            jobs[0].overrides,
+            vec![("positional_lags".to_string(), "50".to_string())]
+        );
+        assert_eq!(
+            jobs[1].overrides,
+            vec![("temperature".to_string(), "25.0".to_string())]
+        );
+
+        let statuses = run(&jobs, &RafftConfig::new(), 37.0);
+        assert!(
+            matches!(&statuses[0], JobStatus::Done { best_structure, .. } if best_structure.len() == 28)
+        );
+        assert!(matches!(statuses[1], JobStatus::Failed(_)));
+        assert!(matches!(statuses[2], JobStatus::Failed(_)));
+
+        let output = std::fs::read_to_string(directory.join("a.tsv")).unwrap();
+        assert!(output.starts_with("depth\tenergy\tstructure\n0\t0.00\t"));
+
+        let mut results = vec![];
+        write_results(&jobs, &statuses, &mut results).unwrap();
+        let results = String::from_utf8(results).unwrap();
+        assert_eq!(results.lines().count(), 4);
+        assert_eq!(
+            results.lines().nth(1).unwrap().split('\t').nth(2),
+            Some("done")
+        );
+        assert_eq!(
+            results.lines().nth(2).unwrap().split('\t').nth(2),
+            Some("failed")
+        );
+
+        assert!(matches!(
+            read_manifest("input\tlags\n".as_bytes(), &directory),
+            Err(Error::MissingColumn("output"))
+        ));
+        assert!(matches!(
+            read_manifest("input\toutput\tlags\n".as_bytes(), &directory),
+            Err(Error::UnknownParameter(_))
+        ));
+        assert!(matches!(
+            read_manifest("input\toutput\tseed\na\tb\tx\n".as_bytes(), &directory),
+            Err(Error::InvalidValue(..))
+        ));
+        assert!(matches!(
+            read_manifest("input\toutput\na\n".as_bytes(), &directory),
+            Err(Error::RowLength(2, 1, 2))
+        ));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,143 @@
+//! This module provides temperature scans of a sequence, i.e. folding it at every temperature of a range,
+//! in parallel. The best free energy and the number of pairs of the dominant (lowest free energy) structure
+//! per temperature describe the melting behavior of the sequence and can be written as CSV for plotting.
+
+use crate::encoding::{self, EncodedSequence};
+use crate::fast_folding::RafftConfig;
+use crate::session::RafftSession;
+use rayon::prelude::*;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+/// The dominant structure of a sequence at a single temperature, see [`melt()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeltingPoint {
+    /// temperature in °C
+    pub temperature: f64,
+    /// lowest free energy in `kcal/mol`
+    pub best_energy: f64,
+    /// structure of lowest free energy in dot-bracket notation
+    pub best_structure: String,
+    /// number of pairs of the structure of lowest free energy
+    pub pairs: usize,
+    /// number of structures in the fast folding graph
+    pub structures: usize,
+}
+
+/// Column names of [`write_csv()`].
+pub const CSV_HEADER: &str = "temperature,best_energy,pairs,structures,best_structure";
+
+/// Fold `sequence` using the default [`RafftConfig`] at every temperature from the start to the end of `temperatures`
+/// (in °C) in increments of `step`, see [`melt_with()`].
+pub fn melt(
+    sequence: &str,
+    temperatures: RangeInclusive<f64>,
+    step: f64,
+) -> Result<Vec<MeltingPoint>, encoding::Error> {
+    melt_with(sequence, &RafftConfig::new(), temperatures, step)
+}
+
+/// Fold `sequence` using `config` at every temperature from the start to the end of `temperatures` (in °C)
+/// in increments of `step`, in parallel. The end is included if it is reached by the increments.
+/// The results are in order of increasing temperature.
+/// Panics if `step` is not positive.
+pub fn melt_with(
+    sequence: &str,
+    config: &RafftConfig,
+    temperatures: RangeInclusive<f64>,
+    step: f64,
+) -> Result<Vec<MeltingPoint>, encoding::Error> {
+    assert!(step > 0.0, "temperature step has to be positive");
+
+    // validate the sequence once instead of per temperature
+    EncodedSequence::new(sequence)?;
+
+    let (start, end) = temperatures.into_inner();
+    // tolerate rounding errors of the increments
+    let steps = if end >= start {
+        ((end - start) / step + 1e-9).floor() as usize + 1
+    } else {
+        0
+    };
+
+    // the encoding is shared by all temperatures
+    let session = RafftSession::default();
+
+    Ok(config.install(|| {
+        (0..steps)
+            .into_par_iter()
+            .map(|k| fold_at(sequence, config, start + k as f64 * step, &session))
+            .collect()
+    }))
+}
+
+// Fold `sequence` at a single temperature, see `RafftSession::fold_at_with()`.
+fn fold_at(
+    sequence: &str,
+    config: &RafftConfig,
+    temperature: f64,
+    session: &RafftSession,
+) -> MeltingPoint {
+    session
+        .fold_at_with(sequence, config.clone(), Some(temperature), |result| {
+            MeltingPoint {
+                temperature,
+                best_energy: result.energy(),
+                best_structure: result.best().structure.to_string(),
+                pairs: result.best().structure.pairs(),
+                structures: result.graph().iter().count(),
+            }
+        })
+        .expect("sequence was validated before")
+}
+
+/// Write melting points as comma-separated values with a header line (see [`CSV_HEADER`]).
+pub fn write_csv<W: Write>(points: &[MeltingPoint], writer: &mut W) -> io::Result<()> {
+    writeln!(writer, "{}", CSV_HEADER)?;
+
+    for point in points {
+        writeln!(
+            writer,
+            "{},{:.2},{},{},{}",
+            point.temperature,
+            point.best_energy,
+            point.pairs,
+            point.structures,
+            point.best_structure
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::SMALL_SEQUENCE;
+
+    #[test]
+    fn test_melt() {
+        let sequence = SMALL_SEQUENCE;
+        let points = melt(sequence, 20.0..=80.0, 7.5).unwrap();
+
+        assert_eq!(points.len(), 9);
+        assert_eq!(points[0].temperature, 20.0);
+        assert_eq!(points[8].temperature, 80.0);
+        assert!(points
+            .windows(2)
+            .all(|points| points[0].temperature < points[1].temperature));
+        assert!(points
+            .iter()
+            .all(|point| point.best_structure.len() == sequence.len()));
+
+        assert_eq!(melt(sequence, 37.0..=37.0, 1.0).unwrap().len(), 1);
+        assert!(melt(sequence, 50.0..=37.0, 1.0).unwrap().is_empty());
+        assert!(melt("ACGUN", 37.0..=37.0, 1.0).is_err());
+
+        let mut csv = vec![];
+        write_csv(&points, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 10);
+        assert!(csv.lines().nth(1).unwrap().starts_with("20,"));
+    }
+}
@@ -0,0 +1,702 @@
+//! This module provides a search for secondary structure motifs among the structures of a fast folding graph,
+//! e.g. specific hairpin tetraloops or apical loops of a certain size that might form kissing interactions.
+//!
+//! A [`Motif`] is either a structure in dot-bracket notation matched at any position, or a loop of a certain
+//! type and size. Both can be restricted to a sequence of IUPAC nucleotide codes. Motifs are parsed from strings:
+//!
+//! - `((((....))))` matches a hairpin of four stacked pairs and a tetraloop,
+//! - `((((....)))):GGGGNNNNCCCC` additionally requires the given nucleotides,
+//! - `hairpin:4:GNRA` matches GNRA tetraloops closed by any pair,
+//! - `interior:2-6` matches interior loops of two to six unpaired nucleotides.
+//!
+//! Loop types are `hairpin`, `stack`, `bulge`, `interior` and `multi`, sizes are the number of unpaired
+//! nucleotides of a loop, and sequences of loops refer to their unpaired nucleotides in `5'` to `3'` order.
+//!
+//! ```no_run
+//! let result = rafft::fold_sequence("GGGGAAAACCCCAUAUGGGGAAAACCCC").unwrap();
+//!
+//! for found in result.find_motif("hairpin:4:GNRA").unwrap() {
+//!     println!("{}-{} in {} trajectories", found.start, found.end, found.trajectories.len());
+//! }
+//! ```
+//!
+//! Structures are classified by the presence of named functional elements, e.g. aptamers or terminators,
+//! using an [`ElementClassifier`]. Elements are patterns in the notation of hard constraints, which may occur
+//! anywhere within a region of the sequence:
+//!
+//! ```no_run
+//! use rafft::motifs::ElementClassifier;
+//!
+//! let classifier = ElementClassifier::new()
+//!     .element("aptamer", 1..=12, "((((xxxx))))")
+//!     .unwrap()
+//!     .element("terminator", 13..=28, "((((....))))")
+//!     .unwrap();
+//! let result = rafft::fold_sequence("GGGGAAAACCCCAUAUGGGGAAAACCCC").unwrap();
+//!
+//! for annotation in result.classify(&classifier) {
+//!     println!("{}: {:?}", annotation.index, annotation.elements);
+//! }
+//! ```
+
+use crate::analysis::trajectories;
+use crate::encoding::{self, PairTable, SeqPos1};
+use crate::folding_graph::{RafftGraph, RafftNodeId};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Type of a loop of an RNA secondary structure in the Nearest-Neighbor model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoopType {
+    /// The loop not closed by any pair.
+    Exterior,
+    /// A loop closed by a pair that does not enclose any other pair.
+    Hairpin,
+    /// Two consecutive pairs.
+    Stack,
+    /// An interior loop with unpaired nucleotides on only one side.
+    Bulge,
+    /// An interior loop with unpaired nucleotides on both sides.
+    Interior,
+    /// A loop enclosing at least two pairs.
+    Multi,
+}
+
+/// Errors of parsing a [`Motif`].
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The structure of the motif is not a valid dot-bracket notation.
+    #[error("invalid motif structure: {0}")]
+    Structure(#[from] encoding::Error),
+    /// The loop type of the motif is unknown.
+    #[error("unknown loop type {0:?} (expected hairpin, stack, bulge, interior or multi)")]
+    LoopType(String),
+    /// The loop size of the motif is invalid.
+    #[error("invalid loop size {0:?} (expected SIZE or MIN-MAX)")]
+    Size(String),
+    /// The pattern of a structural element is empty or longer than its region.
+    #[error("the pattern of length {length} of element {name:?} does not fit into its region")]
+    Element {
+        /// name of the element
+        name: String,
+        /// length of the pattern
+        length: usize,
+    },
+    /// The sequence of the motif contains invalid codes or does not match the length of its structure.
+    #[error(
+        "invalid motif sequence {0:?} (expected IUPAC codes, one per position of the structure)"
+    )]
+    Sequence(String),
+}
+
+/// A secondary structure motif, see the [module](self) for its string representation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Motif {
+    /// A structure matched at any position: paired positions of the motif have to be paired with
+    /// the corresponding positions, unpaired positions of the motif have to be unpaired.
+    Structure {
+        /// structure of the motif
+        structure: PairTable,
+        /// IUPAC codes of the nucleotides per position of the structure
+        sequence: Option<String>,
+    },
+    /// A loop closed by a pair.
+    Loop {
+        /// type of the loop, other than [`LoopType::Exterior`]
+        loop_type: LoopType,
+        /// numbers of unpaired nucleotides of the loop
+        sizes: RangeInclusive<usize>,
+        /// IUPAC codes of the unpaired nucleotides of the loop
+        sequence: Option<String>,
+    },
+}
+
+impl FromStr for Motif {
+    type Err = Error;
+
+    fn from_str(motif: &str) -> Result<Self, Self::Err> {
+        let mut parts = motif.trim().split(':');
+        let pattern = parts.next().unwrap_or_default();
+
+        if !pattern.is_empty() && pattern.chars().all(|c| ".()".contains(c)) {
+            let structure: PairTable = pattern.parse()?;
+            let sequence = parts.next().map(parse_sequence).transpose()?;
+
+            if parts.next().is_some()
+                || sequence
+                    .as_ref()
+                    .is_some_and(|s| s.len() != structure.len())
+            {
+                return Err(Error::Sequence(motif.to_string()));
+            }
+
+            return Ok(Self::Structure {
+                structure,
+                sequence,
+            });
+        }
+
+        let loop_type = match pattern.to_lowercase().as_str() {
+            "hairpin" => LoopType::Hairpin,
+            "stack" => LoopType::Stack,
+            "bulge" => LoopType::Bulge,
+            "interior" => LoopType::Interior,
+            "multi" => LoopType::Multi,
+            _ => return Err(Error::LoopType(pattern.to_string())),
+        };
+        let sizes = match parts.next() {
+            Some(sizes) if !sizes.is_empty() => parse_sizes(sizes)?,
+            _ => 0..=usize::MAX,
+        };
+        let sequence = parts.next().map(parse_sequence).transpose()?;
+
+        if parts.next().is_some() {
+            return Err(Error::Sequence(motif.to_string()));
+        }
+
+        Ok(Self::Loop {
+            loop_type,
+            sizes,
+            sequence,
+        })
+    }
+}
+
+// Parse a loop size `SIZE` or `MIN-MAX`.
+fn parse_sizes(sizes: &str) -> Result<RangeInclusive<usize>, Error> {
+    let error = || Error::Size(sizes.to_string());
+    let (min, max) = sizes.split_once('-').unwrap_or((sizes, sizes));
+    let (min, max): (usize, usize) = (
+        min.trim().parse().map_err(|_| error())?,
+        max.trim().parse().map_err(|_| error())?,
+    );
+
+    if min > max {
+        return Err(error());
+    }
+
+    Ok(min..=max)
+}
+
+// Parse a sequence of IUPAC codes, normalized to upper case and `U`.
+fn parse_sequence(sequence: &str) -> Result<String, Error> {
+    let normalized: String = sequence
+        .chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            'T' => 'U',
+            c => c,
+        })
+        .collect();
+
+    if normalized.chars().all(|c| "ACGURYSWKMBDHVN".contains(c)) {
+        Ok(normalized)
+    } else {
+        Err(Error::Sequence(sequence.to_string()))
+    }
+}
+
+// Return whether the nucleotide `nucleotide` (upper case, `U` instead of `T`) matches the IUPAC `code`.
+fn iupac_matches(code: char, nucleotide: char) -> bool {
+    let nucleotides = match code {
+        'R' => "AG",
+        'Y' => "CU",
+        'S' => "CG",
+        'W' => "AU",
+        'K' => "GU",
+        'M' => "AC",
+        'B' => "CGU",
+        'D' => "AGU",
+        'H' => "ACU",
+        'V' => "ACG",
+        'N' => "ACGU",
+        _ => return code == nucleotide,
+    };
+
+    nucleotides.contains(nucleotide)
+}
+
+// Return whether the nucleotides of `sequence` at the `1`-indexed `positions` match `pattern`, if any.
+fn sequence_matches(pattern: Option<&str>, sequence: &[char], positions: &[usize]) -> bool {
+    let Some(pattern) = pattern else {
+        return true;
+    };
+
+    pattern.chars().count() == positions.len()
+        && pattern
+            .chars()
+            .zip(positions)
+            .all(|(code, &k)| sequence.get(k - 1).is_some_and(|&n| iupac_matches(code, n)))
+}
+
+impl Motif {
+    /// Return the regions `(start, end)` (`1`-indexed, inclusive) of `structure` of `sequence` matching the motif,
+    /// i.e. the positions of structure motifs or the closing pairs of loops, in `5'` to `3'` order.
+    /// Strand separators `&` in `sequence` are skipped.
+    pub fn matches(&self, sequence: &str, structure: &PairTable) -> Vec<(usize, usize)> {
+        let sequence: Vec<char> = sequence
+            .chars()
+            .filter(|&c| c != '&')
+            .map(|c| match c.to_ascii_uppercase() {
+                'T' => 'U',
+                c => c,
+            })
+            .collect();
+
+        match self {
+            Self::Structure {
+                structure: motif,
+                sequence: pattern,
+            } => {
+                let length = motif.len();
+                let positions: Vec<usize> = (1..=length).collect();
+
+                (0..(structure.len() + 1).saturating_sub(length.max(1)))
+                    .filter(|&offset| {
+                        positions.iter().all(|&k| {
+                            match (
+                                motif.partner(SeqPos1(k)),
+                                structure.partner(SeqPos1(offset + k)),
+                            ) {
+                                (None, None) => true,
+                                (Some(p), Some(q)) => q.0 == offset + p.0,
+                                _ => false,
+                            }
+                        })
+                    })
+                    .filter(|&offset| {
+                        let positions: Vec<usize> = positions.iter().map(|k| offset + k).collect();
+                        sequence_matches(pattern.as_deref(), &sequence, &positions)
+                    })
+                    .map(|offset| (offset + 1, offset + length))
+                    .collect()
+            }
+            Self::Loop {
+                loop_type,
+                sizes,
+                sequence: pattern,
+            } => structure
+                .paired()
+                .filter(|&(i, j)| {
+                    let (unpaired, enclosed) = structure.loop_members(i + 1, j - 1);
+                    let closed_type = match enclosed[..] {
+                        [] => LoopType::Hairpin,
+                        [(p, q)] => match (p - i - 1, j - q - 1) {
+                            (0, 0) => LoopType::Stack,
+                            (0, _) | (_, 0) => LoopType::Bulge,
+                            _ => LoopType::Interior,
+                        },
+                        _ => LoopType::Multi,
+                    };
+
+                    closed_type == *loop_type
+                        && sizes.contains(&unpaired.len())
+                        && sequence_matches(pattern.as_deref(), &sequence, &unpaired)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A region matching a motif in some structures of a fast folding graph, see [`find_motif()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MotifMatch {
+    /// first position of the region (`1`-indexed)
+    pub start: usize,
+    /// last position of the region (`1`-indexed, inclusive)
+    pub end: usize,
+    /// structures containing the motif at this region, in the order of [`RafftGraph::iter()`]
+    pub structures: Vec<RafftNodeId>,
+    /// trajectories with any structure containing the motif at this region,
+    /// as indices in the order of [`trajectories()`]
+    pub trajectories: Vec<usize>,
+}
+
+/// Return the regions of the structures of `graph`, constructed for `sequence`, matching `motif`,
+/// ordered by their positions, together with the structures and trajectories containing them.
+pub fn find_motif(graph: &RafftGraph, sequence: &str, motif: &Motif) -> Vec<MotifMatch> {
+    let mut regions: BTreeMap<(usize, usize), Vec<RafftNodeId>> = BTreeMap::new();
+    let mut matched: HashMap<RafftNodeId, Vec<(usize, usize)>> = HashMap::new();
+
+    for node in graph.iter() {
+        let found = motif.matches(sequence, &node.structure.pair_table());
+
+        for &region in &found {
+            regions.entry(region).or_default().push(node.id);
+        }
+        if !found.is_empty() {
+            matched.insert(node.id, found);
+        }
+    }
+
+    let mut containing: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+    for (index, trajectory) in trajectories(graph).iter().enumerate() {
+        let mut found: Vec<(usize, usize)> = trajectory
+            .nodes()
+            .iter()
+            .filter_map(|id| matched.get(id))
+            .flatten()
+            .copied()
+            .collect();
+        found.sort_unstable();
+        found.dedup();
+
+        for region in found {
+            containing.entry(region).or_default().push(index);
+        }
+    }
+
+    regions
+        .into_iter()
+        .map(|((start, end), structures)| MotifMatch {
+            start,
+            end,
+            structures,
+            trajectories: containing.remove(&(start, end)).unwrap_or_default(),
+        })
+        .collect()
+}
+
+/// A named structural element, see [`ElementClassifier::element()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StructuralElement {
+    /// name of the element
+    pub name: String,
+    /// positions (`1`-indexed, inclusive) the element has to be formed within
+    pub region: RangeInclusive<usize>,
+    /// pattern in the notation of hard constraints
+    pub pattern: String,
+    // partner in the pattern per position (`1`-indexed, `0` for unpaired positions)
+    partners: Vec<usize>,
+}
+
+impl StructuralElement {
+    /// Return whether the element is formed in `structure`, i.e. its pattern is satisfied at any position
+    /// within its region.
+    pub fn is_formed(&self, structure: &PairTable) -> bool {
+        let length = self.partners.len() - 1;
+        let first = *self.region.start() - 1;
+        let last = (*self.region.end()).min(structure.len());
+
+        if last < first + length {
+            return false;
+        }
+
+        (first..=last - length).any(|offset| {
+            self.pattern.bytes().enumerate().all(|(k, symbol)| {
+                let partner = structure.partner(SeqPos1(offset + k + 1));
+
+                match symbol {
+                    b'x' => partner.is_none(),
+                    b'|' => partner.is_some(),
+                    b'(' | b')' => partner == Some(SeqPos1(offset + self.partners[k + 1])),
+                    _ => true,
+                }
+            })
+        })
+    }
+}
+
+/// A set of named structural elements to classify structures by the elements they form,
+/// see the [module](self).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ElementClassifier {
+    elements: Vec<StructuralElement>,
+}
+
+impl ElementClassifier {
+    /// Create a classifier without any elements.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the element `name`, which is formed if `pattern` is satisfied at any position within `region`
+    /// (`1`-indexed, inclusive). Patterns use the notation of hard constraints: `(` and `)` have to be paired
+    /// with each other, `|` has to be paired, `x` has to be unpaired and `.` is not constrained.
+    /// Returns an error if the pattern is not balanced, contains other symbols or does not fit into the region.
+    pub fn element(
+        mut self,
+        name: &str,
+        region: RangeInclusive<usize>,
+        pattern: &str,
+    ) -> Result<Self, Error> {
+        let mut partners = vec![0; pattern.len() + 1];
+        let mut opened = vec![];
+
+        for (k, symbol) in pattern.chars().enumerate() {
+            match symbol {
+                '(' => opened.push(k + 1),
+                ')' => {
+                    let i = opened
+                        .pop()
+                        .ok_or(encoding::Error::UnbalancedStructure(k + 1))?;
+                    partners[i] = k + 1;
+                    partners[k + 1] = i;
+                }
+                '.' | 'x' | '|' => (),
+                _ => return Err(encoding::Error::InvalidStructureSymbol(symbol).into()),
+            }
+        }
+
+        if let Some(&i) = opened.last() {
+            return Err(encoding::Error::UnbalancedStructure(i).into());
+        }
+        if pattern.is_empty() || *region.start() == 0 || region.clone().count() < pattern.len() {
+            return Err(Error::Element {
+                name: name.to_string(),
+                length: pattern.len(),
+            });
+        }
+
+        self.elements.push(StructuralElement {
+            name: name.to_string(),
+            region,
+            pattern: pattern.to_string(),
+            partners,
+        });
+
+        Ok(self)
+    }
+
+    /// Return the registered elements in the order of registration.
+    pub fn elements(&self) -> &[StructuralElement] {
+        &self.elements
+    }
+
+    /// Return the names of the elements formed in `structure`, in the order of registration.
+    pub fn classify(&self, structure: &PairTable) -> Vec<&str> {
+        self.elements
+            .iter()
+            .filter(|element| element.is_formed(structure))
+            .map(|element| element.name.as_str())
+            .collect()
+    }
+
+    /// Return the elements formed by each structure of `graph`, in the order of [`RafftGraph::iter()`].
+    pub fn annotate(&self, graph: &RafftGraph) -> Vec<ElementAnnotation> {
+        graph
+            .iter()
+            .enumerate()
+            .map(|(index, node)| ElementAnnotation {
+                index,
+                id: node.id,
+                elements: self
+                    .classify(&node.structure.pair_table())
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Write `annotations` of the structures of `graph` as tab-separated values with a header line,
+    /// one line per structure containing its index, structure and free energy followed by one column
+    /// per element, which is `1` if the element is formed and `0` otherwise.
+    pub fn write_tsv<W: Write>(
+        &self,
+        graph: &RafftGraph,
+        annotations: &[ElementAnnotation],
+        writer: &mut W,
+    ) -> io::Result<()> {
+        write!(writer, "index\tstructure\tenergy")?;
+        for element in &self.elements {
+            write!(writer, "\t{}", element.name)?;
+        }
+        writeln!(writer)?;
+
+        for annotation in annotations {
+            let node = match graph.node(annotation.id) {
+                Some(node) => node,
+                None => continue,
+            };
+
+            write!(
+                writer,
+                "{}\t{}\t{:.2}",
+                annotation.index,
+                node.structure
+                    .pair_table()
+                    .to_string_with_cut_points(graph.cut_points()),
+                node.energy as f64 * 0.01
+            )?;
+            for element in &self.elements {
+                let formed = annotation.elements.contains(&element.name);
+                write!(writer, "\t{}", formed as u8)?;
+            }
+            writeln!(writer)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The structural elements formed by a structure of a fast folding graph, see [`ElementClassifier::annotate()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElementAnnotation {
+    /// index of the structure in the order of [`RafftGraph::iter()`]
+    pub index: usize,
+    /// the structure
+    pub id: RafftNodeId,
+    /// names of the formed elements, in the order of registration
+    pub elements: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{small_graph, SMALL_SEQUENCE};
+
+    #[test]
+    fn test_motif_matches() {
+        let sequence = "GGGGAAAACCCCAUAUGGGGGGCAACCCCC";
+        let structure: PairTable = "((((....))))....(((((....)))))".parse().unwrap();
+
+        let matches = |motif: &str| {
+            motif
+                .parse::<Motif>()
+                .unwrap()
+                .matches(sequence, &structure)
+        };
+
+        assert_eq!(matches("((((....))))"), vec![(1, 12), (18, 29)]);
+        assert_eq!(matches("((((....)))):GGGGAAAANNNN"), vec![(1, 12)]);
+        assert_eq!(matches("(((....)))"), vec![(2, 11), (19, 28)]);
+        assert_eq!(matches(".(((....)))."), vec![]);
+        assert_eq!(matches("hairpin:4"), vec![(4, 9), (21, 26)]);
+        assert_eq!(matches("hairpin:4:GNRA"), vec![(21, 26)]);
+        assert_eq!(matches("hairpin:5-8"), vec![]);
+        assert_eq!(matches("stack").len(), 3 + 4);
+        assert_eq!(matches("interior"), vec![]);
+
+        let interior: PairTable = "((..((....))...))".parse().unwrap();
+        let motif: Motif = "interior:5:AANNN".parse().unwrap();
+        assert_eq!(motif.matches("GGAAGGGAAACCAAACC", &interior), vec![(2, 16)]);
+        assert_eq!(
+            "bulge"
+                .parse::<Motif>()
+                .unwrap()
+                .matches("GGAAGGGAAACCAAACC", &interior),
+            vec![]
+        );
+
+        assert!(matches!("((..".parse::<Motif>(), Err(Error::Structure(_))));
+        assert!(matches!(
+            "((..)):GGAA".parse::<Motif>(),
+            Err(Error::Sequence(_))
+        ));
+        assert!(matches!("loop:4".parse::<Motif>(), Err(Error::LoopType(_))));
+        assert!(matches!("".parse::<Motif>(), Err(Error::LoopType(_))));
+        assert!(matches!(
+            "hairpin:5-3".parse::<Motif>(),
+            Err(Error::Size(_))
+        ));
+        assert!(matches!(
+            "hairpin:4:GNXA".parse::<Motif>(),
+            Err(Error::Sequence(_))
+        ));
+    }
+
+    #[test]
+    fn test_element_classifier() {
+        let classifier = ElementClassifier::new()
+            .element("hairpin", 1..=30, "((((xxxx))))")
+            .unwrap()
+            .element("3' hairpin", 13..=30, "(((....)))")
+            .unwrap()
+            .element("paired end", 28..=30, "||")
+            .unwrap();
+        assert_eq!(classifier.elements().len(), 3);
+
+        let structure = |s: &str| s.parse::<PairTable>().unwrap();
+        assert_eq!(
+            classifier.classify(&structure("((((....))))....(((((....)))))")),
+            vec!["hairpin", "3' hairpin", "paired end"]
+        );
+        assert_eq!(
+            classifier.classify(&structure("((((....)))).................")),
+            vec!["hairpin"]
+        );
+        // unpaired positions of the pattern have to be unpaired
+        assert_eq!(
+            classifier.classify(&structure("((((.().))))..................")),
+            Vec::<&str>::new()
+        );
+        assert!(classifier.classify(&structure("")).is_empty());
+
+        assert!(matches!(
+            ElementClassifier::new().element("a", 1..=10, "(((...))"),
+            Err(Error::Structure(_))
+        ));
+        assert!(matches!(
+            ElementClassifier::new().element("a", 1..=10, "((..]]"),
+            Err(Error::Structure(_))
+        ));
+        assert!(matches!(
+            ElementClassifier::new().element("a", 1..=5, "((...))"),
+            Err(Error::Element { length: 7, .. })
+        ));
+
+        let graph = small_graph();
+
+        let annotations = classifier.annotate(&graph);
+        assert_eq!(annotations.len(), graph.iter().count());
+        assert!(annotations[0].elements.is_empty());
+        for (annotation, node) in annotations.iter().zip(graph.iter()) {
+            assert_eq!(annotation.id, node.id);
+            assert_eq!(
+                annotation.elements,
+                classifier.classify(&node.structure.pair_table())
+            );
+        }
+
+        let mut tsv = vec![];
+        classifier
+            .write_tsv(&graph, &annotations, &mut tsv)
+            .unwrap();
+        let tsv = String::from_utf8(tsv).unwrap();
+        assert_eq!(tsv.lines().count(), annotations.len() + 1);
+        assert!(tsv.lines().all(|line| line.split('\t').count() == 6));
+    }
+
+    #[test]
+    fn test_find_motif() {
+        let sequence = SMALL_SEQUENCE;
+        let graph = small_graph();
+
+        let motif: Motif = "hairpin:4".parse().unwrap();
+        let found = find_motif(&graph, sequence, &motif);
+        assert!(!found.is_empty());
+        assert!(
+            found
+                .windows(2)
+                .all(|regions| (regions[0].start, regions[0].end)
+                    < (regions[1].start, regions[1].end))
+        );
+
+        let leaves = trajectories(&graph);
+        for region in &found {
+            for &id in &region.structures {
+                let structure = graph.node(id).unwrap().structure.pair_table();
+                assert!(motif
+                    .matches(sequence, &structure)
+                    .contains(&(region.start, region.end)));
+            }
+            for &index in &region.trajectories {
+                assert!(leaves[index]
+                    .nodes()
+                    .iter()
+                    .any(|id| region.structures.contains(id)));
+            }
+        }
+
+        // the open chain does not contain any hairpin
+        assert!(found
+            .iter()
+            .all(|region| !region.structures.contains(&graph.root())));
+        assert!(find_motif(&graph, sequence, &"multi".parse().unwrap()).is_empty());
+    }
+}
@@ -0,0 +1,150 @@
+//! This module provides [`Objective`], a common interface to score the fast folding graph of a sequence,
+//! e.g. to rank candidate sequences during sequence design or batch screening.
+//! Scores are minimized, i.e. lower scores are better.
+//!
+//! Besides the built-in objectives, closures `Fn(&str, &RafftGraph) -> f64` implement [`Objective`]:
+//!
+//! ```no_run
+//! use rafft::fast_folding::RafftConfig;
+//! use rafft::folding_graph::RafftGraph;
+//! use rafft::objective::fold_and_score;
+//!
+//! let pairs = |_: &str, graph: &RafftGraph| {
+//!     -(graph.iter().map(|node| node.structure.pairs()).max().unwrap_or(0) as f64)
+//! };
+//! let score = fold_and_score(&RafftConfig::new(), "GGGGAAAACCCC", &pairs);
+//! ```
+
+use crate::encoding::PairTable;
+use crate::fast_folding::RafftConfig;
+use crate::folding_graph::{RafftGraph, RafftNodeInfo};
+use crate::kinetics::{populations, ArrheniusModel};
+use crate::vienna::VCompound;
+
+/// A score of the constructed fast folding graph of a sequence, where lower scores are better.
+pub trait Objective {
+    /// Return the score of `graph`, which was constructed for `sequence`.
+    fn score(&self, sequence: &str, graph: &RafftGraph) -> f64;
+}
+
+impl<F> Objective for F
+where
+    F: Fn(&str, &RafftGraph) -> f64,
+{
+    fn score(&self, sequence: &str, graph: &RafftGraph) -> f64 {
+        self(sequence, graph)
+    }
+}
+
+/// Base pair distance between the target structure and the structure of lowest free energy in the graph.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetDistance(pub PairTable);
+
+impl Objective for TargetDistance {
+    fn score(&self, _sequence: &str, graph: &RafftGraph) -> f64 {
+        best_node(graph).structure.pair_table().distance(&self.0) as f64
+    }
+}
+
+/// Free energy (`kcal/mol`) of the target structure relative to the structure of lowest free energy
+/// in the graph. The gap is negative if the target is more stable than any structure found.
+/// Panics if the target does not match the length of the sequence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnergyGap(pub PairTable);
+
+impl Objective for EnergyGap {
+    fn score(&self, sequence: &str, graph: &RafftGraph) -> f64 {
+        let target = VCompound::new(sequence).evaluate_structure(self.0.view());
+
+        (target - best_node(graph).energy) as f64 * 0.01
+    }
+}
+
+/// Population missing from the target state at `time` under `model` (see [`populations()`]),
+/// i.e. `1` minus the total population of all structures within `tolerance` (base pair distance)
+/// of the target structure.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetPopulation {
+    /// target structure
+    pub target: PairTable,
+    /// maximum base pair distance of structures attributed to the target state
+    pub tolerance: usize,
+    /// kinetic model
+    pub model: ArrheniusModel,
+    /// time at which the population is observed
+    pub time: f64,
+}
+
+impl Objective for TargetPopulation {
+    fn score(&self, _sequence: &str, graph: &RafftGraph) -> f64 {
+        let population: f64 = graph
+            .iter()
+            .zip(populations(graph, &self.model, self.time))
+            .filter(|(node, _)| {
+                node.structure.pair_table().distance(&self.target) <= self.tolerance
+            })
+            .map(|(_, population)| population)
+            .sum();
+
+        1.0 - population
+    }
+}
+
+/// Construct the fast folding graph of `sequence` using `config` and return its score under `objective`.
+pub fn fold_and_score<O: Objective + ?Sized>(
+    config: &RafftConfig,
+    sequence: &str,
+    objective: &O,
+) -> f64 {
+    let mut graph = config.folding_graph(sequence);
+    graph.construct_trajectories();
+
+    objective.score(sequence, &graph)
+}
+
+// structure of lowest free energy, ties are broken by the order of `RafftGraph::iter()`
+fn best_node(graph: &RafftGraph) -> &RafftNodeInfo {
+    graph
+        .iter()
+        .min_by_key(|node| node.energy)
+        .expect("the root is always present")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_objectives() {
+        let sequence = "GGGGAAAACCCCAUAUAUAUAUAUAU";
+        let mut graph = RafftConfig::new().folding_graph(sequence);
+        graph.construct_trajectories();
+
+        let best = best_node(&graph).structure.pair_table().into_owned();
+        let open = PairTable::new(sequence.len());
+
+        assert_eq!(TargetDistance(best.clone()).score(sequence, &graph), 0.0);
+        assert_eq!(
+            TargetDistance(open.clone()).score(sequence, &graph),
+            best.pairs() as f64
+        );
+
+        assert_eq!(EnergyGap(best.clone()).score(sequence, &graph), 0.0);
+        assert!(EnergyGap(open.clone()).score(sequence, &graph) > 0.0);
+
+        // the complete population eventually leaves the open chain
+        let population = TargetPopulation {
+            target: open,
+            tolerance: 0,
+            model: ArrheniusModel::default(),
+            time: 1000.0,
+        };
+        assert!(population.score(sequence, &graph) > 0.9);
+
+        let pairs = |_: &str, graph: &RafftGraph| -(best_node(graph).structure.pairs() as f64);
+        assert_eq!(
+            fold_and_score(&RafftConfig::new(), sequence, &pairs),
+            -(best.pairs() as f64)
+        );
+    }
+}
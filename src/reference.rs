@@ -0,0 +1,213 @@
+//! This module allows to compare fast folding graphs to the output of the
+//! [reference implementation](https://github.com/strevol-mpi-mis/RAFFT) of RAFFT
+//! in order to detect behavioral drift from the original algorithm.
+//!
+//! The reference output is expected in the trajectory format of RAFFT, which is also printed by `rufft --compat`:
+//!
+//! ```text
+//! # ---------0----------
+//! ........ 0.00
+//! # ---------1----------
+//! ((....)) -1.20
+//! ```
+
+use crate::folding_graph::RafftGraph;
+use std::process::Command;
+use thiserror::Error;
+
+/// Error type representing errors that may arise while running or parsing the reference implementation.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// The reference command could not be executed.
+    #[error("failed to run reference command: {0}")]
+    Io(#[from] std::io::Error),
+    /// The reference command was empty after substituting placeholders.
+    #[error("empty reference command")]
+    EmptyCommand,
+    /// The reference command terminated unsuccessfully.
+    #[error("reference command failed: {0}")]
+    Failed(String),
+    /// A line of the reference output could not be parsed.
+    #[error("unexpected line in reference output: {0:?}")]
+    Parse(String),
+}
+
+/// A structure with its free energy in `kcal/mol`.
+pub type ReferenceStructure = (String, f64);
+
+/// A difference between a [`RafftGraph`] and the output of the reference implementation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Discrepancy {
+    /// The depth is only present in the reference output.
+    MissingDepth(usize),
+    /// The depth is only present in the fast folding graph.
+    ExtraDepth(usize),
+    /// A structure of the reference output was not found at the same depth.
+    MissingStructure {
+        /// depth of the structure
+        depth: usize,
+        /// dot-bracket notation of the missing structure
+        structure: String,
+    },
+    /// A structure was found at a depth where the reference output does not list it.
+    ExtraStructure {
+        /// depth of the structure
+        depth: usize,
+        /// dot-bracket notation of the unexpected structure
+        structure: String,
+    },
+    /// A structure was found in both outputs but the energies differ by more than the tolerance.
+    Energy {
+        /// depth of the structure
+        depth: usize,
+        /// dot-bracket notation of the structure
+        structure: String,
+        /// energy reported by the reference implementation
+        expected: f64,
+        /// energy reported by the fast folding graph
+        found: f64,
+    },
+}
+
+/// Parse the trajectory output of the reference implementation into structures per depth.
+/// Lines that are empty or do not follow a depth marker are ignored.
+pub fn parse_trajectories(output: &str) -> Result<Vec<Vec<ReferenceStructure>>, Error> {
+    let mut layers: Vec<Vec<ReferenceStructure>> = vec![];
+
+    for line in output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        if line.starts_with('#') {
+            layers.push(vec![]);
+        } else if let Some(layer) = layers.last_mut() {
+            let mut fields = line.split_whitespace();
+
+            let structure = fields
+                .next()
+                .filter(|s| s.chars().all(|c| matches!(c, '.' | '(' | ')')))
+                .ok_or_else(|| Error::Parse(line.to_string()))?;
+            let energy = fields
+                .next()
+                .and_then(|e| e.trim_matches(|c| c == '(' || c == ')').parse::<f64>().ok())
+                .ok_or_else(|| Error::Parse(line.to_string()))?;
+
+            layer.push((structure.to_string(), energy));
+        }
+    }
+
+    Ok(layers)
+}
+
+/// Run the reference implementation and return its standard output.
+/// The placeholders `{sequence}` and `{saved_trajectories}` in `command` are substituted before
+/// the command is split at whitespace, e.g. `python rafft.py -s {sequence} -ms {saved_trajectories} --traj`.
+pub fn run_reference(
+    command: &str,
+    sequence: &str,
+    saved_trajectories: usize,
+) -> Result<String, Error> {
+    let command = command
+        .replace("{sequence}", sequence)
+        .replace("{saved_trajectories}", &saved_trajectories.to_string());
+    let mut parts = command.split_whitespace();
+
+    let program = parts.next().ok_or(Error::EmptyCommand)?;
+    let output = Command::new(program).args(parts).output()?;
+
+    if !output.status.success() {
+        return Err(Error::Failed(
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Compare the layers of a constructed [`RafftGraph`] (see [`RafftGraph::compat_layers()`]) to
+/// the reference output.
+/// Structures are compared per depth regardless of their order, energies (`kcal/mol`) have to
+/// agree within `tolerance`.
+pub fn compare(
+    graph: &RafftGraph,
+    reference: &[Vec<ReferenceStructure>],
+    tolerance: f64,
+) -> Vec<Discrepancy> {
+    let layers = graph.compat_layers();
+    let mut discrepancies = vec![];
+
+    for depth in 0..layers.len().max(reference.len()) {
+        let (found, expected) = match (layers.get(depth), reference.get(depth)) {
+            (Some(found), Some(expected)) => (found, expected),
+            (Some(_), None) => {
+                discrepancies.push(Discrepancy::ExtraDepth(depth));
+                continue;
+            }
+            _ => {
+                discrepancies.push(Discrepancy::MissingDepth(depth));
+                continue;
+            }
+        };
+
+        let found: Vec<(String, f64)> = found
+            .1
+            .iter()
+            .map(|node| (node.structure.to_string(), node.energy as f64 * 0.01))
+            .collect();
+
+        for (structure, energy) in expected {
+            match found.iter().find(|(s, _)| s == structure) {
+                Some((_, e)) if (e - energy).abs() > tolerance => {
+                    discrepancies.push(Discrepancy::Energy {
+                        depth,
+                        structure: structure.clone(),
+                        expected: *energy,
+                        found: *e,
+                    })
+                }
+                Some(_) => (),
+                None => discrepancies.push(Discrepancy::MissingStructure {
+                    depth,
+                    structure: structure.clone(),
+                }),
+            }
+        }
+
+        for (structure, _) in &found {
+            if !expected.iter().any(|(s, _)| s == structure) {
+                discrepancies.push(Discrepancy::ExtraStructure {
+                    depth,
+                    structure: structure.clone(),
+                });
+            }
+        }
+    }
+
+    discrepancies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_trajectories() {
+        let output = "# ---------0----------\n\
+                      .......... 0.00\n\
+                      # ---------1----------\n\
+                      ((....)).. -1.20\n\
+                      .((....)). -0.90\n";
+
+        let layers = parse_trajectories(output).unwrap();
+
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers[0], vec![("..........".to_string(), 0.0)]);
+        assert_eq!(layers[1][1], (".((....)).".to_string(), -0.9));
+
+        assert!(matches!(
+            parse_trajectories("# ---0---\nAUGC 0.0"),
+            Err(Error::Parse(_))
+        ));
+    }
+}
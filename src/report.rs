@@ -0,0 +1,291 @@
+//! This module provides standalone HTML reports of fast folding graphs, available using the `drawing` feature,
+//! e.g. to share results with collaborators who do not use the command line.
+//!
+//! A report consists of the structure of lowest free energy, a chart of the free energies of all structures
+//! against their depth, drawings of the structures along the trajectory towards the structure of lowest free energy
+//! (see [`crate::drawing`]) and the table of all structures.
+//! The data of the chart is embedded as JSON (see [`graph_json()`]) in the script element `rafft-data`,
+//! such that it can be re-plotted without parsing the page.
+
+use crate::drawing::svg_with_formation_depths;
+use crate::export::graph_json;
+use crate::folding_graph::{RafftGraph, RafftNodeId};
+use serde_json::json;
+use std::collections::HashSet;
+use std::fmt::Write;
+
+// size of the energy chart in user units, the plot area is padded by `MARGIN`
+const WIDTH: f64 = 640.0;
+const HEIGHT: f64 = 320.0;
+const MARGIN: f64 = 50.0;
+
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em; }
+code, td.structure { font-family: monospace; }
+table { border-collapse: collapse; }
+th, td { padding: 0.2em 0.8em; text-align: left; }
+tr.best { background: #fff3cd; }
+.drawings { display: flex; flex-wrap: wrap; gap: 1em; }
+.drawings svg { width: 260px; height: auto; }";
+
+/// Return a standalone HTML page summarizing `graph` constructed for `sequence`.
+/// The page does not reference any external resources.
+pub fn html_report(graph: &RafftGraph, sequence: &str) -> String {
+    let best = graph
+        .iter()
+        .min_by_key(|node| node.energy)
+        .expect("the root is always present");
+    let mut trajectory = graph.path_to_root(best.id);
+    trajectory.reverse();
+    let on_trajectory: HashSet<_> = trajectory.iter().copied().collect();
+    let cut_points = graph.cut_points();
+
+    let data = json!({
+        "sequence": sequence,
+        "graph": graph_json(graph, None),
+        "best_trajectory": graph
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| on_trajectory.contains(&node.id))
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>(),
+    });
+
+    let mut html = String::new();
+    // writing to a String never fails
+    let _ = writeln!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>RAFFT report</title>\n<style>\n{}\n</style>\n</head>\n<body>",
+        STYLE
+    );
+    let _ = writeln!(html, "<h1>RAFFT report</h1>");
+    let _ = writeln!(
+        html,
+        "<p>Sequence (length {}): <code>{}</code></p>",
+        sequence.chars().filter(|&c| c != '&').count(),
+        escape(sequence)
+    );
+    let _ = writeln!(
+        html,
+        "<p>Structure of lowest free energy: <code>{}</code> ({:.2} kcal/mol, depth {})</p>",
+        best.structure
+            .pair_table()
+            .to_string_with_cut_points(cut_points),
+        best.energy as f64 * 0.01,
+        best.depth
+    );
+    let _ = writeln!(
+        html,
+        "<p>{} structures in {} trajectories{}.</p>",
+        graph.iter().count(),
+        graph.leaves().len(),
+        if graph.is_truncated() {
+            ", the construction was stopped early"
+        } else {
+            ""
+        }
+    );
+
+    let _ = writeln!(html, "<h2>Free energy by depth</h2>");
+    html.push_str(&energy_chart(graph, &trajectory));
+
+    let _ = writeln!(
+        html,
+        "<h2>Trajectory towards the structure of lowest free energy</h2>"
+    );
+    let _ = writeln!(html, "<div class=\"drawings\">");
+    for node in trajectory.iter().filter_map(|&id| graph.node(id)) {
+        let title = format!("[{}] {:.2} kcal/mol", node.depth, node.energy as f64 * 0.01);
+        html.push_str(&svg_with_formation_depths(
+            sequence,
+            &node.structure.pair_table(),
+            &graph.pair_formation_depths(node.id),
+            Some(&title),
+        ));
+    }
+    let _ = writeln!(html, "</div>");
+
+    let _ = writeln!(html, "<h2>Structures</h2>");
+    let _ = writeln!(
+        html,
+        "<table>\n<tr><th>depth</th><th>structure</th><th>energy (kcal/mol)</th></tr>"
+    );
+    for node in graph.iter() {
+        let _ = writeln!(
+            html,
+            "<tr{}><td>{}</td><td class=\"structure\">{}</td><td>{:.2}</td></tr>",
+            if on_trajectory.contains(&node.id) {
+                " class=\"best\""
+            } else {
+                ""
+            },
+            node.depth,
+            escape(
+                &node
+                    .structure
+                    .pair_table()
+                    .to_string_with_cut_points(cut_points)
+            ),
+            node.energy as f64 * 0.01
+        );
+    }
+    let _ = writeln!(html, "</table>");
+
+    // `</` must not appear within script elements
+    let _ = writeln!(
+        html,
+        "<script type=\"application/json\" id=\"rafft-data\">{}</script>",
+        data.to_string().replace("</", "<\\/")
+    );
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+// Scatter plot of the free energies of all structures against their depth,
+// connecting the structures along the trajectory towards the structure of lowest free energy.
+fn energy_chart(graph: &RafftGraph, trajectory: &[RafftNodeId]) -> String {
+    let on_trajectory: HashSet<_> = trajectory.iter().copied().collect();
+    let max_depth = graph
+        .iter()
+        .map(|node| node.depth)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let energies: Vec<f64> = graph.iter().map(|node| node.energy as f64 * 0.01).collect();
+    let top = energies.iter().copied().fold(0.0, f64::max);
+    let bottom = energies.iter().copied().fold(top - 1.0, f64::min);
+
+    let point = |depth: usize, energy: f64| {
+        (
+            MARGIN + (WIDTH - 2.0 * MARGIN) * depth as f64 / max_depth as f64,
+            MARGIN + (HEIGHT - 2.0 * MARGIN) * (top - energy) / (top - bottom),
+        )
+    };
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH:.0}" height="{HEIGHT:.0}" viewBox="0 0 {WIDTH:.0} {HEIGHT:.0}" font-family="sans-serif" font-size="11">"#
+    );
+
+    // axes with labels at their ends
+    let (x0, y0) = point(0, bottom);
+    let (x1, y1) = point(max_depth, top);
+    let _ = writeln!(
+        svg,
+        r#"<polyline points="{x0:.2},{y1:.2} {x0:.2},{y0:.2} {x1:.2},{y0:.2}" fill="none" stroke="black" stroke-width="1"/>"#
+    );
+    let _ = writeln!(
+        svg,
+        r#"<text x="{:.2}" y="{:.2}" text-anchor="end">{:.2}</text><text x="{:.2}" y="{:.2}" text-anchor="end">{:.2}</text>"#,
+        x0 - 5.0,
+        y1 + 4.0,
+        top,
+        x0 - 5.0,
+        y0 + 4.0,
+        bottom
+    );
+    let _ = writeln!(
+        svg,
+        r#"<text x="{:.2}" y="{:.2}" text-anchor="middle">0</text><text x="{:.2}" y="{:.2}" text-anchor="middle">{}</text>"#,
+        x0,
+        y0 + 15.0,
+        x1,
+        y0 + 15.0,
+        max_depth
+    );
+    let _ = writeln!(
+        svg,
+        r#"<text x="{:.2}" y="{:.2}" text-anchor="middle">depth</text><text x="{:.2}" y="{:.2}" text-anchor="middle" transform="rotate(-90 {:.2} {:.2})">free energy (kcal/mol)</text>"#,
+        (x0 + x1) / 2.0,
+        HEIGHT - 10.0,
+        15.0,
+        (y0 + y1) / 2.0,
+        15.0,
+        (y0 + y1) / 2.0
+    );
+
+    let trajectory = trajectory
+        .iter()
+        .filter_map(|&id| graph.node(id))
+        .map(|node| {
+            let (x, y) = point(node.depth, node.energy as f64 * 0.01);
+            format!("{:.2},{:.2}", x, y)
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    let _ = writeln!(
+        svg,
+        r#"<polyline points="{}" fill="none" stroke="darkorange" stroke-width="1.5"/>"#,
+        trajectory
+    );
+
+    for (node, &energy) in graph.iter().zip(&energies) {
+        let (x, y) = point(node.depth, energy);
+        let _ = writeln!(
+            svg,
+            r#"<circle cx="{:.2}" cy="{:.2}" r="3" fill="{}"/>"#,
+            x,
+            y,
+            if on_trajectory.contains(&node.id) {
+                "darkorange"
+            } else {
+                "steelblue"
+            }
+        );
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fast_folding::RafftConfig;
+
+    #[test]
+    fn test_html_report() {
+        let sequence = "GGGGAAAACCCCAUAUAUGGGGAAAACCCC";
+        let mut graph = RafftConfig::new().folding_graph(sequence);
+        graph.construct_trajectories();
+
+        let html = html_report(&graph, sequence);
+        let best = graph.iter().min_by_key(|node| node.energy).unwrap();
+        let trajectory = graph.path_to_root(best.id);
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.trim_end().ends_with("</html>"));
+        // standalone: no external resources
+        assert!(!html.contains("src=") && !html.contains("href="));
+        // one drawing per structure along the best trajectory plus the chart
+        assert_eq!(html.matches("<svg").count(), trajectory.len() + 1);
+        assert_eq!(
+            html.matches("<tr").count(),
+            graph.iter().count() + 1,
+            "one row per structure"
+        );
+        assert_eq!(
+            html.matches("<tr class=\"best\">").count(),
+            trajectory.len()
+        );
+
+        let start = html.find("id=\"rafft-data\">").unwrap() + "id=\"rafft-data\">".len();
+        let end = start + html[start..].find("</script>").unwrap();
+        let data: serde_json::Value = serde_json::from_str(&html[start..end]).unwrap();
+        assert_eq!(data["sequence"], sequence);
+        assert_eq!(
+            data["graph"]["nodes"].as_array().unwrap().len(),
+            graph.iter().count()
+        );
+        assert_eq!(
+            data["best_trajectory"].as_array().unwrap().len(),
+            trajectory.len()
+        );
+    }
+}
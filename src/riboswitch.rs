@@ -0,0 +1,151 @@
+//! This module provides a two-state analysis of fast folding graphs, a common workflow for riboswitches:
+//! Given two alternative target structures, e.g. terminator and antiterminator, it reports which
+//! structures of the graph reach each target, their populations under the kinetic model of
+//! [`crate::kinetics`] and where the trajectories towards both targets diverge.
+
+use crate::encoding::PairTable;
+use crate::folding_graph::{RafftGraph, RafftNodeId};
+use crate::kinetics::{populations, ArrheniusModel};
+
+/// Summary of the structures of a [`RafftGraph`] reaching a target structure.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetReport {
+    /// structures within the tolerated base pair distance of the target that are closer to it than to the
+    /// alternative target, in the order of [`RafftGraph::iter()`]
+    pub reached: Vec<RafftNodeId>,
+    /// the structure closest to the target, ties are broken by free energy
+    pub closest: RafftNodeId,
+    /// base pair distance of the closest structure to the target
+    pub distance: usize,
+    /// total population of the reaching structures
+    pub population: f64,
+    /// trajectory from the root to the closest structure
+    pub trajectory: Vec<RafftNodeId>,
+}
+
+/// Result of [`two_state_analysis()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TwoStateReport {
+    /// report for the first target
+    pub first: TargetReport,
+    /// report for the second target
+    pub second: TargetReport,
+    /// last structure shared by the trajectories towards both targets,
+    /// see [`RafftGraph::node()`] for its depth and free energy
+    pub divergence: RafftNodeId,
+}
+
+struct Candidate {
+    id: RafftNodeId,
+    energy: i32,
+    distances: [usize; 2],
+    population: f64,
+}
+
+impl Candidate {
+    // structures equally close to both targets are attributed to the first one
+    fn attributed_to(&self, target: usize, tolerance: usize) -> bool {
+        let (own, other) = (self.distances[target], self.distances[1 - target]);
+        own <= tolerance && (own < other || (own == other && target == 0))
+    }
+}
+
+/// Analyze which structures of a constructed `graph` reach `first` or `second` within `tolerance`
+/// (base pair distance) and their populations at `time` under `model` (see [`populations()`]).
+/// Structures that are equally close to both targets are attributed to `first`.
+/// Panics if the targets do not match the length of the sequence.
+pub fn two_state_analysis(
+    graph: &RafftGraph,
+    first: &PairTable,
+    second: &PairTable,
+    tolerance: usize,
+    model: &ArrheniusModel,
+    time: f64,
+) -> TwoStateReport {
+    let candidates: Vec<Candidate> = graph
+        .iter()
+        .zip(populations(graph, model, time))
+        .map(|(node, population)| {
+            let pt = node.structure.pair_table();
+            Candidate {
+                id: node.id,
+                energy: node.energy,
+                distances: [pt.distance(first), pt.distance(second)],
+                population,
+            }
+        })
+        .collect();
+
+    let report = |target: usize| {
+        let closest = candidates
+            .iter()
+            .min_by_key(|candidate| (candidate.distances[target], candidate.energy))
+            .expect("the root is always present");
+        let reached: Vec<&Candidate> = candidates
+            .iter()
+            .filter(|candidate| candidate.attributed_to(target, tolerance))
+            .collect();
+
+        let mut trajectory = graph.path_to_root(closest.id);
+        trajectory.reverse();
+
+        TargetReport {
+            reached: reached.iter().map(|candidate| candidate.id).collect(),
+            closest: closest.id,
+            distance: closest.distances[target],
+            population: reached.iter().map(|candidate| candidate.population).sum(),
+            trajectory,
+        }
+    };
+
+    let (first, second) = (report(0), report(1));
+
+    let divergence = first
+        .trajectory
+        .iter()
+        .zip(&second.trajectory)
+        .take_while(|(a, b)| a == b)
+        .last()
+        .map(|(a, _)| *a)
+        .expect("trajectories start at the root");
+
+    TwoStateReport {
+        first,
+        second,
+        divergence,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::small_graph;
+
+    #[test]
+    fn test_two_state_analysis() {
+        let ffgraph = small_graph();
+
+        let first = "((((....))))....((((....))))".parse().unwrap();
+        let second = "((((....((((....))))....))))".parse().unwrap();
+
+        let report = two_state_analysis(
+            &ffgraph,
+            &first,
+            &second,
+            0,
+            &ArrheniusModel::default(),
+            100.0,
+        );
+
+        assert_eq!(report.first.distance, 0);
+        assert_eq!(report.second.distance, 0);
+        assert_eq!(report.first.reached, vec![report.first.closest]);
+        assert_eq!(report.second.reached, vec![report.second.closest]);
+        assert!(report.first.population + report.second.population <= 1.0 + 1e-9);
+
+        assert_eq!(report.first.trajectory[0], ffgraph.root());
+        assert_eq!(report.second.trajectory[0], ffgraph.root());
+        assert!(report.first.trajectory.contains(&report.divergence));
+        assert!(report.second.trajectory.contains(&report.divergence));
+    }
+}
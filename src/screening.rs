@@ -0,0 +1,242 @@
+//! This module provides a fast pre-screening of sequences that only computes the autocorrelation
+//! spectrum of each sequence and summarizes its signal strength, without constructing fast folding graphs.
+//! Sequences with a strong, concentrated signal are likely to form long stacks and can be prioritized
+//! for full RAFFT runs, e.g. in transcriptome-wide scans.
+//!
+//! Sequences are read from FASTA files using [`read_fasta()`].
+//! The effect of point mutations on the spectrum of a single sequence is scanned by [`screen_mutations()`].
+
+use crate::encoding::{self, EncodedSequence, SeqPos0};
+use crate::fast_folding::RafftConfig;
+use rayon::prelude::*;
+use std::io::{self, BufRead, Write};
+
+/// Summary statistics of the (kernel-transformed) autocorrelation spectrum of a sequence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpectrumSummary {
+    /// length of the sequence
+    pub length: usize,
+    /// positional lag with the highest correlation
+    pub peak_lag: usize,
+    /// highest correlation of the spectrum
+    pub peak_height: f64,
+    /// highest prominence of any peak of the spectrum, `0` if there is no peak
+    pub peak_prominence: f64,
+    /// normalized spectral entropy, see [`CorrelationSpectrum::entropy()`](crate::autocorrelation::CorrelationSpectrum::entropy)
+    pub spectral_entropy: f64,
+}
+
+/// Header line of the tab-separated output of [`write_tsv()`].
+pub const TSV_HEADER: &str =
+    "name\tlength\tpeak_lag\tpeak_height\tpeak_prominence\tspectral_entropy";
+
+/// Summarize the autocorrelation spectrum of `sequence` using the base pair weights, blocked regions
+/// and correlation kernel of `config`.
+pub fn screen(sequence: &str, config: &RafftConfig) -> Result<SpectrumSummary, encoding::Error> {
+    Ok(summarize(&config.encode(sequence)?, config))
+}
+
+/// Screen all point mutations of `sequence` in parallel, see [`screen()`].
+/// Returns the `0`-indexed position (without strand separators), the substituted nucleotide and the summary
+/// of each mutant, ordered by position and nucleotide. Blocked regions of `config` are not mutated.
+pub fn screen_mutations(
+    sequence: &str,
+    config: &RafftConfig,
+) -> Result<Vec<(SeqPos0, char, SpectrumSummary)>, encoding::Error> {
+    let encoded = config.encode(sequence)?;
+    let nucleotides: Vec<char> = encoded.decode().chars().collect();
+
+    Ok(config.install(|| {
+        nucleotides
+            .par_iter()
+            .enumerate()
+            .filter(|&(_, &original)| original != 'N')
+            .flat_map_iter(|(i, &original)| {
+                let encoded = &encoded;
+                ['A', 'C', 'G', 'U']
+                    .into_iter()
+                    .filter(move |&nucleotide| nucleotide != original)
+                    .map(move |nucleotide| {
+                        let mutant = encoded
+                            .with_mutation(SeqPos0(i), nucleotide)
+                            .expect("unmasked position and valid nucleotide");
+                        (SeqPos0(i), nucleotide, summarize(&mutant, config))
+                    })
+            })
+            .collect()
+    }))
+}
+
+// Summarize the autocorrelation spectrum of an encoded sequence, see `screen()`.
+fn summarize(encoded: &EncodedSequence, config: &RafftConfig) -> SpectrumSummary {
+    let spectrum = config
+        .correlation_kernel
+        .apply(&encoded.autocorrelation_with(1.0, &*config.fft_backend));
+
+    let peak_lag = spectrum.top_lags(1).first().copied().unwrap_or(0);
+
+    SpectrumSummary {
+        length: encoded.len(),
+        peak_lag,
+        peak_height: spectrum.view().get(peak_lag).copied().unwrap_or(0.0),
+        peak_prominence: spectrum
+            .peaks(0.0)
+            .iter()
+            .map(|peak| peak.prominence)
+            .fold(0.0, f64::max),
+        spectral_entropy: spectrum.entropy(),
+    }
+}
+
+/// Screen all `(name, sequence)` records in parallel, see [`screen()`].
+/// Results are in the order of `records`.
+pub fn screen_all(
+    records: &[(String, String)],
+    config: &RafftConfig,
+) -> Vec<Result<SpectrumSummary, encoding::Error>> {
+    screen_all_with(records, config, |_| ())
+}
+
+/// Like [`screen_all()`], but calls `on_result` from the worker threads as soon as each record is screened,
+/// e.g. to report progress.
+pub fn screen_all_with<F>(
+    records: &[(String, String)],
+    config: &RafftConfig,
+    on_result: F,
+) -> Vec<Result<SpectrumSummary, encoding::Error>>
+where
+    F: Fn(&Result<SpectrumSummary, encoding::Error>) + Sync,
+{
+    config.install(|| {
+        records
+            .par_iter()
+            .map(|(_, sequence)| {
+                let result = screen(sequence, config);
+                on_result(&result);
+                result
+            })
+            .collect()
+    })
+}
+
+/// Read `(name, sequence)` records from FASTA.
+/// Names are the header lines without `>`, sequences may span several lines and are converted to upper case.
+/// Sequence lines preceding the first header are assigned an empty name.
+pub fn read_fasta<R: BufRead>(reader: R) -> io::Result<Vec<(String, String)>> {
+    let mut records: Vec<(String, String)> = vec![];
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+
+        if let Some(name) = line.strip_prefix('>') {
+            records.push((name.trim().to_string(), String::new()));
+        } else if !line.is_empty() {
+            if records.is_empty() {
+                records.push((String::new(), String::new()));
+            }
+            records
+                .last_mut()
+                .expect("a record is always present")
+                .1
+                .push_str(&line.to_uppercase());
+        }
+    }
+
+    Ok(records)
+}
+
+/// Write screening results as tab-separated values with a header line (see [`TSV_HEADER`]).
+/// Records that could not be encoded are skipped and returned with their errors, in the order of `records`.
+pub fn write_tsv<'a, W: Write>(
+    records: &'a [(String, String)],
+    results: &'a [Result<SpectrumSummary, encoding::Error>],
+    writer: &mut W,
+) -> io::Result<Vec<(&'a str, &'a encoding::Error)>> {
+    writeln!(writer, "{}", TSV_HEADER)?;
+    let mut skipped = vec![];
+
+    for ((name, _), result) in records.iter().zip(results) {
+        match result {
+            Ok(summary) => writeln!(
+                writer,
+                "{}\t{}\t{}\t{:.4}\t{:.4}\t{:.4}",
+                name,
+                summary.length,
+                summary.peak_lag,
+                summary.peak_height,
+                summary.peak_prominence,
+                summary.spectral_entropy
+            )?,
+            Err(error) => skipped.push((name.as_str(), error)),
+        }
+    }
+
+    Ok(skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_screening() {
+        let fasta = ">hairpin some description\n\
+                     GGGGGGAAAACCCC\n\
+                     CC\n\
+                     \n\
+                     >repeat\n\
+                     aaaaaaaaaaaaaaaa\n\
+                     >invalid\n\
+                     ACGUN\n";
+
+        let records = read_fasta(fasta.as_bytes()).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            records[0],
+            (
+                "hairpin some description".to_string(),
+                "GGGGGGAAAACCCCCC".to_string()
+            )
+        );
+        assert_eq!(records[1].1, "A".repeat(16));
+
+        let results = screen_all(&records, &RafftConfig::new());
+        let (hairpin, repeat) = (results[0].as_ref().unwrap(), results[1].as_ref().unwrap());
+
+        assert_eq!(hairpin.length, 16);
+        // the stem of the hairpin is aligned at lag 15
+        assert_eq!(hairpin.peak_lag, 15);
+        assert!(hairpin.peak_height > 0.0);
+        // sequences without any complementary positions have no signal
+        assert!(repeat.peak_height.abs() < 1e-9);
+        assert!(hairpin.spectral_entropy < repeat.spectral_entropy);
+        assert!(matches!(
+            results[2],
+            Err(encoding::Error::InvalidNucleotide('N'))
+        ));
+
+        let mut tsv = vec![];
+        let skipped = write_tsv(&records, &results, &mut tsv).unwrap();
+        assert_eq!(String::from_utf8(tsv).unwrap().lines().count(), 3);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].0, "invalid");
+    }
+
+    #[test]
+    fn test_mutation_screening() {
+        let config = RafftConfig::new().block_region(0..2);
+        let mutants = screen_mutations("GGGGAAAACCCC", &config).unwrap();
+
+        // three substitutions at each of the 10 unblocked positions
+        assert_eq!(mutants.len(), 30);
+        assert_eq!((mutants[0].0, mutants[0].1), (SeqPos0(2), 'A'));
+
+        for (position, nucleotide, summary) in mutants {
+            let mut mutant: Vec<char> = "GGGGAAAACCCC".chars().collect();
+            mutant[position.0] = nucleotide;
+            let mutant: String = mutant.into_iter().collect();
+            assert_eq!(summary, screen(&mutant, &config).unwrap());
+        }
+    }
+}
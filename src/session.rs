@@ -0,0 +1,474 @@
+//! This module provides [`RafftSession`], which owns the resources that folds borrow: caches of encodings and
+//! ViennaRNA fold compounds, the plans of the FFT backend and a thread pool. This gives embedders explicit control
+//! of their lifetimes, e.g. to fold the same sequences repeatedly with different parameters in parameter sweeps
+//! or design loops, and to release all resources by dropping the session.
+//!
+//! Encodings are cached per sequence and encoding options (base pair weights and blocked regions),
+//! fold compounds per sequence and energy model (temperature and global parameters, see
+//! [`set_global_temperature()`](crate::set_global_temperature)). Both caches evict their least recently
+//! used entries when full. Fold compounds are owned by the fast folding graphs constructed with them and
+//! return to the session once the graph is [recycled](RafftSession::recycle()):
+//!
+//! ```no_run
+//! use rafft::fast_folding::RafftConfig;
+//! use rafft::session::RafftSession;
+//!
+//! let session = RafftSession::new(16).threads(4).unwrap();
+//!
+//! for lags in [10, 50, 100] {
+//!     let config = RafftConfig::new().positional_lags(lags);
+//!     let (structure, energy) = session
+//!         .fold_with("GGGGAAAACCCC", config, |result| (result.structure(), result.energy()))
+//!         .unwrap();
+//!     println!("{} {} {:.2}", lags, structure, energy);
+//! }
+//!
+//! let results = session.par_fold_with(&["GGGGAAAACCCC", "GGGAAACCC"], &RafftConfig::new(), |result| result.energy());
+//! ```
+
+use crate::encoding::{self, EncodedSequence};
+use crate::fast_folding::{RafftConfig, RafftResult};
+use crate::fft::{default_backend, FftBackend, PlannedRustFft};
+use crate::folding_graph::RafftGraph;
+use crate::vienna::{model_generation, VCompound};
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+// Encodings depend on the sequence, the base pair weights (as bits) and the blocked regions.
+type EncodingKey = (String, [u64; 3], Vec<Range<usize>>);
+// Fold compounds depend on the sequence, the temperature (as bits, `None` for the global default)
+// and the generation of the global energy model.
+type CompoundKey = (String, Option<u64>, usize);
+
+/// A bounded cache evicting its least recently used entries first.
+struct Lru<K, V> {
+    capacity: usize,
+    entries: HashMap<K, V>,
+    order: VecDeque<K>,
+}
+
+impl<K: Clone + Eq + Hash, V> Lru<K, V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    // Return the entry of `key`, marking it as most recently used.
+    fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let position = self.order.iter().position(|k| k == key)?;
+        let key = self.order.remove(position)?;
+        self.order.push_back(key);
+        self.entries.get_mut(self.order.back()?)
+    }
+
+    // Return the entry of `key`, inserting `default()` if it is missing.
+    fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, default: F) -> Option<&mut V> {
+        if self.capacity == 0 {
+            return None;
+        }
+
+        if !self.entries.contains_key(&key) {
+            if self.entries.len() >= self.capacity {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.entries.remove(&oldest);
+                }
+            }
+
+            self.order.push_back(key.clone());
+            self.entries.insert(key.clone(), default());
+        }
+
+        self.get_mut(&key)
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+struct Caches {
+    encodings: Lru<EncodingKey, EncodedSequence>,
+    // idle fold compounds, several per key if graphs were constructed concurrently
+    compounds: Lru<CompoundKey, Vec<VCompound>>,
+}
+
+/// Resources shared by folds, i.e. caches of encodings and fold compounds of sequences that are folded repeatedly,
+/// the FFT backend and a thread pool, see the [module](self).
+/// A session can be shared between threads, e.g. by parallel sweeps.
+pub struct RafftSession {
+    caches: Mutex<Caches>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    fft_backend: Arc<dyn FftBackend>,
+    pool: Option<ThreadPool>,
+}
+
+impl RafftSession {
+    /// Create a session caching up to `capacity` encodings and fold compounds of different sequences each.
+    /// A capacity of `0` disables caching.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            caches: Mutex::new(Caches {
+                encodings: Lru::new(capacity),
+                compounds: Lru::new(capacity),
+            }),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+            fft_backend: Arc::new(PlannedRustFft::new()),
+            pool: None,
+        }
+    }
+
+    /// Use `backend` for folds of this session whose [`RafftConfig`] uses the default backend
+    /// (see [`crate::fft::default_backend()`]). By default, a [`PlannedRustFft`] keeps the plans of the
+    /// transforms of the session.
+    pub fn fft_backend(mut self, backend: Arc<dyn FftBackend>) -> Self {
+        self.fft_backend = backend;
+        self
+    }
+
+    /// Run parallel folds (see [`RafftSession::par_fold_with()`]) and [`RafftSession::install()`] on a thread pool
+    /// of `threads` threads owned by the session. By default, the pool of the caller is used.
+    /// Returns an error if the pool cannot be created.
+    pub fn threads(mut self, threads: usize) -> Result<Self, ThreadPoolBuildError> {
+        self.pool = Some(ThreadPoolBuilder::new().num_threads(threads).build()?);
+        Ok(self)
+    }
+
+    /// Run `op` on the thread pool of the session (see [`RafftSession::threads()`]), or in the current pool.
+    pub fn install<R, F>(&self, op: F) -> R
+    where
+        F: FnOnce() -> R + Send,
+        R: Send,
+    {
+        match &self.pool {
+            Some(pool) => pool.install(op),
+            None => op(),
+        }
+    }
+
+    /// Return an empty [`RafftGraph`] for `sequence`, re-using its cached encoding and an idle fold compound.
+    /// Pass the graph to [`RafftSession::recycle()`] once it is no longer needed to return the fold compound.
+    pub fn folding_graph(
+        &self,
+        sequence: &str,
+        config: RafftConfig,
+    ) -> Result<RafftGraph, encoding::Error> {
+        self.folding_graph_at(sequence, config, None)
+    }
+
+    // Return an empty graph like `folding_graph()` at `temperature` (`None` for the global default).
+    fn folding_graph_at(
+        &self,
+        sequence: &str,
+        config: RafftConfig,
+        temperature: Option<f64>,
+    ) -> Result<RafftGraph, encoding::Error> {
+        let encoded = self.encoding(sequence, &config)?;
+        let fc = self.compound(sequence, temperature);
+
+        let config = if config.fft_backend.name() == default_backend().name() {
+            config.fft_backend(self.fft_backend.clone())
+        } else {
+            config
+        };
+
+        Ok(RafftGraph::new(encoded, fc, config))
+    }
+
+    /// Fold `sequence` using `config` like [`fold_sequence_with()`](crate::fold_sequence_with), re-using
+    /// cached data. Pass [`RafftResult::into_graph()`] to [`RafftSession::recycle()`] to return the fold compound.
+    pub fn fold(
+        &self,
+        sequence: &str,
+        config: RafftConfig,
+    ) -> Result<RafftResult, encoding::Error> {
+        self.fold_at(sequence, config, None)
+    }
+
+    // Fold `sequence` like `fold()` at `temperature` (`None` for the global default).
+    fn fold_at(
+        &self,
+        sequence: &str,
+        config: RafftConfig,
+        temperature: Option<f64>,
+    ) -> Result<RafftResult, encoding::Error> {
+        let mut graph = self.folding_graph_at(sequence, config, temperature)?;
+        graph.construct_trajectories();
+
+        Ok(RafftResult::from_graph(sequence, graph))
+    }
+
+    /// Fold `sequence` using `config` like [`RafftSession::fold()`] and return the outcome of `inspect` on the result,
+    /// after which the fold compound is returned to the session.
+    pub fn fold_with<R, F>(
+        &self,
+        sequence: &str,
+        config: RafftConfig,
+        inspect: F,
+    ) -> Result<R, encoding::Error>
+    where
+        F: FnOnce(&RafftResult) -> R,
+    {
+        self.fold_at_with(sequence, config, None, inspect)
+    }
+
+    /// Fold `sequence` at `temperature` (in °C) like [`RafftSession::fold_with()`], e.g. in temperature scans,
+    /// where fold compounds are cached per temperature. `None` uses the global default temperature.
+    ///
+    /// Parallel scans call this from their worker threads, such that each fast folding graph is constructed
+    /// and inspected in a worker thread and only the outcome of `inspect` is sent back.
+    pub fn fold_at_with<R, F>(
+        &self,
+        sequence: &str,
+        config: RafftConfig,
+        temperature: Option<f64>,
+        inspect: F,
+    ) -> Result<R, encoding::Error>
+    where
+        F: FnOnce(&RafftResult) -> R,
+    {
+        let result = self.fold_at(sequence, config, temperature)?;
+        let outcome = inspect(&result);
+        self.recycle(result.into_graph());
+
+        Ok(outcome)
+    }
+
+    /// Fold all `sequences` using `config` in parallel on the thread pool of the session
+    /// and return the outcomes of `inspect` in the order of the sequences, see [`RafftSession::fold_with()`].
+    pub fn par_fold_with<R, F>(
+        &self,
+        sequences: &[&str],
+        config: &RafftConfig,
+        inspect: F,
+    ) -> Vec<Result<R, encoding::Error>>
+    where
+        F: Fn(&RafftResult) -> R + Sync,
+        R: Send,
+    {
+        self.install(|| {
+            sequences
+                .par_iter()
+                .map(|sequence| self.fold_with(sequence, config.clone(), &inspect))
+                .collect()
+        })
+    }
+
+    /// Return the fold compound of `graph` to the session, such that it can be re-used for the same sequence.
+    /// Fold compounds of an outdated energy model are dropped.
+    pub fn recycle(&self, graph: RafftGraph) {
+        let Some(mut fc) = graph.into_fold_compound() else {
+            return;
+        };
+        let (sequence, temperature, generation) = fc.model();
+
+        if generation != model_generation() {
+            return;
+        }
+
+        let key = (
+            sequence.to_string(),
+            temperature.map(f64::to_bits),
+            generation,
+        );
+        fc.remove_soft_constraints();
+
+        if let Some(idle) = self.lock().compounds.get_or_insert_with(key, Vec::new) {
+            idle.push(fc);
+        }
+    }
+
+    /// Return the number of encodings and fold compounds that were re-used.
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Return the number of encodings and fold compounds that had to be created.
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Drop all cached encodings and fold compounds. The plans of the FFT backend and the thread pool are kept.
+    pub fn clear(&self) {
+        let mut caches = self.lock();
+        caches.encodings.clear();
+        caches.compounds.clear();
+    }
+
+    // Return the encoding of `sequence` using `config`, from the cache if possible.
+    pub(crate) fn encoding(
+        &self,
+        sequence: &str,
+        config: &RafftConfig,
+    ) -> Result<EncodedSequence, encoding::Error> {
+        let weights = &config.basepair_weights;
+        let key = (
+            sequence.to_string(),
+            [weights.AU, weights.GC, weights.GU].map(f64::to_bits),
+            config.blocked_regions.clone(),
+        );
+
+        if let Some(encoded) = self.lock().encodings.get_mut(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(encoded.clone());
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let encoded = config.encode(sequence)?;
+        self.lock()
+            .encodings
+            .get_or_insert_with(key, || encoded.clone());
+
+        Ok(encoded)
+    }
+
+    // Return an idle fold compound of `sequence` at `temperature` (`None` for the global default),
+    // or a new one if there is none.
+    pub(crate) fn compound(&self, sequence: &str, temperature: Option<f64>) -> VCompound {
+        let key = (
+            sequence.to_string(),
+            temperature.map(f64::to_bits),
+            model_generation(),
+        );
+
+        if let Some(fc) = self
+            .lock()
+            .compounds
+            .get_mut(&key)
+            .and_then(|idle| idle.pop())
+        {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return fc;
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        match temperature {
+            Some(temperature) => VCompound::with_temperature(sequence, temperature),
+            None => VCompound::new(sequence),
+        }
+    }
+
+    // A panic while holding the lock cannot leave the caches inconsistent.
+    fn lock(&self) -> std::sync::MutexGuard<'_, Caches> {
+        self.caches
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Default for RafftSession {
+    /// Create a session caching up to `64` sequences.
+    fn default() -> Self {
+        Self::new(64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session() {
+        let sequence = "GGGGAAAACCCCAUAUAUGGGGAAAACCCC";
+        let session = RafftSession::new(2);
+
+        let results: Vec<_> = [10, 50, 100]
+            .iter()
+            .map(|&lags| {
+                session
+                    .fold_with(
+                        sequence,
+                        RafftConfig::new().positional_lags(lags),
+                        |result| (result.structure(), result.energy()),
+                    )
+                    .unwrap()
+            })
+            .collect();
+
+        // the first fold creates the encoding and the fold compound, later folds re-use both
+        assert_eq!(session.misses(), 2);
+        assert_eq!(session.hits(), 4);
+        for (lags, result) in [10, 50, 100].iter().zip(&results) {
+            let expected =
+                crate::fold_sequence_with(sequence, |config| config.positional_lags(*lags))
+                    .unwrap();
+            assert_eq!(result, &(expected.structure(), expected.energy()));
+        }
+
+        // other weights are encoded anew, but share the fold compound
+        session
+            .fold_with(
+                sequence,
+                RafftConfig::new().basepair_weights(1.0, 2.0, 0.5),
+                |_| (),
+            )
+            .unwrap();
+        assert_eq!((session.misses(), session.hits()), (3, 5));
+
+        // results that are not recycled keep their fold compound
+        let result = session.fold(sequence, RafftConfig::new()).unwrap();
+        session.fold(sequence, RafftConfig::new()).unwrap();
+        assert_eq!((session.misses(), session.hits()), (4, 8));
+        session.recycle(result.into_graph());
+
+        // the least recently used sequence is evicted
+        for other in ["GGGAAACCC", "GGGGAAAACCC"] {
+            session
+                .fold_with(other, RafftConfig::new(), |_| ())
+                .unwrap();
+        }
+        let misses = session.misses();
+        session
+            .fold_with(sequence, RafftConfig::new(), |_| ())
+            .unwrap();
+        assert_eq!(session.misses(), misses + 2);
+
+        session.clear();
+        assert!(session.fold(sequence, RafftConfig::new()).is_ok());
+        assert!(session.fold("GGXA", RafftConfig::new()).is_err());
+
+        let disabled = RafftSession::new(0);
+        disabled
+            .fold_with(sequence, RafftConfig::new(), |_| ())
+            .unwrap();
+        disabled
+            .fold_with(sequence, RafftConfig::new(), |_| ())
+            .unwrap();
+        assert_eq!((disabled.misses(), disabled.hits()), (4, 0));
+    }
+
+    #[test]
+    fn test_session_resources() {
+        let sequences = ["GGGGAAAACCCCAUAUAUGGGGAAAACCCC", "GGGAAACCC", "GGXA"];
+        let session = RafftSession::new(4).threads(2).unwrap();
+
+        assert_eq!(session.install(rayon::current_num_threads), 2);
+        assert_eq!(
+            RafftSession::default().install(rayon::current_num_threads),
+            rayon::current_num_threads()
+        );
+
+        let results = session.par_fold_with(&sequences, &RafftConfig::new(), |result| {
+            (result.structure(), result.energy())
+        });
+        assert_eq!(results.len(), 3);
+        for (sequence, result) in sequences.iter().zip(&results).take(2) {
+            let expected = crate::fold_sequence(sequence).unwrap();
+            assert_eq!(
+                result.as_ref().unwrap(),
+                &(expected.structure(), expected.energy())
+            );
+        }
+        assert!(results[2].is_err());
+    }
+}
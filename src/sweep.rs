@@ -0,0 +1,295 @@
+//! This module provides parameter sweeps, i.e. folding the same sequence for every combination of
+//! positional lags, branches, base pair weights and temperatures on a grid, in parallel.
+//!
+//! Grids can be read from TOML files, omitted parameters keep their default values:
+//!
+//! ```toml
+//! positional_lags = [50, 100]
+//! branches = [100, 1000]
+//! basepair_weights = [[2.0, 3.0, 1.0], [1.0, 1.0, 1.0]]
+//! temperatures = [25.0, 37.0]
+//! # optional reference structure to compute base pair distances to
+//! reference = "((((....))))"
+//! ```
+
+use crate::encoding::{self, EncodedSequence, PairTable};
+use crate::fast_folding::RafftConfig;
+use crate::folding_graph::RafftNodeInfo;
+use crate::session::RafftSession;
+use itertools::iproduct;
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::io::{self, Write};
+use std::path::Path;
+use thiserror::Error;
+
+/// Error type representing errors that may arise while reading grids or running sweeps.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// The grid file could not be read.
+    #[error("failed to read grid: {0}")]
+    Io(#[from] io::Error),
+    /// The grid file is not valid TOML or contains unknown parameters.
+    #[error("invalid grid: {0}")]
+    Toml(#[from] toml::de::Error),
+    /// The sequence or reference structure is invalid.
+    #[error(transparent)]
+    Encoding(#[from] encoding::Error),
+    /// The reference structure does not match the length of the sequence.
+    #[error("reference structure has length {0}, but the sequence has length {1}")]
+    ReferenceLength(usize, usize),
+}
+
+/// Values of the parameters to sweep over.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SweepGrid {
+    /// numbers of positional lags, see [`RafftConfig::positional_lags()`]
+    pub positional_lags: Vec<usize>,
+    /// numbers of branches, see [`RafftConfig::maximum_branches()`]
+    pub branches: Vec<usize>,
+    /// base pair weights `(AU, GC, GU)`, see [`RafftConfig::basepair_weights()`]
+    pub basepair_weights: Vec<(f64, f64, f64)>,
+    /// temperatures in °C
+    pub temperatures: Vec<f64>,
+    /// reference structure in dot-bracket notation
+    pub reference: Option<String>,
+}
+
+impl Default for SweepGrid {
+    /// A grid containing only the default parameters of RAFFT.
+    fn default() -> Self {
+        Self {
+            positional_lags: vec![100],
+            branches: vec![1000],
+            basepair_weights: vec![(2.0, 3.0, 1.0)],
+            temperatures: vec![37.0],
+            reference: None,
+        }
+    }
+}
+
+impl SweepGrid {
+    /// Parse a grid from a TOML document.
+    pub fn from_toml(document: &str) -> Result<Self, Error> {
+        Ok(toml::from_str(document)?)
+    }
+
+    /// Read a grid from a TOML file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    /// Return all combinations of parameters on the grid.
+    pub fn cells(&self) -> Vec<SweepCell> {
+        iproduct!(
+            &self.positional_lags,
+            &self.branches,
+            &self.basepair_weights,
+            &self.temperatures
+        )
+        .map(
+            |(&positional_lags, &branches, &basepair_weights, &temperature)| SweepCell {
+                positional_lags,
+                branches,
+                basepair_weights,
+                temperature,
+            },
+        )
+        .collect()
+    }
+}
+
+/// A single combination of parameters of a [`SweepGrid`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SweepCell {
+    /// number of positional lags
+    pub positional_lags: usize,
+    /// number of branches
+    pub branches: usize,
+    /// base pair weights `(AU, GC, GU)`
+    pub basepair_weights: (f64, f64, f64),
+    /// temperature in °C
+    pub temperature: f64,
+}
+
+/// Summary of the fast folding graph constructed for a [`SweepCell`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepResult {
+    /// parameters used
+    pub cell: SweepCell,
+    /// structure of lowest free energy in dot-bracket notation
+    pub best_structure: String,
+    /// lowest free energy in `kcal/mol`
+    pub best_energy: f64,
+    /// number of structures in the fast folding graph
+    pub structures: usize,
+    /// base pair distance of the best structure to the reference structure, if provided
+    pub distance: Option<usize>,
+    /// smallest base pair distance of any structure in the graph to the reference structure, if provided
+    pub min_distance: Option<usize>,
+}
+
+/// Column names of [`write_tsv()`].
+pub const TSV_HEADER: &str = "positional_lags\tbranches\tAU\tGC\tGU\ttemperature\tbest_energy\tstructures\tdistance\tmin_distance\tbest_structure";
+
+/// Fold `sequence` for every cell of `grid` in parallel. All other parameters are taken from `config`.
+/// The results are in the order of [`SweepGrid::cells()`].
+pub fn sweep(
+    sequence: &str,
+    config: &RafftConfig,
+    grid: &SweepGrid,
+) -> Result<Vec<SweepResult>, Error> {
+    sweep_with(sequence, config, grid, |_| ())
+}
+
+/// Like [`sweep()`], but calls `on_result` from the worker threads as soon as each cell is folded,
+/// e.g. to report progress.
+pub fn sweep_with<F>(
+    sequence: &str,
+    config: &RafftConfig,
+    grid: &SweepGrid,
+    on_result: F,
+) -> Result<Vec<SweepResult>, Error>
+where
+    F: Fn(&SweepResult) + Sync,
+{
+    // validate the sequence once instead of per cell
+    EncodedSequence::new(sequence)?;
+
+    let reference = grid
+        .reference
+        .as_deref()
+        .map(str::parse::<PairTable>)
+        .transpose()?;
+
+    if let Some(reference) = &reference {
+        if reference.len() != sequence.len() {
+            return Err(Error::ReferenceLength(reference.len(), sequence.len()));
+        }
+    }
+
+    let cells = grid.cells();
+    let reference = reference.as_ref();
+    // cells of the same temperature share fold compounds
+    let session = RafftSession::default();
+
+    Ok(config.install(|| {
+        cells
+            .into_par_iter()
+            .map(|cell| {
+                let result = fold_cell(sequence, config, cell, reference, &session);
+                on_result(&result);
+                result
+            })
+            .collect()
+    }))
+}
+
+// Fold a single cell, see `RafftSession::fold_at_with()`.
+fn fold_cell(
+    sequence: &str,
+    config: &RafftConfig,
+    cell: SweepCell,
+    reference: Option<&PairTable>,
+    session: &RafftSession,
+) -> SweepResult {
+    let (au, gc, gu) = cell.basepair_weights;
+    let config = config
+        .clone()
+        .positional_lags(cell.positional_lags)
+        .maximum_branches(cell.branches)
+        .basepair_weights(au, gc, gu);
+
+    session
+        .fold_at_with(sequence, config, Some(cell.temperature), |result| {
+            let distance = |node: &RafftNodeInfo| {
+                reference.map(|reference| reference.distance(&node.structure.pair_table()))
+            };
+            let best = result.best();
+
+            SweepResult {
+                cell,
+                best_structure: best.structure.to_string(),
+                best_energy: result.energy(),
+                structures: result.graph().iter().count(),
+                distance: distance(best),
+                min_distance: result.graph().iter().filter_map(distance).min(),
+            }
+        })
+        .expect("sequence was validated before")
+}
+
+/// Write sweep results as tab-separated values with a header line (see [`TSV_HEADER`]).
+/// Missing distances are written as `NA`.
+pub fn write_tsv<W: Write>(results: &[SweepResult], writer: &mut W) -> io::Result<()> {
+    writeln!(writer, "{}", TSV_HEADER)?;
+
+    let format_distance =
+        |distance: Option<usize>| distance.map_or_else(|| "NA".to_string(), |d| d.to_string());
+
+    for result in results {
+        let cell = &result.cell;
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{:.2}\t{}\t{}\t{}\t{}",
+            cell.positional_lags,
+            cell.branches,
+            cell.basepair_weights.0,
+            cell.basepair_weights.1,
+            cell.basepair_weights.2,
+            cell.temperature,
+            result.best_energy,
+            result.structures,
+            format_distance(result.distance),
+            format_distance(result.min_distance),
+            result.best_structure
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::SMALL_SEQUENCE;
+
+    #[test]
+    fn test_sweep() {
+        let grid = SweepGrid::from_toml(
+            "positional_lags = [10, 100]\n\
+             basepair_weights = [[2.0, 3.0, 1.0], [1.0, 1.0, 1.0]]\n\
+             temperatures = [25.0, 37.0, 50.0]\n\
+             reference = \"((((....))))....((((....))))\"",
+        )
+        .unwrap();
+
+        assert_eq!(grid.branches, vec![1000]);
+        assert_eq!(grid.cells().len(), 12);
+        assert!(SweepGrid::from_toml("lags = [10]").is_err());
+
+        let sequence = SMALL_SEQUENCE;
+        let config = RafftConfig::new().maximum_trajectories(3);
+        let results = sweep(sequence, &config, &grid).unwrap();
+
+        assert_eq!(results.len(), 12);
+        for (result, cell) in results.iter().zip(grid.cells()) {
+            assert_eq!(result.cell, cell);
+            assert!(result.min_distance <= result.distance);
+        }
+
+        let mut tsv = vec![];
+        write_tsv(&results, &mut tsv).unwrap();
+        assert_eq!(String::from_utf8(tsv).unwrap().lines().count(), 13);
+
+        let grid = SweepGrid {
+            reference: Some("((...))".to_string()),
+            ..SweepGrid::default()
+        };
+        assert!(matches!(
+            sweep(sequence, &config, &grid),
+            Err(Error::ReferenceLength(7, 28))
+        ));
+    }
+}
@@ -0,0 +1,196 @@
+//! This module provides [`proptest`] strategies generating random RNA sequences and secondary structures,
+//! together with invariants the core types have to satisfy.
+//! It is available using the `testing` feature and should help to safely modify the encoding and scoring routines.
+//! Small fixtures shared by tests, e.g. [`small_graph()`], are provided as well.
+
+use crate::encoding::{EncodedSequence, PairTable};
+use crate::{fast_folding::RafftConfig, folding_graph::RafftGraph};
+use ndarray::Axis;
+use proptest::prelude::*;
+use std::ops::Range;
+
+/// Strategy generating RNA sequences over the alphabet `A`, `C`, `G`, `U` with lengths in `length`.
+pub fn sequences(length: Range<usize>) -> impl Strategy<Value = String> {
+    proptest::collection::vec(
+        prop_oneof![Just('A'), Just('C'), Just('G'), Just('U')],
+        length,
+    )
+    .prop_map(|nucleotides| nucleotides.into_iter().collect())
+}
+
+/// Strategy generating complexes of up to `strands` sequences (see [`sequences()`]) separated by `&`.
+pub fn complexes(length: Range<usize>, strands: usize) -> impl Strategy<Value = String> {
+    proptest::collection::vec(sequences(length), 1..strands.max(1) + 1)
+        .prop_map(|strands| strands.join("&"))
+}
+
+/// Strategy generating secondary structures without crossing pairs with lengths in `length`.
+/// Hairpin loops enclose at least `min_unpaired` positions.
+pub fn structures(length: Range<usize>, min_unpaired: usize) -> impl Strategy<Value = PairTable> {
+    proptest::collection::vec(0u8..3, length)
+        .prop_map(move |choices| nested_structure(&choices, min_unpaired))
+}
+
+// Interpret choices as unpaired (0), opening (1) or closing (2) positions.
+// Closing positions are only paired if they would not create a hairpin loop that is too small.
+// Opening positions that are never closed remain unpaired.
+fn nested_structure(choices: &[u8], min_unpaired: usize) -> PairTable {
+    let mut pt = PairTable::new(choices.len());
+    let mut stack: Vec<usize> = vec![];
+
+    for (i, &choice) in choices.iter().enumerate() {
+        match (choice, stack.last()) {
+            (1, _) => stack.push(i + 1),
+            (2, Some(&j)) if i + 1 - j > min_unpaired => {
+                stack.pop();
+                pt.insert(j as i16, (i + 1) as i16);
+            }
+            _ => (),
+        }
+    }
+
+    pt
+}
+
+/// Return whether the pairs of `pt` are symmetric, i.e. `pt[pt[i]] == i` for all paired positions `i`.
+pub fn is_symmetric(pt: &PairTable) -> bool {
+    let view = pt.view();
+
+    view.len() == pt.len() + 1
+        && view
+            .indexed_iter()
+            .skip(1)
+            .filter(|(_, &j)| j != 0)
+            .all(|(i, &j)| j > 0 && (j as usize) <= pt.len() && view[j as usize] as usize == i)
+}
+
+/// Return whether `pt` is recovered after converting it to dot-bracket notation and back.
+pub fn dot_bracket_roundtrip(pt: &PairTable) -> bool {
+    match pt.to_string().parse::<PairTable>() {
+        Ok(parsed) => parsed == *pt,
+        Err(_) => false,
+    }
+}
+
+/// Return whether `encoded.subsequence(start, end)` consists exactly of the columns of `encoded`
+/// that correspond to its parent indices.
+pub fn subsequence_roundtrip(encoded: &EncodedSequence, start: usize, end: usize) -> bool {
+    let sub = encoded.subsequence(start, end);
+
+    let consistent = sub
+        .parent_positions()
+        .all(|(k, p)| match encoded.from_parent(p) {
+            Some(local) => {
+                sub.forward.index_axis(Axis(1), k.0) == encoded.forward.index_axis(Axis(1), local.0)
+                    && sub.mirrored.index_axis(Axis(1), k.0)
+                        == encoded.mirrored.index_axis(Axis(1), local.0)
+            }
+            None => false,
+        });
+
+    consistent
+}
+
+/// Return whether `sequence` (possibly a complex, see [`EncodedSequence::cut_points()`]) is recovered
+/// by decoding its encoding. `T` is recovered as `U`.
+pub fn decode_roundtrip(sequence: &str) -> bool {
+    match EncodedSequence::new(sequence) {
+        Ok(encoded) => {
+            let expected = sequence.replace('T', "U");
+            encoded.to_string() == expected && encoded.decode() == expected.replace('&', "")
+        }
+        Err(_) => false,
+    }
+}
+
+/// Return whether the positions of `fragment`, a subsequence of `encoded`, map to the parent positions
+/// of `encoded` and back, and whether its decoded nucleotides are those of `encoded` at these positions.
+pub fn parent_mapping_roundtrip(encoded: &EncodedSequence, fragment: &EncodedSequence) -> bool {
+    let nucleotides: Vec<char> = encoded.decode().chars().collect();
+
+    fragment.parent_positions().count() == fragment.len()
+        && fragment
+            .parent_positions()
+            .zip(fragment.decode().chars())
+            .all(|((local, parent), c)| {
+                fragment.to_parent(local) == Some(parent)
+                    && fragment.from_parent(parent) == Some(local)
+                    && matches!(encoded.from_parent(parent), Some(k) if nucleotides[k.0] == c)
+            })
+}
+
+/// Two hairpins joined by a short linker, small enough to construct complete fast folding graphs in tests.
+pub const SMALL_SEQUENCE: &str = "GGGGAAAACCCCAUAUGGGGAAAACCCC";
+
+/// Return the fast folding graph of [`SMALL_SEQUENCE`] keeping `3` trajectories, after construction.
+pub fn small_graph() -> RafftGraph {
+    let mut ffgraph = RafftConfig::new()
+        .maximum_trajectories(3)
+        .folding_graph(SMALL_SEQUENCE);
+    ffgraph.construct_trajectories();
+    ffgraph
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn test_structure_invariants(pt in structures(0..200, 3)) {
+            prop_assert!(is_symmetric(&pt));
+            prop_assert!(dot_bracket_roundtrip(&pt));
+        }
+
+        #[test]
+        fn test_subsequence_invariants(
+            sequence in sequences(1..100),
+            a in 0usize..100,
+            b in 0usize..100,
+        ) {
+            let encoded = EncodedSequence::new(&sequence).unwrap();
+            let (start, end) = (a % (sequence.len() + 1), b % (sequence.len() + 1));
+
+            prop_assert!(subsequence_roundtrip(&encoded, start, end));
+
+            // subsequences of subsequences have to map to the original sequence as well
+            let sub = encoded.subsequence(start, end);
+            let (start, end) = (a % (sub.len() + 1), b % (sub.len() + 1));
+            prop_assert!(subsequence_roundtrip(&sub, start, end));
+
+            // and nesting retains the positions of the original sequence
+            let nested = sub.subsequence(start, end);
+            prop_assert!(nested
+                .parent_positions()
+                .all(|(_, p)| encoded.from_parent(p).is_some() && sub.from_parent(p).is_some()));
+            prop_assert!(nested.len() <= sub.len());
+        }
+
+        #[test]
+        fn test_encoding_invariants(
+            sequence in complexes(1..50, 3),
+            a in 0usize..150,
+            b in 0usize..150,
+            c in 0usize..150,
+        ) {
+            prop_assert!(decode_roundtrip(&sequence));
+            prop_assert!(decode_roundtrip(&sequence.replace('U', "T")));
+
+            let encoded = EncodedSequence::new(&sequence).unwrap();
+            let length = encoded.len() + 1;
+            prop_assert!(parent_mapping_roundtrip(&encoded, &encoded));
+
+            let sub = encoded.subsequence(a % length, b % length);
+            prop_assert!(parent_mapping_roundtrip(&encoded, &sub));
+
+            let nested = sub.subsequence(c % (sub.len() + 1), a % (sub.len() + 1));
+            prop_assert!(parent_mapping_roundtrip(&encoded, &nested));
+            prop_assert!(parent_mapping_roundtrip(&sub, &nested));
+
+            let mut bounds = [a % length, b % length, c % length];
+            bounds.sort_unstable();
+            let concatenation = encoded.concatenation(&[0..bounds[0], bounds[1]..bounds[2]]);
+            prop_assert!(parent_mapping_roundtrip(&encoded, &concatenation));
+        }
+    }
+}
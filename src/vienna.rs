@@ -1,36 +1,154 @@
 //! This module provides some limited functionality of ViennaRNA for use in RAFFT.
+use crate::encoding::{self, ungapped, PairTable};
+use crate::motifs::LoopType;
 use librna_sys::{
-    vrna_eval_structure_pt, vrna_fold_compound, vrna_fold_compound_free, vrna_fold_compound_t,
-    vrna_md_defaults_temperature, vrna_md_t, vrna_params_load, VRNA_OPTION_EVAL_ONLY,
-    VRNA_PARAMETER_FORMAT_DEFAULT, VRNA_VERSION,
+    vrna_eval_loop_pt, vrna_eval_structure_pt, vrna_fold_compound, vrna_fold_compound_free,
+    vrna_fold_compound_t, vrna_md_defaults_temperature, vrna_md_set_default, vrna_md_t,
+    vrna_params_load, vrna_params_load_DNA_Mathews1999, vrna_params_load_DNA_Mathews2004,
+    vrna_params_load_RNA_Andronescu2007, vrna_params_load_RNA_Turner1999,
+    vrna_params_load_RNA_Turner2004, vrna_path, vrna_plot_coords_pt, vrna_sc_add_bp,
+    vrna_sc_add_up, vrna_sc_remove, VRNA_MOVESET_DEFAULT, VRNA_OPTION_DEFAULT,
+    VRNA_OPTION_EVAL_ONLY, VRNA_PARAMETER_FORMAT_DEFAULT, VRNA_PATH_NO_TRANSITION_OUTPUT,
+    VRNA_PATH_STEEPEST_DESCENT, VRNA_PLOT_TYPE_CIRCULAR, VRNA_PLOT_TYPE_NAVIEW,
+    VRNA_PLOT_TYPE_PUZZLER, VRNA_PLOT_TYPE_SIMPLE, VRNA_PLOT_TYPE_TURTLE, VRNA_VERSION,
 };
-use ndarray::ArrayView1;
+use ndarray::{Array1, ArrayView1};
+use rayon::prelude::*;
+use serde::Serialize;
 use std::ffi::CString;
+use std::os::raw::c_void;
 use std::path::PathBuf;
+use std::ptr::NonNull;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{PoisonError, RwLock};
 
 /// A wrapper struct around `vrna_fold_compound_t` from ViennaRNA with limited functionality,
+///
+/// The fold compound is owned exclusively by the wrapper and freed exactly once when it is dropped.
+/// ViennaRNA copies the sequence and the model details when creating the fold compound and the energy
+/// parameters are derived from them, so a `VCompound` does not reference any memory owned by Rust.
+///
+/// A `VCompound` is `Send`, since ViennaRNA does not associate fold compounds with threads.
+/// It is not `Sync`, because evaluations pass the fold compound as mutable pointer and may modify it.
 pub struct VCompound {
-    fc: *mut vrna_fold_compound_t,
+    fc: NonNull<vrna_fold_compound_t>,
+    // sequence and temperature the fold compound was created for, to create equivalent fold compounds
+    sequence: String,
+    temperature: Option<f64>,
+    // built-in parameter set loaded instead of the global parameters, see `VCompound::with_options()`
+    parameter_set: Option<ParameterSet>,
+    // global energy model at creation, see `model_generation()`
+    generation: usize,
 }
 
+// Safety: the fold compound is owned exclusively (see above) and only accessed through `&self` or `&mut self`,
+// so moving it to another thread cannot create concurrent accesses.
+unsafe impl Send for VCompound {}
+
 impl VCompound {
     /// Create a new `VCompound` wrapper object from a string representing an RNA sequence.
+    /// Gaps are removed (see [`encoding::ungapped()`]), such that positions match the [`EncodedSequence`](crate::encoding::EncodedSequence).
+    /// Panics if ViennaRNA fails to create the fold compound.
     pub fn new(sequence: &str) -> Self {
-        let csequence = CString::new(sequence).expect("CString::new failed");
-        let fc = unsafe {
-            let md = std::ptr::null::<vrna_md_t>();
+        Self::with_model(sequence, None)
+    }
+
+    /// Create a new `VCompound` wrapper object for an RNA sequence using the energy model at `temperature` (in °C)
+    /// instead of the global default temperature (see [`set_global_temperature()`]).
+    /// Panics if ViennaRNA fails to create the fold compound.
+    pub fn with_temperature(sequence: &str, temperature: f64) -> Self {
+        Self::with_model(sequence, Some(temperature))
+    }
+
+    // Create a fold compound for `sequence` at `temperature`, or the global default temperature.
+    // The global parameters are copied into the fold compound, so they must not be swapped concurrently:
+    // callers have to hold `GLOBAL_PARAMETERS`.
+    fn create(sequence: &str, temperature: Option<f64>) -> Self {
+        let csequence = CString::new(ungapped(sequence)).expect("CString::new failed");
+        let fc = match temperature {
+            // Safety: ViennaRNA copies the sequence and falls back to the default model details for NULL
+            None => unsafe {
+                let md = std::ptr::null::<vrna_md_t>();
+
+                vrna_fold_compound(csequence.as_ptr(), md, VRNA_OPTION_EVAL_ONLY)
+            },
+            // Safety: `md` is initialized by ViennaRNA and copied into the fold compound,
+            // so it only has to outlive the call to `vrna_fold_compound()`
+            Some(temperature) => unsafe {
+                let mut md = std::mem::MaybeUninit::<vrna_md_t>::uninit();
+                vrna_md_set_default(md.as_mut_ptr());
 
-            vrna_fold_compound(csequence.as_ptr(), md, VRNA_OPTION_EVAL_ONLY)
+                let mut md = md.assume_init();
+                md.temperature = temperature;
+
+                vrna_fold_compound(csequence.as_ptr(), &md, VRNA_OPTION_EVAL_ONLY)
+            },
         };
 
-        Self { fc }
+        Self::from_raw(fc, sequence, temperature)
+    }
+
+    // Take ownership of a fold compound returned by ViennaRNA, which returns NULL on failure.
+    fn from_raw(fc: *mut vrna_fold_compound_t, sequence: &str, temperature: Option<f64>) -> Self {
+        Self {
+            fc: NonNull::new(fc).expect("ViennaRNA failed to create a fold compound"),
+            sequence: sequence.to_string(),
+            temperature,
+            parameter_set: None,
+            generation: model_generation(),
+        }
+    }
+
+    /// Create a new `VCompound` wrapper object for an RNA sequence using the energy model described by `options`
+    /// instead of the global defaults. A parameter set is loaded only while the fold compound is created
+    /// and the global parameters are restored afterwards.
+    /// Panics if ViennaRNA fails to create the fold compound.
+    pub fn with_options(sequence: &str, options: &ModelOptions) -> Self {
+        let Some(set) = options.parameter_set else {
+            return Self::with_model(sequence, options.temperature);
+        };
+
+        // hold the write lock, such that no other fold compound is created with the swapped parameters
+        let global = GLOBAL_PARAMETERS
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        // ViennaRNA copies the loaded parameters into the fold compound
+        load_parameter_set(set);
+        let mut vc = Self::create(sequence, options.temperature);
+        global.load();
+
+        vc.parameter_set = Some(set);
+        // the energy model differs from the global one, so the fold compound is never re-used
+        vc.generation = usize::MAX;
+        vc
+    }
+
+    // The sequence, temperature (`None` for the global default) and global energy model the fold compound
+    // was created for, such that equivalent fold compounds can be re-used.
+    pub(crate) fn model(&self) -> (&str, Option<f64>, usize) {
+        (&self.sequence, self.temperature, self.generation)
+    }
+
+    // Create a fold compound for `sequence` at `temperature`, or the global default temperature,
+    // while no parameter set is loaded temporarily by `with_options()`.
+    fn with_model(sequence: &str, temperature: Option<f64>) -> Self {
+        let _global = GLOBAL_PARAMETERS
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        Self::create(sequence, temperature)
+    }
+
+    // All calls into ViennaRNA go through this pointer, which is valid until `self` is dropped.
+    fn as_ptr(&self) -> *mut vrna_fold_compound_t {
+        self.fc.as_ptr()
     }
 
     /// Compute the minimum free energy of an RNA secondary structure provided as a pair table.
     /// Pair tables are 1-indexed and contain the structure's length at position 0.
     pub fn evaluate_structure(&self, pairtable: ArrayView1<i16>) -> i32 {
         assert_eq!(pairtable.len(), self.len() + 1);
-        unsafe { vrna_eval_structure_pt(self.fc, pairtable.as_ptr()) }
+        unsafe { vrna_eval_structure_pt(self.as_ptr(), pairtable.as_ptr()) }
     }
 
     /// Compute the minimum free energy of an RNA secondary structure provided as a pair table.
@@ -40,39 +158,290 @@ impl VCompound {
         self.evaluate_structure(pairtable) as f64 * 0.01
     }
 
+    /// Compute the free energies of many `structures` in `dcal/mol` using this fold compound, in their order.
+    /// This avoids creating a fold compound per structure, which dominates the evaluation of short sequences.
+    /// Panics if any structure does not match the length of the sequence.
+    pub fn eval_many(&self, structures: &[PairTable]) -> Vec<i32> {
+        structures
+            .iter()
+            .map(|structure| self.evaluate_structure(structure.view()))
+            .collect()
+    }
+
+    /// Compute the free energies of many `structures` like [`VCompound::eval_many()`], evaluating chunks of
+    /// `chunk_size` structures in parallel. Fold compounds cannot be shared between threads, so each chunk
+    /// is evaluated using its own fold compound for the same sequence and temperature; soft constraints
+    /// (see [`VCompound::add_unpaired_soft_constraint()`]) are not taken into account.
+    pub fn par_eval_many(&self, structures: &[PairTable], chunk_size: usize) -> Vec<i32> {
+        // `self` is not `Sync`, so only its model is shared
+        let sequence = self.sequence.as_str();
+        let options = ModelOptions {
+            temperature: self.temperature,
+            parameter_set: self.parameter_set,
+        };
+
+        structures
+            .par_chunks(chunk_size.max(1))
+            .flat_map_iter(|chunk| Self::with_options(sequence, &options).eval_many(chunk))
+            .collect()
+    }
+
+    /// Perform a steepest descent gradient walk from an RNA secondary structure provided as a pair table
+    /// to a local minimum, using the default move set of ViennaRNA (insertion and deletion of single pairs).
+    /// Returns the pair table of the local minimum.
+    pub fn gradient_walk(&self, pairtable: ArrayView1<i16>) -> Array1<i16> {
+        assert_eq!(pairtable.len(), self.len() + 1);
+        let mut pairtable = pairtable.to_owned();
+
+        unsafe {
+            vrna_path(
+                self.as_ptr(),
+                pairtable.as_mut_ptr(),
+                u32::MAX,
+                VRNA_PATH_STEEPEST_DESCENT | VRNA_MOVESET_DEFAULT | VRNA_PATH_NO_TRANSITION_OUTPUT,
+            );
+        }
+
+        pairtable
+    }
+
+    /// Decompose the free energy of an RNA secondary structure provided as a pair table into
+    /// the contributions of its loops, starting with the exterior loop and followed by the loops
+    /// closed by each pair in `5'` to `3'` order of their closing pairs.
+    /// Energies are in `dcal/mol` and sum up to [`VCompound::evaluate_structure()`].
+    pub fn loop_contributions(&self, pairtable: ArrayView1<i16>) -> Vec<LoopContribution> {
+        assert_eq!(pairtable.len(), self.len() + 1);
+
+        let exterior = LoopContribution {
+            loop_type: LoopType::Exterior,
+            closing_pair: None,
+            energy: unsafe { vrna_eval_loop_pt(self.as_ptr(), 0, pairtable.as_ptr()) },
+        };
+
+        let closed = (1..pairtable.len())
+            .filter(|&i| pairtable[i] as usize > i)
+            .map(|i| {
+                let j = pairtable[i] as usize;
+
+                // collect the pairs directly enclosed by (i, j)
+                let mut inner = vec![];
+                let mut k = i + 1;
+                while k < j {
+                    if pairtable[k] as usize > k {
+                        inner.push((k, pairtable[k] as usize));
+                        k = pairtable[k] as usize;
+                    }
+                    k += 1;
+                }
+
+                let loop_type = match inner[..] {
+                    [] => LoopType::Hairpin,
+                    [(p, q)] => match (p - i - 1, j - q - 1) {
+                        (0, 0) => LoopType::Stack,
+                        (0, _) | (_, 0) => LoopType::Bulge,
+                        _ => LoopType::Interior,
+                    },
+                    _ => LoopType::Multi,
+                };
+
+                LoopContribution {
+                    loop_type,
+                    closing_pair: Some((i, j)),
+                    energy: unsafe {
+                        vrna_eval_loop_pt(self.as_ptr(), i as i32, pairtable.as_ptr())
+                    },
+                }
+            });
+
+        std::iter::once(exterior).chain(closed).collect()
+    }
+
+    /// Add a soft constraint, i.e. a pseudo-energy in `kcal/mol`, for position `i` (`1`-indexed) being unpaired.
+    /// Soft constraints accumulate and are included in all subsequent evaluations, e.g. to incorporate
+    /// probing data or custom pseudo-energies. Negative energies favor unpaired positions.
+    /// Panics if `i` is out of range.
+    /// Refer to the [upstream API](https://www.tbi.univie.ac.at/RNA/ViennaRNA/doc/html/group__soft__constraints.html) for details.
+    pub fn add_unpaired_soft_constraint(&mut self, i: usize, energy: f64) {
+        assert!(0 < i && i <= self.len());
+        unsafe {
+            vrna_sc_add_up(self.as_ptr(), i as i32, energy, VRNA_OPTION_DEFAULT);
+        }
+    }
+
+    /// Add a soft constraint, i.e. a pseudo-energy in `kcal/mol`, for positions `i` and `j` (`1`-indexed, `i < j`)
+    /// forming a pair, see [`VCompound::add_unpaired_soft_constraint()`]. Negative energies favor the pair.
+    /// Panics if `i` or `j` is out of range or `i >= j`.
+    pub fn add_pair_soft_constraint(&mut self, i: usize, j: usize, energy: f64) {
+        assert!(0 < i && i < j && j <= self.len());
+        unsafe {
+            vrna_sc_add_bp(
+                self.as_ptr(),
+                i as i32,
+                j as i32,
+                energy,
+                VRNA_OPTION_DEFAULT,
+            );
+        }
+    }
+
+    /// Remove all soft constraints.
+    pub fn remove_soft_constraints(&mut self) {
+        unsafe {
+            vrna_sc_remove(self.as_ptr());
+        }
+    }
+
     /// Return the length of the underlying sequence of the fold compound.
     #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         // Safety: fc.length is a u32 field in ViennaRNA
-        unsafe { (*self.fc).length as usize }
+        unsafe { (*self.as_ptr()).length as usize }
     }
 }
 
 impl Drop for VCompound {
     fn drop(&mut self) {
+        // Safety: the fold compound is owned exclusively and `VCompound` is neither `Clone` nor `Copy`
         unsafe {
-            vrna_fold_compound_free(self.fc);
+            vrna_fold_compound_free(self.as_ptr());
+        }
+    }
+}
+
+/// Conversions between [`PairTable`] and the `short *` pair tables of ViennaRNA,
+/// which share the same layout: `1`-indexed with the length of the structure at position `0`.
+impl PairTable {
+    /// Return a pointer to the pair table that can be passed to libRNA functions expecting a `const short *`
+    /// (e.g. `vrna_eval_structure_pt()`) without copying.
+    /// The pointer is valid as long as `self` is neither modified nor dropped.
+    pub fn as_vrna_ptr(&self) -> *const i16 {
+        debug_assert!(self.0.is_standard_layout());
+        self.0.as_ptr()
+    }
+
+    /// Return a pointer to the pair table that can be passed to libRNA functions modifying a `short *`
+    /// in place (e.g. `vrna_path()`) without copying.
+    /// The pointer is valid as long as `self` is neither modified otherwise nor dropped.
+    /// Callers have to ensure that the length at position `0` is retained and pairs remain symmetric.
+    pub fn as_vrna_mut_ptr(&mut self) -> *mut i16 {
+        debug_assert!(self.0.is_standard_layout());
+        self.0.as_mut_ptr()
+    }
+
+    /// Copy a pair table allocated by ViennaRNA, e.g. returned by `vrna_ptable()`.
+    /// The pair table is not freed, i.e. ownership remains with the caller.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` has to point to a valid pair table, i.e. a non-negative length `n` followed by `n` entries.
+    pub unsafe fn from_vrna(ptr: *const i16) -> Self {
+        let length = *ptr;
+        assert!(length >= 0, "negative pair table length");
+
+        PairTable(ArrayView1::from_shape_ptr(length as usize + 1, ptr).to_owned())
+    }
+
+    /// Copy a pair table in the layout of ViennaRNA from `table`, checking that the length at position `0`
+    /// matches and that all pairs are within range and symmetric.
+    pub fn from_vrna_slice(table: &[i16]) -> Result<Self, encoding::Error> {
+        let length = table.len().saturating_sub(1);
+
+        if table.first().map(|&n| n as usize) != Some(length) {
+            return Err(encoding::Error::InvalidPairTable(0));
+        }
+
+        for (i, &j) in table.iter().enumerate().skip(1) {
+            let valid = j == 0
+                || (j > 0
+                    && j as usize <= length
+                    && j as usize != i
+                    && table[j as usize] as usize == i);
+
+            if !valid {
+                return Err(encoding::Error::InvalidPairTable(i));
+            }
         }
+
+        Ok(PairTable(Array1::from_vec(table.to_vec())))
     }
 }
 
-// TODO: check safety for this
-// https://medium.com/dwelo-r-d/wrapping-unsafe-c-libraries-in-rust-d75aeb283c65
-// see bindings.rs #[pyclass(unsendable)]
-//unsafe impl Send for VCompound {}
-//unsafe impl Sync for VCompound {}
+/// Free energy contribution of a single loop, see [`loop_contributions()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LoopContribution {
+    /// type of the loop
+    pub loop_type: LoopType,
+    /// pair closing the loop (`1`-indexed), or `None` for the exterior loop
+    pub closing_pair: Option<(usize, usize)>,
+    /// free energy contribution in `dcal/mol`
+    pub energy: i32,
+}
+
+/// Compute the free energy of `structure` on `sequence` in `dcal/mol`, using the global energy parameters
+/// and temperature. Strands of complexes are separated by `&` in `sequence`.
+pub fn evaluate_structure(sequence: &str, structure: &PairTable) -> i32 {
+    VCompound::new(sequence).evaluate_structure(structure.view())
+}
+
+/// Decompose the free energy of `structure` on `sequence` into the contributions of its loops,
+/// similar to `RNAeval -v`, using the global energy parameters and temperature.
+/// Energies are in `dcal/mol`.
+pub fn loop_contributions(sequence: &str, structure: &PairTable) -> Vec<LoopContribution> {
+    VCompound::new(sequence).loop_contributions(structure.view())
+}
+
+// Incremented whenever the global energy model changes, such that cached fold compounds can be invalidated.
+static MODEL_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+// Return the current generation of the global energy model.
+pub(crate) fn model_generation() -> usize {
+    MODEL_GENERATION.load(Ordering::Relaxed)
+}
 
 /// Set the temperature of the Nearest-Neighbor model in `ViennaRNA` globally.
 /// Refer to the [upstream API](https://www.tbi.univie.ac.at/RNA/ViennaRNA/doc/html/group__model__details.html#gaf9e527e9a2f7e6fd6e42bc6e602f5445) for details.
+/// Fold compounds created concurrently wait for the change, but existing fold compounds keep the previous model.
 pub fn set_global_temperature(temperature: f64) {
+    let _global = GLOBAL_PARAMETERS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner);
     unsafe {
         vrna_md_defaults_temperature(temperature);
     }
+    MODEL_GENERATION.fetch_add(1, Ordering::Relaxed);
 }
 
 /// Read the parameters of the Nearest-Neighbor model from a file and sets them globally.
 /// Refer to the [upstream API](https://www.tbi.univie.ac.at/RNA/ViennaRNA/doc/html/group__energy__parameters__rw.html#gabb0583595c67094986ef90cb4f1c7555) for details.
 pub fn set_global_energy_parameters(parameters: PathBuf) {
+    let mut global = GLOBAL_PARAMETERS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner);
+    load_parameter_file(&parameters);
+    *global = GlobalParameters::File(parameters);
+    MODEL_GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+// The parameters loaded globally, restored after parameter sets are loaded temporarily.
+enum GlobalParameters {
+    Set(ParameterSet),
+    File(PathBuf),
+}
+
+impl GlobalParameters {
+    fn load(&self) {
+        match self {
+            Self::Set(set) => load_parameter_set(*set),
+            Self::File(parameters) => load_parameter_file(parameters),
+        }
+    }
+}
+
+// Fold compounds are created under the read lock, swapping the parameters requires the write lock.
+static GLOBAL_PARAMETERS: RwLock<GlobalParameters> =
+    RwLock::new(GlobalParameters::Set(ParameterSet::RnaTurner2004));
+
+fn load_parameter_file(parameters: &std::path::Path) {
     let cparams =
         std::ffi::CString::new(parameters.to_str().unwrap()).expect("CString::new failed");
     unsafe {
@@ -80,6 +449,147 @@ pub fn set_global_energy_parameters(parameters: PathBuf) {
     }
 }
 
+fn load_parameter_set(set: ParameterSet) {
+    unsafe {
+        match set {
+            ParameterSet::RnaTurner2004 => vrna_params_load_RNA_Turner2004(),
+            ParameterSet::RnaTurner1999 => vrna_params_load_RNA_Turner1999(),
+            ParameterSet::RnaAndronescu2007 => vrna_params_load_RNA_Andronescu2007(),
+            ParameterSet::DnaMathews2004 => vrna_params_load_DNA_Mathews2004(),
+            ParameterSet::DnaMathews1999 => vrna_params_load_DNA_Mathews1999(),
+        };
+    }
+}
+
+/// Nearest-Neighbor parameter sets shipped with ViennaRNA.
+/// RNA–DNA hybrid parameters are not built in and have to be loaded from a file using
+/// [`set_global_energy_parameters()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParameterSet {
+    /// RNA parameters of Turner 2004 (ViennaRNA default)
+    RnaTurner2004,
+    /// RNA parameters of Turner 1999
+    RnaTurner1999,
+    /// RNA parameters of Andronescu 2007
+    RnaAndronescu2007,
+    /// DNA parameters of Mathews 2004
+    DnaMathews2004,
+    /// DNA parameters of Mathews 1999
+    DnaMathews1999,
+}
+
+impl FromStr for ParameterSet {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "rna" | "turner2004" => Ok(Self::RnaTurner2004),
+            "turner1999" => Ok(Self::RnaTurner1999),
+            "andronescu2007" => Ok(Self::RnaAndronescu2007),
+            "dna" | "mathews2004" => Ok(Self::DnaMathews2004),
+            "mathews1999" => Ok(Self::DnaMathews1999),
+            _ => Err(format!(
+                "unknown parameter set {:?} (expected one of rna, turner2004, turner1999, andronescu2007, dna, mathews2004, mathews1999)",
+                name
+            )),
+        }
+    }
+}
+
+/// Load one of the built-in parameter sets of the Nearest-Neighbor model and set it globally.
+/// For DNA parameters, consider disabling `GU` pairs using [`RafftConfig::basepair_weights()`](crate::fast_folding::RafftConfig::basepair_weights).
+pub fn set_global_parameter_set(set: ParameterSet) {
+    let mut global = GLOBAL_PARAMETERS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner);
+    load_parameter_set(set);
+    *global = GlobalParameters::Set(set);
+    MODEL_GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Energy model to evaluate structures with instead of the global defaults, see [`VCompound::with_options()`].
+/// Unset options fall back to the global defaults.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ModelOptions {
+    /// temperature in °C
+    pub temperature: Option<f64>,
+    /// built-in parameter set of the Nearest-Neighbor model
+    pub parameter_set: Option<ParameterSet>,
+}
+
+/// Layouts of secondary structure plots computed by ViennaRNA, as used by `RNAplot` and `RNAfold`,
+/// see [`plot_coordinates()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum PlotLayout {
+    /// simple radial layout
+    Simple,
+    /// layout of Bruccoleri and Heinrichs (default of `RNAplot`)
+    #[default]
+    Naview,
+    /// nucleotides on a circle
+    Circular,
+    /// RNAturtle layout
+    Turtle,
+    /// RNApuzzler layout, avoiding overlaps
+    Puzzler,
+}
+
+impl FromStr for PlotLayout {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "simple" => Ok(Self::Simple),
+            "naview" => Ok(Self::Naview),
+            "circular" => Ok(Self::Circular),
+            "turtle" => Ok(Self::Turtle),
+            "puzzler" => Ok(Self::Puzzler),
+            _ => Err(format!(
+                "unknown plot layout {:?} (expected one of simple, naview, circular, turtle, puzzler)",
+                name
+            )),
+        }
+    }
+}
+
+extern "C" {
+    // coordinates are allocated by ViennaRNA using `malloc()`
+    fn free(ptr: *mut c_void);
+}
+
+/// Return the `(x, y)` coordinates of each nucleotide of `structure` in the plot `layout` of ViennaRNA
+/// (see `vrna_plot_coords_pt()`), e.g. to draw structures consistently with `RNAfold` figures
+/// (see [`crate::drawing`]) or in external plotting scripts.
+/// Returns an empty vector if ViennaRNA fails to compute the layout.
+pub fn plot_coordinates(structure: &PairTable, layout: PlotLayout) -> Vec<(f64, f64)> {
+    let plot_type = match layout {
+        PlotLayout::Simple => VRNA_PLOT_TYPE_SIMPLE,
+        PlotLayout::Naview => VRNA_PLOT_TYPE_NAVIEW,
+        PlotLayout::Circular => VRNA_PLOT_TYPE_CIRCULAR,
+        PlotLayout::Turtle => VRNA_PLOT_TYPE_TURTLE,
+        PlotLayout::Puzzler => VRNA_PLOT_TYPE_PUZZLER,
+    };
+    let (mut x, mut y) = (std::ptr::null_mut::<f32>(), std::ptr::null_mut::<f32>());
+
+    // Safety: ViennaRNA allocates `length` coordinates each, which are copied before they are freed
+    unsafe {
+        let length = vrna_plot_coords_pt(structure.as_vrna_ptr(), &mut x, &mut y, plot_type as i32);
+        let coordinates = if length > 0 && !x.is_null() && !y.is_null() {
+            std::slice::from_raw_parts(x, length as usize)
+                .iter()
+                .zip(std::slice::from_raw_parts(y, length as usize))
+                .map(|(&x, &y)| (x as f64, y as f64))
+                .collect()
+        } else {
+            vec![]
+        };
+
+        free(x as *mut c_void);
+        free(y as *mut c_void);
+        coordinates
+    }
+}
+
 /// Return the version string of the statically linked `ViennaRNA` library.
 // Safety: the version string of ViennaRNA should always be valid Unicode
 pub const VIENNA_VERSION: &str = unsafe { std::str::from_utf8_unchecked(VRNA_VERSION) };
@@ -107,4 +617,174 @@ mod tests {
 
         assert_eq!(-25.8f64, vc.evaluate_structure_f64(pt.view()));
     }
+
+    #[test]
+    fn test_vrna_pair_table() {
+        let sequence = "GGGGAAAACCCC";
+        let mut pt: PairTable = "((((....))))".parse().unwrap();
+        let vc = VCompound::new(sequence);
+
+        let energy = unsafe { vrna_eval_structure_pt(vc.as_ptr(), pt.as_vrna_ptr()) };
+        assert_eq!(energy, vc.evaluate_structure(pt.view()));
+
+        let copy = unsafe { PairTable::from_vrna(pt.as_vrna_mut_ptr()) };
+        assert_eq!(copy, pt);
+
+        assert_eq!(
+            PairTable::from_vrna_slice(pt.view().as_slice().unwrap()).unwrap(),
+            pt
+        );
+        assert_eq!(
+            PairTable::from_vrna_slice(&[3, 3, 0, 1]).unwrap(),
+            "(.)".parse().unwrap()
+        );
+        assert!(matches!(
+            PairTable::from_vrna_slice(&[4, 3, 0, 1]),
+            Err(encoding::Error::InvalidPairTable(0))
+        ));
+        assert!(matches!(
+            PairTable::from_vrna_slice(&[3, 3, 0, 2]),
+            Err(encoding::Error::InvalidPairTable(1))
+        ));
+        assert!(matches!(
+            PairTable::from_vrna_slice(&[2, 1, 0]),
+            Err(encoding::Error::InvalidPairTable(1))
+        ));
+        assert!(PairTable::from_vrna_slice(&[]).is_err());
+    }
+
+    #[test]
+    fn test_compound_ownership() {
+        let sequence = "GGGGAAAACCCC";
+        let pt: PairTable = "((((....))))".parse().unwrap();
+
+        // every compound is freed exactly once, which is checked by address sanitizers and valgrind
+        let energies: Vec<i32> = (0..100)
+            .map(|_| VCompound::new(sequence).evaluate_structure(pt.view()))
+            .collect();
+        assert!(energies.windows(2).all(|e| e[0] == e[1]));
+
+        // compounds can be moved to other threads
+        let vc = VCompound::with_temperature(sequence, 37.0);
+        let handle = std::thread::spawn(move || vc.evaluate_structure(pt.view()));
+        assert_eq!(handle.join().unwrap(), energies[0]);
+    }
+
+    #[test]
+    fn test_soft_constraints() {
+        let mut vc = VCompound::new("GGGGAAAACCCC");
+        let pt: PairTable = "((((....))))".parse().unwrap();
+        let energy = vc.evaluate_structure(pt.view());
+
+        vc.add_pair_soft_constraint(1, 12, -2.0);
+        assert_eq!(vc.evaluate_structure(pt.view()), energy - 200);
+
+        vc.add_unpaired_soft_constraint(6, 0.5);
+        vc.add_unpaired_soft_constraint(6, 0.5);
+        // constraints on pairs that are not formed do not contribute
+        vc.add_pair_soft_constraint(2, 10, -3.0);
+        assert_eq!(vc.evaluate_structure(pt.view()), energy - 100);
+
+        vc.remove_soft_constraints();
+        assert_eq!(vc.evaluate_structure(pt.view()), energy);
+    }
+
+    #[test]
+    fn test_loop_contributions() {
+        let sequence = "GGGGAAAACCCCAUAUGGGGAAAACCCCA";
+        let structure = "((((....))))..(((((....))).))"
+            .parse::<PairTable>()
+            .unwrap();
+        let vc = VCompound::new(sequence);
+
+        let loops = vc.loop_contributions(structure.view());
+        let types: Vec<_> = loops.iter().map(|l| l.loop_type).collect();
+
+        assert_eq!(loops.len(), structure.pairs() + 1);
+        assert_eq!(loops[0].closing_pair, None);
+        assert_eq!(loops[1].closing_pair, Some((1, 12)));
+        assert_eq!(
+            types,
+            vec![
+                LoopType::Exterior,
+                LoopType::Stack,
+                LoopType::Stack,
+                LoopType::Stack,
+                LoopType::Hairpin,
+                LoopType::Stack,
+                LoopType::Bulge,
+                LoopType::Stack,
+                LoopType::Stack,
+                LoopType::Hairpin,
+            ]
+        );
+        assert_eq!(
+            loops.iter().map(|l| l.energy).sum::<i32>(),
+            vc.evaluate_structure(structure.view())
+        );
+    }
+
+    #[test]
+    fn test_plot_coordinates() {
+        let structure: PairTable = "((((....))))..((...))".parse().unwrap();
+
+        for layout in ["simple", "naview", "circular", "turtle", "puzzler"] {
+            let coordinates = plot_coordinates(&structure, layout.parse().unwrap());
+
+            assert_eq!(coordinates.len(), structure.len());
+            assert!(coordinates
+                .iter()
+                .all(|(x, y)| x.is_finite() && y.is_finite()));
+        }
+
+        // circular layouts place all nucleotides at the same distance from their center
+        let coordinates = plot_coordinates(&structure, PlotLayout::Circular);
+        let n = coordinates.len() as f64;
+        let (cx, cy) = coordinates
+            .iter()
+            .fold((0.0, 0.0), |(cx, cy), (x, y)| (cx + x / n, cy + y / n));
+        let radii: Vec<f64> = coordinates
+            .iter()
+            .map(|(x, y)| ((x - cx).powi(2) + (y - cy).powi(2)).sqrt())
+            .collect();
+        assert!(radii
+            .iter()
+            .all(|radius| (radius - radii[0]).abs() < 1e-3 * radii[0]));
+
+        assert_eq!(PlotLayout::default(), PlotLayout::Naview);
+        assert!("unknown".parse::<PlotLayout>().is_err());
+    }
+
+    #[test]
+    fn test_eval_many() {
+        let sequence = "GGGGAAAACCCCAUAUAUGGGGAAAACCCC";
+        let structures: Vec<PairTable> = [
+            "..............................",
+            "((((....))))..................",
+            "((((....))))......((((....))))",
+            "((((....(((((....)))))....))))",
+            "(((......)))......(((......)))",
+        ]
+        .iter()
+        .map(|structure| structure.parse().unwrap())
+        .collect();
+
+        let vc = VCompound::new(sequence);
+        let expected: Vec<i32> = structures
+            .iter()
+            .map(|structure| evaluate_structure(sequence, structure))
+            .collect();
+
+        assert_eq!(vc.eval_many(&structures), expected);
+        for chunk_size in [0, 1, 2, 10] {
+            assert_eq!(vc.par_eval_many(&structures, chunk_size), expected);
+        }
+        assert!(vc.eval_many(&[]).is_empty());
+
+        let warm = VCompound::with_temperature(sequence, 50.0);
+        assert_eq!(
+            warm.par_eval_many(&structures, 2),
+            warm.eval_many(&structures)
+        );
+    }
 }
@@ -0,0 +1,65 @@
+//! Golden-output comparison against the reference implementation of RAFFT.
+//!
+//! The reference command is taken from the environment variable `RAFFT_REFERENCE`
+//! (see [`rafft::reference::run_reference()`]); alternatively `rafft.py` is used if it is found on `PATH`.
+//! The test passes trivially if the reference implementation is not available.
+#![cfg(feature = "viennarna")]
+
+use rafft::fast_folding::RafftConfig;
+use rafft::reference::{compare, parse_trajectories, run_reference};
+
+const SEQUENCES: &[&str] = &[
+    "GGGUUUGCGGUGUAAGUGCAGCCCGUCUUACACCGUGCGGCACAGGCACUAGUACUGAUGUCGUAUACAGGGCUUUUGACAU",
+    "GUGCCUUGCGCCGGGAAACCACGCAAGGGGCGUAUGGCGCGCCGAUGAAGGUGUAGA",
+    "UGCGGUGUAAGUGC",
+];
+
+fn reference_command() -> Option<String> {
+    if let Ok(command) = std::env::var("RAFFT_REFERENCE") {
+        return Some(command);
+    }
+
+    std::env::var_os("PATH").and_then(|paths| {
+        std::env::split_paths(&paths)
+            .map(|dir| dir.join("rafft.py"))
+            .find(|path| path.is_file())
+            .map(|path| {
+                format!(
+                    "python {} -s {{sequence}} -ms {{saved_trajectories}} --traj",
+                    path.display()
+                )
+            })
+    })
+}
+
+#[test]
+fn test_reference_trajectories() {
+    let command = match reference_command() {
+        Some(command) => command,
+        None => {
+            eprintln!("reference implementation of RAFFT not available, skipping");
+            return;
+        }
+    };
+
+    for saved_trajectories in [1, 5] {
+        let config = RafftConfig::new().maximum_trajectories(saved_trajectories);
+
+        for sequence in SEQUENCES {
+            let output = run_reference(&command, sequence, saved_trajectories).unwrap();
+            let reference = parse_trajectories(&output).unwrap();
+
+            let mut ffgraph = config.folding_graph(sequence);
+            ffgraph.construct_trajectories();
+
+            let discrepancies = compare(&ffgraph, &reference, 0.01);
+            assert!(
+                discrepancies.is_empty(),
+                "{} (saved trajectories: {}): {:?}",
+                sequence,
+                saved_trajectories,
+                discrepancies
+            );
+        }
+    }
+}